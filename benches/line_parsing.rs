@@ -0,0 +1,65 @@
+// Compares the allocating `lines()` + `collect()` reader loop with the
+// buffer-reusing one used by the monitor.
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use std::hint::black_box;
+use std::io::BufRead;
+
+// The module is self-contained, so it is compiled straight into the bench;
+// only the line reader is exercised and its unit tests are not run from here
+#[path = "../src/stream/lines.rs"]
+#[allow(dead_code, unused_imports)]
+mod lines;
+
+const PACKET: &str =
+    "packet,video,0,1234567,13.717411,1234567,13.717411,3003,0.033367,N/A,N/A,45678,1880,K_";
+const FRAME: &str = "frame,media_type=video,stream_index=0,key_frame=1,pts=126000,pts_time=1.400000,pkt_dts=126000,pkt_dts_time=1.400000,best_effort_timestamp=126000,best_effort_timestamp_time=1.400000,duration=3600,duration_time=0.040000,pkt_pos=564,pkt_size=24316,width=1280,height=720,pix_fmt=yuv420p,sample_aspect_ratio=1:1,pict_type=I,interlaced_frame=0,top_field_first=0,repeat_pict=0,color_range=tv,color_space=bt709,color_primaries=bt709,color_transfer=bt709,chroma_location=left";
+
+fn sample_output() -> String {
+    let mut output = String::new();
+    for _ in 0..5_000 {
+        output.push_str(PACKET);
+        output.push('\n');
+        output.push_str(FRAME);
+        output.push('\n');
+    }
+    output
+}
+
+fn allocating(input: &[u8]) -> usize {
+    let mut fields = 0;
+    for line in input.lines() {
+        let line = line.unwrap();
+        let parts: Vec<&str> = line.split(',').collect();
+        fields += black_box(parts.len());
+    }
+    fields
+}
+
+fn reusing(input: &[u8]) -> usize {
+    let mut fields = 0;
+    lines::for_each_line(input, |line| {
+        let parts = lines::Fields::split(line);
+        fields += black_box(parts.as_slice().len());
+        Ok(())
+    })
+    .unwrap();
+    fields
+}
+
+fn bench_line_parsing(c: &mut Criterion) {
+    let output = sample_output();
+    let input = output.as_bytes();
+    assert_eq!(allocating(input), reusing(input));
+
+    let mut group = c.benchmark_group("ffprobe_stdout");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    group.bench_function("lines_collect", |b| b.iter(|| allocating(black_box(input))));
+    group.bench_function("read_until_fields", |b| {
+        b.iter(|| reusing(black_box(input)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_line_parsing);
+criterion_main!(benches);
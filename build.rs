@@ -1,3 +1,7 @@
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
 fn main() {
     #[cfg(target_os = "windows")]
     {
@@ -12,4 +16,54 @@ fn main() {
                 .expect("Failed to compile Windows resource");
         }
     }
+
+    build_info();
+}
+
+/// Exposes the labels of `ffmpeg_exporter_build_info` to the crate as
+/// environment variables
+fn build_info() {
+    // Builds without the repository, like the Docker image, pass it in
+    println!("cargo:rerun-if-env-changed=GIT_SHA");
+    for git_file in [".git/HEAD", ".git/refs/heads"] {
+        if Path::new(git_file).exists() {
+            println!("cargo:rerun-if-changed={}", git_file);
+        }
+    }
+    let git_sha = env::var("GIT_SHA")
+        .ok()
+        .filter(|sha| !sha.is_empty())
+        .or_else(|| output("git", &["rev-parse", "--short=12", "HEAD"]))
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=FFMPEG_EXPORTER_GIT_SHA={}", git_sha);
+
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = output(&rustc, &["--version"])
+        .and_then(|version| version.split_whitespace().nth(1).map(str::to_string))
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=FFMPEG_EXPORTER_RUSTC={}", rustc_version);
+
+    // `default` is set whenever default features are, even though there are none
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .filter(|feature| feature != "default")
+        .collect();
+    features.sort();
+    println!(
+        "cargo:rustc-env=FFMPEG_EXPORTER_FEATURES={}",
+        features.join(",")
+    );
+}
+
+/// Trimmed stdout of a successful command
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    Some(stdout.trim().to_string())
 }
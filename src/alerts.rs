@@ -0,0 +1,238 @@
+// alerts.rs
+
+use crate::config::ResourceLabels;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Thresholds of the generated alerting rules
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertThresholds {
+    /// Seconds a stream may be disconnected before it counts as down
+    pub down_for: u64,
+    /// Seconds without data before a stream counts as stalled, the
+    /// `--stall-timeout` of the exporter
+    pub stall_timeout: u64,
+    /// Corrupt packets, codec and continuity errors per minute that make a
+    /// corruption burst
+    pub corruption_per_minute: u64,
+    /// Range of the RMS level of audio tracks in dBFS
+    pub loudness_min: f64,
+    pub loudness_max: f64,
+    /// Seconds the level may be out of range before alerting
+    pub loudness_for: u64,
+}
+
+#[derive(Serialize)]
+pub struct RuleFile {
+    groups: Vec<RuleGroup>,
+}
+
+#[derive(Serialize)]
+struct RuleGroup {
+    name: &'static str,
+    rules: Vec<Rule>,
+}
+
+#[derive(Serialize)]
+struct Rule {
+    alert: &'static str,
+    expr: String,
+    #[serde(rename = "for", skip_serializing_if = "Option::is_none")]
+    for_: Option<String>,
+    labels: BTreeMap<&'static str, &'static str>,
+    annotations: BTreeMap<&'static str, String>,
+}
+
+impl Rule {
+    fn new(alert: &'static str, expr: String, severity: &'static str) -> Self {
+        Self {
+            alert,
+            expr,
+            for_: None,
+            labels: BTreeMap::from([("severity", severity)]),
+            annotations: BTreeMap::new(),
+        }
+    }
+
+    fn lasting(mut self, seconds: u64) -> Self {
+        if seconds > 0 {
+            self.for_ = Some(format!("{}s", seconds));
+        }
+        self
+    }
+
+    fn annotate(mut self, summary: &str, description: String) -> Self {
+        self.annotations.insert("summary", summary.to_string());
+        self.annotations.insert("description", description);
+        self
+    }
+}
+
+/// Prometheus alerting rules for the metrics of this exporter, with the
+/// metric names prefixed as configured in `resource`
+pub fn rules(thresholds: &AlertThresholds, resource: &ResourceLabels) -> RuleFile {
+    let m = |name: &str| resource.metric(name);
+    // Leaves out streams paused through the admin API. `unless` binds
+    // tighter than `or`, so the expression is put in parentheses.
+    let unless_paused = |expr: &str| {
+        format!(
+            "({}) unless on (stream, probe_id) {} == 1",
+            expr,
+            m("ffmpeg_stream_paused")
+        )
+    };
+    let mut rules = vec![
+        Rule::new(
+            "FFmpegStreamDown",
+            unless_paused(&format!("{} == 0", m("ffmpeg_stream_connection_state"))),
+            "critical",
+        )
+        .lasting(thresholds.down_for)
+        .annotate(
+            "Stream {{ $labels.stream }} is down",
+            format!(
+                "ffprobe has not been connected to {{{{ $labels.stream }}}} on {{{{ $labels.probe_id }}}} for {} seconds.",
+                thresholds.down_for
+            ),
+        ),
+        Rule::new(
+            "FFmpegStreamCorruptionBurst",
+            unless_paused(&format!(
+                "sum by (stream, probe_id) (increase({{__name__=~\"{}|{}|{}\"}}[1m])) >= {}",
+                m("ffmpeg_packet_corrupt_total"),
+                m("ffmpeg_codec_errors_total"),
+                m("ffmpeg_ts_cc_errors_total"),
+                thresholds.corruption_per_minute
+            )),
+            "warning",
+        )
+        .annotate(
+            "Stream {{ $labels.stream }} is corrupted",
+            format!(
+                "{{{{ $labels.stream }}}} had {{{{ $value }}}} corrupt packets, codec or continuity errors within a minute, the threshold is {}.",
+                thresholds.corruption_per_minute
+            ),
+        ),
+        Rule::new(
+            "FFmpegAudioLoudnessOutOfRange",
+            unless_paused(&format!(
+                "(avg by (stream, probe_id, stream_id, track) ({level}) < {min}) or (avg by (stream, probe_id, stream_id, track) ({level}) > {max})",
+                level = m("ffmpeg_audio_rms_level_dbfs"),
+                min = thresholds.loudness_min,
+                max = thresholds.loudness_max
+            )),
+            "warning",
+        )
+        .lasting(thresholds.loudness_for)
+        .annotate(
+            "Audio of stream {{ $labels.stream }} is too quiet or too loud",
+            format!(
+                "Audio track {{{{ $labels.track }}}} of {{{{ $labels.stream }}}} has been at {{{{ $value }}}} dBFS RMS for {} seconds, outside {} to {} dBFS.",
+                thresholds.loudness_for, thresholds.loudness_min, thresholds.loudness_max
+            ),
+        ),
+    ];
+    // Without the watchdog the stall gauge stays 0
+    if thresholds.stall_timeout > 0 {
+        rules.insert(
+            1,
+            Rule::new(
+                "FFmpegStreamStalled",
+                unless_paused(&format!("{} == 1", m("ffmpeg_stream_stalled"))),
+                "critical",
+            )
+            .annotate(
+                "Stream {{ $labels.stream }} stalled",
+                format!(
+                    "ffprobe is connected to {{{{ $labels.stream }}}} but received no packets or frames for {} seconds.",
+                    thresholds.stall_timeout
+                ),
+            ),
+        );
+    }
+    RuleFile {
+        groups: vec![RuleGroup {
+            name: "ffmpeg_exporter",
+            rules,
+        }],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::StreamMetrics;
+    use prometheus::Registry;
+    use regex::Regex;
+    use std::collections::HashMap;
+
+    fn thresholds() -> AlertThresholds {
+        AlertThresholds {
+            down_for: 60,
+            stall_timeout: 30,
+            corruption_per_minute: 10,
+            loudness_min: -40.0,
+            loudness_max: -6.0,
+            loudness_for: 120,
+        }
+    }
+
+    #[test]
+    fn test_rules() {
+        let yaml =
+            serde_yaml::to_string(&rules(&thresholds(), &ResourceLabels::default())).unwrap();
+        let file: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+        let rules = file["groups"][0]["rules"].as_sequence().unwrap();
+        let alerts: Vec<_> = rules.iter().map(|r| r["alert"].as_str().unwrap()).collect();
+        assert_eq!(
+            alerts,
+            [
+                "FFmpegStreamDown",
+                "FFmpegStreamStalled",
+                "FFmpegStreamCorruptionBurst",
+                "FFmpegAudioLoudnessOutOfRange"
+            ]
+        );
+        assert_eq!(rules[0]["for"].as_str(), Some("60s"));
+        assert!(rules[1].get("for").is_none());
+        assert!(rules[2]["expr"].as_str().unwrap().contains(">= 10)"));
+        let loudness = rules[3]["expr"].as_str().unwrap();
+        assert!(loudness.contains("< -40") && loudness.contains("> -6"));
+
+        // Every rule refers to metrics this exporter exports
+        let metrics = StreamMetrics::new(&Registry::new(), &HashMap::new()).unwrap();
+        let exported = metrics.names();
+        let name = Regex::new(r"ffmpeg_[a-z0-9_]+").unwrap();
+        for rule in rules {
+            for metric in name.find_iter(rule["expr"].as_str().unwrap()) {
+                assert!(exported.contains(metric.as_str()), "{:?}", metric);
+            }
+        }
+
+        let without_watchdog = AlertThresholds {
+            stall_timeout: 0,
+            ..thresholds()
+        };
+        let file = super::rules(&without_watchdog, &ResourceLabels::default());
+        assert!(
+            file.groups[0]
+                .rules
+                .iter()
+                .all(|rule| rule.alert != "FFmpegStreamStalled")
+        );
+
+        // Expressions use the prefixed names
+        let resource = ResourceLabels {
+            metric_prefix: Some("acme".to_string()),
+            ..Default::default()
+        };
+        for rule in &super::rules(&thresholds(), &resource).groups[0].rules {
+            assert!(
+                name.find_iter(&rule.expr)
+                    .all(|metric| rule.expr[..metric.start()].ends_with("acme_")),
+                "{}",
+                rule.expr
+            );
+        }
+    }
+}
@@ -0,0 +1,422 @@
+// dashboard.rs
+
+use crate::config::ResourceLabels;
+use serde_json::{Value, json};
+use std::collections::BTreeSet;
+
+/// Width of a dashboard row in grid units
+const GRID_WIDTH: u32 = 24;
+const PANEL_HEIGHT: u32 = 8;
+
+/// Labels of the probe, set on every series when configured
+const RESOURCE_LABELS: [&str; 3] = ["region", "site", "probe_id"];
+
+/// Builds panels row by row, two or four to a row
+struct Layout {
+    panels: Vec<Value>,
+    y: u32,
+    x: u32,
+}
+
+impl Layout {
+    fn row(&mut self, title: &str) {
+        if self.x > 0 {
+            self.y += PANEL_HEIGHT;
+            self.x = 0;
+        }
+        self.panels.push(json!({
+            "type": "row",
+            "title": title,
+            "collapsed": false,
+            "id": self.panels.len() + 1,
+            "gridPos": { "x": 0, "y": self.y, "w": GRID_WIDTH, "h": 1 },
+            "panels": [],
+        }));
+        self.y += 1;
+    }
+
+    fn add(&mut self, mut panel: Value, width: u32) {
+        if self.x + width > GRID_WIDTH {
+            self.y += PANEL_HEIGHT;
+            self.x = 0;
+        }
+        panel["id"] = json!(self.panels.len() + 1);
+        panel["gridPos"] = json!({ "x": self.x, "y": self.y, "w": width, "h": PANEL_HEIGHT });
+        self.panels.push(panel);
+        self.x += width;
+    }
+}
+
+fn datasource() -> Value {
+    json!({ "type": "prometheus", "uid": "${datasource}" })
+}
+
+/// A time series panel with one query per `(expression, legend)`
+fn timeseries(title: &str, unit: &str, queries: &[(String, &str)]) -> Value {
+    let targets: Vec<_> = queries
+        .iter()
+        .zip('A'..)
+        .map(|((expr, legend), ref_id)| {
+            json!({
+                "datasource": datasource(),
+                "expr": expr,
+                "legendFormat": legend,
+                "refId": ref_id.to_string(),
+            })
+        })
+        .collect();
+    json!({
+        "type": "timeseries",
+        "title": title,
+        "datasource": datasource(),
+        "fieldConfig": { "defaults": { "unit": unit }, "overrides": [] },
+        "options": { "legend": { "displayMode": "list", "placement": "bottom" } },
+        "targets": targets,
+    })
+}
+
+fn stat(title: &str, expr: String, unit: &str) -> Value {
+    json!({
+        "type": "stat",
+        "title": title,
+        "datasource": datasource(),
+        "fieldConfig": { "defaults": { "unit": unit }, "overrides": [] },
+        "options": { "reduceOptions": { "calcs": ["lastNotNull"] } },
+        "targets": [{ "datasource": datasource(), "expr": expr, "refId": "A" }],
+    })
+}
+
+/// A multi-value template variable over the values of `label` on `metric`
+fn variable(label: &str, metric: &str) -> Value {
+    json!({
+        "type": "query",
+        "name": label,
+        "label": label,
+        "datasource": datasource(),
+        "query": {
+            "query": format!("label_values({}, {})", metric, label),
+            "refId": "PrometheusVariableQueryEditor-VariableQuery",
+        },
+        "refresh": 2,
+        "multi": true,
+        "includeAll": true,
+        "allValue": ".*",
+        "current": { "text": "All", "value": "$__all" },
+        "sort": 1,
+    })
+}
+
+/// A Grafana dashboard for the metrics of this exporter, with a variable
+/// per label of the probe, per constant label, per custom stream label in
+/// `label_names` and for the stream itself, which every query filters on.
+/// Metric names carry the prefix of `resource`.
+pub fn grafana(resource: &ResourceLabels, label_names: &BTreeSet<String>) -> Value {
+    let labels: Vec<&str> = RESOURCE_LABELS
+        .into_iter()
+        .chain(resource.labels.keys().map(String::as_str))
+        .chain(label_names.iter().map(String::as_str))
+        .chain(["stream"])
+        .collect();
+    let selector = labels
+        .iter()
+        .map(|label| format!("{}=~\"${}\"", label, label))
+        .collect::<Vec<_>>()
+        .join(",");
+    let m = |metric: &str| format!("{}{{{}}}", resource.metric(metric), selector);
+    // Per second rates summed per stream and the `by` labels
+    let rate = |metric: &str, by: &[&str]| {
+        let by: Vec<_> = ["stream"].iter().chain(by).copied().collect();
+        format!(
+            "sum by ({}) (rate({}[$__rate_interval]))",
+            by.join(", "),
+            m(metric)
+        )
+    };
+
+    let mut layout = Layout {
+        panels: Vec::new(),
+        y: 0,
+        x: 0,
+    };
+    layout.row("Overview");
+    layout.add(
+        stat(
+            "Connected streams",
+            format!("sum({})", m("ffmpeg_stream_connection_state")),
+            "none",
+        ),
+        6,
+    );
+    layout.add(
+        stat(
+            "Stalled streams",
+            format!("sum({})", m("ffmpeg_stream_stalled")),
+            "none",
+        ),
+        6,
+    );
+    layout.add(
+        stat(
+            "Restarts in range",
+            format!(
+                "sum(increase({}[$__range]))",
+                m("ffmpeg_stream_restarts_total")
+            ),
+            "none",
+        ),
+        6,
+    );
+    layout.add(
+        stat(
+            "Downtime in range",
+            format!(
+                "sum(increase({}[$__range]))",
+                m("ffmpeg_stream_downtime_seconds_total")
+            ),
+            "s",
+        ),
+        6,
+    );
+
+    layout.row("Connection");
+    layout.add(
+        timeseries(
+            "Connection state",
+            "none",
+            &[(m("ffmpeg_stream_connection_state"), "{{stream}}")],
+        ),
+        12,
+    );
+    layout.add(
+        timeseries(
+            "Restarts",
+            "ops",
+            &[(
+                rate("ffmpeg_stream_restarts_total", &["reason"]),
+                "{{stream}} {{reason}}",
+            )],
+        ),
+        12,
+    );
+    layout.add(
+        timeseries(
+            "Stalled",
+            "none",
+            &[(m("ffmpeg_stream_stalled"), "{{stream}}")],
+        ),
+        12,
+    );
+    layout.add(
+        timeseries(
+            "Outage duration p90",
+            "s",
+            &[(
+                format!(
+                    "histogram_quantile(0.9, sum by (stream, le) (rate({}[$__rate_interval])))",
+                    m("ffmpeg_stream_outage_duration_seconds_bucket")
+                ),
+                "{{stream}}",
+            )],
+        ),
+        12,
+    );
+
+    layout.row("Video and audio");
+    layout.add(
+        timeseries(
+            "Frame rate",
+            "fps",
+            &[(m("ffmpeg_fps"), "{{stream}} #{{stream_id}} {{media_type}}")],
+        ),
+        12,
+    );
+    layout.add(
+        timeseries(
+            "Bitrate",
+            "Kbits",
+            &[(
+                m("ffmpeg_bitrate_kbits"),
+                "{{stream}} #{{stream_id}} {{media_type}}",
+            )],
+        ),
+        12,
+    );
+    layout.add(
+        timeseries(
+            "Black, frozen and silent",
+            "none",
+            &[
+                (m("ffmpeg_video_black"), "{{stream}} black"),
+                (m("ffmpeg_video_frozen"), "{{stream}} frozen"),
+                (m("ffmpeg_audio_silent"), "{{stream}} silent #{{stream_id}}"),
+            ],
+        ),
+        12,
+    );
+    layout.add(
+        timeseries(
+            "Audio RMS level",
+            "dB",
+            &[(
+                format!(
+                    "max by (stream, stream_id) ({})",
+                    m("ffmpeg_audio_rms_level_dbfs")
+                ),
+                "{{stream}} #{{stream_id}}",
+            )],
+        ),
+        12,
+    );
+
+    layout.row("Errors");
+    layout.add(
+        timeseries(
+            "Errors by category",
+            "ops",
+            &[(
+                rate("ffmpeg_errors_total", &["category"]),
+                "{{stream}} {{category}}",
+            )],
+        ),
+        12,
+    );
+    layout.add(
+        timeseries(
+            "Corrupt and dropped packets",
+            "ops",
+            &[
+                (
+                    rate("ffmpeg_packet_corrupt_total", &[]),
+                    "{{stream}} corrupt",
+                ),
+                (
+                    rate("ffmpeg_dropped_packets_total", &[]),
+                    "{{stream}} dropped",
+                ),
+            ],
+        ),
+        12,
+    );
+    layout.add(
+        timeseries(
+            "Codec errors",
+            "ops",
+            &[(
+                rate("ffmpeg_codec_errors_total", &["error_type"]),
+                "{{stream}} {{error_type}}",
+            )],
+        ),
+        12,
+    );
+    layout.add(
+        timeseries(
+            "Continuity counter errors",
+            "ops",
+            &[(rate("ffmpeg_ts_cc_errors_total", &[]), "{{stream}}")],
+        ),
+        12,
+    );
+
+    let mut variables = vec![json!({
+        "type": "datasource",
+        "name": "datasource",
+        "label": "Data source",
+        "query": "prometheus",
+        "current": {},
+    })];
+    let state = resource.metric("ffmpeg_stream_connection_state");
+    variables.extend(labels.iter().map(|label| variable(label, &state)));
+
+    json!({
+        "title": "FFmpeg Exporter",
+        "uid": "ffmpeg-exporter",
+        "tags": ["ffmpeg", "streaming"],
+        "editable": true,
+        "schemaVersion": 39,
+        "refresh": "30s",
+        "time": { "from": "now-6h", "to": "now" },
+        "timezone": "browser",
+        "templating": { "list": variables },
+        "panels": layout.panels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::StreamMetrics;
+    use prometheus::Registry;
+    use regex::Regex;
+    use std::collections::{BTreeMap, HashMap};
+
+    #[test]
+    fn test_grafana_dashboard() {
+        let labels = BTreeSet::from(["channel".to_string()]);
+        let resource = ResourceLabels {
+            labels: BTreeMap::from([("team".to_string(), "video".to_string())]),
+            ..Default::default()
+        };
+        let dashboard = grafana(&resource, &labels);
+
+        let variables: Vec<_> = dashboard["templating"]["list"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|variable| variable["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            variables,
+            [
+                "datasource",
+                "region",
+                "site",
+                "probe_id",
+                "team",
+                "channel",
+                "stream"
+            ]
+        );
+
+        // Every query refers to metrics this exporter exports
+        let metrics = StreamMetrics::new(&Registry::new(), &HashMap::new()).unwrap();
+        let exported = metrics.names();
+        let name = Regex::new(r"ffmpeg_[a-z0-9_]+").unwrap();
+        let panels = dashboard["panels"].as_array().unwrap();
+        for target in panels.iter().flat_map(|panel| {
+            panel["targets"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|target| target["expr"].as_str().unwrap())
+        }) {
+            assert!(target.contains("channel=~\"$channel\""), "{}", target);
+            for metric in name.find_iter(target) {
+                let metric = metric.as_str();
+                let family = metric.strip_suffix("_bucket").unwrap_or(metric);
+                assert!(exported.contains(family), "{} is not exported", metric);
+            }
+        }
+
+        // Panels do not overlap
+        let mut cells = BTreeSet::new();
+        for panel in panels {
+            let pos = &panel["gridPos"];
+            let (x, y) = (pos["x"].as_u64().unwrap(), pos["y"].as_u64().unwrap());
+            let (w, h) = (pos["w"].as_u64().unwrap(), pos["h"].as_u64().unwrap());
+            for cell in (x..x + w).flat_map(|cx| (y..y + h).map(move |cy| (cx, cy))) {
+                assert!(cells.insert(cell), "{} overlaps", panel["title"]);
+            }
+        }
+
+        // Queries and variables use the prefixed names
+        let resource = ResourceLabels {
+            metric_prefix: Some("acme".to_string()),
+            ..Default::default()
+        };
+        let dashboard = grafana(&resource, &BTreeSet::new()).to_string();
+        assert!(
+            name.find_iter(&dashboard)
+                .all(|metric| dashboard[..metric.start()].ends_with("acme_"))
+        );
+    }
+}
@@ -0,0 +1,260 @@
+use crate::config::ResourceAttributes;
+use chrono::{SecondsFormat, Utc};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+/// Events buffered for each live subscriber before it misses some
+const SUBSCRIBER_BUFFER: usize = 1024;
+
+/// Corruption events within `BURST_WINDOW` that make a burst
+const BURST_THRESHOLD: usize = 10;
+const BURST_WINDOW: Duration = Duration::from_secs(10);
+
+/// Kinds counting towards a corruption burst
+const CORRUPTION_KINDS: &[&str] = &["packet_corrupt", "codec_error", "cc_error"];
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Event {
+    /// RFC 3339 time the event was recorded
+    pub timestamp: String,
+    pub stream: String,
+    /// `packet_corrupt`, `codec_error`, `dropped_packets`, `udp_overrun`,
+    /// `cc_error`, `rtp_packet_loss`, `corruption_burst`, `stall`,
+    /// `format_change`, `pause` or `reconnect`
+    pub kind: &'static str,
+    /// Classification within the kind, e.g. the codec `error_type`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<&'static str>,
+    /// The ffprobe stderr line or error that caused the event
+    pub message: String,
+    #[serde(flatten)]
+    pub resource: ResourceAttributes,
+}
+
+/// Ring buffer of the most recent classified events of all streams, so
+/// incident responders get the evidence behind the counter increments. Live
+/// subscribers get every event as it is recorded.
+#[derive(Clone)]
+pub struct EventLog {
+    events: Arc<Mutex<VecDeque<Event>>>,
+    capacity: usize,
+    live: broadcast::Sender<Event>,
+    resource: ResourceAttributes,
+}
+
+impl EventLog {
+    /// A capacity of zero disables the buffer, not the live subscriptions
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+            live: broadcast::channel(SUBSCRIBER_BUFFER).0,
+            resource: ResourceAttributes::default(),
+        }
+    }
+
+    /// Attaches the probe labels to every event
+    pub fn with_resource(mut self, resource: ResourceAttributes) -> Self {
+        self.resource = resource;
+        self
+    }
+
+    pub fn recorder(&self, stream: &str) -> EventRecorder {
+        EventRecorder {
+            log: self.clone(),
+            stream: stream.to_string(),
+            resource: self.resource.clone(),
+            burst: Arc::default(),
+        }
+    }
+
+    /// Events recorded from now on; a subscriber lagging more than
+    /// `SUBSCRIBER_BUFFER` events behind misses the oldest
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.live.subscribe()
+    }
+
+    fn push(&self, event: Event) {
+        // Fails only without subscribers
+        let _ = self.live.send(event.clone());
+        if self.capacity == 0 {
+            return;
+        }
+        let mut events = self.events.lock().unwrap();
+        if events.len() == self.capacity {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    /// The buffered events, oldest first, optionally only those of one stream
+    pub fn list(&self, stream: Option<&str>) -> Vec<Event> {
+        self.events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|event| stream.is_none_or(|stream| event.stream == stream))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Records events of one stream into the shared `EventLog`
+#[derive(Clone)]
+pub struct EventRecorder {
+    log: EventLog,
+    stream: String,
+    resource: ResourceAttributes,
+    burst: Arc<Mutex<BurstDetector>>,
+}
+
+impl EventRecorder {
+    pub fn record(&self, kind: &'static str, detail: Option<&'static str>, message: &str) {
+        self.push(kind, detail, message);
+        if CORRUPTION_KINDS.contains(&kind) && self.burst.lock().unwrap().observe(Instant::now()) {
+            self.push(
+                "corruption_burst",
+                None,
+                &format!(
+                    "{} corruption events within {} seconds",
+                    BURST_THRESHOLD,
+                    BURST_WINDOW.as_secs()
+                ),
+            );
+        }
+    }
+
+    fn push(&self, kind: &'static str, detail: Option<&'static str>, message: &str) {
+        self.log.push(Event {
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            stream: self.stream.clone(),
+            kind,
+            detail,
+            message: message.trim().to_string(),
+            resource: self.resource.clone(),
+        });
+    }
+}
+
+/// Spots bursts of corruption events, so subscribers get one event per burst
+/// rather than having to count the single ones
+#[derive(Default)]
+struct BurstDetector {
+    /// Times of the corruption events within the window
+    times: VecDeque<Instant>,
+    in_burst: bool,
+}
+
+impl BurstDetector {
+    /// Returns whether this event starts a burst. A burst ends once a whole
+    /// window passed without corruption.
+    fn observe(&mut self, now: Instant) -> bool {
+        while self
+            .times
+            .front()
+            .is_some_and(|&time| now.duration_since(time) > BURST_WINDOW)
+        {
+            self.times.pop_front();
+        }
+        if self.times.is_empty() {
+            self.in_burst = false;
+        }
+        self.times.push_back(now);
+        if self.times.len() > BURST_THRESHOLD {
+            self.times.pop_front();
+        }
+        if self.in_burst || self.times.len() < BURST_THRESHOLD {
+            return false;
+        }
+        self.in_burst = true;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_log_ring_buffer() {
+        let log = EventLog::new(2);
+        let sport = log.recorder("sport");
+        let news = log.recorder("news");
+        sport.record("packet_corrupt", None, "first");
+        news.record("codec_error", Some("concealment"), "second\n");
+        sport.record("reconnect", None, "third");
+
+        let messages: Vec<_> = log.list(None).into_iter().map(|e| e.message).collect();
+        assert_eq!(messages, ["second", "third"]);
+        let sport_events = log.list(Some("sport"));
+        assert_eq!(sport_events.len(), 1);
+        assert_eq!(sport_events[0].kind, "reconnect");
+
+        let disabled = EventLog::new(0);
+        disabled
+            .recorder("sport")
+            .record("reconnect", None, "ignored");
+        assert!(disabled.list(None).is_empty());
+    }
+
+    #[test]
+    fn test_live_subscription() {
+        // Subscribers get events even without a buffer
+        let log = EventLog::new(0);
+        let mut live = log.subscribe();
+        let sport = log.recorder("sport");
+        sport.record("reconnect", Some("stall"), "FFprobe process restarted");
+        let event = live.try_recv().unwrap();
+        assert_eq!(event.stream, "sport");
+        assert_eq!(event.detail, Some("stall"));
+
+        for _ in 0..BURST_THRESHOLD {
+            sport.record("packet_corrupt", None, "corrupt");
+        }
+        let kinds: Vec<_> = std::iter::from_fn(|| live.try_recv().ok())
+            .map(|event| event.kind)
+            .collect();
+        assert_eq!(kinds.len(), BURST_THRESHOLD + 1);
+        assert_eq!(kinds.last(), Some(&"corruption_burst"));
+    }
+
+    #[test]
+    fn test_event_resource_attributes() {
+        let log = EventLog::new(1).with_resource(ResourceAttributes {
+            region: Some("eu-west".to_string()),
+            site: None,
+            probe_id: "probe-1".to_string(),
+        });
+        log.recorder("sport").record("reconnect", None, "restarted");
+        let json = serde_json::to_value(&log.list(None)[0]).unwrap();
+        assert_eq!(json["region"], "eu-west");
+        assert_eq!(json["probe_id"], "probe-1");
+        // Unset labels are left out rather than null
+        assert!(json.get("site").is_none());
+    }
+
+    #[test]
+    fn test_burst_detector() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut burst = BurstDetector::default();
+
+        // Nine events within the window are no burst, the tenth starts one
+        for i in 0..9 {
+            assert!(!burst.observe(at(i * 1000)));
+        }
+        assert!(burst.observe(at(9000)));
+        // Continued corruption belongs to the same burst
+        assert!(!burst.observe(at(15_000)));
+        assert!(!burst.observe(at(24_000)));
+
+        // After a quiet window, a new burst can start
+        for i in 0..9 {
+            assert!(!burst.observe(at(40_000 + i * 100)));
+        }
+        assert!(burst.observe(at(41_000)));
+    }
+}
@@ -7,6 +7,7 @@ pub fn init_logging() -> Result<()> {
         .unwrap_or_else(|_| EnvFilter::new("info,ffmpeg_monitor=debug"));
 
     // Initialize subscriber with stdout logging
+    #[cfg(not(feature = "tokio-console"))]
     tracing_subscriber::fmt()
         .with_env_filter(env_filter)
         .with_target(true)
@@ -14,5 +15,71 @@ pub fn init_logging() -> Result<()> {
         .with_span_events(FmtSpan::CLOSE)
         .init();
 
+    // Serve task instrumentation to tokio-console next to the regular log output
+    #[cfg(feature = "tokio-console")]
+    {
+        use tracing_subscriber::prelude::*;
+
+        tracing_subscriber::registry()
+            .with(console_subscriber::spawn())
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .with_target(true)
+                    .with_thread_ids(true)
+                    .with_span_events(FmtSpan::CLOSE)
+                    .with_filter(env_filter),
+            )
+            .init();
+    }
+
     Ok(())
 }
+
+/// Decides which raw ffprobe lines get logged, so per-line logging stays off
+/// the hot path unless explicitly requested.
+pub struct LineSampler {
+    enabled: bool,
+    every: u64,
+    seen: u64,
+}
+
+impl LineSampler {
+    pub fn new(enabled: bool, every: u64) -> Self {
+        Self {
+            enabled: enabled && tracing::enabled!(tracing::Level::DEBUG),
+            every: every.max(1),
+            seen: 0,
+        }
+    }
+
+    pub fn should_log(&mut self) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let log = self.seen.is_multiple_of(self.every);
+        self.seen += 1;
+        log
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_sampler_disabled() {
+        let mut sampler = LineSampler::new(false, 1);
+        assert!(!sampler.should_log());
+    }
+
+    #[test]
+    fn test_line_sampler_every_nth() {
+        let mut sampler = LineSampler {
+            enabled: true,
+            every: 3,
+            seen: 0,
+        };
+        let logged: Vec<bool> = (0..6).map(|_| sampler.should_log()).collect();
+        assert_eq!(logged, vec![true, false, false, true, false, false]);
+    }
+}
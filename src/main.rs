@@ -1,87 +1,199 @@
 use anyhow::{Context, Result};
-use clap::Parser;
 
+mod alerts;
 mod config;
+mod dashboard;
+mod events;
 mod logging;
 mod metrics;
 mod server;
 mod stream;
 
-use crate::config::{Args, StreamType};
-use crate::metrics::{AppState, StreamMetrics};
-use crate::stream::FFprobeMonitor;
-use std::sync::atomic::Ordering;
+use crate::alerts::AlertThresholds;
+use crate::config::{Args, Command, DashboardFormat, MonitorOptions, ResourceLabels, StreamConfig};
+use crate::events::EventLog;
+use crate::metrics::{
+    AppState, RemoteWriter, RuntimeMetrics, register_build_info, register_ffprobe_info,
+    register_process_metrics,
+};
+use crate::server::TlsFiles;
+use crate::stream::{FFprobeBuild, discover_variants, kill_orphans};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use tokio::task;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
 #[tokio::main]
 async fn main() -> Result<()> {
     // Parse command line arguments
-    let args = Args::parse();
+    let (args, streams) = Args::load().context("Failed to load configuration")?;
+    let resource = ResourceLabels::from_args(&args);
+    // Generated files go to stdout, which logging would mix into
+    match &args.command {
+        Some(Command::Dashboard { format }) => {
+            let dashboard = match format {
+                DashboardFormat::Grafana => {
+                    dashboard::grafana(&resource, &StreamConfig::label_names(&streams))
+                }
+            };
+            println!("{}", serde_json::to_string_pretty(&dashboard)?);
+            return Ok(());
+        }
+        Some(Command::Alerts {
+            down_for,
+            corruption_threshold,
+            loudness_min,
+            loudness_max,
+            loudness_for,
+        }) => {
+            let rules = alerts::rules(
+                &AlertThresholds {
+                    down_for: *down_for,
+                    stall_timeout: args.stall_timeout,
+                    corruption_per_minute: *corruption_threshold,
+                    loudness_min: *loudness_min,
+                    loudness_max: *loudness_max,
+                    loudness_for: *loudness_for,
+                },
+                &resource,
+            );
+            print!("{}", serde_yaml::to_string(&rules)?);
+            return Ok(());
+        }
+        None => {}
+    }
     logging::init_logging()?;
     info!("Starting FFprobe monitor");
     debug!("Parsed arguments: {:?}", args);
 
+    // Children of a previous run that died without stopping them still hold
+    // their sessions, which the new monitors may need
+    let orphans = kill_orphans();
+    if orphans > 0 {
+        warn!(
+            "Killed {} orphaned child processes of a previous run",
+            orphans
+        );
+    }
+
+    // Detect the ffprobe release, its output format differs between versions,
+    // and the protocols and demuxers it was built with
+    let mut options = MonitorOptions::from(&args);
+    let ffprobe = match FFprobeBuild::detect(&args.ffprobe_path) {
+        Ok(build) => {
+            let version = build.version;
+            info!("Detected ffprobe version {}", build.release);
+            debug!("ffprobe configuration: {}", build.configuration);
+            if !version.is_supported() {
+                warn!(
+                    "ffprobe {} is older than the oldest supported release 4.0, metrics may be incomplete",
+                    version
+                );
+            }
+            if args.export_qp && !version.at_least(4, 4) {
+                warn!(
+                    "ffprobe {} cannot export the quantizer, --export-qp needs 4.4 or newer",
+                    version
+                );
+            }
+            options.ffprobe_version = version;
+            options.ffprobe_components = Arc::new(build.components.clone());
+            Some(build)
+        }
+        Err(e) => {
+            warn!("Failed to detect ffprobe version: {:#}", e);
+            None
+        }
+    };
+
+    // Variants become streams of their own before the label names are fixed
+    let streams = if args.hls_variants {
+        let options = options.clone();
+        task::spawn_blocking(move || discover_variants(streams, &options)).await?
+    } else {
+        streams
+    };
+
     // Create app state and metrics
-    let (app_state, registry) = AppState::new();
-    let metrics = StreamMetrics::new(&registry)?;
+    info!("Probe resource labels: {:?}", resource);
+    let label_names = StreamConfig::label_names(&streams);
+    let events = EventLog::new(args.events_capacity).with_resource(resource.attributes());
+    let (app_state, registry) =
+        AppState::new(&resource, options, label_names, events, ffprobe.is_some())?;
+    register_build_info(&registry)?;
+    if let Some(build) = &ffprobe {
+        register_ffprobe_info(&registry, build)?;
+    }
+    register_process_metrics(&registry)?;
+    RuntimeMetrics::new(&registry)?.spawn_updater();
+    let remote_write = if args.remote_write.url.is_some() {
+        let running = Arc::new(AtomicBool::new(true));
+        let writer = RemoteWriter::new(&registry, &args.remote_write)
+            .context("Failed to set up remote write")?
+            .spawn(running.clone());
+        Some((running, writer))
+    } else {
+        None
+    };
 
-    // Determine stream type
-    let stream_type =
-        StreamType::from_input(&args.input).context("Failed to determine stream type")?;
+    // Start one monitor per configured stream, more can be added via the API
+    let manager = app_state.streams.clone();
+    for stream in streams {
+        manager.add(stream)?;
+    }
 
     // Start HTTP server in background
     let metrics_server = {
         let state = app_state.clone();
+        let address = args.metrics_address.clone();
         let port = args.metrics_port;
-        task::spawn(async move { server::run_server(state, port).await })
+        let tls = args
+            .metrics_tls_cert
+            .clone()
+            .zip(args.metrics_tls_key.clone())
+            .map(|(cert, key)| TlsFiles { cert, key });
+        let auth = args.auth.clone();
+        task::spawn(async move { server::run_server(state, &address, port, tls, &auth).await })
     };
 
-    // Create monitor
-    let monitor = FFprobeMonitor::new(
-        args.ffprobe_path,
-        args.input,
-        stream_type,
-        metrics,
-        args.probe_size,
-        args.analyze_duration,
-        args.report,
-    );
-
     // Set up Ctrl+C handler
-    let running = monitor.get_running_handle();
+    let shutdown = manager.clone();
     ctrlc::set_handler(move || {
         info!("Received interrupt signal, shutting down...");
-        running.store(false, Ordering::SeqCst);
+        shutdown.stop_all();
     })?;
 
-    // Start FFprobe monitoring in a separate blocking task
-    let ffprobe_task =
-        task::spawn_blocking(move || monitor.run().context("Failed to run FFprobe monitor"));
-
-    // Wait for either task to complete
+    // Run until the server fails or a shutdown is requested
     tokio::select! {
         result = metrics_server => {
-            if let Err(e) = result {
-                error!("Metrics server error: {:#}", e);
-                std::process::exit(1);
-            }
-        }
-        result = ffprobe_task => {
             match result {
-                Ok(Ok(())) => {
-                    info!("FFprobe monitor shut down gracefully");
-                }
+                Ok(Ok(())) => {}
                 Ok(Err(e)) => {
-                    error!("FFprobe monitoring error: {:#}", e);
+                    error!("Metrics server error: {:#}", e);
                     std::process::exit(1);
                 }
                 Err(e) => {
-                    error!("FFprobe task panicked: {}", e);
+                    error!("Metrics server task error: {:#}", e);
                     std::process::exit(1);
                 }
             }
+            manager.stop_all();
+        }
+        _ = manager.wait_for_shutdown() => {}
+    }
+
+    match manager.join_all().await {
+        Ok(()) => {
+            info!("FFprobe monitors shut down gracefully");
         }
+        Err(e) => {
+            error!("FFprobe monitoring error: {:#}", e);
+            std::process::exit(1);
+        }
+    }
+    if let Some((running, writer)) = remote_write {
+        running.store(false, Ordering::SeqCst);
+        let _ = task::spawn_blocking(move || writer.join()).await;
     }
 
     Ok(())
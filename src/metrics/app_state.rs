@@ -1,19 +1,54 @@
+use crate::config::{MonitorOptions, ResourceLabels};
+use crate::events::EventLog;
+use crate::stream::StreamManager;
+use anyhow::Result;
 use prometheus::Registry;
+use std::collections::BTreeSet;
 use std::sync::Arc;
+use tokio::sync::Semaphore;
 use tracing::debug;
 
 #[derive(Clone)]
 pub struct AppState {
     pub registry: Arc<Registry>,
+    pub streams: StreamManager,
+    pub resource: ResourceLabels,
+    pub options: MonitorOptions,
+    pub events: EventLog,
+    /// Permits of the `/probe` requests running at once
+    pub probes: Arc<Semaphore>,
+    /// Whether `ffprobe -version` ran at startup
+    pub ffprobe_found: bool,
 }
 
 impl AppState {
-    pub fn new() -> (Self, Registry) {
+    pub fn new(
+        resource: &ResourceLabels,
+        options: MonitorOptions,
+        label_names: BTreeSet<String>,
+        events: EventLog,
+        ffprobe_found: bool,
+    ) -> Result<(Self, Registry)> {
+        let registry = Registry::new_custom(
+            resource.metric_prefix.clone(),
+            Some(resource.const_labels()),
+        )?;
         debug!("Created new prometheus registry");
-        let registry = Registry::new();
         let state = Self {
             registry: Arc::new(registry.clone()),
+            streams: StreamManager::new(
+                registry.clone(),
+                options.clone(),
+                label_names,
+                events.clone(),
+                resource.attributes(),
+            ),
+            resource: resource.clone(),
+            probes: Arc::new(Semaphore::new(options.max_probes as usize)),
+            options,
+            events,
+            ffprobe_found,
         };
-        (state, registry)
+        Ok((state, registry))
     }
 }
@@ -0,0 +1,91 @@
+// metrics/build_info.rs
+
+use crate::stream::FFprobeBuild;
+use anyhow::Result;
+use prometheus::{Gauge, GaugeVec, Opts, Registry};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Version of the exporter and how it was built, set at compile time by
+/// build.rs
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const GIT_SHA: &str = env!("FFMPEG_EXPORTER_GIT_SHA");
+const RUSTC: &str = env!("FFMPEG_EXPORTER_RUSTC");
+const FEATURES: &str = env!("FFMPEG_EXPORTER_FEATURES");
+
+/// Registers `ffmpeg_exporter_build_info`, always 1, and the time the
+/// exporter started, so version skew and restarts show across a fleet
+pub fn register_build_info(registry: &Registry) -> Result<()> {
+    let build_info = GaugeVec::new(
+        Opts::new(
+            "ffmpeg_exporter_build_info",
+            "Build information of the exporter, always 1",
+        ),
+        &["version", "git_sha", "rustc", "features"],
+    )?;
+    build_info
+        .with_label_values(&[VERSION, GIT_SHA, RUSTC, FEATURES])
+        .set(1.0);
+
+    let start_time = Gauge::new(
+        "ffmpeg_exporter_start_time_seconds",
+        "Start time of the exporter since the unix epoch in seconds",
+    )?;
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    start_time.set(started.as_secs_f64());
+
+    registry.register(Box::new(build_info))?;
+    registry.register(Box::new(start_time))?;
+    Ok(())
+}
+
+/// Registers `ffmpeg_ffprobe_info`, always 1, naming the release and build
+/// configuration of the ffprobe found at startup
+pub fn register_ffprobe_info(registry: &Registry, build: &FFprobeBuild) -> Result<()> {
+    let ffprobe_info = GaugeVec::new(
+        Opts::new(
+            "ffmpeg_ffprobe_info",
+            "Release and build configuration of ffprobe, always 1",
+        ),
+        &["version", "configuration"],
+    )?;
+    ffprobe_info
+        .with_label_values(&[&build.release, &build.configuration])
+        .set(1.0);
+    registry.register(Box::new(ffprobe_info))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_info() {
+        let registry = Registry::new();
+        register_build_info(&registry).unwrap();
+        let families = registry.gather();
+
+        let info = families
+            .iter()
+            .find(|family| family.get_name() == "ffmpeg_exporter_build_info")
+            .unwrap();
+        let metric = &info.get_metric()[0];
+        assert_eq!(metric.get_gauge().get_value(), 1.0);
+        let labels: Vec<_> = metric
+            .get_label()
+            .iter()
+            .map(|label| (label.get_name(), label.get_value()))
+            .collect();
+        assert!(labels.contains(&("version", VERSION)));
+        assert!(labels.contains(&("git_sha", GIT_SHA)) && !GIT_SHA.is_empty());
+        assert!(labels.contains(&("rustc", RUSTC)) && !RUSTC.is_empty());
+
+        let start = families
+            .iter()
+            .find(|family| family.get_name() == "ffmpeg_exporter_start_time_seconds")
+            .unwrap();
+        assert!(start.get_metric()[0].get_gauge().get_value() > 1.6e9);
+    }
+}
@@ -0,0 +1,126 @@
+// metrics/cardinality.rs
+
+use crate::config::{LabelLimits, anchored};
+use prometheus::Counter;
+use regex::Regex;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+/// Value of a label that stands for all values beyond the limits
+pub const OTHER: &str = "other";
+
+#[derive(Default)]
+struct Seen {
+    admitted: HashSet<String>,
+    suppressed: HashSet<String>,
+}
+
+/// Decides which values of a label taken from the input are exported. The
+/// first `max_values` allowed values keep their own series, later and denied
+/// ones share the `other` series. Values are remembered for the lifetime of
+/// the stream, so a value keeps its series across reconnects.
+#[derive(Clone)]
+pub struct LabelGuard {
+    max_values: usize,
+    allow: Option<Regex>,
+    deny: Option<Regex>,
+    seen: Arc<Mutex<Seen>>,
+    /// Counts the distinct values exported as `other`
+    suppressed: Counter,
+}
+
+impl LabelGuard {
+    /// A guard letting every value through until limits are set
+    pub fn new(suppressed: Counter) -> Self {
+        Self {
+            max_values: 0,
+            allow: None,
+            deny: None,
+            seen: Arc::default(),
+            suppressed,
+        }
+    }
+
+    /// Applies the limits configured for `label`, whose patterns were
+    /// validated with the configuration
+    pub fn limit(&mut self, label: &str, limits: &LabelLimits) {
+        let pattern = |patterns: &std::collections::BTreeMap<String, String>| {
+            patterns
+                .get(label)
+                .and_then(|pattern| anchored(pattern).ok())
+        };
+        self.max_values = limits.max_values;
+        self.allow = pattern(&limits.allow);
+        self.deny = pattern(&limits.deny);
+    }
+
+    /// `value` if it may have a series of its own, `other` otherwise
+    pub fn admit<'a>(&self, value: &'a str) -> &'a str {
+        if self.max_values == 0 && self.allow.is_none() && self.deny.is_none() {
+            return value;
+        }
+        let mut seen = self.seen.lock().unwrap();
+        if seen.admitted.contains(value) {
+            return value;
+        }
+        if seen.suppressed.contains(value) {
+            return OTHER;
+        }
+        let listed = self
+            .allow
+            .as_ref()
+            .is_none_or(|allow| allow.is_match(value))
+            && !self.deny.as_ref().is_some_and(|deny| deny.is_match(value));
+        if listed && (self.max_values == 0 || seen.admitted.len() < self.max_values) {
+            seen.admitted.insert(value.to_string());
+            value
+        } else {
+            seen.suppressed.insert(value.to_string());
+            self.suppressed.inc();
+            OTHER
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_label_guard() {
+        let counter = Counter::new("suppressed", "Suppressed values").unwrap();
+        let mut guard = LabelGuard::new(counter.clone());
+        assert_eq!(guard.admit("7"), "7");
+
+        guard.limit(
+            "pid",
+            &LabelLimits {
+                max_values: 2,
+                allow: BTreeMap::new(),
+                deny: BTreeMap::from([("pid".to_string(), "8191|0".to_string())]),
+            },
+        );
+        assert_eq!(guard.admit("8191"), OTHER);
+        // Anchored, so 256 is not denied by 0
+        assert_eq!(guard.admit("256"), "256");
+        assert_eq!(guard.admit("257"), "257");
+        assert_eq!(guard.admit("258"), OTHER);
+        // Admitted values keep their series, each suppressed one counts once
+        assert_eq!(guard.admit("256"), "256");
+        assert_eq!(guard.admit("258"), OTHER);
+        assert_eq!(counter.get(), 2.0);
+
+        let mut guard = LabelGuard::new(counter.clone());
+        guard.limit(
+            "stream_id",
+            &LabelLimits {
+                max_values: 0,
+                allow: BTreeMap::from([("stream_id".to_string(), "[0-1]".to_string())]),
+                deny: BTreeMap::new(),
+            },
+        );
+        assert_eq!(guard.admit("1"), "1");
+        assert_eq!(guard.admit("12"), OTHER);
+    }
+}
@@ -1,37 +1,218 @@
+use crate::config::{LabelLimits, Severity, StaleGauges};
+use crate::metrics::cardinality::LabelGuard;
 use anyhow::Result;
-use prometheus::{CounterVec, GaugeVec, Opts, Registry};
+use prometheus::core::Collector;
+use prometheus::{
+    Counter, CounterVec, Gauge, GaugeVec, Histogram, HistogramOpts, HistogramVec, Opts, Registry,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Variable labels of `ffmpeg_color_info`, in the order of their values
+pub const COLOR_INFO_LABELS: [&str; 6] = [
+    "stream_id",
+    "primaries",
+    "trc",
+    "space",
+    "range",
+    "mastering_display",
+];
+
+/// Variable labels of `ffmpeg_stream_info`, in the order of their values
+pub const STREAM_INFO_LABELS: [&str; 7] = [
+    "stream_id",
+    "media_type",
+    "codec",
+    "profile",
+    "width",
+    "height",
+    "pix_fmt",
+];
 
 #[derive(Clone)]
 pub struct StreamMetrics {
     pub fps: GaugeVec,
-    pub frame_counter: GaugeVec,
+    pub frame_counter: CounterVec,
+    pub session_frames: GaugeVec,
     pub bitrate: GaugeVec,
+    pub bitrate_avg: GaugeVec,
     pub packet_corrupt: CounterVec,
     pub connection_state: GaugeVec,
     pub connection_reset: CounterVec,
+    pub restarts: CounterVec,
+    pub downtime: CounterVec,
+    pub outage_duration: HistogramVec,
+    pub retry_backoff: Gauge,
+    pub connect_duration: HistogramVec,
     pub dropped_packets: CounterVec,
     pub codec_errors: CounterVec,
+    pub hls_pdt_offset: GaugeVec,
+    pub hls_live_latency: GaugeVec,
+    pub hls_segments: CounterVec,
+    pub hls_segment_duration: HistogramVec,
+    pub hls_segment_target_deviation: GaugeVec,
+    pub hls_segment_size: HistogramVec,
+    pub hls_segment_fetch_duration: HistogramVec,
+    pub hls_segment_fetch_errors: CounterVec,
+    pub hls_playlist_age: GaugeVec,
+    pub hls_playlist_stale: CounterVec,
+    pub hls_parts: CounterVec,
+    pub hls_part_duration: HistogramVec,
+    pub hls_part_target_deviation: GaugeVec,
+    pub dash_manifest_valid: GaugeVec,
+    pub dash_manifest_errors: CounterVec,
+    pub dash_publish_age: GaugeVec,
+    pub dash_timeline_gap: GaugeVec,
+    pub dash_live_edge_age: GaugeVec,
+    pub stderr_lines_dropped: CounterVec,
+    pub udp_overruns: CounterVec,
+    pub udp_receive_buffer: Gauge,
+    pub ts_cc_errors: CounterVec,
+    pub pcr_interval: GaugeVec,
+    pub pcr_interval_violations: CounterVec,
+    pub pcr_accuracy: GaugeVec,
+    pub pcr_jitter: GaugeVec,
+    pub ts_table_changes: CounterVec,
+    pub ts_program_present: GaugeVec,
+    pub tr101290_errors: CounterVec,
+    pub ts_null_packet_ratio: GaugeVec,
+    pub srt_rtt: GaugeVec,
+    pub srt_bandwidth: GaugeVec,
+    pub srt_receive_rate: GaugeVec,
+    pub srt_flight_size: GaugeVec,
+    pub srt_receive_buffer: GaugeVec,
+    pub srt_receive_buffer_available: GaugeVec,
+    pub srt_packets_received: Counter,
+    pub srt_packets_lost: Counter,
+    pub srt_packets_dropped: Counter,
+    pub srt_packets_retransmitted: Counter,
+    pub srt_packets_belated: Counter,
+    pub rtmp_connect: Gauge,
+    pub rtmp_metadata_duration: Gauge,
+    pub rtmp_metadata_info: GaugeVec,
+    pub rtmp_metadata_datarate: GaugeVec,
+    pub rtp_packets_lost: CounterVec,
+    pub rtp_jitter: GaugeVec,
+    pub av_sync_skew: GaugeVec,
+    pub gop_length: GaugeVec,
+    pub keyframe_interval: GaugeVec,
+    pub keyframes_late: CounterVec,
+    pub packet_size: HistogramVec,
+    pub stream_info: GaugeVec,
+    pub format_changes: CounterVec,
+    pub audio_sample_rate: GaugeVec,
+    pub audio_channels: GaugeVec,
+    pub audio_channel_layout: GaugeVec,
+    pub audio_track_bitrate: GaugeVec,
+    pub audio_track_frames: CounterVec,
+    pub audio_track_present: GaugeVec,
+    pub frames_dropped: CounterVec,
+    pub frames_duplicated: CounterVec,
+    pub frame_interval_jitter: HistogramVec,
+    pub stalled: GaugeVec,
+    pub stalls: CounterVec,
+    pub paused: GaugeVec,
+    pub probe_timeouts: CounterVec,
+    pub live_lag: GaugeVec,
+    pub video_interlaced: GaugeVec,
+    pub color_info: GaugeVec,
+    pub video_qp: GaugeVec,
+    pub timecode_seconds: GaugeVec,
+    pub timecode_frames: GaugeVec,
+    pub timecode_discontinuities: CounterVec,
+    pub captions_present: GaugeVec,
+    pub caption_gap: GaugeVec,
+    pub id3_packets: CounterVec,
+    pub id3_gap: GaugeVec,
+    pub subtitle_track_present: GaugeVec,
+    pub subtitle_track_packets: CounterVec,
+    pub last_error_timestamp: Gauge,
+    pub last_error_info: GaugeVec,
+    pub errors: CounterVec,
+    pub video_black: GaugeVec,
+    pub video_black_seconds: Counter,
+    pub video_black_events: Counter,
+    pub video_frozen: GaugeVec,
+    pub video_frozen_seconds: Counter,
+    pub audio_silent: GaugeVec,
+    pub audio_silence_seconds: CounterVec,
+    pub audio_silence_events: CounterVec,
+    pub audio_rms_level: GaugeVec,
+    pub audio_peak_level: GaugeVec,
+    pub audio_clipped_samples: CounterVec,
+    pub quality_psnr: GaugeVec,
+    pub quality_ssim: GaugeVec,
+    pub quality_psnr_per_second: Histogram,
+    pub quality_ssim_per_second: Histogram,
+    pub test_pattern_detected: GaugeVec,
+    pub video_signal_level: GaugeVec,
+    pub video_out_of_range_ratio: GaugeVec,
+    pub video_illegal_frames: CounterVec,
+    pub bitrate_declared_deviation: GaugeVec,
+    pub suppressed_series: CounterVec,
+    /// Limits of the `stream_id` and `pid` label values
+    pub stream_ids: LabelGuard,
+    pub pids: LabelGuard,
 }
 
 impl StreamMetrics {
-    pub fn new(registry: &Registry) -> Result<Self> {
+    /// Creates the metrics of one monitored stream with the default histogram
+    /// buckets
+    #[cfg(test)]
+    pub fn new(registry: &Registry, const_labels: &HashMap<String, String>) -> Result<Self> {
+        Self::with_buckets(registry, const_labels, &BTreeMap::new())
+    }
+
+    /// Creates the metrics of one monitored stream. `const_labels` identify the
+    /// stream and must use the same label names for every stream sharing the
+    /// registry. The bucket boundaries of the histograms named in `buckets`
+    /// replace their defaults.
+    pub fn with_buckets(
+        registry: &Registry,
+        const_labels: &HashMap<String, String>,
+        buckets: &BTreeMap<String, Vec<f64>>,
+    ) -> Result<Self> {
+        let opts =
+            |name: &str, help: &str| Opts::new(name, help).const_labels(const_labels.clone());
+        let buckets =
+            |histogram: &str, default: Vec<f64>| buckets.get(histogram).cloned().unwrap_or(default);
+
         let fps = GaugeVec::new(
-            Opts::new("ffmpeg_fps", "Current frames per second"),
+            opts("ffmpeg_fps", "Current frames per second"),
             &["stream_type", "stream_id", "media_type"],
         )?;
 
-        let frame_counter = GaugeVec::new(
-            Opts::new("ffmpeg_frames", "Number of frames processed"),
+        let frame_counter = CounterVec::new(
+            opts("ffmpeg_frames_total", "Total number of frames processed"),
             &["type", "stream_id", "media_type"],
         )?;
 
+        let session_frames = GaugeVec::new(
+            opts(
+                "ffmpeg_session_frames",
+                "Number of frames processed by the current ffprobe session",
+            ),
+            &["stream_id", "media_type"],
+        )?;
+
         let bitrate = GaugeVec::new(
-            Opts::new("ffmpeg_bitrate_kbits", "Current bitrate in kbits/s"),
+            opts(
+                "ffmpeg_bitrate_kbits",
+                "Bitrate over the last second of stream time in kbits/s",
+            ),
+            &["stream_id", "media_type"],
+        )?;
+
+        let bitrate_avg = GaugeVec::new(
+            opts(
+                "ffmpeg_bitrate_avg_kbits",
+                "Bitrate over the last 10 seconds of stream time in kbits/s",
+            ),
             &["stream_id", "media_type"],
         )?;
 
         let packet_corrupt = CounterVec::new(
-            Opts::new(
+            opts(
                 "ffmpeg_packet_corrupt_total",
                 "Total number of corrupt packets",
             ),
@@ -39,7 +220,7 @@ impl StreamMetrics {
         )?;
 
         let connection_state = GaugeVec::new(
-            Opts::new(
+            opts(
                 "ffmpeg_stream_connection_state",
                 "Current connection state (1 = connected, 0 = disconnected)",
             ),
@@ -47,15 +228,61 @@ impl StreamMetrics {
         )?;
 
         let connection_reset = CounterVec::new(
-            Opts::new(
+            opts(
                 "ffmpeg_stream_connection_reset_total",
                 "Total number of connection resets",
             ),
             &["stream_type"],
         )?;
 
+        let restarts = CounterVec::new(
+            opts(
+                "ffmpeg_stream_restarts_total",
+                "Total number of ffprobe restarts by reason",
+            ),
+            &["stream_type", "reason"],
+        )?;
+
+        let downtime = CounterVec::new(
+            opts(
+                "ffmpeg_stream_downtime_seconds_total",
+                "Total seconds the stream was down, counted while the outage lasts",
+            ),
+            &["stream_type"],
+        )?;
+
+        let outage_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "ffmpeg_stream_outage_duration_seconds",
+                "Duration of ended outages in seconds",
+            )
+            .const_labels(const_labels.clone())
+            .buckets(vec![
+                1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 900.0, 1800.0, 3600.0, 14400.0,
+            ]),
+            &["stream_type"],
+        )?;
+
+        let retry_backoff = Gauge::with_opts(opts(
+            "ffmpeg_retry_backoff_seconds",
+            "Delay before the latest restart of ffprobe, 0 once a session delivers data",
+        ))?;
+
+        let connect_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "ffmpeg_connect_duration_seconds",
+                "Time from starting ffprobe to the first packet or frame of a session in seconds",
+            )
+            .const_labels(const_labels.clone())
+            .buckets(buckets(
+                "connect_duration",
+                vec![0.1, 0.25, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0],
+            )),
+            &["stream_type"],
+        )?;
+
         let dropped_packets = CounterVec::new(
-            Opts::new(
+            opts(
                 "ffmpeg_dropped_packets_total",
                 "Total number of dropped packets",
             ),
@@ -63,32 +290,1334 @@ impl StreamMetrics {
         )?;
 
         let codec_errors = CounterVec::new(
-            Opts::new(
+            opts(
                 "ffmpeg_codec_errors_total",
                 "Total number of codec-specific errors",
             ),
             &["error_type", "stream_id"],
         )?;
 
-        // Register all metrics
-        registry.register(Box::new(fps.clone()))?;
-        registry.register(Box::new(frame_counter.clone()))?;
-        registry.register(Box::new(bitrate.clone()))?;
-        registry.register(Box::new(packet_corrupt.clone()))?;
-        registry.register(Box::new(connection_state.clone()))?;
-        registry.register(Box::new(connection_reset.clone()))?;
-        registry.register(Box::new(dropped_packets.clone()))?;
-        registry.register(Box::new(codec_errors.clone()))?;
-
-        Ok(Self {
+        let hls_pdt_offset = GaugeVec::new(
+            opts(
+                "ffmpeg_hls_program_date_time_offset_seconds",
+                "Wall clock minus EXT-X-PROGRAM-DATE-TIME of the newest playlist segment",
+            ),
+            &["stream_type"],
+        )?;
+
+        let hls_live_latency = GaugeVec::new(
+            opts(
+                "ffmpeg_hls_live_latency_seconds",
+                "Wall clock minus the EXT-X-PROGRAM-DATE-TIME of the live edge of the HLS media playlist",
+            ),
+            &["stream_type"],
+        )?;
+
+        let hls_segments = CounterVec::new(
+            opts(
+                "ffmpeg_hls_segments_total",
+                "Total number of segments added to the HLS media playlist",
+            ),
+            &["stream_type"],
+        )?;
+
+        let hls_segment_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "ffmpeg_hls_segment_duration_seconds",
+                "EXTINF duration of new HLS segments in seconds",
+            )
+            .const_labels(const_labels.clone())
+            .buckets(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 8.0, 10.0, 12.0]),
+            &["stream_type"],
+        )?;
+
+        let hls_segment_target_deviation = GaugeVec::new(
+            opts(
+                "ffmpeg_hls_segment_target_deviation_seconds",
+                "EXTINF duration of the newest HLS segment minus EXT-X-TARGETDURATION",
+            ),
+            &["stream_type"],
+        )?;
+
+        let hls_segment_size = HistogramVec::new(
+            HistogramOpts::new(
+                "ffmpeg_hls_segment_size_bytes",
+                "Size of new HLS segments in bytes",
+            )
+            .const_labels(const_labels.clone())
+            .buckets(prometheus::exponential_buckets(65536.0, 2.0, 10)?),
+            &["stream_type"],
+        )?;
+
+        let hls_segment_fetch_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "ffmpeg_hls_segment_fetch_seconds",
+                "Time to download new HLS segments in seconds",
+            )
+            .const_labels(const_labels.clone())
+            .buckets(vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 4.0, 8.0]),
+            &["stream_type"],
+        )?;
+
+        let hls_segment_fetch_errors = CounterVec::new(
+            opts(
+                "ffmpeg_hls_segment_fetch_errors_total",
+                "Total number of failed HLS segment downloads",
+            ),
+            &["stream_type"],
+        )?;
+
+        let hls_playlist_age = GaugeVec::new(
+            opts(
+                "ffmpeg_hls_playlist_age_seconds",
+                "Seconds since the newest segment of the HLS media playlist changed",
+            ),
+            &["stream_type"],
+        )?;
+
+        let hls_playlist_stale = CounterVec::new(
+            opts(
+                "ffmpeg_hls_playlist_stale_total",
+                "Total number of times the HLS media playlist stopped advancing",
+            ),
+            &["stream_type"],
+        )?;
+
+        let hls_parts = CounterVec::new(
+            opts(
+                "ffmpeg_hls_parts_total",
+                "Total number of LL-HLS partial segments added to the media playlist",
+            ),
+            &["stream_type"],
+        )?;
+
+        let hls_part_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "ffmpeg_hls_part_duration_seconds",
+                "EXT-X-PART duration of new LL-HLS partial segments in seconds",
+            )
+            .const_labels(const_labels.clone())
+            .buckets(vec![0.1, 0.2, 0.33, 0.5, 0.75, 1.0, 1.5, 2.0, 3.0]),
+            &["stream_type"],
+        )?;
+
+        let hls_part_target_deviation = GaugeVec::new(
+            opts(
+                "ffmpeg_hls_part_target_deviation_seconds",
+                "Duration of the newest LL-HLS partial segment minus PART-TARGET",
+            ),
+            &["stream_type"],
+        )?;
+
+        let dash_manifest_valid = GaugeVec::new(
+            opts(
+                "ffmpeg_dash_manifest_valid",
+                "Whether the DASH manifest passed all checks on the last poll (1) or not (0)",
+            ),
+            &["stream_type"],
+        )?;
+
+        let dash_manifest_errors = CounterVec::new(
+            opts(
+                "ffmpeg_dash_manifest_errors_total",
+                "Total number of times a DASH manifest check started failing",
+            ),
+            &["stream_type", "check"],
+        )?;
+
+        let dash_publish_age = GaugeVec::new(
+            opts(
+                "ffmpeg_dash_publish_age_seconds",
+                "Wall clock minus the publishTime of the DASH manifest",
+            ),
+            &["stream_type"],
+        )?;
+
+        let dash_timeline_gap = GaugeVec::new(
+            opts(
+                "ffmpeg_dash_timeline_gap_seconds",
+                "Total duration of the gaps in the segment timelines of the DASH manifest",
+            ),
+            &["stream_type"],
+        )?;
+
+        let dash_live_edge_age = GaugeVec::new(
+            opts(
+                "ffmpeg_dash_live_edge_age_seconds",
+                "Wall clock minus the end of the newest segment of a dynamic DASH manifest",
+            ),
+            &["stream_type"],
+        )?;
+
+        let stderr_lines_dropped = CounterVec::new(
+            opts(
+                "ffmpeg_stderr_lines_dropped_total",
+                "Total number of ffprobe stderr lines dropped by the rate limit",
+            ),
+            &["stream_type"],
+        )?;
+
+        let udp_overruns = CounterVec::new(
+            opts(
+                "ffmpeg_udp_overruns_total",
+                "Total number of UDP circular buffer overruns reported by ffprobe",
+            ),
+            &["stream_type"],
+        )?;
+
+        let udp_receive_buffer = Gauge::with_opts(opts(
+            "ffmpeg_udp_receive_buffer_bytes",
+            "UDP socket receive buffer size the kernel granted when it was smaller than requested",
+        ))?;
+
+        let ts_cc_errors = CounterVec::new(
+            opts(
+                "ffmpeg_ts_cc_errors_total",
+                "Total number of MPEG-TS continuity counter errors by PID",
+            ),
+            &["pid"],
+        )?;
+
+        let pcr_interval = GaugeVec::new(
+            opts(
+                "ffmpeg_pcr_interval_seconds",
+                "Longest interval between two PCRs of a PID over the last second of PCR time",
+            ),
+            &["pid"],
+        )?;
+
+        let pcr_interval_violations = CounterVec::new(
+            opts(
+                "ffmpeg_pcr_interval_violations_total",
+                "Total number of PCR intervals longer than 40 ms",
+            ),
+            &["pid"],
+        )?;
+
+        let pcr_accuracy = GaugeVec::new(
+            opts(
+                "ffmpeg_pcr_accuracy_seconds",
+                "Largest deviation of a PCR from the value its byte position implies over the last second",
+            ),
+            &["pid"],
+        )?;
+
+        let pcr_jitter = GaugeVec::new(
+            opts(
+                "ffmpeg_pcr_jitter_seconds",
+                "Peak to peak variation of PCR arrival time against PCR value over the last second",
+            ),
+            &["pid"],
+        )?;
+
+        let ts_table_changes = CounterVec::new(
+            opts(
+                "ffmpeg_ts_table_change_total",
+                "Total number of changes of the PAT or a PMT",
+            ),
+            &["table"],
+        )?;
+
+        let ts_program_present = GaugeVec::new(
+            opts(
+                "ffmpeg_ts_program_present",
+                "Whether the program is announced in the PAT (1) or left it (0)",
+            ),
+            &["program", "service_name", "provider"],
+        )?;
+
+        let tr101290_errors = CounterVec::new(
+            opts(
+                "ffmpeg_tr101290_errors_total",
+                "Total number of ETSI TR 101 290 check failures by priority and check",
+            ),
+            &["priority", "check"],
+        )?;
+
+        let ts_null_packet_ratio = GaugeVec::new(
+            opts(
+                "ffmpeg_ts_null_packet_ratio",
+                "Share of null packets among the last 5000 transport stream packets",
+            ),
+            &[],
+        )?;
+
+        let srt_rtt = GaugeVec::new(
+            opts(
+                "ffmpeg_srt_rtt_seconds",
+                "Smoothed round trip time of the SRT connection",
+            ),
+            &[],
+        )?;
+
+        let srt_bandwidth = GaugeVec::new(
+            opts(
+                "ffmpeg_srt_bandwidth_kbits",
+                "Estimated bandwidth of the SRT link in kbit/s",
+            ),
+            &[],
+        )?;
+
+        let srt_receive_rate = GaugeVec::new(
+            opts(
+                "ffmpeg_srt_receive_rate_kbits",
+                "SRT receive rate in kbit/s",
+            ),
+            &[],
+        )?;
+
+        let srt_flight_size = GaugeVec::new(
+            opts(
+                "ffmpeg_srt_flight_size_packets",
+                "Packets in flight on the SRT connection",
+            ),
+            &[],
+        )?;
+
+        let srt_receive_buffer = GaugeVec::new(
+            opts(
+                "ffmpeg_srt_receive_buffer_seconds",
+                "Stream time held in the SRT receive buffer",
+            ),
+            &[],
+        )?;
+
+        let srt_receive_buffer_available = GaugeVec::new(
+            opts(
+                "ffmpeg_srt_receive_buffer_available_bytes",
+                "Free space of the SRT receive buffer in bytes",
+            ),
+            &[],
+        )?;
+
+        let srt_packets_received = Counter::with_opts(opts(
+            "ffmpeg_srt_packets_received_total",
+            "Total number of packets received over SRT",
+        ))?;
+
+        let srt_packets_lost = Counter::with_opts(opts(
+            "ffmpeg_srt_packets_lost_total",
+            "Total number of SRT packets reported lost by the receiver",
+        ))?;
+
+        let srt_packets_dropped = Counter::with_opts(opts(
+            "ffmpeg_srt_packets_dropped_total",
+            "Total number of SRT packets dropped for arriving too late to play",
+        ))?;
+
+        let srt_packets_retransmitted = Counter::with_opts(opts(
+            "ffmpeg_srt_packets_retransmitted_total",
+            "Total number of retransmitted SRT packets received",
+        ))?;
+
+        let srt_packets_belated = Counter::with_opts(opts(
+            "ffmpeg_srt_packets_belated_total",
+            "Total number of SRT packets received after they were dropped",
+        ))?;
+
+        let rtmp_connect = Gauge::with_opts(opts(
+            "ffmpeg_rtmp_connect_seconds",
+            "Seconds from starting ffprobe to the first packet of the RTMP stream",
+        ))?;
+
+        let rtmp_metadata_duration = Gauge::with_opts(opts(
+            "ffmpeg_rtmp_metadata_duration_seconds",
+            "Duration announced in the RTMP onMetaData, 0 for live streams",
+        ))?;
+
+        let rtmp_metadata_info = GaugeVec::new(
+            opts(
+                "ffmpeg_rtmp_metadata_info",
+                "Encoder announced in the RTMP onMetaData",
+            ),
+            &["encoder"],
+        )?;
+
+        let rtmp_metadata_datarate = GaugeVec::new(
+            opts(
+                "ffmpeg_rtmp_metadata_datarate_kbits",
+                "Data rate announced in the RTMP onMetaData in kbit/s",
+            ),
+            &["media_type"],
+        )?;
+
+        let rtp_packets_lost = CounterVec::new(
+            opts(
+                "ffmpeg_rtp_packets_lost_total",
+                "Total number of RTP packets missing from the sequence numbers of RTSP inputs",
+            ),
+            &["stream_type"],
+        )?;
+
+        let rtp_jitter = GaugeVec::new(
+            opts(
+                "ffmpeg_rtp_jitter_seconds",
+                "Interarrival jitter of RTSP inputs estimated as in RTCP receiver reports",
+            ),
+            &["stream_id"],
+        )?;
+
+        let av_sync_skew = GaugeVec::new(
+            opts(
+                "ffmpeg_av_sync_skew_ms",
+                "Latest video minus latest audio presentation timestamp in milliseconds",
+            ),
+            &["video_stream_id", "audio_stream_id"],
+        )?;
+
+        let gop_length = GaugeVec::new(
+            opts(
+                "ffmpeg_gop_length_frames",
+                "Number of frames in the last complete GOP",
+            ),
+            &["stream_id"],
+        )?;
+
+        let keyframe_interval = GaugeVec::new(
+            opts(
+                "ffmpeg_keyframe_interval_seconds",
+                "Average interval between the last keyframes in seconds",
+            ),
+            &["stream_id"],
+        )?;
+
+        let keyframes_late = CounterVec::new(
+            opts(
+                "ffmpeg_keyframes_late_total",
+                "Total number of keyframes arriving later than 1.5 times the average interval",
+            ),
+            &["stream_id"],
+        )?;
+
+        let packet_size = HistogramVec::new(
+            HistogramOpts::new(
+                "ffmpeg_packet_size_bytes",
+                "Size of demuxed packets in bytes",
+            )
+            .const_labels(const_labels.clone())
+            .buckets(buckets(
+                "packet_size",
+                prometheus::exponential_buckets(64.0, 4.0, 9)?,
+            )),
+            &["media_type"],
+        )?;
+
+        let stream_info = GaugeVec::new(
+            opts(
+                "ffmpeg_stream_info",
+                "Codec and format of each elementary stream, always 1",
+            ),
+            &STREAM_INFO_LABELS,
+        )?;
+
+        let format_changes = CounterVec::new(
+            opts(
+                "ffmpeg_format_change_total",
+                "Total number of mid-stream format changes",
+            ),
+            &["stream_id", "kind"],
+        )?;
+
+        let audio_sample_rate = GaugeVec::new(
+            opts(
+                "ffmpeg_audio_sample_rate_hz",
+                "Sample rate of the audio stream in Hz",
+            ),
+            &["stream_id"],
+        )?;
+
+        let audio_channels = GaugeVec::new(
+            opts(
+                "ffmpeg_audio_channels",
+                "Number of channels of the audio stream",
+            ),
+            &["stream_id"],
+        )?;
+
+        let audio_channel_layout = GaugeVec::new(
+            opts(
+                "ffmpeg_audio_channel_layout_info",
+                "Channel layout of the audio stream, always 1",
+            ),
+            &["stream_id", "channel_layout"],
+        )?;
+
+        let audio_track_bitrate = GaugeVec::new(
+            opts(
+                "ffmpeg_audio_track_bitrate_kbits",
+                "Bitrate of the audio track over the last second of stream time in kbits/s",
+            ),
+            &["stream_id", "track", "language"],
+        )?;
+
+        let audio_track_frames = CounterVec::new(
+            opts(
+                "ffmpeg_audio_track_frames_total",
+                "Total number of frames received on the audio track",
+            ),
+            &["stream_id", "track", "language"],
+        )?;
+
+        let audio_track_present = GaugeVec::new(
+            opts(
+                "ffmpeg_audio_track_present",
+                "Whether the audio track delivered frames in the last 5 seconds (1 = present)",
+            ),
+            &["stream_id", "track", "language"],
+        )?;
+
+        let frames_dropped = CounterVec::new(
+            opts(
+                "ffmpeg_frames_dropped_estimated_total",
+                "Estimated number of video frames missing from the expected cadence",
+            ),
+            &["stream_id"],
+        )?;
+
+        let frames_duplicated = CounterVec::new(
+            opts(
+                "ffmpeg_frames_duplicated_total",
+                "Total number of video frames repeating the previous timestamp",
+            ),
+            &["stream_id"],
+        )?;
+
+        let frame_interval_jitter = HistogramVec::new(
+            HistogramOpts::new(
+                "ffmpeg_frame_interval_jitter_seconds",
+                "Deviation of the interval between video frames from the expected frame duration in seconds",
+            )
+            .const_labels(const_labels.clone())
+            .buckets(buckets(
+                "frame_interval_jitter",
+                vec![0.001, 0.002, 0.005, 0.01, 0.02, 0.04, 0.08, 0.16, 0.32, 0.64],
+            )),
+            &["stream_id"],
+        )?;
+
+        let stalled = GaugeVec::new(
+            opts(
+                "ffmpeg_stream_stalled",
+                "Whether ffprobe delivered no packets or frames within the stall timeout (1 = stalled)",
+            ),
+            &["stream_type"],
+        )?;
+
+        let stalls = CounterVec::new(
+            opts(
+                "ffmpeg_stream_stalls_total",
+                "Total number of times the stream stalled",
+            ),
+            &["stream_type"],
+        )?;
+
+        let paused = GaugeVec::new(
+            opts(
+                "ffmpeg_stream_paused",
+                "Whether monitoring of the stream is paused through the admin API (1 = paused)",
+            ),
+            &["stream_type"],
+        )?;
+
+        let probe_timeouts = CounterVec::new(
+            opts(
+                "ffmpeg_probe_timeouts_total",
+                "Total number of ffprobe processes restarted for producing no output within the probe timeout",
+            ),
+            &["stream_type"],
+        )?;
+
+        let live_lag = GaugeVec::new(
+            opts(
+                "ffmpeg_live_lag_seconds",
+                "How far the stream time of a live input has fallen behind the wall clock in seconds",
+            ),
+            &["stream_id"],
+        )?;
+
+        let video_interlaced = GaugeVec::new(
+            opts(
+                "ffmpeg_video_interlaced",
+                "Whether the latest video frame was interlaced (1 = interlaced, 0 = progressive)",
+            ),
+            &["stream_id"],
+        )?;
+
+        let color_info = GaugeVec::new(
+            opts(
+                "ffmpeg_color_info",
+                "Color properties and HDR metadata of the video stream, always 1",
+            ),
+            &COLOR_INFO_LABELS,
+        )?;
+
+        let video_qp = GaugeVec::new(
+            opts(
+                "ffmpeg_video_qp_avg",
+                "Average quantizer of the last 32 decoded video frames",
+            ),
+            &["stream_id"],
+        )?;
+
+        let timecode_seconds = GaugeVec::new(
+            opts(
+                "ffmpeg_timecode_seconds",
+                "Hours, minutes and seconds of the latest embedded timecode in seconds since midnight",
+            ),
+            &["stream_id"],
+        )?;
+
+        let timecode_frames = GaugeVec::new(
+            opts(
+                "ffmpeg_timecode_frames",
+                "Frame count of the latest embedded timecode",
+            ),
+            &["stream_id"],
+        )?;
+
+        let timecode_discontinuities = CounterVec::new(
+            opts(
+                "ffmpeg_timecode_discontinuities_total",
+                "Total number of embedded timecodes not following the previous frame's",
+            ),
+            &["stream_id"],
+        )?;
+
+        let captions_present = GaugeVec::new(
+            opts(
+                "ffmpeg_captions_present",
+                "Whether CEA-608/708 caption data arrived within the last 5 seconds of stream time (1 = yes)",
+            ),
+            &["stream_id"],
+        )?;
+
+        let caption_gap = GaugeVec::new(
+            opts(
+                "ffmpeg_caption_gap_seconds",
+                "Stream time since the last CEA-608/708 caption data in seconds",
+            ),
+            &["stream_id"],
+        )?;
+
+        let id3_packets = CounterVec::new(
+            opts(
+                "ffmpeg_id3_packets_total",
+                "Total number of timed ID3 metadata packets",
+            ),
+            &["stream_id"],
+        )?;
+
+        let id3_gap = GaugeVec::new(
+            opts(
+                "ffmpeg_id3_gap_seconds",
+                "Stream time since the last timed ID3 metadata packet in seconds",
+            ),
+            &["stream_id"],
+        )?;
+
+        let subtitle_track_present = GaugeVec::new(
+            opts(
+                "ffmpeg_subtitle_track_present",
+                "Subtitle tracks found by the stream probe (always 1)",
+            ),
+            &["stream_id", "track", "codec", "language"],
+        )?;
+
+        let subtitle_track_packets = CounterVec::new(
+            opts(
+                "ffmpeg_subtitle_track_packets_total",
+                "Total number of packets per subtitle track",
+            ),
+            &["stream_id", "track", "codec", "language"],
+        )?;
+
+        let last_error_timestamp = Gauge::with_opts(opts(
+            "ffmpeg_last_error_timestamp_seconds",
+            "Unix time of the most recent error of the stream",
+        ))?;
+
+        let last_error_info = GaugeVec::new(
+            opts(
+                "ffmpeg_last_error_info",
+                "Type of the most recent error of the stream (always 1)",
+            ),
+            &["error_type"],
+        )?;
+
+        let errors = CounterVec::new(
+            opts(
+                "ffmpeg_errors_total",
+                "Total number of errors by severity and category",
+            ),
+            &["severity", "category"],
+        )?;
+
+        let video_black = GaugeVec::new(
+            opts(
+                "ffmpeg_video_black",
+                "Whether the video is currently black according to blackdetect (1 = black)",
+            ),
+            &[],
+        )?;
+
+        let video_black_seconds = Counter::with_opts(opts(
+            "ffmpeg_video_black_seconds_total",
+            "Total seconds of black video according to blackdetect",
+        ))?;
+
+        let video_black_events = Counter::with_opts(opts(
+            "ffmpeg_video_black_events_total",
+            "Total number of black video periods according to blackdetect",
+        ))?;
+
+        let video_frozen = GaugeVec::new(
+            opts(
+                "ffmpeg_video_frozen",
+                "Whether the video is currently frozen according to freezedetect (1 = frozen)",
+            ),
+            &[],
+        )?;
+
+        let video_frozen_seconds = Counter::with_opts(opts(
+            "ffmpeg_video_frozen_seconds_total",
+            "Total seconds of frozen video according to freezedetect",
+        ))?;
+
+        let audio_silent = GaugeVec::new(
+            opts(
+                "ffmpeg_audio_silent",
+                "Whether the audio track is currently silent according to silencedetect (1 = silent)",
+            ),
+            &["stream_id", "track", "language"],
+        )?;
+
+        let audio_silence_seconds = CounterVec::new(
+            opts(
+                "ffmpeg_audio_silence_seconds_total",
+                "Total seconds of silence per audio track according to silencedetect",
+            ),
+            &["stream_id", "track", "language"],
+        )?;
+
+        let audio_silence_events = CounterVec::new(
+            opts(
+                "ffmpeg_audio_silence_events_total",
+                "Total number of silences per audio track according to silencedetect",
+            ),
+            &["stream_id", "track", "language"],
+        )?;
+
+        let audio_rms_level = GaugeVec::new(
+            opts(
+                "ffmpeg_audio_rms_level_dbfs",
+                "RMS level of the latest audio frame per channel in dBFS according to astats",
+            ),
+            &["stream_id", "track", "language", "channel"],
+        )?;
+
+        let audio_peak_level = GaugeVec::new(
+            opts(
+                "ffmpeg_audio_peak_level_dbfs",
+                "Peak level of the latest audio frame per channel in dBFS according to astats",
+            ),
+            &["stream_id", "track", "language", "channel"],
+        )?;
+
+        let audio_clipped_samples = CounterVec::new(
+            opts(
+                "ffmpeg_audio_clipped_samples_total",
+                "Total number of audio samples at full scale per channel according to astats",
+            ),
+            &["stream_id", "track", "language", "channel"],
+        )?;
+
+        let quality_psnr = GaugeVec::new(
+            opts(
+                "ffmpeg_quality_psnr_db",
+                "Average PSNR against the reference over the last second of video in dB",
+            ),
+            &[],
+        )?;
+
+        let quality_ssim = GaugeVec::new(
+            opts(
+                "ffmpeg_quality_ssim",
+                "Average SSIM against the reference over the last second of video",
+            ),
+            &[],
+        )?;
+
+        let quality_psnr_per_second = Histogram::with_opts(
+            HistogramOpts::new(
+                "ffmpeg_quality_psnr_per_second_db",
+                "Distribution of the per second average PSNR against the reference in dB",
+            )
+            .const_labels(const_labels.clone())
+            .buckets(prometheus::linear_buckets(20.0, 5.0, 9)?),
+        )?;
+
+        let quality_ssim_per_second = Histogram::with_opts(
+            HistogramOpts::new(
+                "ffmpeg_quality_ssim_per_second",
+                "Distribution of the per second average SSIM against the reference",
+            )
+            .const_labels(const_labels.clone())
+            .buckets(vec![0.5, 0.7, 0.8, 0.9, 0.95, 0.97, 0.98, 0.99, 0.995]),
+        )?;
+
+        let test_pattern_detected = GaugeVec::new(
+            opts(
+                "ffmpeg_test_pattern_detected",
+                "Whether the stream shows a test pattern: colour bars or 1 kHz line-up tone (1 = detected)",
+            ),
+            &["pattern"],
+        )?;
+
+        let video_signal_level = GaugeVec::new(
+            opts(
+                "ffmpeg_video_signal_level",
+                "Minimum, maximum and average level of a plane of the latest video frame according to signalstats",
+            ),
+            &["plane", "stat"],
+        )?;
+
+        let video_out_of_range_ratio = GaugeVec::new(
+            opts(
+                "ffmpeg_video_out_of_range_ratio",
+                "Share of pixels of the latest video frame outside the broadcast range according to signalstats",
+            ),
+            &[],
+        )?;
+
+        let video_illegal_frames = CounterVec::new(
+            opts(
+                "ffmpeg_video_illegal_frames_total",
+                "Total number of video frames with luma below black or above white level",
+            ),
+            &["level"],
+        )?;
+
+        let bitrate_declared_deviation = GaugeVec::new(
+            opts(
+                "ffmpeg_bitrate_declared_deviation_percent",
+                "Deviation of the 10 second bitrate of all elementary streams from the declared bandwidth in percent",
+            ),
+            &[],
+        )?;
+
+        let suppressed_series = CounterVec::new(
+            opts(
+                "ffmpeg_suppressed_series_total",
+                "Total number of distinct label values exported as other because of the label limits",
+            ),
+            &["label"],
+        )?;
+        let stream_ids = LabelGuard::new(suppressed_series.with_label_values(&["stream_id"]));
+        let pids = LabelGuard::new(suppressed_series.with_label_values(&["pid"]));
+
+        let metrics = Self {
             fps,
             frame_counter,
+            session_frames,
             bitrate,
+            bitrate_avg,
             packet_corrupt,
             connection_state,
             connection_reset,
+            restarts,
+            downtime,
+            outage_duration,
+            retry_backoff,
+            connect_duration,
             dropped_packets,
             codec_errors,
-        })
+            hls_pdt_offset,
+            hls_live_latency,
+            hls_segments,
+            hls_segment_duration,
+            hls_segment_target_deviation,
+            hls_segment_size,
+            hls_segment_fetch_duration,
+            hls_segment_fetch_errors,
+            hls_playlist_age,
+            hls_playlist_stale,
+            hls_parts,
+            hls_part_duration,
+            hls_part_target_deviation,
+            dash_manifest_valid,
+            dash_manifest_errors,
+            dash_publish_age,
+            dash_timeline_gap,
+            dash_live_edge_age,
+            stderr_lines_dropped,
+            udp_overruns,
+            udp_receive_buffer,
+            ts_cc_errors,
+            pcr_interval,
+            pcr_interval_violations,
+            pcr_accuracy,
+            pcr_jitter,
+            ts_table_changes,
+            ts_program_present,
+            tr101290_errors,
+            ts_null_packet_ratio,
+            srt_rtt,
+            srt_bandwidth,
+            srt_receive_rate,
+            srt_flight_size,
+            srt_receive_buffer,
+            srt_receive_buffer_available,
+            srt_packets_received,
+            srt_packets_lost,
+            srt_packets_dropped,
+            srt_packets_retransmitted,
+            srt_packets_belated,
+            rtmp_connect,
+            rtmp_metadata_duration,
+            rtmp_metadata_info,
+            rtmp_metadata_datarate,
+            rtp_packets_lost,
+            rtp_jitter,
+            av_sync_skew,
+            gop_length,
+            keyframe_interval,
+            keyframes_late,
+            packet_size,
+            stream_info,
+            format_changes,
+            audio_sample_rate,
+            audio_channels,
+            audio_channel_layout,
+            audio_track_bitrate,
+            audio_track_frames,
+            audio_track_present,
+            frames_dropped,
+            frames_duplicated,
+            frame_interval_jitter,
+            stalled,
+            stalls,
+            paused,
+            probe_timeouts,
+            live_lag,
+            video_interlaced,
+            color_info,
+            video_qp,
+            timecode_seconds,
+            timecode_frames,
+            timecode_discontinuities,
+            captions_present,
+            caption_gap,
+            id3_packets,
+            id3_gap,
+            subtitle_track_present,
+            subtitle_track_packets,
+            last_error_timestamp,
+            last_error_info,
+            errors,
+            video_black,
+            video_black_seconds,
+            video_black_events,
+            video_frozen,
+            video_frozen_seconds,
+            audio_silent,
+            audio_silence_seconds,
+            audio_silence_events,
+            audio_rms_level,
+            audio_peak_level,
+            audio_clipped_samples,
+            quality_psnr,
+            quality_ssim,
+            quality_psnr_per_second,
+            quality_ssim_per_second,
+            test_pattern_detected,
+            video_signal_level,
+            video_out_of_range_ratio,
+            video_illegal_frames,
+            bitrate_declared_deviation,
+            suppressed_series,
+            stream_ids,
+            pids,
+        };
+
+        // Register all metrics
+        for collector in metrics.collectors() {
+            registry.register(collector)?;
+        }
+
+        Ok(metrics)
+    }
+
+    fn collectors(&self) -> Vec<Box<dyn Collector>> {
+        vec![
+            Box::new(self.fps.clone()),
+            Box::new(self.frame_counter.clone()),
+            Box::new(self.session_frames.clone()),
+            Box::new(self.bitrate.clone()),
+            Box::new(self.bitrate_avg.clone()),
+            Box::new(self.packet_corrupt.clone()),
+            Box::new(self.connection_state.clone()),
+            Box::new(self.connection_reset.clone()),
+            Box::new(self.restarts.clone()),
+            Box::new(self.downtime.clone()),
+            Box::new(self.outage_duration.clone()),
+            Box::new(self.retry_backoff.clone()),
+            Box::new(self.connect_duration.clone()),
+            Box::new(self.dropped_packets.clone()),
+            Box::new(self.codec_errors.clone()),
+            Box::new(self.hls_pdt_offset.clone()),
+            Box::new(self.hls_live_latency.clone()),
+            Box::new(self.hls_segments.clone()),
+            Box::new(self.hls_segment_duration.clone()),
+            Box::new(self.hls_segment_target_deviation.clone()),
+            Box::new(self.hls_segment_size.clone()),
+            Box::new(self.hls_segment_fetch_duration.clone()),
+            Box::new(self.hls_segment_fetch_errors.clone()),
+            Box::new(self.hls_playlist_age.clone()),
+            Box::new(self.hls_playlist_stale.clone()),
+            Box::new(self.hls_parts.clone()),
+            Box::new(self.hls_part_duration.clone()),
+            Box::new(self.hls_part_target_deviation.clone()),
+            Box::new(self.dash_manifest_valid.clone()),
+            Box::new(self.dash_manifest_errors.clone()),
+            Box::new(self.dash_publish_age.clone()),
+            Box::new(self.dash_timeline_gap.clone()),
+            Box::new(self.dash_live_edge_age.clone()),
+            Box::new(self.stderr_lines_dropped.clone()),
+            Box::new(self.udp_overruns.clone()),
+            Box::new(self.udp_receive_buffer.clone()),
+            Box::new(self.ts_cc_errors.clone()),
+            Box::new(self.pcr_interval.clone()),
+            Box::new(self.pcr_interval_violations.clone()),
+            Box::new(self.pcr_accuracy.clone()),
+            Box::new(self.pcr_jitter.clone()),
+            Box::new(self.ts_table_changes.clone()),
+            Box::new(self.ts_program_present.clone()),
+            Box::new(self.tr101290_errors.clone()),
+            Box::new(self.ts_null_packet_ratio.clone()),
+            Box::new(self.srt_rtt.clone()),
+            Box::new(self.srt_bandwidth.clone()),
+            Box::new(self.srt_receive_rate.clone()),
+            Box::new(self.srt_flight_size.clone()),
+            Box::new(self.srt_receive_buffer.clone()),
+            Box::new(self.srt_receive_buffer_available.clone()),
+            Box::new(self.srt_packets_received.clone()),
+            Box::new(self.srt_packets_lost.clone()),
+            Box::new(self.srt_packets_dropped.clone()),
+            Box::new(self.srt_packets_retransmitted.clone()),
+            Box::new(self.srt_packets_belated.clone()),
+            Box::new(self.rtmp_connect.clone()),
+            Box::new(self.rtmp_metadata_duration.clone()),
+            Box::new(self.rtmp_metadata_info.clone()),
+            Box::new(self.rtmp_metadata_datarate.clone()),
+            Box::new(self.rtp_packets_lost.clone()),
+            Box::new(self.rtp_jitter.clone()),
+            Box::new(self.av_sync_skew.clone()),
+            Box::new(self.gop_length.clone()),
+            Box::new(self.keyframe_interval.clone()),
+            Box::new(self.keyframes_late.clone()),
+            Box::new(self.packet_size.clone()),
+            Box::new(self.stream_info.clone()),
+            Box::new(self.format_changes.clone()),
+            Box::new(self.audio_sample_rate.clone()),
+            Box::new(self.audio_channels.clone()),
+            Box::new(self.audio_channel_layout.clone()),
+            Box::new(self.audio_track_bitrate.clone()),
+            Box::new(self.audio_track_frames.clone()),
+            Box::new(self.audio_track_present.clone()),
+            Box::new(self.frames_dropped.clone()),
+            Box::new(self.frames_duplicated.clone()),
+            Box::new(self.frame_interval_jitter.clone()),
+            Box::new(self.stalled.clone()),
+            Box::new(self.paused.clone()),
+            Box::new(self.stalls.clone()),
+            Box::new(self.probe_timeouts.clone()),
+            Box::new(self.live_lag.clone()),
+            Box::new(self.video_interlaced.clone()),
+            Box::new(self.color_info.clone()),
+            Box::new(self.video_qp.clone()),
+            Box::new(self.timecode_seconds.clone()),
+            Box::new(self.timecode_frames.clone()),
+            Box::new(self.timecode_discontinuities.clone()),
+            Box::new(self.captions_present.clone()),
+            Box::new(self.caption_gap.clone()),
+            Box::new(self.id3_packets.clone()),
+            Box::new(self.id3_gap.clone()),
+            Box::new(self.subtitle_track_present.clone()),
+            Box::new(self.subtitle_track_packets.clone()),
+            Box::new(self.last_error_timestamp.clone()),
+            Box::new(self.last_error_info.clone()),
+            Box::new(self.errors.clone()),
+            Box::new(self.video_black.clone()),
+            Box::new(self.video_black_seconds.clone()),
+            Box::new(self.video_black_events.clone()),
+            Box::new(self.video_frozen.clone()),
+            Box::new(self.video_frozen_seconds.clone()),
+            Box::new(self.audio_silent.clone()),
+            Box::new(self.audio_silence_seconds.clone()),
+            Box::new(self.audio_silence_events.clone()),
+            Box::new(self.audio_rms_level.clone()),
+            Box::new(self.audio_peak_level.clone()),
+            Box::new(self.audio_clipped_samples.clone()),
+            Box::new(self.quality_psnr.clone()),
+            Box::new(self.quality_ssim.clone()),
+            Box::new(self.quality_psnr_per_second.clone()),
+            Box::new(self.quality_ssim_per_second.clone()),
+            Box::new(self.test_pattern_detected.clone()),
+            Box::new(self.video_signal_level.clone()),
+            Box::new(self.video_out_of_range_ratio.clone()),
+            Box::new(self.video_illegal_frames.clone()),
+            Box::new(self.bitrate_declared_deviation.clone()),
+            Box::new(self.suppressed_series.clone()),
+        ]
+    }
+
+    /// Applies the limits of the `stream_id` and `pid` label values, before
+    /// any of them is exported
+    pub fn with_label_limits(mut self, limits: &LabelLimits) -> Self {
+        self.stream_ids.limit("stream_id", limits);
+        self.pids.limit("pid", limits);
+        self
+    }
+
+    /// Counts an error of `error_type` and remembers it as the most recent
+    /// error of the stream. Only the latest type is exported, keeping the info
+    /// metric at one series.
+    pub fn record_error(&self, error_type: &str, severity: Severity) {
+        self.errors
+            .with_label_values(&[severity.as_str(), error_type])
+            .inc();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.last_error_info.reset();
+        self.last_error_info
+            .with_label_values(&[error_type])
+            .set(1.0);
+        self.last_error_timestamp.set(now.as_secs_f64());
+    }
+
+    /// Clears the gauges measuring the flowing stream, which a disconnected
+    /// stream would otherwise report at their last value. Gauges describing
+    /// the stream, like its codecs and tracks, stay.
+    pub fn clear_session_gauges(&self, mode: StaleGauges) {
+        let gauges = [
+            &self.fps,
+            &self.bitrate,
+            &self.bitrate_avg,
+            &self.pcr_interval,
+            &self.pcr_accuracy,
+            &self.pcr_jitter,
+            &self.rtp_jitter,
+            &self.av_sync_skew,
+            &self.gop_length,
+            &self.keyframe_interval,
+            &self.live_lag,
+            &self.video_qp,
+            &self.timecode_seconds,
+            &self.timecode_frames,
+            &self.caption_gap,
+            &self.id3_gap,
+            &self.audio_silent,
+            &self.audio_rms_level,
+            &self.audio_peak_level,
+            &self.test_pattern_detected,
+            &self.video_signal_level,
+            &self.ts_null_packet_ratio,
+            &self.srt_rtt,
+            &self.srt_bandwidth,
+            &self.srt_receive_rate,
+            &self.srt_flight_size,
+            &self.srt_receive_buffer,
+            &self.srt_receive_buffer_available,
+            &self.video_black,
+            &self.video_frozen,
+            &self.quality_psnr,
+            &self.quality_ssim,
+            &self.video_out_of_range_ratio,
+            &self.bitrate_declared_deviation,
+        ];
+        match mode {
+            StaleGauges::Keep => {}
+            StaleGauges::Remove => gauges.iter().for_each(|gauges| gauges.reset()),
+            StaleGauges::Reset => gauges.iter().for_each(|gauges| zero(gauges)),
+        }
+    }
+
+    /// Unregisters every collector of the stream, dropping all of its label
+    /// sets from the exposition.
+    pub fn remove(&self, registry: &Registry) -> Result<()> {
+        for collector in self.collectors() {
+            registry.unregister(collector)?;
+        }
+        Ok(())
+    }
+
+    /// Names of all metric families of a stream
+    #[cfg(test)]
+    pub fn names(&self) -> std::collections::BTreeSet<String> {
+        self.collectors()
+            .iter()
+            .flat_map(|collector| collector.desc())
+            .map(|desc| desc.fq_name.clone())
+            .collect()
+    }
+}
+
+/// Sets every label set of `gauges` to 0
+fn zero(gauges: &GaugeVec) {
+    let desc = gauges.desc();
+    let Some(names) = desc.first().map(|desc| &desc.variable_labels) else {
+        return;
+    };
+    for family in gauges.collect() {
+        for metric in family.get_metric() {
+            // The collected labels include the constant ones, sorted by name
+            let values: Vec<&str> = names
+                .iter()
+                .filter_map(|name| {
+                    metric
+                        .get_label()
+                        .iter()
+                        .find(|label| label.get_name() == name)
+                        .map(|label| label.get_value())
+                })
+                .collect();
+            gauges.with_label_values(&values).set(0.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remove_drops_all_series() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        metrics
+            .fps
+            .with_label_values(&["srt", "0", "video"])
+            .set(25.0);
+        metrics
+            .frame_counter
+            .with_label_values(&["processed", "0", "video"])
+            .inc();
+        metrics.bitrate.with_label_values(&["0", "video"]).set(1.0);
+        metrics
+            .codec_errors
+            .with_label_values(&["sei_error", "0"])
+            .inc();
+        assert!(!registry.gather().is_empty());
+
+        metrics.remove(&registry).unwrap();
+        assert!(registry.gather().is_empty());
+
+        // The same stream can be registered again after removal
+        assert!(StreamMetrics::new(&registry, &HashMap::new()).is_ok());
+    }
+
+    #[test]
+    fn test_clear_session_gauges() {
+        let labels = HashMap::from([("stream".to_string(), "studio".to_string())]);
+        let metrics = StreamMetrics::new(&Registry::new(), &labels).unwrap();
+        let fps = || metrics.fps.with_label_values(&["srt", "0", "video"]);
+        let series = || metrics.fps.collect()[0].get_metric().len();
+        fps().set(25.0);
+        metrics.srt_rtt.with_label_values(&[]).set(12.0);
+        metrics
+            .stream_info
+            .with_label_values(&["0", "video", "h264", "High", "1920", "1080", "yuv420p"])
+            .set(1.0);
+
+        metrics.clear_session_gauges(StaleGauges::Keep);
+        assert_eq!(fps().get(), 25.0);
+
+        metrics.clear_session_gauges(StaleGauges::Reset);
+        assert_eq!(series(), 1);
+        assert_eq!(fps().get(), 0.0);
+        assert_eq!(metrics.srt_rtt.with_label_values(&[]).get(), 0.0);
+
+        fps().set(25.0);
+        metrics.clear_session_gauges(StaleGauges::Remove);
+        assert_eq!(series(), 0);
+        // Gauges without labels of their own are dropped as well
+        assert!(metrics.srt_rtt.collect()[0].get_metric().is_empty());
+        // The description of the stream stays
+        assert_eq!(metrics.stream_info.collect()[0].get_metric().len(), 1);
+    }
+
+    #[test]
+    fn test_record_error_keeps_latest() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        metrics.record_error("packet_corrupt", Severity::Error);
+        metrics.record_error("concealment", Severity::Warning);
+
+        let info = metrics.last_error_info.collect();
+        assert_eq!(info[0].get_metric().len(), 1);
+        assert_eq!(
+            metrics
+                .last_error_info
+                .with_label_values(&["concealment"])
+                .get(),
+            1.0
+        );
+        assert!(metrics.last_error_timestamp.get() > 0.0);
+        assert_eq!(
+            metrics
+                .errors
+                .with_label_values(&["warning", "concealment"])
+                .get(),
+            1.0
+        );
+    }
+
+    #[test]
+    fn test_histogram_buckets() {
+        let buckets = BTreeMap::from([("frame_interval_jitter".to_string(), vec![0.1, 1.0])]);
+        let metrics =
+            StreamMetrics::with_buckets(&Registry::new(), &HashMap::new(), &buckets).unwrap();
+        let bounds = |histogram: &HistogramVec, label: &str| {
+            histogram.with_label_values(&[label]).observe(0.0);
+            histogram.collect()[0].get_metric()[0]
+                .get_histogram()
+                .get_bucket()
+                .iter()
+                .map(|bucket| bucket.get_upper_bound())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(bounds(&metrics.frame_interval_jitter, "0"), [0.1, 1.0]);
+        // Histograms without overrides keep their defaults
+        assert_eq!(bounds(&metrics.connect_duration, "srt").len(), 8);
+    }
+
+    fn stream_labels(name: &str) -> HashMap<String, String> {
+        HashMap::from([("stream".to_string(), name.to_string())])
+    }
+
+    #[test]
+    fn test_streams_share_registry() {
+        let registry = Registry::new();
+        let sport = StreamMetrics::new(&registry, &stream_labels("sport")).unwrap();
+        let news = StreamMetrics::new(&registry, &stream_labels("news")).unwrap();
+        sport.connection_state.with_label_values(&["srt"]).set(1.0);
+        news.connection_state.with_label_values(&["hls"]).set(1.0);
+
+        let series = |registry: &Registry| -> usize {
+            registry
+                .gather()
+                .iter()
+                .find(|family| family.get_name() == "ffmpeg_stream_connection_state")
+                .map_or(0, |family| family.get_metric().len())
+        };
+        assert_eq!(series(&registry), 2);
+
+        // Removing one stream leaves the other one untouched
+        news.remove(&registry).unwrap();
+        assert_eq!(series(&registry), 1);
+
+        // A stream name can only be registered once
+        assert!(StreamMetrics::new(&registry, &stream_labels("sport")).is_err());
     }
 }
@@ -1,5 +1,14 @@
 mod app_state;
+mod build_info;
+mod cardinality;
 mod collectors;
+pub mod openmetrics;
+mod remote_write;
+mod runtime;
 
 pub use app_state::AppState;
-pub use collectors::StreamMetrics;
+pub use build_info::{register_build_info, register_ffprobe_info};
+pub use cardinality::OTHER;
+pub use collectors::{COLOR_INFO_LABELS, STREAM_INFO_LABELS, StreamMetrics};
+pub use remote_write::RemoteWriter;
+pub use runtime::{RuntimeMetrics, register_process_metrics};
@@ -0,0 +1,227 @@
+// metrics/openmetrics.rs
+
+use prometheus::proto::{LabelPair, MetricFamily, MetricType};
+use std::fmt::Write;
+
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+const MEDIA_TYPE: &str = "application/openmetrics-text";
+
+/// Whether an `Accept` header prefers OpenMetrics over the classic text
+/// format, going by the quality values of its media ranges. Ties go to the
+/// classic format.
+pub fn preferred(accept: &str) -> bool {
+    let mut openmetrics = 0.0;
+    let mut text = 0.0;
+    for range in accept.split(',') {
+        let mut params = range.split(';').map(str::trim);
+        let media_type = params.next().unwrap_or_default().to_ascii_lowercase();
+        let quality = params
+            .filter_map(|param| param.strip_prefix("q="))
+            .find_map(|q| q.parse::<f64>().ok())
+            .unwrap_or(1.0);
+        let best = match media_type.as_str() {
+            MEDIA_TYPE => &mut openmetrics,
+            "text/plain" | "text/*" | "*/*" => &mut text,
+            _ => continue,
+        };
+        *best = f64::max(*best, quality);
+    }
+    openmetrics > text
+}
+
+/// Encodes metric families in the OpenMetrics text format. Counter families
+/// lose their `_total` suffix, which OpenMetrics only puts on the samples,
+/// so the series names are those of the classic format.
+pub fn encode(families: &[MetricFamily]) -> String {
+    let mut out = String::new();
+    for family in families {
+        let name = family.get_name();
+        let (family_name, kind) = match family.get_field_type() {
+            MetricType::COUNTER => match name.strip_suffix("_total") {
+                Some(stem) => (stem, "counter"),
+                // Counter samples must end in _total, so keep the name
+                None => (name, "unknown"),
+            },
+            MetricType::GAUGE => (name, "gauge"),
+            MetricType::HISTOGRAM => (name, "histogram"),
+            MetricType::SUMMARY => (name, "summary"),
+            MetricType::UNTYPED => (name, "unknown"),
+        };
+        let _ = writeln!(out, "# TYPE {} {}", family_name, kind);
+        if !family.get_help().is_empty() {
+            let _ = writeln!(out, "# HELP {} {}", family_name, escape(family.get_help()));
+        }
+
+        for sample in samples(family) {
+            let sample_name = format!("{}{}", name, sample.suffix);
+            write_sample(
+                &mut out,
+                &sample_name,
+                sample.labels,
+                sample.extra,
+                sample.value,
+            );
+        }
+    }
+    out.push_str("# EOF\n");
+    out
+}
+
+/// A sample of a metric family as a scrape shows it
+pub struct Sample<'a> {
+    /// Appended to the family name, like `_bucket` or `_sum`
+    pub suffix: &'static str,
+    pub labels: &'a [LabelPair],
+    /// The `le` label of buckets or the `quantile` label of quantiles
+    pub extra: Option<(&'static str, String)>,
+    pub value: f64,
+}
+
+/// The samples of a metric family: histograms and summaries become their
+/// buckets or quantiles, `_sum` and `_count`. Histograms get the `+Inf`
+/// bucket the client library leaves out.
+pub fn samples(family: &MetricFamily) -> impl Iterator<Item = Sample<'_>> {
+    let kind = family.get_field_type();
+    family.get_metric().iter().flat_map(move |metric| {
+        let labels = metric.get_label();
+        let sample = |suffix, extra, value| Sample {
+            suffix,
+            labels,
+            extra,
+            value,
+        };
+        let mut samples = Vec::new();
+        match kind {
+            MetricType::COUNTER => samples.push(sample("", None, metric.get_counter().get_value())),
+            MetricType::GAUGE => samples.push(sample("", None, metric.get_gauge().get_value())),
+            MetricType::UNTYPED => samples.push(sample("", None, metric.get_untyped().get_value())),
+            MetricType::HISTOGRAM => {
+                let histogram = metric.get_histogram();
+                let mut infinite = false;
+                for bucket in histogram.get_bucket() {
+                    let bound = bucket.get_upper_bound();
+                    infinite |= bound == f64::INFINITY;
+                    let le = ("le", format_value(bound));
+                    samples.push(sample(
+                        "_bucket",
+                        Some(le),
+                        bucket.get_cumulative_count() as f64,
+                    ));
+                }
+                let count = histogram.get_sample_count() as f64;
+                if !infinite {
+                    samples.push(sample("_bucket", Some(("le", "+Inf".into())), count));
+                }
+                samples.push(sample("_sum", None, histogram.get_sample_sum()));
+                samples.push(sample("_count", None, count));
+            }
+            MetricType::SUMMARY => {
+                let summary = metric.get_summary();
+                for q in summary.get_quantile() {
+                    let quantile = ("quantile", format_value(q.get_quantile()));
+                    samples.push(sample("", Some(quantile), q.get_value()));
+                }
+                samples.push(sample("_sum", None, summary.get_sample_sum()));
+                samples.push(sample("_count", None, summary.get_sample_count() as f64));
+            }
+        }
+        samples
+    })
+}
+
+fn write_sample(
+    out: &mut String,
+    name: &str,
+    labels: &[LabelPair],
+    extra: Option<(&str, String)>,
+    value: f64,
+) {
+    let pairs: Vec<_> = labels
+        .iter()
+        .map(|label| format!("{}=\"{}\"", label.get_name(), escape(label.get_value())))
+        .chain(extra.map(|(key, value)| format!("{}=\"{}\"", key, value)))
+        .collect();
+    if pairs.is_empty() {
+        let _ = writeln!(out, "{} {}", name, format_value(value));
+    } else {
+        let _ = writeln!(
+            out,
+            "{}{{{}}} {}",
+            name,
+            pairs.join(","),
+            format_value(value)
+        );
+    }
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Escapes help texts and label values
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{CounterVec, Gauge, Histogram, HistogramOpts, Opts, Registry};
+
+    #[test]
+    fn test_preferred() {
+        // Prometheus with OpenMetrics scraping enabled
+        assert!(preferred(
+            "application/openmetrics-text;version=1.0.0;q=0.5,application/openmetrics-text;version=0.0.1;q=0.4,text/plain;version=0.0.4;q=0.3,*/*;q=0.2"
+        ));
+        assert!(!preferred("text/plain;version=0.0.4;q=1,*/*;q=0.1"));
+        assert!(!preferred("*/*"));
+        assert!(!preferred("application/openmetrics-text;q=0.5,text/plain"));
+        assert!(preferred("application/openmetrics-text"));
+    }
+
+    #[test]
+    fn test_encode() {
+        let registry = Registry::new();
+        let errors =
+            CounterVec::new(Opts::new("ffmpeg_errors_total", "Total errors"), &["type"]).unwrap();
+        let fps = Gauge::new("ffmpeg_fps", "Frames per second").unwrap();
+        let latency = Histogram::with_opts(
+            HistogramOpts::new("ffmpeg_latency_seconds", "Latency").buckets(vec![0.5, 1.0]),
+        )
+        .unwrap();
+        registry.register(Box::new(errors.clone())).unwrap();
+        registry.register(Box::new(fps.clone())).unwrap();
+        registry.register(Box::new(latency.clone())).unwrap();
+        errors.with_label_values(&["say \"hi\"\n"]).inc();
+        fps.set(25.0);
+        latency.observe(0.7);
+
+        assert_eq!(
+            encode(&registry.gather()),
+            "# TYPE ffmpeg_errors counter\n\
+             # HELP ffmpeg_errors Total errors\n\
+             ffmpeg_errors_total{type=\"say \\\"hi\\\"\\n\"} 1\n\
+             # TYPE ffmpeg_fps gauge\n\
+             # HELP ffmpeg_fps Frames per second\n\
+             ffmpeg_fps 25\n\
+             # TYPE ffmpeg_latency_seconds histogram\n\
+             # HELP ffmpeg_latency_seconds Latency\n\
+             ffmpeg_latency_seconds_bucket{le=\"0.5\"} 0\n\
+             ffmpeg_latency_seconds_bucket{le=\"1\"} 1\n\
+             ffmpeg_latency_seconds_bucket{le=\"+Inf\"} 1\n\
+             ffmpeg_latency_seconds_sum 0.7\n\
+             ffmpeg_latency_seconds_count 1\n\
+             # EOF\n"
+        );
+    }
+}
@@ -0,0 +1,318 @@
+// metrics/remote_write.rs
+
+use crate::config::{RemoteWriteOptions, redact};
+use crate::metrics::openmetrics::samples;
+use crate::stream::http_agent;
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use prometheus::proto::{LabelPair, MetricFamily};
+use prometheus::{Counter, Gauge, Registry};
+use prost::Message;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+/// How often the wait for the next write checks for shutdown
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Messages of the remote write 1.0 protocol, from `prompb/remote.proto` and
+/// `prompb/types.proto` of Prometheus
+#[derive(Clone, PartialEq, Message)]
+pub struct WriteRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct TimeSeries {
+    /// Sorted by name, starting with `__name__`
+    #[prost(message, repeated, tag = "1")]
+    pub labels: Vec<Label>,
+    #[prost(message, repeated, tag = "2")]
+    pub samples: Vec<Sample>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Label {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub value: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Sample {
+    #[prost(double, tag = "1")]
+    pub value: f64,
+    /// Milliseconds since the unix epoch
+    #[prost(int64, tag = "2")]
+    pub timestamp: i64,
+}
+
+/// Pushes everything `/metrics` shows to a remote write endpoint at a fixed
+/// interval. Writes that fail are dropped rather than queued: the next one
+/// carries the current values, counters included, so an outage of the
+/// endpoint only costs resolution.
+pub struct RemoteWriter {
+    registry: Registry,
+    url: String,
+    interval: Duration,
+    authorization: Option<String>,
+    agent: ureq::Agent,
+    samples: Counter,
+    failures: Counter,
+    last_success: Gauge,
+}
+
+impl RemoteWriter {
+    pub fn new(registry: &Registry, options: &RemoteWriteOptions) -> Result<Self> {
+        let url = options.url.clone().context("No remote write URL")?;
+        let authorization = options
+            .username
+            .as_ref()
+            .zip(options.password.as_ref())
+            .map(|(username, password)| {
+                let credentials = format!("{}:{}", username, password.expose());
+                format!("Basic {}", STANDARD.encode(credentials))
+            });
+        let agent = http_agent(&options.http())?;
+
+        let samples = Counter::new(
+            "ffmpeg_exporter_remote_write_samples_total",
+            "Total number of samples accepted by the remote write endpoint",
+        )?;
+        let failures = Counter::new(
+            "ffmpeg_exporter_remote_write_failures_total",
+            "Total number of remote writes that failed and were dropped",
+        )?;
+        let last_success = Gauge::new(
+            "ffmpeg_exporter_remote_write_last_success_timestamp_seconds",
+            "Unix time of the last successful remote write",
+        )?;
+        registry.register(Box::new(samples.clone()))?;
+        registry.register(Box::new(failures.clone()))?;
+        registry.register(Box::new(last_success.clone()))?;
+
+        Ok(Self {
+            registry: registry.clone(),
+            url,
+            interval: options.interval(),
+            authorization,
+            agent,
+            samples,
+            failures,
+            last_success,
+        })
+    }
+
+    /// Writes on a thread of its own, the requests block, until `running` is
+    /// cleared
+    pub fn spawn(self, running: Arc<AtomicBool>) -> std::thread::JoinHandle<()> {
+        info!(
+            "Writing metrics to {} every {} seconds",
+            redact(&self.url),
+            self.interval.as_secs()
+        );
+        std::thread::spawn(move || {
+            while running.load(Ordering::SeqCst) {
+                let started = Instant::now();
+                self.write();
+                let mut remaining = self.interval.saturating_sub(started.elapsed());
+                while !remaining.is_zero() && running.load(Ordering::SeqCst) {
+                    let step = remaining.min(STOP_POLL_INTERVAL);
+                    std::thread::sleep(step);
+                    remaining -= step;
+                }
+            }
+            debug!("Remote write to {} stopped", redact(&self.url));
+        })
+    }
+
+    fn write(&self) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let timeseries = timeseries(&self.registry.gather(), now.as_millis() as i64);
+        let count = timeseries.len();
+        let body = match body(timeseries) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Failed to compress the remote write request: {}", e);
+                self.failures.inc();
+                return;
+            }
+        };
+
+        let mut request = self
+            .agent
+            .post(&self.url)
+            .timeout(self.interval)
+            .set("Content-Encoding", "snappy")
+            .set("Content-Type", "application/x-protobuf")
+            .set(
+                "User-Agent",
+                concat!("ffmpeg_exporter/", env!("CARGO_PKG_VERSION")),
+            )
+            .set("X-Prometheus-Remote-Write-Version", "0.1.0");
+        if let Some(authorization) = &self.authorization {
+            request = request.set("Authorization", authorization);
+        }
+        match request.send_bytes(&body) {
+            Ok(_) => {
+                debug!("Wrote {} samples in {} bytes", count, body.len());
+                self.samples.inc_by(count as f64);
+                self.last_success.set(now.as_secs_f64());
+            }
+            Err(ureq::Error::Status(status, response)) => {
+                let message = response.into_string().unwrap_or_default();
+                warn!(
+                    "Remote write rejected with status {}: {}",
+                    status,
+                    message.trim()
+                );
+                self.failures.inc();
+            }
+            Err(e) => {
+                warn!("Remote write failed: {}", e);
+                self.failures.inc();
+            }
+        }
+    }
+}
+
+/// Encodes a write request, snappy compressed in the block format as remote
+/// write requires
+fn body(timeseries: Vec<TimeSeries>) -> Result<Vec<u8>, snap::Error> {
+    snap::raw::Encoder::new().compress_vec(&WriteRequest { timeseries }.encode_to_vec())
+}
+
+/// Flattens metric families into one series per sample, named and labeled
+/// like the samples of a scrape
+pub fn timeseries(families: &[MetricFamily], timestamp: i64) -> Vec<TimeSeries> {
+    families
+        .iter()
+        .flat_map(|family| {
+            samples(family).map(move |sample| TimeSeries {
+                labels: series_labels(
+                    &format!("{}{}", family.get_name(), sample.suffix),
+                    sample.labels,
+                    sample.extra,
+                ),
+                samples: vec![Sample {
+                    value: sample.value,
+                    timestamp,
+                }],
+            })
+        })
+        .collect()
+}
+
+fn series_labels(name: &str, labels: &[LabelPair], extra: Option<(&str, String)>) -> Vec<Label> {
+    let mut series_labels: Vec<Label> = std::iter::once(("__name__", name.to_string()))
+        .chain(
+            labels
+                .iter()
+                .map(|label| (label.get_name(), label.get_value().to_string())),
+        )
+        .chain(extra)
+        .map(|(name, value)| Label {
+            name: name.to_string(),
+            value,
+        })
+        .collect();
+    series_labels.sort_by(|a, b| a.name.cmp(&b.name));
+    series_labels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{CounterVec, Histogram, HistogramOpts, Opts};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_timeseries() {
+        let registry = Registry::new_custom(
+            Some("acme".to_string()),
+            Some(HashMap::from([("probe_id".to_string(), "vm".to_string())])),
+        )
+        .unwrap();
+        let errors = CounterVec::new(
+            Opts::new("ffmpeg_errors_total", "Errors"),
+            &["severity", "category"],
+        )
+        .unwrap();
+        let connect = Histogram::with_opts(
+            HistogramOpts::new("ffmpeg_connect_duration_seconds", "Connect")
+                .buckets(vec![0.5, 1.0]),
+        )
+        .unwrap();
+        registry.register(Box::new(errors.clone())).unwrap();
+        registry.register(Box::new(connect.clone())).unwrap();
+        errors.with_label_values(&["error", "cc_error"]).inc_by(3.0);
+        connect.observe(0.7);
+
+        let series = timeseries(&registry.gather(), 1_767_225_600_000);
+        let names = |series: &TimeSeries| {
+            series
+                .labels
+                .iter()
+                .map(|label| format!("{}={}", label.name, label.value))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        let found: Vec<(String, f64)> = series
+            .iter()
+            .map(|series| (names(series), series.samples[0].value))
+            .collect();
+        let expected = [
+            (
+                "__name__=acme_ffmpeg_connect_duration_seconds_bucket,le=0.5,probe_id=vm",
+                0.0,
+            ),
+            (
+                "__name__=acme_ffmpeg_connect_duration_seconds_bucket,le=1,probe_id=vm",
+                1.0,
+            ),
+            (
+                "__name__=acme_ffmpeg_connect_duration_seconds_bucket,le=+Inf,probe_id=vm",
+                1.0,
+            ),
+            (
+                "__name__=acme_ffmpeg_connect_duration_seconds_sum,probe_id=vm",
+                0.7,
+            ),
+            (
+                "__name__=acme_ffmpeg_connect_duration_seconds_count,probe_id=vm",
+                1.0,
+            ),
+            (
+                "__name__=acme_ffmpeg_errors_total,category=cc_error,probe_id=vm,severity=error",
+                3.0,
+            ),
+        ];
+        assert_eq!(
+            found,
+            expected.map(|(labels, value)| (labels.to_string(), value))
+        );
+        assert!(
+            series
+                .iter()
+                .all(|series| series.samples[0].timestamp == 1_767_225_600_000)
+        );
+
+        // The request survives encoding and compression
+        let request = WriteRequest {
+            timeseries: series.clone(),
+        };
+        let decompressed = snap::raw::Decoder::new()
+            .decompress_vec(&body(series).unwrap())
+            .unwrap();
+        assert_eq!(
+            WriteRequest::decode(decompressed.as_slice()).unwrap(),
+            request
+        );
+    }
+}
@@ -0,0 +1,162 @@
+// metrics/runtime.rs
+
+use anyhow::Result;
+use prometheus::{CounterVec, Gauge, Opts, Registry};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+const UPDATE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Metrics about the exporter's own tokio runtime, used to diagnose
+/// sluggishness when many streams are monitored. Every monitor runs on the
+/// blocking pool, whose thread counts tokio only reports when built with
+/// `--cfg tokio_unstable`, as for tokio-console.
+#[derive(Clone)]
+pub struct RuntimeMetrics {
+    pub workers: Gauge,
+    pub alive_tasks: Gauge,
+    pub global_queue_depth: Gauge,
+    pub worker_busy_seconds: CounterVec,
+    pub worker_parks: CounterVec,
+    #[cfg(tokio_unstable)]
+    pub blocking_threads: Gauge,
+    #[cfg(tokio_unstable)]
+    pub idle_blocking_threads: Gauge,
+    #[cfg(tokio_unstable)]
+    pub blocking_queue_depth: Gauge,
+}
+
+impl RuntimeMetrics {
+    pub fn new(registry: &Registry) -> Result<Self> {
+        let workers = Gauge::new(
+            "ffmpeg_exporter_tokio_workers",
+            "Number of tokio runtime worker threads",
+        )?;
+
+        let alive_tasks = Gauge::new(
+            "ffmpeg_exporter_tokio_alive_tasks",
+            "Number of alive tasks in the tokio runtime",
+        )?;
+
+        let global_queue_depth = Gauge::new(
+            "ffmpeg_exporter_tokio_global_queue_depth",
+            "Number of tasks waiting in the tokio runtime's global queue",
+        )?;
+
+        let worker_busy_seconds = CounterVec::new(
+            Opts::new(
+                "ffmpeg_exporter_tokio_worker_busy_seconds_total",
+                "Total time a tokio worker thread has been busy",
+            ),
+            &["worker"],
+        )?;
+
+        let worker_parks = CounterVec::new(
+            Opts::new(
+                "ffmpeg_exporter_tokio_worker_parks_total",
+                "Total number of times a tokio worker thread parked",
+            ),
+            &["worker"],
+        )?;
+
+        #[cfg(tokio_unstable)]
+        let blocking_threads = Gauge::new(
+            "ffmpeg_exporter_tokio_blocking_threads",
+            "Number of threads of the tokio blocking pool, busy or idle",
+        )?;
+
+        #[cfg(tokio_unstable)]
+        let idle_blocking_threads = Gauge::new(
+            "ffmpeg_exporter_tokio_idle_blocking_threads",
+            "Number of idle threads of the tokio blocking pool",
+        )?;
+
+        #[cfg(tokio_unstable)]
+        let blocking_queue_depth = Gauge::new(
+            "ffmpeg_exporter_tokio_blocking_queue_depth",
+            "Number of tasks waiting for a thread of the tokio blocking pool",
+        )?;
+
+        registry.register(Box::new(workers.clone()))?;
+        registry.register(Box::new(alive_tasks.clone()))?;
+        registry.register(Box::new(global_queue_depth.clone()))?;
+        registry.register(Box::new(worker_busy_seconds.clone()))?;
+        registry.register(Box::new(worker_parks.clone()))?;
+        #[cfg(tokio_unstable)]
+        {
+            registry.register(Box::new(blocking_threads.clone()))?;
+            registry.register(Box::new(idle_blocking_threads.clone()))?;
+            registry.register(Box::new(blocking_queue_depth.clone()))?;
+        }
+
+        Ok(Self {
+            workers,
+            alive_tasks,
+            global_queue_depth,
+            worker_busy_seconds,
+            worker_parks,
+            #[cfg(tokio_unstable)]
+            blocking_threads,
+            #[cfg(tokio_unstable)]
+            idle_blocking_threads,
+            #[cfg(tokio_unstable)]
+            blocking_queue_depth,
+        })
+    }
+
+    pub fn update(&self, handle: &Handle) {
+        let runtime = handle.metrics();
+        self.workers.set(runtime.num_workers() as f64);
+        self.alive_tasks.set(runtime.num_alive_tasks() as f64);
+        self.global_queue_depth
+            .set(runtime.global_queue_depth() as f64);
+        #[cfg(tokio_unstable)]
+        {
+            self.blocking_threads
+                .set(runtime.num_blocking_threads() as f64);
+            self.idle_blocking_threads
+                .set(runtime.num_idle_blocking_threads() as f64);
+            self.blocking_queue_depth
+                .set(runtime.blocking_queue_depth() as f64);
+        }
+
+        for worker in 0..runtime.num_workers() {
+            let label = worker.to_string();
+
+            // The runtime reports cumulative values, counters only move forward by the delta
+            let busy = self.worker_busy_seconds.with_label_values(&[&label]);
+            let total_busy = runtime.worker_total_busy_duration(worker).as_secs_f64();
+            busy.inc_by((total_busy - busy.get()).max(0.0));
+
+            let parks = self.worker_parks.with_label_values(&[&label]);
+            let total_parks = runtime.worker_park_count(worker) as f64;
+            parks.inc_by((total_parks - parks.get()).max(0.0));
+        }
+    }
+
+    /// Periodically samples the current runtime until the task is aborted.
+    pub fn spawn_updater(self) -> tokio::task::JoinHandle<()> {
+        let handle = Handle::current();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(UPDATE_INTERVAL);
+            loop {
+                interval.tick().await;
+                self.update(&handle);
+            }
+        })
+    }
+}
+
+/// Registers the standard `process_*` metrics of the exporter itself: CPU
+/// time, memory, file descriptors, threads and start time. The ffprobe and
+/// ffmpeg children are not included. They are read from `/proc`, so other
+/// platforms go without.
+pub fn register_process_metrics(registry: &Registry) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    registry.register(Box::new(
+        prometheus::process_collector::ProcessCollector::for_self(),
+    ))?;
+    #[cfg(not(target_os = "linux"))]
+    let _ = registry;
+    Ok(())
+}
@@ -1,32 +1,674 @@
-use crate::metrics::AppState;
-use axum::{Router, extract::State, routing::get};
-use prometheus::{Encoder, TextEncoder};
-use std::net::SocketAddr;
+use crate::config::{AuthOptions, StreamConfig};
+use crate::events::Event;
+use crate::metrics::{AppState, openmetrics};
+use crate::stream::{self, StreamReport};
+use axum::{
+    Json, Router,
+    extract::{Path, Query, Request, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    middleware::{self, Next},
+    response::{
+        Html, IntoResponse, Response,
+        sse::{self, KeepAlive, Sse},
+    },
+    routing::{get, post},
+};
+use axum_server::tls_rustls::RustlsConfig;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::{DateTime, Utc};
+use prometheus::{Encoder, Registry, TextEncoder};
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use tokio::net::TcpListener;
-use tracing::info;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use tower_http::compression::CompressionLayer;
+use tracing::{info, warn};
+
+/// How often the TLS certificate and key are checked for changes
+const TLS_RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+/// PEM certificate chain and private key to serve HTTPS with
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl TlsFiles {
+    fn modified(&self) -> Option<(SystemTime, SystemTime)> {
+        let modified = |path: &PathBuf| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        Some((modified(&self.cert)?, modified(&self.key)?))
+    }
+
+    /// Reloads the certificate and key whenever one of them changes, e.g.
+    /// after a renewal by cert-manager or certbot, keeping the previous ones
+    /// while the new files do not load
+    fn watch(self, config: RustlsConfig) {
+        tokio::spawn(async move {
+            let mut loaded = self.modified();
+            loop {
+                tokio::time::sleep(TLS_RELOAD_INTERVAL).await;
+                let modified = self.modified();
+                if modified.is_none() || modified == loaded {
+                    continue;
+                }
+                match config.reload_from_pem_file(&self.cert, &self.key).await {
+                    Ok(()) => {
+                        info!("Reloaded TLS certificate {}", self.cert.display());
+                        loaded = modified;
+                    }
+                    Err(e) => warn!(
+                        "Failed to reload TLS certificate {}: {}",
+                        self.cert.display(),
+                        e
+                    ),
+                }
+            }
+        });
+    }
+}
+
+/// The `Authorization` header values the HTTP endpoints accept
+struct Credentials {
+    basic: Option<String>,
+    bearer: Option<String>,
+}
+
+impl Credentials {
+    fn new(auth: &AuthOptions) -> Option<Self> {
+        let basic =
+            auth.username
+                .as_ref()
+                .zip(auth.password.as_ref())
+                .map(|(username, password)| {
+                    let pair = format!("{}:{}", username, password.expose());
+                    format!("Basic {}", BASE64.encode(pair))
+                });
+        let bearer = auth
+            .token
+            .as_ref()
+            .map(|token| format!("Bearer {}", token.expose()));
+        (basic.is_some() || bearer.is_some()).then_some(Self { basic, bearer })
+    }
+
+    fn accepts(&self, authorization: &[u8]) -> bool {
+        [&self.basic, &self.bearer]
+            .into_iter()
+            .flatten()
+            .any(|expected| constant_time_eq(expected.as_bytes(), authorization))
+    }
+
+    /// The challenge of a 401 response, offering basic auth if configured
+    fn challenge(&self) -> HeaderValue {
+        HeaderValue::from_static(match self.basic {
+            Some(_) => "Basic realm=\"ffmpeg_exporter\", charset=\"UTF-8\"",
+            None => "Bearer",
+        })
+    }
+}
+
+/// Compares without leaking the length of the matching prefix through timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+async fn require_auth(
+    State(credentials): State<Arc<Credentials>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .is_some_and(|value| credentials.accepts(value.as_bytes()));
+    if !authorized {
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, credentials.challenge())],
+            "Unauthorized",
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+/// Encodes in OpenMetrics if the `Accept` header prefers it, the classic
+/// text format otherwise
+fn encode(registry: &Registry, headers: &HeaderMap) -> Response {
+    let openmetrics = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(openmetrics::preferred);
+    if openmetrics {
+        let body = openmetrics::encode(&registry.gather());
+        return ([(header::CONTENT_TYPE, openmetrics::CONTENT_TYPE)], body).into_response();
+    }
 
-async fn metrics_handler(State(state): State<AppState>) -> String {
     let encoder = TextEncoder::new();
     let mut buffer = Vec::new();
-    encoder
-        .encode(&state.registry.gather(), &mut buffer)
-        .unwrap();
-    String::from_utf8(buffer).unwrap()
+    encoder.encode(&registry.gather(), &mut buffer).unwrap();
+    String::from_utf8(buffer).unwrap().into_response()
+}
+
+/// The status page, polling the status API and subscribing to the events
+const INDEX_HTML: &str = include_str!("../resources/index.html");
+
+async fn index_handler() -> Html<&'static str> {
+    Html(INDEX_HTML)
+}
+
+async fn metrics_handler(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    encode(&state.registry, &headers)
+}
+
+/// Liveness: answers as long as the HTTP server runs
+async fn healthz_handler() -> &'static str {
+    "ok"
+}
+
+/// Readiness: ffprobe was found and at least one stream delivers data
+async fn readyz_handler(State(state): State<AppState>) -> (StatusCode, String) {
+    if !state.ffprobe_found {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "ffprobe not found".to_string(),
+        );
+    }
+    match state.streams.healthy() {
+        0 => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "No stream delivers data".to_string(),
+        ),
+        healthy => (StatusCode::OK, format!("{} streams healthy", healthy)),
+    }
+}
+
+#[derive(Deserialize)]
+struct ProbeParams {
+    target: String,
+}
+
+async fn probe_handler(
+    State(state): State<AppState>,
+    Query(params): Query<ProbeParams>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let Ok(_permit) = state.probes.try_acquire() else {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("{} probes are running already", state.options.max_probes),
+        ));
+    };
+    let registry = stream::probe(&params.target, &state.resource, state.options.clone())
+        .await
+        .map_err(|e| {
+            warn!("Probe of {} failed: {:#}", params.target, e);
+            (StatusCode::BAD_REQUEST, format!("{:#}", e))
+        })?;
+    Ok(encode(&registry, &headers))
+}
+
+#[derive(Deserialize)]
+struct EventParams {
+    stream: Option<String>,
+}
+
+async fn events_handler(
+    State(state): State<AppState>,
+    Query(params): Query<EventParams>,
+) -> Json<Vec<Event>> {
+    Json(state.events.list(params.stream.as_deref()))
+}
+
+/// Pushes events as they are recorded, named by their kind. Subscribers
+/// falling behind by more than the buffer miss the oldest events.
+async fn event_stream_handler(
+    State(state): State<AppState>,
+    Query(params): Query<EventParams>,
+) -> Sse<impl Stream<Item = Result<sse::Event, Infallible>>> {
+    let events = BroadcastStream::new(state.events.subscribe()).filter_map(move |event| {
+        let event = event.ok()?;
+        if params.stream.as_ref().is_some_and(|s| *s != event.stream) {
+            return None;
+        }
+        sse::Event::default()
+            .event(event.kind)
+            .json_data(&event)
+            .ok()
+            .map(Ok)
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+async fn snapshot_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Response, (StatusCode, String)> {
+    if state.options.snapshot_interval == 0 {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "Snapshots are disabled, see --snapshot-interval".to_string(),
+        ));
+    }
+    let snapshots = state.streams.snapshots(&name).ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            format!("Stream {} is not monitored", name),
+        )
+    })?;
+    let snapshot = snapshots.latest().ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            format!("No snapshot of stream {} yet", name),
+        )
+    })?;
+    let captured = DateTime::<Utc>::from(snapshot.captured)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string();
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/jpeg".to_string()),
+            (header::CACHE_CONTROL, "no-cache".to_string()),
+            (header::LAST_MODIFIED, captured),
+        ],
+        snapshot.jpeg.to_vec(),
+    )
+        .into_response())
+}
+
+/// Runs ffprobe against the input of a stream and returns its description
+/// of the format, streams and programs, or of the error as 502
+async fn deep_probe_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Response, (StatusCode, String)> {
+    let probe = state.streams.deep_probe(&name).ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            format!("Stream {} is not monitored", name),
+        )
+    })?;
+    let (status, output) = tokio::task::spawn_blocking(move || probe.run())
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| {
+            warn!("Deep probe of {} failed: {:#}", name, e);
+            (StatusCode::BAD_GATEWAY, format!("{:#}", e))
+        })?;
+    let code = if status.success() {
+        StatusCode::OK
+    } else {
+        StatusCode::BAD_GATEWAY
+    };
+    Ok((code, [(header::CONTENT_TYPE, "application/json")], output).into_response())
+}
+
+async fn pause_stream_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    set_paused(&state, &name, true)
+}
+
+async fn resume_stream_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    set_paused(&state, &name, false)
+}
+
+fn set_paused(
+    state: &AppState,
+    name: &str,
+    paused: bool,
+) -> Result<StatusCode, (StatusCode, String)> {
+    if state.streams.set_paused(name, paused) {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err((
+            StatusCode::NOT_FOUND,
+            format!("Stream {} is not monitored", name),
+        ))
+    }
+}
+
+async fn list_streams_handler(State(state): State<AppState>) -> Json<Vec<StreamReport>> {
+    Json(state.streams.reports())
+}
+
+async fn stream_status_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<StreamReport>, (StatusCode, String)> {
+    state.streams.report(&name).map(Json).ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            format!("Stream {} is not monitored", name),
+        )
+    })
+}
+
+async fn add_stream_handler(
+    State(state): State<AppState>,
+    Json(stream): Json<StreamConfig>,
+) -> Result<(StatusCode, Json<StreamConfig>), (StatusCode, String)> {
+    state.streams.add(stream.clone()).map_err(|e| {
+        if e.is::<stream::AlreadyMonitored>() {
+            return (StatusCode::CONFLICT, e.to_string());
+        }
+        warn!("Failed to add stream {}: {:#}", stream.name, e);
+        (StatusCode::BAD_REQUEST, format!("{:#}", e))
+    })?;
+    Ok((StatusCode::CREATED, Json(stream)))
+}
+
+async fn remove_stream_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    match state.streams.remove(&name).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err((
+            StatusCode::NOT_FOUND,
+            format!("Stream {} is not monitored", name),
+        )),
+        Err(e) => {
+            warn!("Failed to remove stream {}: {:#}", name, e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)))
+        }
+    }
+}
+
+/// Binds to `address`, which may be a hostname, an IP address or an IPv6
+/// address in brackets, and tries each address it resolves to in turn.
+/// Serves HTTPS with `tls`, plain HTTP otherwise. All endpoints but the
+/// health checks require the credentials of `auth`, if there are any.
+/// All endpoints, behind authentication except for the health checks
+fn router(state: AppState, auth: &AuthOptions) -> Router {
+    let mut app = Router::new()
+        .route("/", get(index_handler))
+        // Expositions with per-PID and per-rendition series get large, so
+        // they are compressed for clients sending Accept-Encoding: gzip
+        .route(
+            "/metrics",
+            get(metrics_handler).layer(CompressionLayer::new()),
+        )
+        .route("/probe", get(probe_handler).layer(CompressionLayer::new()))
+        .route("/events", get(events_handler))
+        .route("/snapshot/{name}", get(snapshot_handler))
+        .route("/api/v1/events/stream", get(event_stream_handler))
+        .route(
+            "/api/v1/streams",
+            get(list_streams_handler).post(add_stream_handler),
+        )
+        .route(
+            "/api/v1/streams/{name}",
+            get(stream_status_handler).delete(remove_stream_handler),
+        )
+        .route("/api/v1/streams/{name}/probe", post(deep_probe_handler))
+        .route("/api/v1/streams/{name}/pause", post(pause_stream_handler))
+        .route("/api/v1/streams/{name}/resume", post(resume_stream_handler));
+    if let Some(credentials) = Credentials::new(auth) {
+        info!("HTTP endpoints require authentication");
+        app = app.route_layer(middleware::from_fn_with_state(
+            Arc::new(credentials),
+            require_auth,
+        ));
+    }
+    // Kubelet probes carry no credentials
+    app.route("/healthz", get(healthz_handler))
+        .route("/readyz", get(readyz_handler))
+        .with_state(state)
 }
 
 pub async fn run_server(
     state: AppState,
+    address: &str,
     port: u16,
+    tls: Option<TlsFiles>,
+    auth: &AuthOptions,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let app = Router::new()
-        .route("/metrics", get(metrics_handler))
-        .with_state(state);
+    let app = router(state, auth);
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let host = address
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(address);
+    let listener = TcpListener::bind((host, port)).await.map_err(|e| {
+        format!(
+            "Failed to bind metrics server to {}:{}: {}",
+            address, port, e
+        )
+    })?;
 
-    info!("Metrics server listening on http://{}", addr);
-    let listener = TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    let local_addr = listener.local_addr()?;
+    let Some(tls) = tls else {
+        info!("Metrics server listening on http://{}", local_addr);
+        axum::serve(listener, app).await?;
+        return Ok(());
+    };
+
+    let config = RustlsConfig::from_pem_file(&tls.cert, &tls.key)
+        .await
+        .map_err(|e| {
+            format!(
+                "Failed to load TLS certificate {} and key {}: {}",
+                tls.cert.display(),
+                tls.key.display(),
+                e
+            )
+        })?;
+    tls.watch(config.clone());
+    info!("Metrics server listening on https://{}", local_addr);
+    axum_server::from_tcp_rustls(listener.into_std()?, config)
+        .serve(app.into_make_service())
+        .await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Args, MonitorOptions, ResourceLabels, Secret};
+    use crate::events::EventLog;
+    use axum::body::Body;
+    use clap::Parser;
+    use std::collections::BTreeSet;
+    use tower::ServiceExt;
+
+    fn state(ffprobe_found: bool) -> AppState {
+        state_with(ffprobe_found, "/nonexistent/ffprobe")
+    }
+
+    fn state_with(ffprobe_found: bool, ffprobe_path: &str) -> AppState {
+        let args = Args::parse_from([
+            "ffmpeg_exporter",
+            "--input",
+            "unused",
+            "--ffprobe-path",
+            ffprobe_path,
+        ]);
+        let (state, _) = AppState::new(
+            &ResourceLabels::from_args(&args),
+            MonitorOptions::from(&args),
+            BTreeSet::new(),
+            EventLog::new(0),
+            ffprobe_found,
+        )
+        .unwrap();
+        state
+    }
+
+    fn auth() -> AuthOptions {
+        AuthOptions {
+            username: Some("prometheus".to_string()),
+            password: Some(Secret::new("hunter2")),
+            token: Some(Secret::new("s3cret")),
+            ..Default::default()
+        }
+    }
+
+    async fn get(app: &Router, uri: &str, authorization: Option<&str>) -> Response {
+        let mut request = Request::get(uri);
+        if let Some(authorization) = authorization {
+            request = request.header(header::AUTHORIZATION, authorization);
+        }
+        app.clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn body(response: Response) -> String {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    fn stream_config(name: &str) -> StreamConfig {
+        StreamConfig {
+            name: name.to_string(),
+            input: "srt://localhost:9000".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_authentication() {
+        let app = router(state(true), &auth());
+
+        let response = get(&app, "/metrics", None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers()[header::WWW_AUTHENTICATE],
+            "Basic realm=\"ffmpeg_exporter\", charset=\"UTF-8\""
+        );
+
+        let wrong_basic = format!("Basic {}", BASE64.encode("prometheus:hunter3"));
+        for authorization in [wrong_basic.as_str(), "Bearer s3cre", "s3cret"] {
+            let response = get(&app, "/metrics", Some(authorization)).await;
+            assert_eq!(
+                response.status(),
+                StatusCode::UNAUTHORIZED,
+                "{}",
+                authorization
+            );
+        }
+
+        let basic = format!("Basic {}", BASE64.encode("prometheus:hunter2"));
+        for authorization in [basic.as_str(), "Bearer s3cret"] {
+            let response = get(&app, "/metrics", Some(authorization)).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", authorization);
+            let response = get(&app, "/api/v1/streams", Some(authorization)).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", authorization);
+        }
+
+        // Kubelet probes carry no credentials
+        let response = get(&app, "/healthz", None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = get(&app, "/readyz", None).await;
+        assert_ne!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_bearer_challenge() {
+        let auth = AuthOptions {
+            token: Some(Secret::new("s3cret")),
+            ..Default::default()
+        };
+        let app = router(state(true), &auth);
+
+        let response = get(&app, "/metrics", None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Bearer");
+        let response = get(&app, "/metrics", Some("Bearer s3cret")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_readyz_without_ffprobe() {
+        let app = router(state(false), &AuthOptions::default());
+        let response = get(&app, "/readyz", None).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body(response).await, "ffprobe not found");
+    }
+
+    #[tokio::test]
+    async fn test_readyz_without_healthy_stream() {
+        let state = state(true);
+        let app = router(state.clone(), &AuthOptions::default());
+        let response = get(&app, "/readyz", None).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        // ffprobe cannot be spawned, so the stream never delivers data
+        state.streams.add(stream_config("sport")).unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(state.streams.healthy(), 0);
+        let response = get(&app, "/readyz", None).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body(response).await, "No stream delivers data");
+
+        state.streams.stop_all();
+        state.streams.join_all().await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_readyz_with_delivering_stream() {
+        let ffprobe = stream::fake_ffprobe(stream::DELIVERING_FFPROBE);
+        let state = state_with(true, &ffprobe);
+        let app = router(state.clone(), &AuthOptions::default());
+
+        state.streams.add(stream_config("sport")).unwrap();
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        while state.streams.healthy() == 0 {
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "stream never got healthy"
+            );
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        let response = get(&app, "/readyz", None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body(response).await, "1 streams healthy");
+
+        state.streams.stop_all();
+        state.streams.join_all().await.unwrap();
+        assert_eq!(state.streams.healthy(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_add_stream_conflict() {
+        let state = state(true);
+        let app = router(state.clone(), &AuthOptions::default());
+        let post = || {
+            let body = serde_json::to_string(&stream_config("sport")).unwrap();
+            let request = Request::post("/api/v1/streams")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            app.clone().oneshot(request)
+        };
+
+        assert_eq!(post().await.unwrap().status(), StatusCode::CREATED);
+        let response = post().await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(body(response).await, "Stream sport is already monitored");
+
+        state.streams.stop_all();
+        state.streams.join_all().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_without_authentication() {
+        let app = router(state(true), &AuthOptions::default());
+        let response = get(&app, "/metrics", None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,928 @@
+// stream/analysis.rs
+
+use crate::config::MonitorOptions;
+use crate::metrics::StreamMetrics;
+use crate::stream::lines::{Fields, for_each_line};
+use crate::stream::process::{spawn, terminate};
+use crate::stream::records::FrameRecord;
+use crate::stream::test_pattern::{
+    Sustained, TONE_MEASURES, TONE_SAMPLE_RATE, bit_depth, is_bars, is_tone,
+};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+
+/// Escapes `value` for use as a filter option inside a filtergraph. Option
+/// values are unescaped twice, once when the graph is split into filters and
+/// once when the filter arguments are split into options.
+fn escape_filter_value(value: &str) -> String {
+    let mut option = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '\\' | '\'' | ':') {
+            option.push('\\');
+        }
+        option.push(c);
+    }
+    format!("'{}'", option.replace('\'', "'\\''"))
+}
+
+/// The enabled filters of the default video stream
+fn video_filters(options: &MonitorOptions) -> Vec<String> {
+    let mut video_filters = Vec::new();
+    if options.blackdetect {
+        video_filters.push(format!(
+            "blackdetect=pix_th={}",
+            options.black_pixel_threshold
+        ));
+    }
+    if options.freezedetect {
+        video_filters.push(format!(
+            "freezedetect=n={}dB:d={}",
+            options.freeze_noise, options.freeze_duration
+        ));
+    }
+    if options.signalstats {
+        // Also measures the share of pixels outside the broadcast range
+        video_filters.push("signalstats=stat=brng".to_string());
+    } else if options.test_pattern_detect {
+        video_filters.push("signalstats".to_string());
+    }
+    video_filters
+}
+
+/// Whether the default video stream gets an output
+fn has_video_output(options: &MonitorOptions) -> bool {
+    !video_filters(options).is_empty() || options.reference.is_some()
+}
+
+/// The enabled filters of every audio track
+fn audio_filters(options: &MonitorOptions) -> Vec<String> {
+    let mut audio_filters = Vec::new();
+    if options.silencedetect {
+        audio_filters.push(format!(
+            "silencedetect=n={}dB:d={}",
+            options.silence_noise, options.silence_duration
+        ));
+    }
+    let mut measures = Vec::new();
+    if options.astats {
+        measures.extend(["Peak_level", "RMS_level", "Peak_count"]);
+    }
+    if options.test_pattern_detect {
+        audio_filters.insert(0, format!("aresample={}", TONE_SAMPLE_RATE));
+        for measure in TONE_MEASURES {
+            if !measures.contains(&measure) {
+                measures.push(measure);
+            }
+        }
+    }
+    if !measures.is_empty() {
+        // Statistics of every single frame, limited to what is used
+        audio_filters.push(format!(
+            "astats=metadata=1:reset=1:measure_perchannel={}:measure_overall=none",
+            measures.join("+")
+        ));
+    }
+    audio_filters
+}
+
+/// The lavfi filtergraph decoding `input` through the enabled content
+/// analysis filters, `None` if no analysis is enabled. Every analyzed stream
+/// gets its own `outN` pad, which ffprobe reports as stream index N: the
+/// default video stream first, then the `audio_tracks` in order. With a
+/// `reference` the video is compared with the default video stream of the
+/// reference, both starting at timestamp zero.
+pub fn analysis_graph(
+    input: &str,
+    format: Option<&str>,
+    reference: Option<&str>,
+    options: &MonitorOptions,
+    audio_tracks: &[[String; 3]],
+) -> Option<String> {
+    let mut streams = Vec::new();
+    let mut chains = Vec::new();
+    let mut video_filters = video_filters(options);
+    if reference.is_some() {
+        video_filters
+            .push("setpts=PTS-STARTPTS[main];[main][ref0]psnr[psnr];[psnr][ref1]ssim".to_string());
+    }
+    if !video_filters.is_empty() {
+        streams.push("dv".to_string());
+        chains.push(video_filters.join(","));
+    }
+    let audio_filters = audio_filters(options);
+    if !audio_filters.is_empty() {
+        for [stream_id, ..] in audio_tracks {
+            streams.push(stream_id.clone());
+            chains.push(audio_filters.join(","));
+        }
+    }
+    if streams.is_empty() {
+        return None;
+    }
+
+    let mut graph = format!("movie={}", escape_filter_value(input));
+    if let Some(format) = format {
+        graph.push_str(&format!(":f={}", format));
+    }
+    graph.push_str(&format!(":s={}", streams.join("+")));
+    for i in 0..streams.len() {
+        graph.push_str(&format!("[in{}]", i));
+    }
+    if let Some(reference) = reference {
+        graph.push_str(&format!(
+            ";movie={}:s=dv,setpts=PTS-STARTPTS,split[ref0][ref1]",
+            escape_filter_value(reference)
+        ));
+    }
+    for (i, chain) in chains.iter().enumerate() {
+        graph.push_str(&format!(";[in{}]{}[out{}]", i, chain, i));
+    }
+    Some(graph)
+}
+
+/// Arguments of the ffprobe session printing the frames of the analysis graph
+pub fn analysis_args(graph: &str) -> Vec<String> {
+    [
+        "-v",
+        "error",
+        "-f",
+        "lavfi",
+        "-i",
+        graph,
+        "-show_frames",
+        "-of",
+        "csv=nokey=0",
+    ]
+    .into_iter()
+    .map(str::to_string)
+    .collect()
+}
+
+/// How a detection changed with the latest frame
+#[derive(Debug, Default, PartialEq)]
+struct Detection {
+    /// The condition lasts at least the minimum duration
+    active: bool,
+    /// The condition just reached the minimum duration
+    started: bool,
+    /// Seconds of the condition not counted before
+    seconds: f64,
+}
+
+/// Follows the start and end tags a detection filter attaches to frames.
+/// Conditions shorter than `min_duration` are ignored.
+struct Detector {
+    start_tag: &'static str,
+    end_tag: &'static str,
+    min_duration: f64,
+    start: Option<f64>,
+    /// Up to where the current condition was counted, once active
+    counted: Option<f64>,
+}
+
+impl Detector {
+    fn new(start_tag: &'static str, end_tag: &'static str, min_duration: f64) -> Self {
+        Self {
+            start_tag,
+            end_tag,
+            min_duration,
+            start: None,
+            counted: None,
+        }
+    }
+
+    fn observe(&mut self, fields: &Fields, pts: f64) -> Detection {
+        let tag = |key| fields.get(key).and_then(|v| v.parse::<f64>().ok());
+        if let Some(start) = tag(self.start_tag) {
+            self.start = Some(start);
+            self.counted = None;
+        }
+        let Some(start) = self.start else {
+            return Detection::default();
+        };
+
+        let end = tag(self.end_tag);
+        let now = end.unwrap_or(pts);
+        let mut detection = Detection::default();
+        if now - start >= self.min_duration {
+            detection.started = self.counted.is_none();
+            detection.seconds = (now - self.counted.unwrap_or(start)).max(0.0);
+            detection.active = end.is_none();
+            self.counted = Some(now);
+        }
+        if end.is_some() {
+            self.start = None;
+            self.counted = None;
+        }
+        detection
+    }
+}
+
+/// PSNR of identical frames is infinite; it is capped so averages and
+/// histogram sums stay finite.
+const MAX_PSNR: f64 = 100.0;
+
+/// Averages the reference comparison scores over each second of stream time
+#[derive(Default)]
+struct QualityWindow {
+    second: Option<i64>,
+    psnr: (f64, u32),
+    ssim: (f64, u32),
+}
+
+impl QualityWindow {
+    /// Adds the scores of a frame. Returns the average PSNR and SSIM of the
+    /// previous second once a frame of a new second arrives.
+    fn observe(
+        &mut self,
+        pts: f64,
+        psnr: Option<f64>,
+        ssim: Option<f64>,
+    ) -> Option<(Option<f64>, Option<f64>)> {
+        let average = |(sum, count): (f64, u32)| (count > 0).then(|| sum / f64::from(count));
+        let second = pts.floor() as i64;
+        let mut completed = None;
+        if self.second != Some(second) {
+            if self.second.is_some() {
+                completed = Some((average(self.psnr), average(self.ssim)));
+            }
+            *self = Self {
+                second: Some(second),
+                ..Self::default()
+            };
+        }
+        if let Some(psnr) = psnr {
+            self.psnr.0 += psnr.min(MAX_PSNR);
+            self.psnr.1 += 1;
+        }
+        if let Some(ssim) = ssim {
+            self.ssim.0 += ssim;
+            self.ssim.1 += 1;
+        }
+        completed
+    }
+}
+
+/// Legal luma range of limited range video in 8 bit levels
+const LEGAL_LUMA: (f64, f64) = (16.0, 235.0);
+
+/// Exports the `signalstats` levels of a video frame. Full range video has no
+/// illegal levels.
+fn observe_signal_levels(frame: &FrameRecord, fields: &Fields, metrics: &StreamMetrics) {
+    let stat = |name: &str| {
+        fields
+            .get(&format!("tag:lavfi.signalstats.{}", name))
+            .and_then(|v| v.parse::<f64>().ok())
+    };
+    for (plane, prefix) in [("y", "Y"), ("u", "U"), ("v", "V")] {
+        for (name, suffix) in [("min", "MIN"), ("max", "MAX"), ("avg", "AVG")] {
+            if let Some(level) = stat(&format!("{}{}", prefix, suffix)) {
+                metrics
+                    .video_signal_level
+                    .with_label_values(&[plane, name])
+                    .set(level);
+            }
+        }
+    }
+    if let Some(ratio) = stat("BRNG") {
+        metrics
+            .video_out_of_range_ratio
+            .with_label_values(&[])
+            .set(ratio);
+    }
+
+    if frame.color_range == "pc" {
+        return;
+    }
+    let scale = f64::from(1u32 << (bit_depth(fields.get("pix_fmt").unwrap_or("")) - 8));
+    if stat("YMIN").is_some_and(|min| min < LEGAL_LUMA.0 * scale) {
+        metrics
+            .video_illegal_frames
+            .with_label_values(&["super_black"])
+            .inc();
+    }
+    if stat("YMAX").is_some_and(|max| max > LEGAL_LUMA.1 * scale) {
+        metrics
+            .video_illegal_frames
+            .with_label_values(&["super_white"])
+            .inc();
+    }
+}
+
+/// Full scale in dBFS. Integer samples reach it only on the negative side,
+/// so the level is compared with a small tolerance.
+const CLIP_LEVEL: f64 = -0.01;
+
+/// The analysis of one audio track
+struct AudioAnalysis {
+    /// Stream id, track and language
+    labels: [String; 3],
+    silence: Option<Detector>,
+    /// Whether the levels are exported
+    levels: bool,
+    tone: Option<Sustained>,
+    /// The track carried line-up tone for the test pattern duration
+    tone_detected: bool,
+}
+
+impl AudioAnalysis {
+    /// Exports the per channel `astats` tags of a frame. Channels are
+    /// numbered from 1 like the tags.
+    fn observe_levels(&self, fields: &Fields, metrics: &StreamMetrics) {
+        if !self.levels {
+            return;
+        }
+        for part in fields.as_slice() {
+            let Some((channel, rms)) = part
+                .strip_prefix("tag:lavfi.astats.")
+                .and_then(|tag| tag.split_once(".RMS_level="))
+            else {
+                continue;
+            };
+            let [stream_id, track, language] = &self.labels;
+            let labels = [stream_id.as_str(), track, language, channel];
+            let stat = |name| {
+                fields
+                    .get(&format!("tag:lavfi.astats.{}.{}", channel, name))
+                    .and_then(|v| v.parse::<f64>().ok())
+            };
+            if let Ok(rms) = rms.parse::<f64>() {
+                metrics.audio_rms_level.with_label_values(&labels).set(rms);
+            }
+            let Some(peak) = stat("Peak_level") else {
+                continue;
+            };
+            metrics
+                .audio_peak_level
+                .with_label_values(&labels)
+                .set(peak);
+            if peak >= CLIP_LEVEL
+                && let Some(count) = stat("Peak_count")
+            {
+                metrics
+                    .audio_clipped_samples
+                    .with_label_values(&labels)
+                    .inc_by(count);
+            }
+        }
+    }
+
+    fn observe_silence(&mut self, fields: &Fields, pts: f64, metrics: &StreamMetrics) {
+        let Some(detector) = &mut self.silence else {
+            return;
+        };
+        let [stream_id, track, language] = &self.labels;
+        let labels = [stream_id.as_str(), track, language];
+        let silence = detector.observe(fields, pts);
+        if silence.started {
+            metrics
+                .audio_silence_events
+                .with_label_values(&labels)
+                .inc();
+        }
+        metrics
+            .audio_silence_seconds
+            .with_label_values(&labels)
+            .inc_by(silence.seconds);
+        metrics
+            .audio_silent
+            .with_label_values(&labels)
+            .set(if silence.active { 1.0 } else { 0.0 });
+    }
+}
+
+/// Turns the detection tags of the analysis frames into metrics
+pub struct AnalysisTracker {
+    black: Detector,
+    freeze: Detector,
+    /// The audio tracks by output index
+    audio: HashMap<String, AudioAnalysis>,
+    /// Set when the video is compared with a reference
+    quality: Option<QualityWindow>,
+    bars: Option<Sustained>,
+    signal_levels: bool,
+    /// Whether the video is frozen, shared with the monitor restarting
+    /// ffprobe on freezes
+    frozen: Arc<AtomicBool>,
+}
+
+impl AnalysisTracker {
+    /// `audio_tracks` must be the tracks the graph was built with
+    pub fn new(options: &MonitorOptions, audio_tracks: &[[String; 3]]) -> Self {
+        let mut audio = HashMap::new();
+        if !audio_filters(options).is_empty() {
+            let first = usize::from(has_video_output(options));
+            for (i, labels) in audio_tracks.iter().enumerate() {
+                // silencedetect only tags silences once they lasted its duration
+                let silence = options.silencedetect.then(|| {
+                    Detector::new("tag:lavfi.silence_start", "tag:lavfi.silence_end", 0.0)
+                });
+                let analysis = AudioAnalysis {
+                    labels: labels.clone(),
+                    silence,
+                    levels: options.astats,
+                    tone: options
+                        .test_pattern_detect
+                        .then(|| Sustained::new(options.test_pattern_duration)),
+                    tone_detected: false,
+                };
+                audio.insert((first + i).to_string(), analysis);
+            }
+        }
+        Self {
+            audio,
+            quality: options.reference.as_ref().map(|_| QualityWindow::default()),
+            signal_levels: options.signalstats,
+            bars: options
+                .test_pattern_detect
+                .then(|| Sustained::new(options.test_pattern_duration)),
+            black: Detector::new(
+                "tag:lavfi.black_start",
+                "tag:lavfi.black_end",
+                options.black_duration,
+            ),
+            // freezedetect only tags freezes once they lasted its duration
+            freeze: Detector::new(
+                "tag:lavfi.freezedetect.freeze_start",
+                "tag:lavfi.freezedetect.freeze_end",
+                0.0,
+            ),
+            frozen: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn observe(&mut self, fields: &Fields, metrics: &StreamMetrics) {
+        let Some(frame) = FrameRecord::parse(fields) else {
+            return;
+        };
+        let Some(pts) = frame.pts_time else {
+            return;
+        };
+        if frame.media_type == "video" {
+            if self.signal_levels {
+                observe_signal_levels(&frame, fields, metrics);
+            }
+            if let Some(bars) = &mut self.bars {
+                let detected = bars.observe(pts, is_bars(fields));
+                set_test_pattern(metrics, "bars", detected);
+            }
+
+            let black = self.black.observe(fields, pts);
+            if black.started {
+                metrics.video_black_events.inc();
+            }
+            metrics.video_black_seconds.inc_by(black.seconds);
+            metrics
+                .video_black
+                .with_label_values(&[])
+                .set(if black.active { 1.0 } else { 0.0 });
+
+            let freeze = self.freeze.observe(fields, pts);
+            metrics.video_frozen_seconds.inc_by(freeze.seconds);
+            metrics
+                .video_frozen
+                .with_label_values(&[])
+                .set(if freeze.active { 1.0 } else { 0.0 });
+            self.frozen.store(freeze.active, Ordering::Relaxed);
+
+            if let Some(quality) = &mut self.quality {
+                let tag = |key| fields.get(key).and_then(|v| v.parse::<f64>().ok());
+                let psnr = tag("tag:lavfi.psnr.psnr_avg");
+                if let Some((psnr, ssim)) = quality.observe(pts, psnr, tag("tag:lavfi.ssim.All")) {
+                    if let Some(psnr) = psnr {
+                        metrics.quality_psnr.with_label_values(&[]).set(psnr);
+                        metrics.quality_psnr_per_second.observe(psnr);
+                    }
+                    if let Some(ssim) = ssim {
+                        metrics.quality_ssim.with_label_values(&[]).set(ssim);
+                        metrics.quality_ssim_per_second.observe(ssim);
+                    }
+                }
+            }
+        } else if let Some(audio) = self.audio.get_mut(frame.stream_id) {
+            audio.observe_levels(fields, metrics);
+            audio.observe_silence(fields, pts, metrics);
+            if let Some(tone) = &mut audio.tone {
+                audio.tone_detected = tone.observe(pts, is_tone(fields));
+                // Line-up tone is often only on the first track, so any track counts
+                let detected = self.audio.values().any(|audio| audio.tone_detected);
+                set_test_pattern(metrics, "tone", detected);
+            }
+        }
+    }
+}
+
+fn set_test_pattern(metrics: &StreamMetrics, pattern: &str, detected: bool) {
+    metrics
+        .test_pattern_detected
+        .with_label_values(&[pattern])
+        .set(if detected { 1.0 } else { 0.0 });
+}
+
+/// A running analysis ffprobe. It is terminated when dropped, so it never
+/// outlives the monitoring session it belongs to.
+pub struct AnalysisSession {
+    child: Child,
+    /// Time the ffprobe gets to exit before it is killed
+    grace: Duration,
+    frozen: Arc<AtomicBool>,
+}
+
+impl AnalysisSession {
+    pub fn start(
+        mut cmd: Command,
+        metrics: StreamMetrics,
+        mut tracker: AnalysisTracker,
+        grace: Duration,
+    ) -> Result<Self> {
+        let mut child = spawn(&mut cmd).context("Failed to spawn analysis ffprobe process")?;
+        let stdout = child.stdout.take().context("Failed to capture stdout")?;
+        let stderr = child.stderr.take().context("Failed to capture stderr")?;
+        let frozen = tracker.frozen.clone();
+
+        metrics.video_black.with_label_values(&[]).set(0.0);
+        metrics.video_frozen.with_label_values(&[]).set(0.0);
+        metrics.audio_silent.reset();
+        metrics.test_pattern_detected.reset();
+        metrics.audio_rms_level.reset();
+        metrics.audio_peak_level.reset();
+        thread::spawn(move || {
+            if let Err(e) = process_analysis(BufReader::new(stdout), &metrics, &mut tracker) {
+                warn!(?e, "Error processing analysis output");
+            }
+        });
+        // Only errors are printed, e.g. when the input cannot be opened twice
+        thread::spawn(move || {
+            let _ = for_each_line(BufReader::new(stderr), |line| {
+                warn!("Analysis ffprobe: {}", line);
+                Ok(())
+            });
+        });
+        Ok(Self {
+            child,
+            grace,
+            frozen,
+        })
+    }
+}
+
+impl AnalysisSession {
+    /// Whether freezedetect currently reports frozen video
+    pub fn frozen(&self) -> bool {
+        self.frozen.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for AnalysisSession {
+    fn drop(&mut self) {
+        terminate(&mut self.child, self.grace);
+    }
+}
+
+/// Reads the frames of the analysis session until its ffprobe exits
+fn process_analysis(
+    reader: impl BufRead,
+    metrics: &StreamMetrics,
+    tracker: &mut AnalysisTracker,
+) -> Result<()> {
+    for_each_line(reader, |line| {
+        let fields = Fields::split(line);
+        if fields.as_slice()[0] == "frame" {
+            tracker.observe(&fields, metrics);
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Args;
+    use clap::Parser;
+    use prometheus::Registry;
+    use prometheus::core::Collector;
+    use std::collections::HashMap;
+
+    fn options(args: &[&str]) -> MonitorOptions {
+        let mut argv = vec!["ffmpeg_exporter", "--input", "unused"];
+        argv.extend_from_slice(args);
+        MonitorOptions::from(&Args::parse_from(argv))
+    }
+
+    fn tracks() -> Vec<[String; 3]> {
+        [["1", "0", "eng"], ["2", "1", "und"]]
+            .map(|labels| labels.map(str::to_string))
+            .to_vec()
+    }
+
+    #[test]
+    fn test_analysis_graph() {
+        assert_eq!(
+            analysis_graph("in.ts", None, None, &options(&[]), &[]),
+            None
+        );
+
+        let black = options(&["--blackdetect", "--black-pixel-threshold", "0.2"]);
+        assert_eq!(
+            analysis_graph("srt://host:9000", None, None, &black, &[]).unwrap(),
+            r"movie='srt\://host\:9000':s=dv[in0];[in0]blackdetect=pix_th=0.2[out0]"
+        );
+        assert_eq!(
+            analysis_graph("/dev/video0", Some("v4l2"), None, &black, &[]).unwrap(),
+            r"movie='/dev/video0':f=v4l2:s=dv[in0];[in0]blackdetect=pix_th=0.2[out0]"
+        );
+        assert_eq!(escape_filter_value("it's"), r"'it\'\''s'");
+
+        let freeze = options(&["--freezedetect", "--freeze-noise", "-50"]);
+        assert_eq!(
+            analysis_graph("in.ts", None, None, &freeze, &[]).unwrap(),
+            "movie='in.ts':s=dv[in0];[in0]freezedetect=n=-50dB:d=2[out0]"
+        );
+
+        let silence = options(&["--blackdetect", "--silencedetect"]);
+        assert_eq!(
+            analysis_graph("in.ts", None, None, &silence, &tracks()).unwrap(),
+            "movie='in.ts':s=dv+1+2[in0][in1][in2];\
+             [in0]blackdetect=pix_th=0.1[out0];\
+             [in1]silencedetect=n=-60dB:d=2[out1];\
+             [in2]silencedetect=n=-60dB:d=2[out2]"
+        );
+        let levels = options(&["--astats"]);
+        assert_eq!(
+            analysis_graph("in.ts", None, None, &levels, &tracks()[..1]).unwrap(),
+            "movie='in.ts':s=1[in0];[in0]astats=metadata=1:reset=1:\
+             measure_perchannel=Peak_level+RMS_level+Peak_count:measure_overall=none[out0]"
+        );
+        let freeze = options(&["--freezedetect"]);
+        assert_eq!(
+            analysis_graph("out.ts", None, Some("src:1.ts"), &freeze, &[]).unwrap(),
+            "movie='out.ts':s=dv[in0];\
+             movie='src\\:1.ts':s=dv,setpts=PTS-STARTPTS,split[ref0][ref1];\
+             [in0]freezedetect=n=-60dB:d=2,setpts=PTS-STARTPTS[main];\
+             [main][ref0]psnr[psnr];[psnr][ref1]ssim[out0]"
+        );
+        let pattern = options(&["--test-pattern-detect", "--astats"]);
+        assert_eq!(
+            analysis_graph("in.ts", None, None, &pattern, &tracks()[..1]).unwrap(),
+            "movie='in.ts':s=dv+1[in0][in1];[in0]signalstats[out0];\
+             [in1]aresample=48000,astats=metadata=1:reset=1:\
+             measure_perchannel=Peak_level+RMS_level+Peak_count+Crest_factor+Zero_crossings_rate:\
+             measure_overall=none[out1]"
+        );
+        // Audio analysis needs the probed audio tracks
+        let silence = options(&["--silencedetect"]);
+        assert_eq!(analysis_graph("in.ts", None, None, &silence, &[]), None);
+    }
+
+    #[test]
+    fn test_black_detection() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let mut tracker = AnalysisTracker::new(&options(&["--blackdetect"]), &[]);
+        let mut frame = |line: &str| tracker.observe(&Fields::split(line), &metrics);
+
+        frame("frame,media_type=video,stream_index=0,pts_time=10.0");
+        // A one second fade through black is ignored
+        frame("frame,media_type=video,stream_index=0,pts_time=11.0,tag:lavfi.black_start=11");
+        frame("frame,media_type=video,stream_index=0,pts_time=12.0,tag:lavfi.black_end=12");
+        assert_eq!(metrics.video_black_events.get(), 0.0);
+
+        frame("frame,media_type=video,stream_index=0,pts_time=20.0,tag:lavfi.black_start=20");
+        frame("frame,media_type=video,stream_index=0,pts_time=21.0");
+        assert_eq!(metrics.video_black.with_label_values(&[]).get(), 0.0);
+        frame("frame,media_type=video,stream_index=0,pts_time=23.0");
+        assert_eq!(metrics.video_black.with_label_values(&[]).get(), 1.0);
+        assert_eq!(metrics.video_black_events.get(), 1.0);
+        assert_eq!(metrics.video_black_seconds.get(), 3.0);
+
+        frame("frame,media_type=video,stream_index=0,pts_time=24.5,tag:lavfi.black_end=24.5");
+        assert_eq!(metrics.video_black.with_label_values(&[]).get(), 0.0);
+        assert_eq!(metrics.video_black_events.get(), 1.0);
+        assert_eq!(metrics.video_black_seconds.get(), 4.5);
+    }
+
+    #[test]
+    fn test_freeze_detection() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let mut tracker = AnalysisTracker::new(&options(&["--freezedetect"]), &[]);
+        let frozen = tracker.frozen.clone();
+        let mut frame = |line: &str| tracker.observe(&Fields::split(line), &metrics);
+
+        // The start is reported once the freeze lasted the detection duration
+        frame(
+            "frame,media_type=video,stream_index=0,pts_time=12.0,\
+             tag:lavfi.freezedetect.freeze_start=10,tag:lavfi.freezedetect.freeze_duration=2",
+        );
+        assert_eq!(metrics.video_frozen.with_label_values(&[]).get(), 1.0);
+        assert_eq!(metrics.video_frozen_seconds.get(), 2.0);
+        assert!(frozen.load(Ordering::Relaxed));
+        frame("frame,media_type=video,stream_index=0,pts_time=13.0");
+        assert_eq!(metrics.video_frozen_seconds.get(), 3.0);
+
+        frame(
+            "frame,media_type=video,stream_index=0,pts_time=13.5,tag:lavfi.freezedetect.freeze_end=13.5",
+        );
+        assert_eq!(metrics.video_frozen.with_label_values(&[]).get(), 0.0);
+        assert_eq!(metrics.video_frozen_seconds.get(), 3.5);
+        assert!(!frozen.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_silence_detection() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        // Without video analysis the audio tracks start at output 0
+        let mut tracker = AnalysisTracker::new(&options(&["--silencedetect"]), &tracks());
+        let mut frame = |line: &str| tracker.observe(&Fields::split(line), &metrics);
+
+        frame("frame,media_type=audio,stream_index=1,pts_time=5.0,tag:lavfi.silence_start=3");
+        frame("frame,media_type=audio,stream_index=0,pts_time=5.0");
+        let silent = |labels: [&str; 3]| metrics.audio_silent.with_label_values(&labels).get();
+        assert_eq!(silent(["2", "1", "und"]), 1.0);
+        assert_eq!(silent(["1", "0", "eng"]), 0.0);
+
+        frame(
+            "frame,media_type=audio,stream_index=1,pts_time=6.0,\
+             tag:lavfi.silence_end=6,tag:lavfi.silence_duration=3",
+        );
+        assert_eq!(silent(["2", "1", "und"]), 0.0);
+        let labels = ["2", "1", "und"];
+        assert_eq!(
+            metrics
+                .audio_silence_events
+                .with_label_values(&labels)
+                .get(),
+            1.0
+        );
+        assert_eq!(
+            metrics
+                .audio_silence_seconds
+                .with_label_values(&labels)
+                .get(),
+            3.0
+        );
+    }
+
+    #[test]
+    fn test_audio_levels() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let mut tracker = AnalysisTracker::new(&options(&["--astats"]), &tracks());
+        let mut frame = |line: &str| tracker.observe(&Fields::split(line), &metrics);
+
+        frame(
+            "frame,media_type=audio,stream_index=0,pts_time=1.0,\
+             tag:lavfi.astats.1.Peak_level=-6.02,tag:lavfi.astats.1.RMS_level=-20.5,\
+             tag:lavfi.astats.1.Peak_count=2,\
+             tag:lavfi.astats.2.Peak_level=0.000000,tag:lavfi.astats.2.RMS_level=-3.1,\
+             tag:lavfi.astats.2.Peak_count=7",
+        );
+        frame(
+            "frame,media_type=audio,stream_index=0,pts_time=1.1,\
+             tag:lavfi.astats.1.Peak_level=-0.000265,tag:lavfi.astats.1.RMS_level=-9.0,\
+             tag:lavfi.astats.1.Peak_count=1",
+        );
+
+        let level = |gauge: &prometheus::GaugeVec, channel| {
+            gauge.with_label_values(&["1", "0", "eng", channel]).get()
+        };
+        assert_eq!(level(&metrics.audio_rms_level, "1"), -9.0);
+        assert_eq!(level(&metrics.audio_peak_level, "1"), -0.000265);
+        assert_eq!(level(&metrics.audio_rms_level, "2"), -3.1);
+        assert_eq!(level(&metrics.audio_peak_level, "2"), 0.0);
+        let clipped = |channel| {
+            metrics
+                .audio_clipped_samples
+                .with_label_values(&["1", "0", "eng", channel])
+                .get()
+        };
+        // Peaks below full scale are not clipping
+        assert_eq!(clipped("1"), 1.0);
+        assert_eq!(clipped("2"), 7.0);
+    }
+
+    #[test]
+    fn test_reference_quality() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let mut options = options(&[]);
+        options.reference = Some("src.ts".to_string());
+        let mut tracker = AnalysisTracker::new(&options, &[]);
+        let mut frame = |line: &str| tracker.observe(&Fields::split(line), &metrics);
+
+        frame(
+            "frame,media_type=video,stream_index=0,pts_time=0.0,tag:lavfi.psnr.psnr_avg=40.0,tag:lavfi.ssim.All=0.98",
+        );
+        frame(
+            "frame,media_type=video,stream_index=0,pts_time=0.5,tag:lavfi.psnr.psnr_avg=inf,tag:lavfi.ssim.All=1.0",
+        );
+        // Scores are exported once the second is complete
+        assert_eq!(metrics.quality_psnr_per_second.get_sample_count(), 0);
+        frame(
+            "frame,media_type=video,stream_index=0,pts_time=1.0,tag:lavfi.psnr.psnr_avg=30.0,tag:lavfi.ssim.All=0.9",
+        );
+        assert_eq!(metrics.quality_psnr.with_label_values(&[]).get(), 70.0);
+        assert_eq!(metrics.quality_ssim.with_label_values(&[]).get(), 0.99);
+        assert_eq!(metrics.quality_psnr_per_second.get_sample_count(), 1);
+        assert_eq!(metrics.quality_ssim_per_second.get_sample_sum(), 0.99);
+    }
+
+    #[test]
+    fn test_test_pattern_detection() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let pattern = options(&["--test-pattern-detect", "--test-pattern-duration", "2"]);
+        let mut tracker = AnalysisTracker::new(&pattern, &tracks());
+        let mut frame = |line: &str| tracker.observe(&Fields::split(line), &metrics);
+        let detected = |pattern| {
+            metrics
+                .test_pattern_detected
+                .with_label_values(&[pattern])
+                .get()
+        };
+
+        let bars = "frame,media_type=video,stream_index=0,pix_fmt=yuv420p,\
+                    tag:lavfi.signalstats.YDIF=0.0,tag:lavfi.signalstats.SATAVG=62.3,\
+                    tag:lavfi.signalstats.YLOW=35,tag:lavfi.signalstats.YHIGH=180";
+        frame(&format!("{},pts_time=10.0", bars));
+        frame(&format!("{},pts_time=11.0", bars));
+        assert_eq!(detected("bars"), 0.0);
+        frame(&format!("{},pts_time=12.0", bars));
+        assert_eq!(detected("bars"), 1.0);
+
+        // Tone on the second track only
+        let tone = "frame,media_type=audio,stream_index=2,tag:lavfi.astats.1.RMS_level=-18.0,\
+                    tag:lavfi.astats.1.Crest_factor=1.414,\
+                    tag:lavfi.astats.1.Zero_crossings_rate=0.0417";
+        frame(&format!("{},pts_time=10.0", tone));
+        frame(&format!("{},pts_time=12.0", tone));
+        frame("frame,media_type=audio,stream_index=1,pts_time=12.0");
+        assert_eq!(detected("tone"), 1.0);
+        // Levels are only exported with --astats
+        assert!(metrics.audio_rms_level.collect()[0].get_metric().is_empty());
+    }
+
+    #[test]
+    fn test_signal_levels() {
+        let signal = options(&["--signalstats", "--test-pattern-detect"]);
+        assert_eq!(
+            analysis_graph("in.ts", None, None, &signal, &[]).unwrap(),
+            "movie='in.ts':s=dv[in0];[in0]signalstats=stat=brng[out0]"
+        );
+
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let mut tracker = AnalysisTracker::new(&signal, &[]);
+        let mut frame = |line: &str| tracker.observe(&Fields::split(line), &metrics);
+        let levels = "tag:lavfi.signalstats.YMIN=4,tag:lavfi.signalstats.YMAX=250,\
+                      tag:lavfi.signalstats.YAVG=101.5,tag:lavfi.signalstats.UMIN=16,\
+                      tag:lavfi.signalstats.BRNG=0.012";
+        frame(&format!(
+            "frame,media_type=video,stream_index=0,pts_time=1.0,pix_fmt=yuv420p,color_range=tv,{}",
+            levels
+        ));
+        // Full range video uses the whole range legally
+        frame(&format!(
+            "frame,media_type=video,stream_index=0,pts_time=1.04,pix_fmt=yuvj420p,color_range=pc,{}",
+            levels
+        ));
+        // 10 bit legal range starts at 64
+        frame(
+            "frame,media_type=video,stream_index=0,pts_time=1.08,pix_fmt=yuv420p10le,\
+             color_range=tv,tag:lavfi.signalstats.YMIN=64,tag:lavfi.signalstats.YMAX=940",
+        );
+
+        let level = |plane, stat| {
+            metrics
+                .video_signal_level
+                .with_label_values(&[plane, stat])
+                .get()
+        };
+        assert_eq!(level("y", "min"), 64.0);
+        assert_eq!(level("y", "avg"), 101.5);
+        assert_eq!(level("u", "min"), 16.0);
+        assert_eq!(
+            metrics
+                .video_out_of_range_ratio
+                .with_label_values(&[])
+                .get(),
+            0.012
+        );
+        let illegal = |level| {
+            metrics
+                .video_illegal_frames
+                .with_label_values(&[level])
+                .get()
+        };
+        assert_eq!(illegal("super_black"), 1.0);
+        assert_eq!(illegal("super_white"), 1.0);
+    }
+}
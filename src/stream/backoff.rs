@@ -0,0 +1,108 @@
+// stream/backoff.rs
+
+use std::hash::{BuildHasher, Hasher, RandomState};
+use std::time::{Duration, Instant};
+
+/// Delays between restarts of ffprobe. Every consecutive failure doubles the
+/// delay up to `max`; a session that delivers data starts over at `initial`.
+/// The jitter spreads the restarts of streams that failed together, e.g.
+/// when their origin went down. It also keeps count of how long the stream
+/// has been down for the retry budget.
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    /// Fraction of the delay it may be shortened or lengthened by
+    jitter: f64,
+    current: Duration,
+    /// Restarts since the last session that delivered data
+    retries: u64,
+    /// When the stream stopped delivering data, `None` while it does
+    down_since: Option<Instant>,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration, jitter: f64) -> Self {
+        Self {
+            initial,
+            max: max.max(initial),
+            jitter: jitter.clamp(0.0, 1.0),
+            current: initial,
+            retries: 0,
+            down_since: Some(Instant::now()),
+        }
+    }
+
+    /// The delay before the next attempt, doubling the one after it
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(self.max);
+        self.retries += 1;
+        jittered(delay, self.jitter, random_unit())
+    }
+
+    /// Starts over after a session delivered data
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+        self.retries = 0;
+        self.down_since = None;
+    }
+
+    pub fn retries(&self) -> u64 {
+        self.retries
+    }
+
+    /// How long the stream has been without data, counting from the end of
+    /// the last session that delivered any or from the start
+    pub fn downtime(&mut self) -> Duration {
+        self.down_since.get_or_insert_with(Instant::now).elapsed()
+    }
+}
+
+/// `delay` moved by up to `jitter` of itself, `sample` in `[0, 1)` picking
+/// where
+fn jittered(delay: Duration, jitter: f64, sample: f64) -> Duration {
+    delay.mul_f64(1.0 + jitter * (2.0 * sample - 1.0))
+}
+
+/// A random number in `[0, 1)` from the randomly keyed std hasher, which is
+/// plenty for spreading restarts
+fn random_unit() -> f64 {
+    let bits = RandomState::new().build_hasher().finish() >> 11;
+    bits as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let secs = Duration::from_secs;
+        let mut backoff = Backoff::new(secs(10), secs(60), 0.0);
+        let delays: Vec<_> = (0..5).map(|_| backoff.next_delay()).collect();
+        assert_eq!(delays, [secs(10), secs(20), secs(40), secs(60), secs(60)]);
+        assert_eq!(backoff.retries(), 5);
+        backoff.reset();
+        assert_eq!(backoff.retries(), 0);
+        assert_eq!(backoff.next_delay(), secs(10));
+
+        // A maximum below the initial delay keeps the delay constant
+        let mut backoff = Backoff::new(secs(10), secs(5), 0.0);
+        assert_eq!(backoff.next_delay(), secs(10));
+        assert_eq!(backoff.next_delay(), secs(10));
+    }
+
+    #[test]
+    fn test_jitter() {
+        let delay = Duration::from_secs(10);
+        assert_eq!(jittered(delay, 0.2, 0.0), Duration::from_secs(8));
+        assert_eq!(jittered(delay, 0.2, 0.5), delay);
+        assert_eq!(jittered(delay, 0.0, 0.9), delay);
+
+        let mut backoff = Backoff::new(delay, delay, 0.5);
+        for _ in 0..100 {
+            let delay = backoff.next_delay();
+            assert!(delay >= Duration::from_secs(5) && delay < Duration::from_secs(15));
+        }
+    }
+}
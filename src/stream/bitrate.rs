@@ -0,0 +1,158 @@
+// stream/bitrate.rs
+
+use std::collections::{HashMap, VecDeque};
+
+/// Window of the instantaneous bitrate, in seconds of stream time
+pub const INSTANT_WINDOW: f64 = 1.0;
+/// Window of the averaged bitrate, in seconds of stream time
+pub const AVERAGE_WINDOW: f64 = 10.0;
+
+/// Sums the packet sizes within the last `span` seconds of stream time.
+pub struct BitrateWindow {
+    span: f64,
+    samples: VecDeque<(f64, f64)>,
+    bits: f64,
+    full: bool,
+}
+
+impl BitrateWindow {
+    pub fn new(span: f64) -> Self {
+        Self {
+            span,
+            samples: VecDeque::new(),
+            bits: 0.0,
+            full: false,
+        }
+    }
+
+    pub fn push(&mut self, time: f64, bits: f64) {
+        // Timestamps going backwards or jumping ahead mean a discontinuity
+        if let Some(&(last, _)) = self.samples.back()
+            && (time < last || time - last > self.span)
+        {
+            self.samples.clear();
+            self.bits = 0.0;
+            self.full = false;
+        }
+
+        self.samples.push_back((time, bits));
+        self.bits += bits;
+        while let Some(&(first, bits)) = self.samples.front()
+            && first <= time - self.span
+        {
+            self.samples.pop_front();
+            self.bits -= bits;
+            self.full = true;
+        }
+    }
+
+    /// The bitrate in kbit/s, estimated from the covered time while the
+    /// window is still filling up
+    pub fn kbits_per_sec(&self) -> Option<f64> {
+        if self.full {
+            return Some(self.bits / self.span / 1000.0);
+        }
+
+        // Each packet lasts until the next one, so the newest is not covered yet
+        let &(first, _) = self.samples.front()?;
+        let &(last, last_bits) = self.samples.back()?;
+        let covered = last - first;
+        (covered > 0.0).then(|| (self.bits - last_bits) / covered / 1000.0)
+    }
+}
+
+/// Instantaneous and averaged bitrate of each elementary stream
+#[derive(Default)]
+pub struct BitrateTracker {
+    streams: HashMap<String, [BitrateWindow; 2]>,
+}
+
+impl BitrateTracker {
+    /// Adds a packet of `stream_id` and returns the instantaneous and the
+    /// averaged bitrate in kbit/s
+    pub fn push(&mut self, stream_id: &str, time: f64, bytes: f64) -> (Option<f64>, Option<f64>) {
+        if !self.streams.contains_key(stream_id) {
+            self.streams.insert(
+                stream_id.to_string(),
+                [
+                    BitrateWindow::new(INSTANT_WINDOW),
+                    BitrateWindow::new(AVERAGE_WINDOW),
+                ],
+            );
+        }
+        let [instant, average] = self.streams.get_mut(stream_id).unwrap();
+        instant.push(time, bytes * 8.0);
+        average.push(time, bytes * 8.0);
+        (instant.kbits_per_sec(), average.kbits_per_sec())
+    }
+
+    /// The averaged bitrate of all elementary streams together in kbit/s
+    pub fn total_average(&self) -> Option<f64> {
+        self.streams
+            .values()
+            .filter_map(|[_, average]| average.kbits_per_sec())
+            .reduce(|total, kbits| total + kbits)
+    }
+}
+
+/// How far the measured bitrate is off the declared one in percent; positive
+/// when the stream needs more than declared
+pub fn declared_deviation(measured_kbits: f64, declared_bits: u64) -> Option<f64> {
+    let declared_kbits = declared_bits as f64 / 1000.0;
+    (declared_kbits > 0.0).then(|| (measured_kbits - declared_kbits) / declared_kbits * 100.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_bitrate() {
+        // 25 packets of 5000 bytes per second is 1000 kbit/s
+        let mut tracker = BitrateTracker::default();
+        let mut rates = (None, None);
+        for i in 0..25 * 12 {
+            rates = tracker.push("0", i as f64 * 0.04, 5000.0);
+        }
+        let (instant, average) = rates;
+        assert!((instant.unwrap() - 1000.0).abs() < 1e-6);
+        assert!((average.unwrap() - 1000.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_declared_deviation() {
+        let mut tracker = BitrateTracker::default();
+        assert_eq!(tracker.total_average(), None);
+        for i in 0..25 * 12 {
+            tracker.push("0", i as f64 * 0.04, 5000.0);
+            tracker.push("1", i as f64 * 0.04, 625.0);
+        }
+        let total = tracker.total_average().unwrap();
+        assert!((total - 1125.0).abs() < 1e-6);
+        // An over-declared rung
+        assert!((declared_deviation(total, 1_500_000).unwrap() + 25.0).abs() < 1e-6);
+        assert_eq!(declared_deviation(total, 0), None);
+    }
+
+    #[test]
+    fn test_window_filling_up() {
+        let mut window = BitrateWindow::new(AVERAGE_WINDOW);
+        window.push(0.0, 8000.0);
+        assert_eq!(window.kbits_per_sec(), None);
+        window.push(0.5, 8000.0);
+        window.push(1.0, 8000.0);
+        assert_eq!(window.kbits_per_sec(), Some(16.0));
+    }
+
+    #[test]
+    fn test_discontinuity_resets_window() {
+        let mut window = BitrateWindow::new(INSTANT_WINDOW);
+        for i in 0..50 {
+            window.push(i as f64 * 0.04, 100_000.0);
+        }
+        // Timestamps restart, e.g. after an encoder restart
+        window.push(0.0, 1000.0);
+        window.push(0.5, 1000.0);
+        assert_eq!(window.kbits_per_sec(), Some(2.0));
+    }
+}
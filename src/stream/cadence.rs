@@ -0,0 +1,214 @@
+// stream/cadence.rs
+
+use crate::metrics::StreamMetrics;
+use crate::stream::records::FrameRecord;
+use std::collections::{HashMap, VecDeque};
+
+/// Number of recent frame intervals the measured cadence is taken from
+const MEASURE_HISTORY: usize = 32;
+/// Intervals needed before a measured cadence is trusted
+const MIN_HISTORY: usize = 8;
+/// A gap longer than this factor of the frame duration means lost frames
+const DROP_FACTOR: f64 = 1.5;
+/// Frames whose timestamps are closer than this are repeats
+const DUPLICATE_EPSILON: f64 = 0.001;
+/// Larger jumps are discontinuities rather than drops
+const MAX_GAP: f64 = 10.0;
+/// Frame rates above this are timebases rather than real rates, e.g. `90000/1`
+const MAX_FRAME_RATE: f64 = 300.0;
+
+/// Parses an ffprobe rational frame rate such as `30000/1001`
+pub fn parse_frame_rate(value: &str) -> Option<f64> {
+    let (num, den) = value.split_once('/')?;
+    let rate = num.parse::<f64>().ok()? / den.parse::<f64>().ok()?;
+    (rate.is_finite() && rate > 0.0 && rate <= MAX_FRAME_RATE).then_some(rate)
+}
+
+#[derive(Default)]
+struct CadenceState {
+    last_pts: Option<f64>,
+    intervals: VecDeque<f64>,
+}
+
+impl CadenceState {
+    /// The shortest recent interval; drops only ever lengthen intervals
+    fn measured_duration(&self) -> Option<f64> {
+        if self.intervals.len() < MIN_HISTORY {
+            return None;
+        }
+        self.intervals.iter().copied().reduce(f64::min)
+    }
+}
+
+/// Estimates dropped and duplicated video frames and the frame interval
+/// jitter by comparing the timestamp gaps between frames with the expected
+/// frame duration, measured from recent frames and bounded by the stream's
+/// `r_frame_rate`.
+#[derive(Default)]
+pub struct CadenceTracker {
+    frame_rates: HashMap<String, f64>,
+    streams: HashMap<String, CadenceState>,
+}
+
+impl CadenceTracker {
+    pub fn set_frame_rate(&mut self, stream_id: &str, rate: f64) {
+        self.frame_rates.insert(stream_id.to_string(), rate);
+    }
+
+    pub fn observe(&mut self, frame: &FrameRecord, metrics: &StreamMetrics) {
+        if frame.media_type != "video" {
+            return;
+        }
+        let Some(pts) = frame.pts_time else {
+            return;
+        };
+        if !self.streams.contains_key(frame.stream_id) {
+            self.streams
+                .insert(frame.stream_id.to_string(), CadenceState::default());
+        }
+        let state = self.streams.get_mut(frame.stream_id).unwrap();
+        let Some(last) = state.last_pts.replace(pts) else {
+            return;
+        };
+
+        let interval = pts - last;
+        if interval.abs() < DUPLICATE_EPSILON {
+            metrics
+                .frames_duplicated
+                .with_label_values(&[frame.stream_id])
+                .inc();
+            return;
+        }
+        if !(0.0..MAX_GAP).contains(&interval) {
+            // Timestamp discontinuity, start measuring over
+            state.intervals.clear();
+            return;
+        }
+
+        // r_frame_rate can be twice the real rate of field-coded content, so
+        // it only guards the measured cadence against jitter
+        let nominal = self.frame_rates.get(frame.stream_id).map(|rate| 1.0 / rate);
+        let expected = state
+            .measured_duration()
+            .map(|measured| nominal.map_or(measured, |nominal| nominal.max(measured)));
+        if let Some(expected) = expected {
+            if interval > expected * DROP_FACTOR {
+                let missing = (interval / expected).round() - 1.0;
+                if missing >= 1.0 {
+                    metrics
+                        .frames_dropped
+                        .with_label_values(&[frame.stream_id])
+                        .inc_by(missing);
+                }
+            } else {
+                // Drops are counted above, they would swamp the jitter
+                metrics
+                    .frame_interval_jitter
+                    .with_label_values(&[frame.stream_id])
+                    .observe((interval - expected).abs());
+            }
+        }
+
+        state.intervals.push_back(interval);
+        if state.intervals.len() > MEASURE_HISTORY {
+            state.intervals.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::Registry;
+
+    fn frame(pts: f64) -> FrameRecord<'static> {
+        FrameRecord {
+            media_type: "video",
+            stream_id: "0",
+            pts_time: Some(pts),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_frame_rate() {
+        assert_eq!(parse_frame_rate("25/1"), Some(25.0));
+        assert!((parse_frame_rate("30000/1001").unwrap() - 29.97).abs() < 0.01);
+        assert_eq!(parse_frame_rate("0/0"), None);
+        assert_eq!(parse_frame_rate("90000/1"), None);
+    }
+
+    #[test]
+    fn test_drops_and_duplicates_from_frame_rate() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let mut tracker = CadenceTracker::default();
+        tracker.set_frame_rate("0", 25.0);
+
+        for i in 0..10 {
+            tracker.observe(&frame(i as f64 * 0.04), &metrics);
+        }
+        for pts in [0.40, 0.52, 0.56, 0.56, 0.60] {
+            tracker.observe(&frame(pts), &metrics);
+        }
+        // 0.44 and 0.48 are missing, 0.56 arrives twice
+        assert_eq!(metrics.frames_dropped.with_label_values(&["0"]).get(), 2.0);
+        assert_eq!(
+            metrics.frames_duplicated.with_label_values(&["0"]).get(),
+            1.0
+        );
+
+        // Jumps of the timestamps are not drops
+        tracker.observe(&frame(3600.0), &metrics);
+        tracker.observe(&frame(0.0), &metrics);
+        assert_eq!(metrics.frames_dropped.with_label_values(&["0"]).get(), 2.0);
+    }
+
+    #[test]
+    fn test_drops_from_measured_cadence() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let mut tracker = CadenceTracker::default();
+
+        // 50 fps without a usable frame rate
+        for i in 0..20 {
+            tracker.observe(&frame(i as f64 * 0.02), &metrics);
+        }
+        tracker.observe(&frame(0.5), &metrics);
+        assert_eq!(metrics.frames_dropped.with_label_values(&["0"]).get(), 5.0);
+    }
+
+    #[test]
+    fn test_frame_interval_jitter() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let mut tracker = CadenceTracker::default();
+        tracker.set_frame_rate("0", 25.0);
+
+        for i in 0..10 {
+            tracker.observe(&frame(i as f64 * 0.04), &metrics);
+        }
+        // 10 ms late, then a drop that does not count as jitter
+        tracker.observe(&frame(0.41), &metrics);
+        tracker.observe(&frame(0.53), &metrics);
+        let jitter = metrics.frame_interval_jitter.with_label_values(&["0"]);
+        assert_eq!(jitter.get_sample_count(), 2);
+        assert!((jitter.get_sample_sum() - 0.01).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_field_rate_is_not_a_drop() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let mut tracker = CadenceTracker::default();
+        // 25 fps interlaced content announced with the 50 Hz field rate
+        tracker.set_frame_rate("0", 50.0);
+
+        for i in 0..40 {
+            tracker.observe(&frame(i as f64 * 0.04), &metrics);
+        }
+        assert_eq!(metrics.frames_dropped.with_label_values(&["0"]).get(), 0.0);
+        tracker.observe(&frame(41.0 * 0.04), &metrics);
+        assert_eq!(metrics.frames_dropped.with_label_values(&["0"]).get(), 1.0);
+    }
+}
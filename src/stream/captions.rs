@@ -0,0 +1,126 @@
+// stream/captions.rs
+
+use crate::metrics::StreamMetrics;
+use crate::stream::records::FrameRecord;
+use std::collections::HashMap;
+
+/// Captions count as present while caption data arrived within this much
+/// stream time
+const PRESENCE_TIMEOUT: f64 = 5.0;
+/// Larger jumps between frames are timestamp discontinuities
+const MAX_JUMP: f64 = 10.0;
+
+struct CaptionState {
+    last_pts: f64,
+    /// Stream time of the last caption data, or of the first frame while
+    /// there was none yet
+    since: f64,
+    seen: bool,
+}
+
+/// Follows the CEA-608/708 caption data carried as A/53 side data on video
+/// frames, so an alert can fire when captions silently stop. Gaps are measured
+/// in stream time, which keeps them meaningful when reading files faster than
+/// realtime.
+#[derive(Default)]
+pub struct CaptionTracker {
+    streams: HashMap<String, CaptionState>,
+}
+
+impl CaptionTracker {
+    pub fn observe(&mut self, frame: &FrameRecord, metrics: &StreamMetrics) {
+        if frame.media_type != "video" {
+            return;
+        }
+        let Some(pts) = frame.pts_time else {
+            return;
+        };
+        if !self.streams.contains_key(frame.stream_id) {
+            self.streams.insert(
+                frame.stream_id.to_string(),
+                CaptionState {
+                    last_pts: pts,
+                    since: pts,
+                    seen: false,
+                },
+            );
+        }
+        let state = self.streams.get_mut(frame.stream_id).unwrap();
+
+        // Timestamp discontinuities move the reference along with the stream
+        let jump = pts - state.last_pts;
+        if !(0.0..MAX_JUMP).contains(&jump) {
+            state.since += jump;
+        }
+        state.last_pts = pts;
+        if frame.captions {
+            state.since = pts;
+            state.seen = true;
+        }
+
+        let gap = (pts - state.since).max(0.0);
+        metrics
+            .caption_gap
+            .with_label_values(&[frame.stream_id])
+            .set(gap);
+        metrics
+            .captions_present
+            .with_label_values(&[frame.stream_id])
+            .set(if state.seen && gap < PRESENCE_TIMEOUT {
+                1.0
+            } else {
+                0.0
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::Registry;
+
+    fn frame(pts: f64, captions: bool) -> FrameRecord<'static> {
+        FrameRecord {
+            media_type: "video",
+            stream_id: "0",
+            pts_time: Some(pts),
+            captions,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_caption_gap() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let mut tracker = CaptionTracker::default();
+        let present = || metrics.captions_present.with_label_values(&["0"]).get();
+        let gap = || metrics.caption_gap.with_label_values(&["0"]).get();
+
+        // No captions at all yet
+        tracker.observe(&frame(10.0, false), &metrics);
+        tracker.observe(&frame(12.0, false), &metrics);
+        assert_eq!(present(), 0.0);
+        assert_eq!(gap(), 2.0);
+
+        tracker.observe(&frame(13.0, true), &metrics);
+        tracker.observe(&frame(14.0, false), &metrics);
+        assert_eq!(present(), 1.0);
+        assert_eq!(gap(), 1.0);
+
+        // Captions stop
+        tracker.observe(&frame(19.0, false), &metrics);
+        assert_eq!(present(), 0.0);
+        assert_eq!(gap(), 6.0);
+
+        // A timestamp reset keeps the gap growing steadily
+        tracker.observe(&frame(0.0, false), &metrics);
+        assert_eq!(gap(), 6.0);
+        tracker.observe(&frame(1.0, false), &metrics);
+        assert_eq!(gap(), 7.0);
+
+        tracker.observe(&frame(1.04, true), &metrics);
+        assert_eq!(present(), 1.0);
+        assert_eq!(gap(), 0.0);
+    }
+}
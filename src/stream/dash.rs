@@ -0,0 +1,722 @@
+// stream/dash.rs
+
+use crate::config::{HttpOptions, MonitorOptions};
+use crate::metrics::StreamMetrics;
+use crate::stream::hls::{
+    MIN_REQUEST_TIMEOUT, READ_TIMEOUT, fetch_playlist, http_agent, time_delta,
+};
+use anyhow::Result;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+
+const MIN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(6);
+
+/// Seconds a segment may end in the future before it counts as announced
+/// ahead of its availability, covering clock differences to the packager
+const AVAILABILITY_TOLERANCE: f64 = 2.0;
+
+/// A `SegmentTimeline`, its times in seconds since the start of the
+/// presentation
+#[derive(Debug, Clone, Default)]
+pub struct Timeline {
+    /// End of the last segment, the first repetition of an open ended last
+    /// entry
+    pub end: f64,
+    /// Duration of the last entry if it repeats up to the end of the period
+    /// (`r="-1"`)
+    pub open_duration: Option<f64>,
+    /// End of the period, if it has a duration or a later period starts
+    pub period_end: Option<f64>,
+    pub gaps: usize,
+    /// Total duration of the gaps between segments
+    pub gap_duration: f64,
+    /// Segments starting before the previous one ended
+    pub overlaps: usize,
+}
+
+impl Timeline {
+    /// End of the newest segment available `elapsed` seconds after the start
+    /// of the presentation
+    pub fn end_at(&self, elapsed: f64) -> f64 {
+        let Some(duration) = self.open_duration.filter(|d| *d > 0.0) else {
+            return self.end;
+        };
+        let limit = self.period_end.map_or(elapsed, |end| end.min(elapsed));
+        let repeats = ((limit - self.end) / duration).floor().max(0.0);
+        self.end + repeats * duration
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Manifest {
+    /// `type="dynamic"`: a live presentation that is updated
+    pub dynamic: bool,
+    pub availability_start_time: Option<DateTime<Utc>>,
+    pub publish_time: Option<DateTime<Utc>>,
+    /// `minimumUpdatePeriod` in seconds
+    pub minimum_update_period: Option<f64>,
+    /// `timeShiftBufferDepth` in seconds
+    pub time_shift_buffer_depth: Option<f64>,
+    pub timelines: Vec<Timeline>,
+}
+
+impl Manifest {
+    /// Wall clock time the newest segment of a dynamic manifest available at
+    /// `now` ends
+    pub fn live_edge(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let start = self.availability_start_time?;
+        let elapsed = seconds_between(start, now);
+        let end = self
+            .timelines
+            .iter()
+            .map(|t| t.end_at(elapsed))
+            .reduce(f64::max)?;
+        start.checked_add_signed(time_delta(end)?)
+    }
+
+    /// Names of the checks the manifest fails at `now`. Only manifests with a
+    /// segment timeline have to be updated, so only those can be stale.
+    pub fn failed_checks(&self, now: DateTime<Utc>, stale_factor: f64) -> BTreeSet<&'static str> {
+        let mut failed = BTreeSet::new();
+        if self.timelines.iter().any(|t| t.gaps > 0) {
+            failed.insert("timeline_gap");
+        }
+        if self.timelines.iter().any(|t| t.overlaps > 0) {
+            failed.insert("timeline_overlap");
+        }
+        if !self.dynamic {
+            return failed;
+        }
+
+        let seconds_since = |time: DateTime<Utc>| seconds_between(time, now);
+        let outside_window = match self.live_edge(now) {
+            Some(edge) => {
+                let age = seconds_since(edge);
+                age < -AVAILABILITY_TOLERANCE
+                    || self
+                        .time_shift_buffer_depth
+                        .is_some_and(|depth| age > depth)
+            }
+            // Segments of a live presentation cannot be placed without it
+            None => self.availability_start_time.is_none(),
+        };
+        if outside_window {
+            failed.insert("availability_window");
+        }
+
+        if let (Some(published), Some(period)) = (self.publish_time, self.minimum_update_period)
+            && !self.timelines.is_empty()
+            && seconds_since(published) > stale_factor * period.max(1.0)
+        {
+            failed.insert("stale");
+        }
+        failed
+    }
+}
+
+fn seconds_between(from: DateTime<Utc>, to: DateTime<Utc>) -> f64 {
+    to.signed_duration_since(from).num_milliseconds() as f64 / 1000.0
+}
+
+/// Parses an `xs:dateTime`, which may lack a time zone to mean UTC
+fn parse_date_time(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f").map(|time| time.and_utc())
+        })
+        .ok()
+}
+
+/// Parses an `xs:duration` like `PT1M30.5S` into seconds. Years and months
+/// have no fixed length and are not supported, nor are negative durations.
+pub fn parse_duration(value: &str) -> Option<f64> {
+    let rest = value.trim().strip_prefix('P')?;
+    let (date, time) = rest.split_once('T').unwrap_or((rest, ""));
+    let mut seconds = 0.0;
+    for (part, units) in [
+        (date, &[('D', 86400.0)][..]),
+        (time, &[('H', 3600.0), ('M', 60.0), ('S', 1.0)][..]),
+    ] {
+        let mut rest = part;
+        for &(unit, scale) in units {
+            if let Some((number, tail)) = rest.split_once(unit) {
+                let number = number.parse::<f64>().ok()?;
+                if !number.is_finite() || number < 0.0 {
+                    return None;
+                }
+                seconds += number * scale;
+                rest = tail;
+            }
+        }
+        if !rest.is_empty() {
+            return None;
+        }
+    }
+    Some(seconds).filter(|seconds| seconds.is_finite())
+}
+
+/// Looks up an attribute of an XML start tag, without quotes
+fn xml_attribute<'a>(attributes: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = attributes;
+    while let Some((key, value)) = rest.split_once('=') {
+        let value = value.trim_start();
+        let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let end = value[1..].find(quote)? + 1;
+        if key.trim() == name {
+            return Some(&value[1..end]);
+        }
+        rest = &value[end + 1..];
+    }
+    None
+}
+
+/// XML tags of a document as local name, attributes and whether it is a
+/// closing tag. Comments, declarations and processing instructions are
+/// skipped; a manifest needs no more than that.
+fn tags(text: &str) -> impl Iterator<Item = (&str, &str, bool)> {
+    let mut rest = text;
+    std::iter::from_fn(move || {
+        loop {
+            let start = rest.find('<')?;
+            rest = &rest[start..];
+            if let Some(comment) = rest.strip_prefix("<!--") {
+                rest = comment.split_once("-->").map_or("", |(_, tail)| tail);
+                continue;
+            }
+            let end = rest.find('>')?;
+            let tag = &rest[1..end];
+            rest = &rest[end + 1..];
+            if tag.starts_with('?') || tag.starts_with('!') {
+                continue;
+            }
+            let (closing, tag) = match tag.strip_prefix('/') {
+                Some(tag) => (true, tag),
+                None => (false, tag.trim_end_matches('/')),
+            };
+            let (name, attributes) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+            let local = name.rsplit(':').next().unwrap_or(name);
+            return Some((local, attributes, closing));
+        }
+    })
+}
+
+/// One `S` element: start time, duration and repeat count in timescale units
+struct TimelineEntry {
+    start: Option<u64>,
+    duration: u64,
+    repeat: i64,
+}
+
+/// Follows a segment timeline, an `r` of -1 repeats up to the next entry, or
+/// on the last entry up to the end of the period
+fn timeline(
+    entries: &[TimelineEntry],
+    timescale: u64,
+    offset: u64,
+    period_start: f64,
+    period_end: Option<f64>,
+) -> Timeline {
+    let mut result = Timeline {
+        period_end,
+        ..Default::default()
+    };
+    let mut expected: Option<u64> = None;
+    for (i, entry) in entries.iter().enumerate() {
+        let start = entry.start.or(expected).unwrap_or(0);
+        if let Some(expected) = expected {
+            if start > expected {
+                result.gaps += 1;
+                result.gap_duration += (start - expected) as f64 / timescale as f64;
+            } else if start < expected {
+                result.overlaps += 1;
+            }
+        }
+        let next = entries.get(i + 1).and_then(|next| next.start);
+        let end = match (u64::try_from(entry.repeat), next) {
+            (Ok(repeat), _) => {
+                start.saturating_add(entry.duration.saturating_mul(repeat.saturating_add(1)))
+            }
+            (Err(_), Some(next)) => next.max(start.saturating_add(entry.duration)),
+            (Err(_), None) if i + 1 == entries.len() => {
+                result.open_duration = Some(entry.duration as f64 / timescale as f64);
+                start.saturating_add(entry.duration)
+            }
+            (Err(_), None) => start.saturating_add(entry.duration),
+        };
+        expected = Some(end);
+    }
+    if let Some(end) = expected {
+        result.end = period_start + (end as f64 - offset as f64) / timescale as f64;
+    }
+    result
+}
+
+pub fn parse_manifest(text: &str) -> Result<Manifest> {
+    let mut manifest = Manifest::default();
+    let mut found = false;
+    let mut period_start = 0.0;
+    let mut period_end = None;
+    let mut timescale = 1;
+    let mut offset = 0;
+    let mut entries: Option<Vec<TimelineEntry>> = None;
+
+    for (name, attributes, closing) in tags(text) {
+        let attribute = |name| xml_attribute(attributes, name);
+        match (name, closing) {
+            ("MPD", false) => {
+                found = true;
+                manifest.dynamic = attribute("type") == Some("dynamic");
+                manifest.availability_start_time =
+                    attribute("availabilityStartTime").and_then(parse_date_time);
+                manifest.publish_time = attribute("publishTime").and_then(parse_date_time);
+                manifest.minimum_update_period =
+                    attribute("minimumUpdatePeriod").and_then(parse_duration);
+                manifest.time_shift_buffer_depth =
+                    attribute("timeShiftBufferDepth").and_then(parse_duration);
+            }
+            ("Period", false) => {
+                period_start = attribute("start").and_then(parse_duration).unwrap_or(0.0);
+                period_end = attribute("duration")
+                    .and_then(parse_duration)
+                    .map(|duration| period_start + duration);
+                // The timelines of the previous periods end where this starts
+                for timeline in &mut manifest.timelines {
+                    timeline.period_end.get_or_insert(period_start);
+                }
+            }
+            ("SegmentTemplate", false) => {
+                timescale = attribute("timescale")
+                    .and_then(|v| v.parse().ok())
+                    .filter(|t| *t > 0)
+                    .unwrap_or(1);
+                offset = attribute("presentationTimeOffset")
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(0);
+            }
+            ("SegmentTimeline", false) => entries = Some(Vec::new()),
+            ("S", false) => {
+                if let Some(entries) = entries.as_mut() {
+                    entries.push(TimelineEntry {
+                        start: attribute("t").and_then(|v| v.parse().ok()),
+                        duration: attribute("d").and_then(|v| v.parse().ok()).unwrap_or(0),
+                        repeat: attribute("r").and_then(|v| v.parse().ok()).unwrap_or(0),
+                    });
+                }
+            }
+            ("SegmentTimeline", true) => {
+                if let Some(entries) = entries.take() {
+                    let timeline = timeline(&entries, timescale, offset, period_start, period_end);
+                    manifest.timelines.push(timeline);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if !found {
+        anyhow::bail!("Manifest has no MPD element");
+    }
+    Ok(manifest)
+}
+
+/// Periodically fetches the manifest of a DASH input alongside ffprobe and
+/// exports validity metrics, analogous to the HLS playlist poller.
+pub struct ManifestPoller {
+    url: String,
+    http: HttpOptions,
+    stream_type: &'static str,
+    /// Minimum update periods without a new publishTime before the manifest is stale
+    stale_factor: f64,
+    metrics: StreamMetrics,
+    running: Arc<AtomicBool>,
+}
+
+impl ManifestPoller {
+    pub fn new(
+        url: String,
+        options: &MonitorOptions,
+        stream_type: &'static str,
+        metrics: StreamMetrics,
+        running: Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            url,
+            http: options.http.clone(),
+            stream_type,
+            stale_factor: options.dash_stale_factor,
+            metrics,
+            running,
+        }
+    }
+
+    pub fn spawn(self) -> thread::JoinHandle<()> {
+        thread::spawn(move || self.run())
+    }
+
+    fn run(&self) {
+        info!("Starting DASH manifest poller for {}", self.url);
+        let agent = match http_agent(&self.http) {
+            Ok(agent) => agent,
+            Err(e) => {
+                error!("Failed to set up DASH manifest requests: {:#}", e);
+                return;
+            }
+        };
+        let mut failing = BTreeSet::new();
+        let mut update_period = None;
+
+        while self.running.load(Ordering::SeqCst) {
+            let interval = self.poll_once(&agent, &mut failing, &mut update_period);
+
+            let steps = (interval.as_millis() / 100).max(1);
+            for _ in 0..steps {
+                if !self.running.load(Ordering::SeqCst) {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+        }
+        debug!("DASH manifest poller for {} stopped", self.url);
+    }
+
+    /// How long a manifest request may take: as long as the manifest may go
+    /// without an update
+    fn request_timeout(&self, update_period: Option<f64>) -> Duration {
+        let period = update_period.unwrap_or(DEFAULT_POLL_INTERVAL.as_secs_f64());
+        Duration::try_from_secs_f64(period.max(1.0) * self.stale_factor)
+            .unwrap_or(READ_TIMEOUT)
+            .max(MIN_REQUEST_TIMEOUT)
+    }
+
+    /// Fetches and checks the manifest once, `failing` holds the checks that
+    /// failed on the previous poll and `update_period` the
+    /// `minimumUpdatePeriod` of the last manifest. Returns the time until the
+    /// next poll.
+    fn poll_once(
+        &self,
+        agent: &ureq::Agent,
+        failing: &mut BTreeSet<&'static str>,
+        update_period: &mut Option<f64>,
+    ) -> Duration {
+        let now = Utc::now();
+        let labels = [self.stream_type];
+        let timeout = self.request_timeout(*update_period);
+        let (failed, interval) = match fetch_playlist(agent, &self.url, &self.http, timeout) {
+            Err(e) => {
+                warn!("DASH manifest poll failed: {:#}", e);
+                (BTreeSet::from(["fetch"]), DEFAULT_POLL_INTERVAL)
+            }
+            Ok(text) => match parse_manifest(&text) {
+                Err(e) => {
+                    warn!("Invalid DASH manifest {}: {:#}", self.url, e);
+                    (BTreeSet::from(["parse"]), DEFAULT_POLL_INTERVAL)
+                }
+                Ok(manifest) => {
+                    *update_period = manifest.minimum_update_period;
+                    if let Some(published) = manifest.publish_time {
+                        self.metrics
+                            .dash_publish_age
+                            .with_label_values(&labels)
+                            .set(seconds_between(published, now));
+                    }
+                    let gaps = manifest.timelines.iter().map(|t| t.gap_duration).sum();
+                    self.metrics
+                        .dash_timeline_gap
+                        .with_label_values(&labels)
+                        .set(gaps);
+                    if let Some(edge) = manifest.live_edge(now).filter(|_| manifest.dynamic) {
+                        self.metrics
+                            .dash_live_edge_age
+                            .with_label_values(&labels)
+                            .set(seconds_between(edge, now));
+                    }
+                    let interval = manifest
+                        .minimum_update_period
+                        .and_then(|period| Duration::try_from_secs_f64(period).ok())
+                        .unwrap_or(DEFAULT_POLL_INTERVAL);
+                    (
+                        manifest.failed_checks(now, self.stale_factor),
+                        interval.max(MIN_POLL_INTERVAL),
+                    )
+                }
+            },
+        };
+
+        for check in failed.difference(failing) {
+            warn!("DASH manifest {} fails the {} check", self.url, check);
+            self.metrics
+                .dash_manifest_errors
+                .with_label_values(&[self.stream_type, check])
+                .inc();
+        }
+        let valid = if failed.is_empty() { 1.0 } else { 0.0 };
+        self.metrics
+            .dash_manifest_valid
+            .with_label_values(&labels)
+            .set(valid);
+        *failing = failed;
+        interval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Args;
+    use clap::Parser;
+
+    const MANIFEST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="dynamic"
+     availabilityStartTime="2024-05-01T12:00:00Z" publishTime="2024-05-01T12:01:00Z"
+     minimumUpdatePeriod="PT2S" timeShiftBufferDepth="PT1M">
+  <!-- <Period start="PT1H"> -->
+  <Period id="1" start="PT0S">
+    <AdaptationSet mimeType="video/mp4">
+      <SegmentTemplate timescale="90000" media="v-$Time$.m4s">
+        <SegmentTimeline>
+          <S t="0" d="180000" r="29"/>
+        </SegmentTimeline>
+      </SegmentTemplate>
+    </AdaptationSet>
+    <AdaptationSet mimeType="audio/mp4">
+      <SegmentTemplate timescale='48000' presentationTimeOffset='0'>
+        <SegmentTimeline>
+          <S t="0" d="96000" r="-1"/>
+          <S t="2880000" d="96000"/>
+        </SegmentTimeline>
+      </SegmentTemplate>
+    </AdaptationSet>
+  </Period>
+</MPD>
+"#;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        parse_date_time(time).unwrap()
+    }
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest = parse_manifest(MANIFEST).unwrap();
+        assert!(manifest.dynamic);
+        assert_eq!(manifest.minimum_update_period, Some(2.0));
+        assert_eq!(manifest.time_shift_buffer_depth, Some(60.0));
+        assert_eq!(manifest.timelines.len(), 2);
+        assert_eq!(manifest.timelines[0].end, 60.0);
+        // The repeat up to the next entry leaves no gap
+        assert_eq!(manifest.timelines[1].gaps, 0);
+        assert_eq!(manifest.timelines[1].end, 62.0);
+        assert_eq!(
+            manifest.live_edge(at("2024-05-01T12:01:03Z")),
+            Some(at("2024-05-01T12:01:02Z"))
+        );
+
+        assert_eq!(parse_duration("PT1H2M3.5S"), Some(3723.5));
+        assert_eq!(parse_duration("P1DT1S"), Some(86401.0));
+        assert_eq!(parse_duration("P1Y"), None);
+        assert_eq!(parse_duration("PT-1S"), None);
+        assert_eq!(parse_duration("PTNaNS"), None);
+        assert_eq!(parse_duration("P1e308D"), None);
+        assert_eq!(
+            parse_date_time("2024-05-01T12:00:00.5"),
+            Some(at("2024-05-01T12:00:00.500Z"))
+        );
+        assert!(parse_manifest("<html></html>").is_err());
+    }
+
+    #[test]
+    fn test_manifest_checks() {
+        let manifest = parse_manifest(MANIFEST).unwrap();
+        assert!(
+            manifest
+                .failed_checks(at("2024-05-01T12:01:03Z"), 3.0)
+                .is_empty()
+        );
+        // Segments announced before they are available
+        assert_eq!(
+            manifest.failed_checks(at("2024-05-01T12:00:58Z"), 3.0),
+            BTreeSet::from(["availability_window"])
+        );
+        // Not updated for three minimum update periods
+        assert_eq!(
+            manifest.failed_checks(at("2024-05-01T12:01:07Z"), 3.0),
+            BTreeSet::from(["stale"])
+        );
+        // The newest segment left the time shift buffer
+        assert!(
+            manifest
+                .failed_checks(at("2024-05-01T12:02:10Z"), 100.0)
+                .contains("availability_window")
+        );
+
+        // The 30th video segment starts one segment late
+        let gap = MANIFEST.replace(r#"r="29""#, r#"r="28"/><S t="5400000" d="180000""#);
+        let gap = parse_manifest(&gap).unwrap();
+        assert_eq!(gap.timelines[0].gaps, 1);
+        assert_eq!(gap.timelines[0].gap_duration, 2.0);
+        let overlap =
+            parse_manifest(&MANIFEST.replace(r#"r="29""#, r#"r="29"/><S t="0" d="1""#)).unwrap();
+        let failed = overlap.failed_checks(at("2024-05-01T12:01:03Z"), 3.0);
+        assert!(failed.contains("timeline_overlap"));
+        assert!(
+            gap.failed_checks(at("2024-05-01T12:01:03Z"), 3.0)
+                .contains("timeline_gap")
+        );
+
+        // Static manifests are neither stale nor bound to the wall clock
+        let vod = parse_manifest(&MANIFEST.replace("dynamic", "static")).unwrap();
+        assert!(
+            vod.failed_checks(at("2030-01-01T00:00:00Z"), 3.0)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_open_ended_timeline() {
+        // The audio segments repeat up to the end of the period
+        let open = MANIFEST.replace(r#"<S t="2880000" d="96000"/>"#, "");
+        let manifest = parse_manifest(&open).unwrap();
+        assert_eq!(manifest.timelines[1].end, 2.0);
+        assert_eq!(manifest.timelines[1].open_duration, Some(2.0));
+        // Up to the last segment complete by now
+        assert_eq!(
+            manifest.live_edge(at("2024-05-01T12:01:05Z")),
+            Some(at("2024-05-01T12:01:04Z"))
+        );
+        assert!(
+            manifest
+                .failed_checks(at("2024-05-01T12:01:05Z"), 3.0)
+                .is_empty()
+        );
+
+        // Or the end of the period
+        let ended = open.replace(r#"start="PT0S""#, r#"start="PT0S" duration="PT61S""#);
+        let manifest = parse_manifest(&ended).unwrap();
+        assert_eq!(manifest.timelines[1].period_end, Some(61.0));
+        assert_eq!(
+            manifest.live_edge(at("2024-05-01T12:01:05Z")),
+            Some(at("2024-05-01T12:01:00Z"))
+        );
+        let later = open.replace(
+            "  </Period>\n",
+            "  </Period>\n  <Period id=\"2\" start=\"PT30S\"></Period>\n",
+        );
+        let manifest = parse_manifest(&later).unwrap();
+        assert_eq!(manifest.timelines[1].period_end, Some(30.0));
+    }
+
+    #[test]
+    fn test_hostile_manifest() {
+        let hostile = MANIFEST.replace("PT2S", "PT-1S").replace(
+            r#"<S t="0" d="180000" r="29"/>"#,
+            r#"<S t="18446744073709551615" d="180000" r="9223372036854775807"/>"#,
+        );
+        let manifest = parse_manifest(&hostile).unwrap();
+        assert_eq!(manifest.minimum_update_period, None);
+        // The newest segment ends past the end of time
+        assert_eq!(manifest.live_edge(at("2024-05-01T12:01:03Z")), None);
+        manifest.failed_checks(at("2024-05-01T12:01:03Z"), 3.0);
+
+        // Neither stops the poller
+        let dir = std::env::temp_dir().join(format!("dash-hostile-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("manifest.mpd");
+        std::fs::write(&path, &hostile).unwrap();
+        let url = path.display().to_string();
+        let options = MonitorOptions::from(&Args::parse_from(["ffmpeg_exporter", "--input", &url]));
+        let metrics =
+            StreamMetrics::new(&prometheus::Registry::new(), &Default::default()).unwrap();
+        let poller = ManifestPoller::new(
+            url,
+            &options,
+            "dash",
+            metrics.clone(),
+            Arc::new(AtomicBool::new(true)),
+        );
+        let agent = http_agent(&HttpOptions::default()).unwrap();
+        let interval = poller.poll_once(&agent, &mut BTreeSet::new(), &mut None);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(interval, DEFAULT_POLL_INTERVAL);
+        assert_eq!(
+            metrics
+                .dash_manifest_valid
+                .with_label_values(&["dash"])
+                .get(),
+            1.0
+        );
+    }
+
+    #[test]
+    fn test_request_timeout() {
+        let url = "https://example.com/live.mpd".to_string();
+        let options = MonitorOptions::from(&Args::parse_from(["ffmpeg_exporter", "--input", &url]));
+        let poller = ManifestPoller::new(
+            url,
+            &options,
+            "dash",
+            StreamMetrics::new(&prometheus::Registry::new(), &Default::default()).unwrap(),
+            Arc::new(AtomicBool::new(true)),
+        );
+        assert_eq!(poller.request_timeout(Some(2.0)), Duration::from_secs(6));
+        assert_eq!(poller.request_timeout(None), Duration::from_secs(18));
+        assert_eq!(poller.request_timeout(Some(0.0)), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn test_poll_manifest_metrics() {
+        let dir = std::env::temp_dir().join(format!("dash-poll-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("manifest.mpd");
+        std::fs::write(&path, MANIFEST.replace("dynamic", "static")).unwrap();
+
+        let registry = prometheus::Registry::new();
+        let metrics = StreamMetrics::new(&registry, &std::collections::HashMap::new()).unwrap();
+        let url = path.display().to_string();
+        let options = MonitorOptions::from(&Args::parse_from(["ffmpeg_exporter", "--input", &url]));
+        let poller = ManifestPoller::new(
+            url.clone(),
+            &options,
+            "dash",
+            metrics.clone(),
+            Arc::new(AtomicBool::new(true)),
+        );
+        let agent = http_agent(&HttpOptions::default()).unwrap();
+        let mut failing = BTreeSet::new();
+        let mut update_period = None;
+        let labels = ["dash"];
+        let valid = || metrics.dash_manifest_valid.with_label_values(&labels).get();
+        let errors = |check| {
+            metrics
+                .dash_manifest_errors
+                .with_label_values(&["dash", check])
+                .get()
+        };
+
+        assert_eq!(
+            poller.poll_once(&agent, &mut failing, &mut update_period),
+            Duration::from_secs(2)
+        );
+        assert_eq!(valid(), 1.0);
+
+        std::fs::write(&path, "not a manifest").unwrap();
+        poller.poll_once(&agent, &mut failing, &mut update_period);
+        poller.poll_once(&agent, &mut failing, &mut update_period);
+        assert_eq!(valid(), 0.0);
+        // A check failing on consecutive polls counts once
+        assert_eq!(errors("parse"), 1.0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        poller.poll_once(&agent, &mut failing, &mut update_period);
+        assert_eq!(errors("fetch"), 1.0);
+        assert_eq!(
+            metrics.dash_timeline_gap.with_label_values(&labels).get(),
+            0.0
+        );
+    }
+}
@@ -0,0 +1,157 @@
+// stream/downtime.rs
+
+use crate::config::StaleGauges;
+use crate::metrics::StreamMetrics;
+use std::time::{Duration, Instant};
+
+/// Accounts outages of a stream. An outage starts when `connection_state`
+/// drops to 0 and lasts until a later session delivers its first packet or
+/// frame, so failed reconnects in between do not split it. The downtime is
+/// counted while the outage lasts, its duration observed once it ends.
+///
+/// Once an outage lasted `stale_ttl`, the measurements of the last session
+/// are cleared, so dashboards do not show its frame rate and bitrate through
+/// the outage. Short reconnects leave them.
+pub struct Downtime {
+    stale_gauges: StaleGauges,
+    stale_ttl: Duration,
+    /// Start of the current outage, `None` while the stream is up
+    since: Option<Instant>,
+    /// Part of the current outage already added to the counter
+    counted: Duration,
+    /// Whether the gauges were cleared in the current outage
+    cleared: bool,
+}
+
+impl Downtime {
+    pub fn new(stale_gauges: StaleGauges, stale_ttl: Duration) -> Self {
+        Self {
+            stale_gauges,
+            stale_ttl,
+            since: None,
+            counted: Duration::ZERO,
+            cleared: false,
+        }
+    }
+
+    /// The connection dropped; a no-op during an outage
+    pub fn down(&mut self) {
+        self.down_at(Instant::now());
+    }
+
+    /// Adds the downtime since the last update to the counter, and clears
+    /// the gauges once the outage outlasted the TTL
+    pub fn update(&mut self, metrics: &StreamMetrics, stream_type: &str) {
+        self.update_at(Instant::now(), metrics, stream_type);
+    }
+
+    /// The stream delivers data again, which refills the gauges
+    pub fn up(&mut self, metrics: &StreamMetrics, stream_type: &str) {
+        self.up_at(Instant::now(), metrics, stream_type);
+    }
+
+    fn down_at(&mut self, now: Instant) {
+        if self.since.is_none() {
+            self.since = Some(now);
+            self.counted = Duration::ZERO;
+            self.cleared = false;
+        }
+    }
+
+    fn update_at(&mut self, now: Instant, metrics: &StreamMetrics, stream_type: &str) {
+        let Some(since) = self.since else {
+            return;
+        };
+        let elapsed = now.saturating_duration_since(since);
+        metrics
+            .downtime
+            .with_label_values(&[stream_type])
+            .inc_by(elapsed.saturating_sub(self.counted).as_secs_f64());
+        self.counted = elapsed;
+        if !self.cleared && elapsed >= self.stale_ttl {
+            metrics.clear_session_gauges(self.stale_gauges);
+            self.cleared = true;
+        }
+    }
+
+    fn up_at(&mut self, now: Instant, metrics: &StreamMetrics, stream_type: &str) {
+        if self.since.is_none() {
+            return;
+        }
+        self.update_at(now, metrics, stream_type);
+        metrics
+            .outage_duration
+            .with_label_values(&[stream_type])
+            .observe(self.counted.as_secs_f64());
+        self.since = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::Registry;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_downtime() {
+        let metrics = StreamMetrics::new(&Registry::new(), &HashMap::new()).unwrap();
+        let downtime_total = || metrics.downtime.with_label_values(&["udp"]).get();
+        let outages = || metrics.outage_duration.with_label_values(&["udp"]);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut downtime = Downtime::new(StaleGauges::Keep, Duration::ZERO);
+
+        // Up from the start, nothing to account
+        downtime.update_at(at(1), &metrics, "udp");
+        downtime.up_at(at(2), &metrics, "udp");
+        assert_eq!(downtime_total(), 0.0);
+        assert_eq!(outages().get_sample_count(), 0);
+
+        // Counted while the outage lasts, a failed reconnect does not end it
+        downtime.down_at(at(10));
+        downtime.update_at(at(13), &metrics, "udp");
+        assert_eq!(downtime_total(), 3.0);
+        downtime.down_at(at(14));
+        downtime.up_at(at(20), &metrics, "udp");
+        assert_eq!(downtime_total(), 10.0);
+        assert_eq!(outages().get_sample_count(), 1);
+        assert_eq!(outages().get_sample_sum(), 10.0);
+
+        downtime.down_at(at(30));
+        downtime.up_at(at(35), &metrics, "udp");
+        assert_eq!(downtime_total(), 15.0);
+        assert_eq!(outages().get_sample_count(), 2);
+    }
+
+    #[test]
+    fn test_stale_gauges() {
+        let metrics = StreamMetrics::new(&Registry::new(), &HashMap::new()).unwrap();
+        let fps = || metrics.fps.with_label_values(&["udp", "0", "video"]);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut downtime = Downtime::new(StaleGauges::Reset, Duration::from_secs(30));
+
+        // Up, nothing is stale
+        fps().set(25.0);
+        downtime.update_at(at(100), &metrics, "udp");
+        assert_eq!(fps().get(), 25.0);
+
+        // A reconnect within the TTL keeps the values
+        downtime.down_at(at(10));
+        downtime.update_at(at(39), &metrics, "udp");
+        assert_eq!(fps().get(), 25.0);
+        downtime.up_at(at(40), &metrics, "udp");
+
+        // A failed reconnect does not restart the TTL
+        downtime.down_at(at(50));
+        downtime.down_at(at(70));
+        downtime.update_at(at(80), &metrics, "udp");
+        assert_eq!(fps().get(), 0.0);
+
+        // Cleared once per outage, so values of a new session stay
+        fps().set(25.0);
+        downtime.update_at(at(90), &metrics, "udp");
+        assert_eq!(fps().get(), 25.0);
+    }
+}
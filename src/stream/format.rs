@@ -0,0 +1,681 @@
+// stream/format.rs
+
+use crate::events::EventRecorder;
+use crate::metrics::{COLOR_INFO_LABELS, STREAM_INFO_LABELS, StreamMetrics};
+use crate::stream::records::FrameRecord;
+use prometheus::GaugeVec;
+use prometheus::core::Collector;
+use std::collections::HashMap;
+
+#[derive(Clone, PartialEq)]
+struct VideoFormat {
+    width: u32,
+    height: u32,
+    sample_aspect_ratio: String,
+    display_aspect_ratio: (u64, u64),
+    field_order: Option<FieldOrder>,
+    color: Option<ColorInfo>,
+}
+
+#[derive(Clone, PartialEq)]
+struct ColorInfo {
+    primaries: String,
+    transfer: String,
+    space: String,
+    range: String,
+    mastering_display: bool,
+}
+
+impl ColorInfo {
+    fn from_frame(frame: &FrameRecord) -> Option<Self> {
+        if frame.color_primaries.is_empty() && frame.color_transfer.is_empty() {
+            return None;
+        }
+        Some(Self {
+            primaries: frame.color_primaries.to_string(),
+            transfer: frame.color_transfer.to_string(),
+            space: frame.color_space.to_string(),
+            range: frame.color_range.to_string(),
+            mastering_display: frame.mastering_display,
+        })
+    }
+
+    /// Whether `frame` carries this color description; mastering display
+    /// metadata usually comes with keyframes only, so other frames lacking it
+    /// still match
+    fn matches(&self, frame: &FrameRecord) -> bool {
+        self.primaries == frame.color_primaries
+            && self.transfer == frame.color_transfer
+            && self.space == frame.color_space
+            && self.range == frame.color_range
+            && (self.mastering_display == frame.mastering_display || !frame.key_frame)
+    }
+
+    fn export(&self, stream_id: &str, metrics: &StreamMetrics) {
+        clear_series(&metrics.color_info, &COLOR_INFO_LABELS, stream_id);
+        let mastering_display = if self.mastering_display {
+            "true"
+        } else {
+            "false"
+        };
+        metrics
+            .color_info
+            .with_label_values(&[
+                stream_id,
+                &self.primaries,
+                &self.transfer,
+                &self.space,
+                &self.range,
+                mastering_display,
+            ])
+            .set(1.0);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FieldOrder {
+    Progressive,
+    TopFieldFirst,
+    BottomFieldFirst,
+}
+
+impl FieldOrder {
+    fn from_frame(frame: &FrameRecord) -> Option<Self> {
+        match (frame.interlaced?, frame.top_field_first) {
+            (false, _) => Some(Self::Progressive),
+            (true, Some(false)) => Some(Self::BottomFieldFirst),
+            (true, _) => Some(Self::TopFieldFirst),
+        }
+    }
+}
+
+impl VideoFormat {
+    fn from_frame(frame: &FrameRecord) -> Option<Self> {
+        let (width, height) = (frame.width?, frame.height?);
+        // Unknown SARs are printed as 0:1 or N/A and mean square pixels
+        let (sar_num, sar_den) = frame
+            .sample_aspect_ratio
+            .split_once(':')
+            .and_then(|(num, den)| Some((num.parse::<u64>().ok()?, den.parse::<u64>().ok()?)))
+            .filter(|&(num, den)| num > 0 && den > 0)
+            .unwrap_or((1, 1));
+        Some(Self {
+            width,
+            height,
+            sample_aspect_ratio: frame.sample_aspect_ratio.to_string(),
+            display_aspect_ratio: reduce(width as u64 * sar_num, height as u64 * sar_den),
+            field_order: FieldOrder::from_frame(frame),
+            color: ColorInfo::from_frame(frame),
+        })
+    }
+
+    /// Whether `frame` has this format, checked on the borrowed fields so the
+    /// common unchanged frame allocates nothing
+    fn matches(&self, frame: &FrameRecord) -> bool {
+        frame.width == Some(self.width)
+            && frame.height == Some(self.height)
+            && frame.sample_aspect_ratio == self.sample_aspect_ratio
+            && FieldOrder::from_frame(frame) == self.field_order
+            && match &self.color {
+                Some(color) => color.matches(frame),
+                None => frame.color_primaries.is_empty() && frame.color_transfer.is_empty(),
+            }
+    }
+}
+
+fn reduce(num: u64, den: u64) -> (u64, u64) {
+    let (mut a, mut b) = (num, den);
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    (
+        num.checked_div(a).unwrap_or(num),
+        den.checked_div(a).unwrap_or(den),
+    )
+}
+
+#[derive(Clone, PartialEq)]
+struct AudioFormat {
+    sample_rate: Option<u32>,
+    channels: u32,
+    channel_layout: String,
+}
+
+impl AudioFormat {
+    fn from_frame(frame: &FrameRecord) -> Option<Self> {
+        Some(Self {
+            sample_rate: frame.sample_rate,
+            channels: frame.channels?,
+            channel_layout: frame.channel_layout.to_string(),
+        })
+    }
+
+    fn matches(&self, frame: &FrameRecord) -> bool {
+        self.sample_rate == frame.sample_rate
+            && frame.channels == Some(self.channels)
+            && self.channel_layout == frame.channel_layout
+    }
+
+    fn export(&self, stream_id: &str, metrics: &StreamMetrics) {
+        if let Some(sample_rate) = self.sample_rate {
+            metrics
+                .audio_sample_rate
+                .with_label_values(&[stream_id])
+                .set(sample_rate as f64);
+        }
+        metrics
+            .audio_channels
+            .with_label_values(&[stream_id])
+            .set(self.channels as f64);
+        if !self.channel_layout.is_empty() {
+            metrics
+                .audio_channel_layout
+                .with_label_values(&[stream_id, &self.channel_layout])
+                .set(1.0);
+        }
+    }
+}
+
+/// Counts resolution, aspect ratio and channel layout changes of each stream
+/// within an ffprobe session.
+#[derive(Default)]
+pub struct FormatTracker {
+    video: HashMap<String, VideoFormat>,
+    audio: HashMap<String, AudioFormat>,
+    /// Records each change as a `format_change` event
+    events: Option<EventRecorder>,
+}
+
+impl FormatTracker {
+    pub fn with_events(events: EventRecorder) -> Self {
+        Self {
+            events: Some(events),
+            ..Default::default()
+        }
+    }
+
+    pub fn observe(&mut self, frame: &FrameRecord, metrics: &StreamMetrics) {
+        match frame.media_type {
+            "video" => self.observe_video(frame, metrics),
+            "audio" => self.observe_audio(frame, metrics),
+            _ => {}
+        }
+    }
+
+    fn observe_audio(&mut self, frame: &FrameRecord, metrics: &StreamMetrics) {
+        if self
+            .audio
+            .get(frame.stream_id)
+            .is_some_and(|last| last.matches(frame))
+        {
+            return;
+        }
+        let Some(format) = AudioFormat::from_frame(frame) else {
+            return;
+        };
+        let Some(last) = self.audio.get_mut(frame.stream_id) else {
+            // Decoded frames are authoritative over the initial stream probe
+            clear_series(
+                &metrics.audio_channel_layout,
+                &["stream_id", "channel_layout"],
+                frame.stream_id,
+            );
+            format.export(frame.stream_id, metrics);
+            self.audio.insert(frame.stream_id.to_string(), format);
+            return;
+        };
+        if *last == format {
+            return;
+        }
+
+        if last.channels != format.channels || last.channel_layout != format.channel_layout {
+            metrics
+                .format_changes
+                .with_label_values(&[frame.stream_id, "channel_layout"])
+                .inc();
+            let message = format!(
+                "Stream {} channel layout changed from {} ({} channels) to {} ({} channels)",
+                frame.stream_id,
+                last.channel_layout,
+                last.channels,
+                format.channel_layout,
+                format.channels
+            );
+            record(self.events.as_ref(), "channel_layout", message);
+        }
+        if last.channel_layout != format.channel_layout {
+            clear_series(
+                &metrics.audio_channel_layout,
+                &["stream_id", "channel_layout"],
+                frame.stream_id,
+            );
+        }
+        format.export(frame.stream_id, metrics);
+        *last = format;
+    }
+
+    fn observe_video(&mut self, frame: &FrameRecord, metrics: &StreamMetrics) {
+        if self
+            .video
+            .get(frame.stream_id)
+            .is_some_and(|last| last.matches(frame))
+        {
+            return;
+        }
+        let Some(mut format) = VideoFormat::from_frame(frame) else {
+            return;
+        };
+        let Some(last) = self.video.get_mut(frame.stream_id) else {
+            export_field_order(metrics, frame.stream_id, format.field_order);
+            if let Some(color) = &format.color {
+                color.export(frame.stream_id, metrics);
+            }
+            self.video.insert(frame.stream_id.to_string(), format);
+            return;
+        };
+        // Mastering display metadata usually comes with keyframes only
+        if !frame.key_frame
+            && let (Some(color), Some(last_color)) = (&mut format.color, &last.color)
+        {
+            color.mastering_display = last_color.mastering_display;
+        }
+        if *last == format {
+            return;
+        }
+
+        if (last.width, last.height) != (format.width, format.height) {
+            metrics
+                .format_changes
+                .with_label_values(&[frame.stream_id, "resolution"])
+                .inc();
+            let message = format!(
+                "Stream {} resolution changed from {}x{} to {}x{}",
+                frame.stream_id, last.width, last.height, format.width, format.height
+            );
+            record(self.events.as_ref(), "resolution", message);
+            update_resolution(metrics, frame.stream_id, format.width, format.height);
+        }
+        if last.sample_aspect_ratio != format.sample_aspect_ratio
+            || last.display_aspect_ratio != format.display_aspect_ratio
+        {
+            metrics
+                .format_changes
+                .with_label_values(&[frame.stream_id, "aspect"])
+                .inc();
+            let (dar_num, dar_den) = format.display_aspect_ratio;
+            let message = format!(
+                "Stream {} aspect ratio changed to SAR {} DAR {}:{}",
+                frame.stream_id, format.sample_aspect_ratio, dar_num, dar_den
+            );
+            record(self.events.as_ref(), "aspect", message);
+        }
+        if last.field_order != format.field_order {
+            // Frames lacking the flags don't count as a change
+            if last.field_order.is_some() && format.field_order.is_some() {
+                metrics
+                    .format_changes
+                    .with_label_values(&[frame.stream_id, "field_order"])
+                    .inc();
+                let message = format!("Stream {} field order changed", frame.stream_id);
+                record(self.events.as_ref(), "field_order", message);
+            }
+            export_field_order(metrics, frame.stream_id, format.field_order);
+        }
+        if last.color != format.color
+            && let Some(color) = &format.color
+        {
+            if last.color.is_some() {
+                metrics
+                    .format_changes
+                    .with_label_values(&[frame.stream_id, "color"])
+                    .inc();
+                let message = format!(
+                    "Stream {} color changed to {}/{}/{}/{}",
+                    frame.stream_id, color.primaries, color.transfer, color.space, color.range
+                );
+                record(self.events.as_ref(), "color", message);
+            }
+            color.export(frame.stream_id, metrics);
+        }
+        *last = format;
+    }
+}
+
+fn record(events: Option<&EventRecorder>, change: &'static str, message: String) {
+    if let Some(events) = events {
+        events.record("format_change", Some(change), &message);
+    }
+}
+
+fn export_field_order(metrics: &StreamMetrics, stream_id: &str, order: Option<FieldOrder>) {
+    if let Some(order) = order {
+        let interlaced = order != FieldOrder::Progressive;
+        metrics
+            .video_interlaced
+            .with_label_values(&[stream_id])
+            .set(if interlaced { 1.0 } else { 0.0 });
+    }
+}
+
+/// Label values of the stream's series of an info gauge, in the order of
+/// `label_names`
+fn stream_series(gauge: &GaugeVec, label_names: &[&str], stream_id: &str) -> Vec<Vec<String>> {
+    let mut series = Vec::new();
+    for family in gauge.collect() {
+        for metric in family.get_metric() {
+            let label = |name: &str| {
+                metric
+                    .get_label()
+                    .iter()
+                    .find(|pair| pair.get_name() == name)
+                    .map_or(String::new(), |pair| pair.get_value().to_string())
+            };
+            if label("stream_id") == stream_id {
+                series.push(label_names.iter().map(|name| label(name)).collect());
+            }
+        }
+    }
+    series
+}
+
+/// Drops the stream's series of an info gauge, whichever values they carry
+fn clear_series(gauge: &GaugeVec, label_names: &[&str], stream_id: &str) {
+    for values in stream_series(gauge, label_names, stream_id) {
+        let values: Vec<&str> = values.iter().map(String::as_str).collect();
+        let _ = gauge.remove_label_values(&values);
+    }
+}
+
+/// Rewrites the resolution of the stream's info series, keeping the codec
+/// labels found by the last `-show_streams` probe.
+fn update_resolution(metrics: &StreamMetrics, stream_id: &str, width: u32, height: u32) {
+    for mut values in stream_series(&metrics.stream_info, &STREAM_INFO_LABELS, stream_id) {
+        let old: Vec<&str> = values.iter().map(String::as_str).collect();
+        let _ = metrics.stream_info.remove_label_values(&old);
+        values[4] = width.to_string();
+        values[5] = height.to_string();
+        let new: Vec<&str> = values.iter().map(String::as_str).collect();
+        metrics.stream_info.with_label_values(&new).set(1.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::Registry;
+
+    fn frame(width: u32, height: u32, sar: &'static str) -> FrameRecord<'static> {
+        FrameRecord {
+            media_type: "video",
+            stream_id: "0",
+            width: Some(width),
+            height: Some(height),
+            sample_aspect_ratio: sar,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_format_changes() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let info = ["0", "video", "h264", "High", "1920", "1080", "yuv420p"];
+        metrics.stream_info.with_label_values(&info).set(1.0);
+        let mut tracker = FormatTracker::default();
+        let changes = |kind| metrics.format_changes.with_label_values(&["0", kind]).get();
+
+        tracker.observe(&frame(1920, 1080, "1:1"), &metrics);
+        tracker.observe(&frame(1920, 1080, "1:1"), &metrics);
+        assert_eq!(changes("resolution"), 0.0);
+
+        // Same 16:9 picture at a lower resolution
+        tracker.observe(&frame(1280, 720, "1:1"), &metrics);
+        assert_eq!(changes("resolution"), 1.0);
+        assert_eq!(changes("aspect"), 0.0);
+        let resized = ["0", "video", "h264", "High", "1280", "720", "yuv420p"];
+        assert_eq!(metrics.stream_info.with_label_values(&resized).get(), 1.0);
+        assert_eq!(metrics.stream_info.collect()[0].get_metric().len(), 1);
+
+        // Anamorphic 4:3 in the same raster
+        tracker.observe(&frame(1280, 720, "3:4"), &metrics);
+        assert_eq!(changes("resolution"), 1.0);
+        assert_eq!(changes("aspect"), 1.0);
+    }
+
+    fn audio_frame(channels: u32, layout: &'static str) -> FrameRecord<'static> {
+        FrameRecord {
+            media_type: "audio",
+            stream_id: "1",
+            channels: Some(channels),
+            channel_layout: layout,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_channel_layout_changes() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        // The stream probe reported the layout under a different name
+        metrics
+            .audio_channel_layout
+            .with_label_values(&["1", "5.1"])
+            .set(1.0);
+        let mut tracker = FormatTracker::default();
+        let layout = |name| {
+            metrics
+                .audio_channel_layout
+                .with_label_values(&["1", name])
+                .get()
+        };
+
+        tracker.observe(&audio_frame(6, "5.1(side)"), &metrics);
+        tracker.observe(&audio_frame(6, "5.1(side)"), &metrics);
+        assert_eq!(metrics.audio_channels.with_label_values(&["1"]).get(), 6.0);
+        assert_eq!(layout("5.1(side)"), 1.0);
+        assert_eq!(
+            metrics.audio_channel_layout.collect()[0].get_metric().len(),
+            1
+        );
+
+        // The surround mix collapses to stereo
+        tracker.observe(&audio_frame(2, "stereo"), &metrics);
+        let changes = metrics
+            .format_changes
+            .with_label_values(&["1", "channel_layout"]);
+        assert_eq!(changes.get(), 1.0);
+        assert_eq!(metrics.audio_channels.with_label_values(&["1"]).get(), 2.0);
+        assert_eq!(layout("stereo"), 1.0);
+        assert_eq!(
+            metrics.audio_channel_layout.collect()[0].get_metric().len(),
+            1
+        );
+    }
+
+    fn field_frame(interlaced: bool, top_field_first: bool) -> FrameRecord<'static> {
+        FrameRecord {
+            interlaced: Some(interlaced),
+            top_field_first: Some(top_field_first),
+            ..frame(1920, 1080, "1:1")
+        }
+    }
+
+    #[test]
+    fn test_field_order_changes() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let mut tracker = FormatTracker::default();
+        let interlaced = || metrics.video_interlaced.with_label_values(&["0"]).get();
+        let changes = || {
+            metrics
+                .format_changes
+                .with_label_values(&["0", "field_order"])
+                .get()
+        };
+
+        tracker.observe(&field_frame(false, false), &metrics);
+        assert_eq!(interlaced(), 0.0);
+
+        // An interlaced contribution switches in, then flips its field order
+        tracker.observe(&field_frame(true, true), &metrics);
+        assert_eq!(interlaced(), 1.0);
+        tracker.observe(&field_frame(true, false), &metrics);
+        assert_eq!(interlaced(), 1.0);
+        assert_eq!(changes(), 2.0);
+    }
+
+    fn color_frame(
+        transfer: &'static str,
+        key_frame: bool,
+        mastering: bool,
+    ) -> FrameRecord<'static> {
+        FrameRecord {
+            key_frame,
+            color_primaries: "bt2020",
+            color_transfer: transfer,
+            color_space: "bt2020nc",
+            color_range: "tv",
+            mastering_display: mastering,
+            ..frame(3840, 2160, "1:1")
+        }
+    }
+
+    #[test]
+    fn test_color_changes() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let mut tracker = FormatTracker::default();
+        let info = |transfer, mastering| {
+            metrics
+                .color_info
+                .with_label_values(&["0", "bt2020", transfer, "bt2020nc", "tv", mastering])
+                .get()
+        };
+        let changes = || {
+            metrics
+                .format_changes
+                .with_label_values(&["0", "color"])
+                .get()
+        };
+
+        // PQ with mastering display metadata on keyframes only
+        tracker.observe(&color_frame("smpte2084", true, true), &metrics);
+        tracker.observe(&color_frame("smpte2084", false, false), &metrics);
+        assert_eq!(info("smpte2084", "true"), 1.0);
+        assert_eq!(changes(), 0.0);
+
+        // The feed flips to HLG without mastering metadata
+        tracker.observe(&color_frame("arib-std-b67", true, false), &metrics);
+        assert_eq!(info("arib-std-b67", "false"), 1.0);
+        assert_eq!(metrics.color_info.collect()[0].get_metric().len(), 1);
+        assert_eq!(changes(), 1.0);
+    }
+
+    #[test]
+    fn test_unknown_sar_is_square() {
+        let square = VideoFormat::from_frame(&frame(1920, 1080, "0:1")).unwrap();
+        assert_eq!(square.display_aspect_ratio, (16, 9));
+        let pal = VideoFormat::from_frame(&frame(720, 576, "16:15")).unwrap();
+        assert_eq!(pal.display_aspect_ratio, (4, 3));
+    }
+
+    /// `base` and copies of it that each differ from it in one field
+    fn variants(base: FrameRecord<'static>) -> Vec<FrameRecord<'static>> {
+        let with = |change: fn(&mut FrameRecord<'static>)| {
+            let mut frame = FrameRecord { ..base };
+            change(&mut frame);
+            frame
+        };
+        vec![
+            with(|_| {}),
+            with(|f| f.width = Some(1280)),
+            with(|f| f.width = None),
+            with(|f| f.height = Some(720)),
+            with(|f| f.height = None),
+            with(|f| f.sample_aspect_ratio = "4:3"),
+            with(|f| f.interlaced = Some(true)),
+            with(|f| f.interlaced = None),
+            with(|f| f.top_field_first = Some(false)),
+            with(|f| f.color_primaries = "bt709"),
+            with(|f| f.color_primaries = ""),
+            with(|f| f.color_transfer = "bt709"),
+            with(|f| {
+                f.color_primaries = "";
+                f.color_transfer = "";
+            }),
+            with(|f| f.color_space = "bt709"),
+            with(|f| f.color_range = "pc"),
+            with(|f| f.mastering_display = false),
+            with(|f| f.sample_rate = Some(44100)),
+            with(|f| f.sample_rate = None),
+            with(|f| f.channels = Some(2)),
+            with(|f| f.channels = None),
+            with(|f| f.channel_layout = "stereo"),
+            with(|f| f.pts_time = Some(1.0)),
+            with(|f| f.qp = Some(30.0)),
+            with(|f| f.captions = true),
+        ]
+    }
+
+    fn hdr_frame() -> FrameRecord<'static> {
+        FrameRecord {
+            interlaced: Some(false),
+            top_field_first: Some(true),
+            key_frame: true,
+            color_primaries: "bt2020",
+            color_transfer: "smpte2084",
+            color_space: "bt2020nc",
+            color_range: "tv",
+            mastering_display: true,
+            ..frame(3840, 2160, "1:1")
+        }
+    }
+
+    #[test]
+    fn test_video_format_matches_from_frame() {
+        let last = VideoFormat::from_frame(&hdr_frame()).unwrap();
+        for frame in variants(hdr_frame()) {
+            let format = VideoFormat::from_frame(&frame);
+            assert_eq!(last.matches(&frame), format.as_ref() == Some(&last));
+        }
+        let sdr = FrameRecord {
+            color_primaries: "",
+            color_transfer: "",
+            ..hdr_frame()
+        };
+        let last = VideoFormat::from_frame(&sdr).unwrap();
+        for frame in variants(sdr) {
+            let format = VideoFormat::from_frame(&frame);
+            assert_eq!(last.matches(&frame), format.as_ref() == Some(&last));
+        }
+    }
+
+    #[test]
+    fn test_color_info_matches_from_frame() {
+        let last = ColorInfo::from_frame(&hdr_frame()).unwrap();
+        for frame in variants(hdr_frame()) {
+            let color = ColorInfo::from_frame(&frame);
+            assert_eq!(last.matches(&frame), color.as_ref() == Some(&last));
+        }
+        // Frames between keyframes lack the mastering display metadata
+        let frame = FrameRecord {
+            key_frame: false,
+            mastering_display: false,
+            ..hdr_frame()
+        };
+        assert!(last.matches(&frame));
+    }
+
+    #[test]
+    fn test_audio_format_matches_from_frame() {
+        let base = FrameRecord {
+            sample_rate: Some(48000),
+            ..audio_frame(6, "5.1(side)")
+        };
+        let last = AudioFormat::from_frame(&base).unwrap();
+        for frame in variants(base) {
+            let format = AudioFormat::from_frame(&frame);
+            assert_eq!(last.matches(&frame), format.as_ref() == Some(&last));
+        }
+    }
+}
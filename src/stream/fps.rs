@@ -0,0 +1,140 @@
+// stream/fps.rs
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Frames of each elementary stream the rate is measured over
+const WINDOW: usize = 100;
+/// How often the rates are recomputed
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+struct FpsWindow {
+    media_type: String,
+    times: VecDeque<f64>,
+    /// Frames each recorded timestamp stands for, more than 1 with decimation
+    scale: f64,
+}
+
+/// Measures the frame rate of each elementary stream from the timestamps of
+/// its recent frames. Streams are kept apart, each with the decimation scale
+/// its timestamps were recorded with, and are only allocated for on their
+/// first frame.
+pub struct FpsTracker {
+    streams: HashMap<String, FpsWindow>,
+    last_update: Instant,
+}
+
+impl Default for FpsTracker {
+    fn default() -> Self {
+        Self {
+            streams: HashMap::new(),
+            last_update: Instant::now(),
+        }
+    }
+}
+
+impl FpsTracker {
+    /// Records the timestamp of a frame standing for `scale` frames
+    pub fn observe(&mut self, stream_id: &str, media_type: &str, pts_time: f64, scale: f64) {
+        if !self.streams.contains_key(stream_id) {
+            self.streams.insert(
+                stream_id.to_string(),
+                FpsWindow {
+                    media_type: media_type.to_string(),
+                    times: VecDeque::with_capacity(WINDOW),
+                    scale,
+                },
+            );
+        }
+        let window = self.streams.get_mut(stream_id).unwrap();
+        // Timestamps of another stream type or scale would skew the rate
+        if window.media_type != media_type || window.scale != scale {
+            window.media_type = media_type.to_string();
+            window.scale = scale;
+            window.times.clear();
+        }
+        if window.times.len() == WINDOW {
+            window.times.pop_front();
+        }
+        window.times.push_back(pts_time);
+    }
+
+    /// Calls `report` with the stream id, media type and frame rate of each
+    /// stream, at most once per second
+    pub fn update(&mut self, report: impl FnMut(&str, &str, f64)) {
+        self.update_at(Instant::now(), report)
+    }
+
+    fn update_at(&mut self, now: Instant, mut report: impl FnMut(&str, &str, f64)) {
+        if now.duration_since(self.last_update) < UPDATE_INTERVAL {
+            return;
+        }
+        self.last_update = now;
+        for (stream_id, window) in &self.streams {
+            let (Some(first), Some(last)) = (window.times.front(), window.times.back()) else {
+                continue;
+            };
+            let span = last - first;
+            // Timestamp discontinuities inside the window would give negative
+            // or absurd rates
+            if window.times.len() >= 2 && span > 0.0 {
+                let intervals = (window.times.len() - 1) as f64;
+                report(
+                    stream_id,
+                    &window.media_type,
+                    intervals * window.scale / span,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rates(tracker: &mut FpsTracker, now: Instant) -> Vec<(String, String, f64)> {
+        let mut rates = Vec::new();
+        tracker.update_at(now, |stream_id, media_type, fps| {
+            rates.push((stream_id.to_string(), media_type.to_string(), fps));
+        });
+        rates.sort_by(|a, b| a.0.cmp(&b.0));
+        rates
+    }
+
+    #[test]
+    fn test_fps_per_stream() {
+        let mut tracker = FpsTracker::default();
+        let start = tracker.last_update;
+        // Every 4th frame of 25 fps video, every audio frame at 50 fps
+        for i in 0..200 {
+            if i % 4 == 0 {
+                tracker.observe("0", "video", i as f64 * 0.04, 4.0);
+            }
+            tracker.observe("1", "audio", i as f64 * 0.02, 1.0);
+        }
+        assert!(rates(&mut tracker, start).is_empty());
+
+        let rates = rates(&mut tracker, start + UPDATE_INTERVAL);
+        assert_eq!(rates.len(), 2);
+        assert_eq!((rates[0].0.as_str(), rates[0].1.as_str()), ("0", "video"));
+        assert!((rates[0].2 - 25.0).abs() < 1e-9, "{:?}", rates);
+        assert_eq!((rates[1].0.as_str(), rates[1].1.as_str()), ("1", "audio"));
+        assert!((rates[1].2 - 50.0).abs() < 1e-9, "{:?}", rates);
+    }
+
+    #[test]
+    fn test_fps_window_restarts() {
+        let mut tracker = FpsTracker::default();
+        let start = tracker.last_update;
+        for i in 0..10 {
+            tracker.observe("0", "video", i as f64 * 0.04, 1.0);
+        }
+        // A new scale starts the window over instead of rescaling old frames
+        tracker.observe("0", "video", 0.4, 4.0);
+        assert!(rates(&mut tracker, start + UPDATE_INTERVAL).is_empty());
+        tracker.observe("0", "video", 0.56, 4.0);
+        let rates = rates(&mut tracker, start + UPDATE_INTERVAL * 2);
+        assert!((rates[0].2 - 25.0).abs() < 1e-9, "{:?}", rates);
+    }
+}
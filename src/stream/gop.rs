@@ -0,0 +1,130 @@
+// stream/gop.rs
+
+use crate::metrics::StreamMetrics;
+use crate::stream::records::PacketRecord;
+use std::collections::{HashMap, VecDeque};
+
+/// Number of recent keyframe intervals the average is taken over
+const INTERVAL_HISTORY: usize = 10;
+/// Intervals needed before a keyframe can be judged late
+const MIN_HISTORY: usize = 3;
+/// A keyframe is late when its interval exceeds the average by this factor
+const LATE_FACTOR: f64 = 1.5;
+
+#[derive(Default)]
+struct GopState {
+    last_keyframe: Option<f64>,
+    packets: u64,
+    intervals: VecDeque<f64>,
+}
+
+impl GopState {
+    fn average_interval(&self) -> Option<f64> {
+        if self.intervals.is_empty() {
+            return None;
+        }
+        Some(self.intervals.iter().sum::<f64>() / self.intervals.len() as f64)
+    }
+}
+
+/// Measures GOP length and keyframe cadence of each video stream from the
+/// keyframe flag of its packets.
+#[derive(Default)]
+pub struct GopTracker {
+    streams: HashMap<String, GopState>,
+}
+
+impl GopTracker {
+    pub fn observe(&mut self, packet: &PacketRecord, metrics: &StreamMetrics) {
+        if packet.media_type != "video" {
+            return;
+        }
+        if !self.streams.contains_key(packet.stream_id) {
+            self.streams
+                .insert(packet.stream_id.to_string(), GopState::default());
+        }
+        let state = self.streams.get_mut(packet.stream_id).unwrap();
+
+        if !packet.is_keyframe() {
+            state.packets += 1;
+            return;
+        }
+        let Some(time) = packet.dts_time else {
+            return;
+        };
+
+        if let Some(last) = state.last_keyframe {
+            let interval = time - last;
+            if interval > 0.0 {
+                metrics
+                    .gop_length
+                    .with_label_values(&[packet.stream_id])
+                    .set(state.packets as f64 + 1.0);
+
+                if state.intervals.len() >= MIN_HISTORY
+                    && let Some(average) = state.average_interval()
+                    && interval > average * LATE_FACTOR
+                {
+                    metrics
+                        .keyframes_late
+                        .with_label_values(&[packet.stream_id])
+                        .inc();
+                }
+
+                state.intervals.push_back(interval);
+                if state.intervals.len() > INTERVAL_HISTORY {
+                    state.intervals.pop_front();
+                }
+                if let Some(average) = state.average_interval() {
+                    metrics
+                        .keyframe_interval
+                        .with_label_values(&[packet.stream_id])
+                        .set(average);
+                }
+            } else {
+                // Timestamps went backwards, start measuring over
+                state.intervals.clear();
+            }
+        }
+        state.last_keyframe = Some(time);
+        state.packets = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::Registry;
+
+    fn packet(time: f64, keyframe: bool) -> PacketRecord<'static> {
+        PacketRecord {
+            media_type: "video",
+            stream_id: "0",
+            size: Some(1000.0),
+            dts_time: Some(time),
+            flags: if keyframe { "K__" } else { "___" },
+        }
+    }
+
+    #[test]
+    fn test_gop_metrics() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let mut tracker = GopTracker::default();
+
+        // Four 2 second GOPs of 50 packets at 25 fps
+        for i in 0..=200 {
+            tracker.observe(&packet(i as f64 * 0.04, i % 50 == 0), &metrics);
+        }
+        assert_eq!(metrics.gop_length.with_label_values(&["0"]).get(), 50.0);
+        assert!((metrics.keyframe_interval.with_label_values(&["0"]).get() - 2.0).abs() < 1e-6);
+        assert_eq!(metrics.keyframes_late.with_label_values(&["0"]).get(), 0.0);
+
+        // The encoder skips a keyframe
+        for i in 201..=300 {
+            tracker.observe(&packet(i as f64 * 0.04, i % 100 == 0), &metrics);
+        }
+        assert_eq!(metrics.gop_length.with_label_values(&["0"]).get(), 100.0);
+        assert_eq!(metrics.keyframes_late.with_label_values(&["0"]).get(), 1.0);
+    }
+}
@@ -0,0 +1,1324 @@
+// stream/hls.rs
+
+use crate::config::{HttpOptions, MonitorOptions, StreamConfig, StreamType};
+use crate::metrics::StreamMetrics;
+use anyhow::{Context, Result};
+use chrono::{DateTime, FixedOffset, Utc};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{CryptoProvider, verify_tls12_signature, verify_tls13_signature};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+use url::Url;
+
+const MIN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const MIN_PART_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(6);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest wait for data of a response, for requests without a timeout of
+/// their own
+pub const READ_TIMEOUT: Duration = Duration::from_secs(30);
+pub const MIN_REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+/// How often the playlist age is reported, independently of the polls
+const AGE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub uri: String,
+    /// Media sequence number
+    pub sequence: u64,
+    /// `EXTINF` duration in seconds
+    pub duration: f64,
+    /// Length of an `EXT-X-BYTERANGE` sub-range
+    pub byte_range: Option<u64>,
+    pub program_date_time: Option<DateTime<FixedOffset>>,
+}
+
+/// LL-HLS partial segment (`EXT-X-PART`)
+#[derive(Debug, Clone)]
+pub struct Part {
+    /// Media sequence number of the segment the part belongs to
+    pub sequence: u64,
+    /// Position of the part within its segment
+    pub index: u64,
+    pub duration: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MediaPlaylist {
+    pub target_duration: Option<f64>,
+    pub media_sequence: u64,
+    /// `EXT-X-ENDLIST`: no segments will be added anymore
+    pub ended: bool,
+    pub segments: Vec<Segment>,
+    /// `EXT-X-PART-INF` PART-TARGET of LL-HLS playlists
+    pub part_target: Option<f64>,
+    /// Parts of the newest segments, the last ones may belong to a segment
+    /// still being written
+    pub parts: Vec<Part>,
+    /// `EXT-X-SERVER-CONTROL` CAN-BLOCK-RELOAD=YES
+    pub can_block_reload: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Variant {
+    pub uri: String,
+    /// Declared peak bitrate in bits per second
+    pub bandwidth: Option<u64>,
+    /// Declared average bitrate in bits per second
+    pub average_bandwidth: Option<u64>,
+    /// Declared resolution, e.g. `1280x720`
+    pub resolution: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Playlist {
+    Master(Vec<Variant>),
+    Media(MediaPlaylist),
+}
+
+impl MediaPlaylist {
+    pub fn newest_segment(&self) -> Option<&Segment> {
+        self.segments.last()
+    }
+
+    /// Program date time of the end of the newest media listed, including the
+    /// parts of a segment still being written. `None` without a program date
+    /// time or past the range of timestamps.
+    pub fn live_edge(&self) -> Option<DateTime<FixedOffset>> {
+        let newest = self.newest_segment()?;
+        let pending: f64 = self
+            .parts
+            .iter()
+            .filter(|part| part.sequence > newest.sequence)
+            .map(|part| part.duration)
+            .sum();
+        let duration = newest.duration + pending;
+        newest
+            .program_date_time?
+            .checked_add_signed(time_delta(duration)?)
+    }
+
+    /// `_HLS_msn` and `_HLS_part` of the part following the newest one, which
+    /// a server supporting blocking reload holds the playlist request for
+    pub fn next_part(&self) -> Option<(u64, u64)> {
+        let newest = self.parts.last()?;
+        let complete = self
+            .newest_segment()
+            .is_some_and(|segment| segment.sequence >= newest.sequence);
+        if complete {
+            Some((newest.sequence + 1, 0))
+        } else {
+            Some((newest.sequence, newest.index + 1))
+        }
+    }
+}
+
+fn parse_program_date_time(value: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(value)
+        .or_else(|_| DateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f%z"))
+        .ok()
+}
+
+/// Looks up an attribute of an attribute list like
+/// `BANDWIDTH=1280000,CODECS="avc1.4d401f,mp4a.40.2"`, without quotes
+fn attribute<'a>(attributes: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = attributes;
+    while !rest.is_empty() {
+        let (key, value) = rest.split_once('=')?;
+        let (value, next) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"')?;
+                let next = quoted[end + 1..].strip_prefix(',').unwrap_or_default();
+                (&quoted[..end], next)
+            }
+            None => value.split_once(',').unwrap_or((value, "")),
+        };
+        if key.trim() == name {
+            return Some(value);
+        }
+        rest = next;
+    }
+    None
+}
+
+pub fn parse_playlist(text: &str) -> Result<Playlist> {
+    let mut lines = text.lines().map(str::trim).filter(|l| !l.is_empty());
+    match lines.next() {
+        Some("#EXTM3U") => {}
+        _ => anyhow::bail!("Playlist does not start with #EXTM3U"),
+    }
+
+    let mut variants = Vec::new();
+    let mut pending_variant: Option<Variant> = None;
+    let mut playlist = MediaPlaylist::default();
+    let mut pending_duration: Option<f64> = None;
+    let mut pending_byte_range: Option<u64> = None;
+    let mut pending_pdt: Option<DateTime<FixedOffset>> = None;
+    let mut next_pdt: Option<DateTime<FixedOffset>> = None;
+    let mut pending_parts = 0;
+
+    for line in lines {
+        if let Some(attributes) = line.strip_prefix("#EXT-X-STREAM-INF:") {
+            pending_variant = Some(Variant {
+                uri: String::new(),
+                bandwidth: attribute(attributes, "BANDWIDTH").and_then(|v| v.parse().ok()),
+                average_bandwidth: attribute(attributes, "AVERAGE-BANDWIDTH")
+                    .and_then(|v| v.parse().ok()),
+                resolution: attribute(attributes, "RESOLUTION").map(str::to_string),
+            });
+        } else if let Some(value) = line.strip_prefix("#EXT-X-TARGETDURATION:") {
+            playlist.target_duration = parse_seconds(value);
+        } else if let Some(value) = line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+            playlist.media_sequence = value.parse().unwrap_or_default();
+        } else if let Some(value) = line.strip_prefix("#EXT-X-BYTERANGE:") {
+            let length = value.split('@').next().unwrap_or_default();
+            pending_byte_range = length.parse().ok();
+        } else if let Some(value) = line.strip_prefix("#EXTINF:") {
+            let duration = value.split(',').next().unwrap_or_default();
+            pending_duration = parse_seconds(duration);
+        } else if let Some(attributes) = line.strip_prefix("#EXT-X-PART:") {
+            playlist.parts.push(Part {
+                sequence: playlist.media_sequence + playlist.segments.len() as u64,
+                index: pending_parts,
+                duration: attribute(attributes, "DURATION")
+                    .and_then(parse_seconds)
+                    .unwrap_or(0.0),
+            });
+            pending_parts += 1;
+        } else if let Some(attributes) = line.strip_prefix("#EXT-X-PART-INF:") {
+            playlist.part_target = attribute(attributes, "PART-TARGET").and_then(parse_seconds);
+        } else if let Some(attributes) = line.strip_prefix("#EXT-X-SERVER-CONTROL:") {
+            playlist.can_block_reload = attribute(attributes, "CAN-BLOCK-RELOAD") == Some("YES");
+        } else if let Some(value) = line.strip_prefix("#EXT-X-PROGRAM-DATE-TIME:") {
+            pending_pdt = parse_program_date_time(value);
+        } else if line == "#EXT-X-ENDLIST" {
+            playlist.ended = true;
+        } else if line == "#EXT-X-DISCONTINUITY" {
+            // A discontinuity invalidates extrapolated timestamps until the next PDT tag
+            next_pdt = None;
+        } else if line.starts_with('#') {
+            continue;
+        } else if let Some(mut variant) = pending_variant.take() {
+            variant.uri = line.to_string();
+            variants.push(variant);
+        } else {
+            let duration = pending_duration.take().unwrap_or(0.0);
+            pending_parts = 0;
+            let program_date_time = pending_pdt.take().or(next_pdt);
+            next_pdt =
+                program_date_time.and_then(|pdt| pdt.checked_add_signed(time_delta(duration)?));
+            playlist.segments.push(Segment {
+                uri: line.to_string(),
+                sequence: playlist.media_sequence + playlist.segments.len() as u64,
+                duration,
+                byte_range: pending_byte_range.take(),
+                program_date_time,
+            });
+        }
+    }
+
+    if !variants.is_empty() {
+        return Ok(Playlist::Master(variants));
+    }
+    Ok(Playlist::Media(playlist))
+}
+
+/// Parses a duration in seconds, which `f64` would also accept as negative,
+/// infinite or NaN
+fn parse_seconds(value: &str) -> Option<f64> {
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+}
+
+/// `seconds` to add to a timestamp, `None` if out of range
+pub fn time_delta(seconds: f64) -> Option<chrono::Duration> {
+    chrono::Duration::try_milliseconds((seconds * 1000.0) as i64)
+}
+
+/// Accepts any server certificate, for `--tls-insecure`
+#[derive(Debug)]
+struct NoVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Builds the HTTP agent used for playlist requests, honouring the TLS
+/// options the same way ffprobe does. An origin that accepts a connection
+/// and then stalls fails the request after `READ_TIMEOUT`.
+pub fn http_agent(http: &HttpOptions) -> Result<ureq::Agent> {
+    let builder = ureq::AgentBuilder::new()
+        .timeout_connect(CONNECT_TIMEOUT)
+        .timeout_read(READ_TIMEOUT);
+    let insecure = http.tls_insecure == Some(true);
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let config = match (&http.tls_ca_file, insecure) {
+        (_, true) => config
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoVerification(provider)))
+            .with_no_client_auth(),
+        (Some(path), false) => {
+            let mut roots = RootCertStore::empty();
+            let certs = CertificateDer::pem_file_iter(path)
+                .with_context(|| format!("Failed to read CA file {}", path.display()))?;
+            for cert in certs {
+                roots.add(cert.context("Invalid certificate in CA file")?)?;
+            }
+            config.with_root_certificates(roots).with_no_client_auth()
+        }
+        (None, false) => return Ok(builder.build()),
+    };
+    Ok(builder.tls_config(Arc::new(config)).build())
+}
+
+/// Fetches a playlist or manifest, failing once the whole request took
+/// longer than `timeout`
+pub fn fetch_playlist(
+    agent: &ureq::Agent,
+    location: &str,
+    http: &HttpOptions,
+    timeout: Duration,
+) -> Result<String> {
+    if location.starts_with("http://") || location.starts_with("https://") {
+        let mut request = agent.get(location).timeout(timeout);
+        for (name, value) in http.request_headers() {
+            request = request.set(&name, &value);
+        }
+        request
+            .call()
+            .with_context(|| format!("Failed to fetch playlist {}", location))?
+            .into_string()
+            .context("Failed to read playlist body")
+    } else {
+        std::fs::read_to_string(location)
+            .with_context(|| format!("Failed to read playlist {}", location))
+    }
+}
+
+/// Downloads a segment and returns its size in bytes
+pub fn fetch_segment(agent: &ureq::Agent, location: &str, http: &HttpOptions) -> Result<u64> {
+    if location.starts_with("http://") || location.starts_with("https://") {
+        let mut request = agent.get(location);
+        for (name, value) in http.request_headers() {
+            request = request.set(&name, &value);
+        }
+        let response = request
+            .call()
+            .with_context(|| format!("Failed to fetch segment {}", location))?;
+        std::io::copy(&mut response.into_reader(), &mut std::io::sink())
+            .context("Failed to read segment body")
+    } else {
+        std::fs::read(location)
+            .map(|body| body.len() as u64)
+            .with_context(|| format!("Failed to read segment {}", location))
+    }
+}
+
+/// Picks the segments added since the previous poll by their media sequence
+#[derive(Default)]
+pub struct SegmentAdvance {
+    last_sequence: Option<u64>,
+}
+
+impl SegmentAdvance {
+    /// The segments of the first poll are the backlog, not new segments. A
+    /// media sequence going backwards, e.g. after a packager restart, starts
+    /// over.
+    pub fn new_segments<'a>(&mut self, playlist: &'a MediaPlaylist) -> &'a [Segment] {
+        let Some(newest) = playlist.newest_segment().map(|segment| segment.sequence) else {
+            return &[];
+        };
+        let previous = self.last_sequence.replace(newest);
+        match previous {
+            Some(previous) if previous <= newest => {
+                let first = playlist
+                    .segments
+                    .iter()
+                    .position(|segment| segment.sequence > previous)
+                    .unwrap_or(playlist.segments.len());
+                &playlist.segments[first..]
+            }
+            _ => &[],
+        }
+    }
+}
+
+/// Picks the LL-HLS parts added since the previous poll and remembers the
+/// part to block for on the next playlist request
+#[derive(Default)]
+pub struct PartAdvance {
+    last_part: Option<(u64, u64)>,
+    blocking_reload: Option<(u64, u64)>,
+}
+
+impl PartAdvance {
+    /// Like segments, the parts of the first poll are the backlog. Playlists
+    /// without parts leave the position alone.
+    pub fn new_parts<'a>(&mut self, playlist: &'a MediaPlaylist) -> &'a [Part] {
+        self.blocking_reload = playlist
+            .next_part()
+            .filter(|_| playlist.can_block_reload && !playlist.ended);
+        let Some(newest) = playlist
+            .parts
+            .last()
+            .map(|part| (part.sequence, part.index))
+        else {
+            return &[];
+        };
+        let previous = self.last_part.replace(newest);
+        match previous {
+            Some(previous) if previous <= newest => {
+                let first = playlist
+                    .parts
+                    .iter()
+                    .position(|part| (part.sequence, part.index) > previous)
+                    .unwrap_or(playlist.parts.len());
+                &playlist.parts[first..]
+            }
+            _ => &[],
+        }
+    }
+
+    /// The delivery directive of the next request, cleared so a failing
+    /// request falls back to plain polling
+    pub fn take_blocking_reload(&mut self) -> Option<(u64, u64)> {
+        self.blocking_reload.take()
+    }
+}
+
+/// Adds the LL-HLS delivery directives asking the server to hold the
+/// response until the given part is available
+pub fn blocking_reload_url(media_url: &str, (sequence, part): (u64, u64)) -> String {
+    match Url::parse(media_url) {
+        Ok(mut url) if matches!(url.scheme(), "http" | "https") => {
+            url.query_pairs_mut()
+                .append_pair("_HLS_msn", &sequence.to_string())
+                .append_pair("_HLS_part", &part.to_string());
+            url.to_string()
+        }
+        _ => media_url.to_string(),
+    }
+}
+
+/// Follows how long the newest segment of a playlist has not changed. The
+/// age keeps growing while polls fail.
+#[derive(Default)]
+pub struct Staleness {
+    newest: Option<u64>,
+    advanced_at: Option<Instant>,
+    /// Age after which the playlist counts as stale, unset for ended playlists
+    limit: Option<Duration>,
+    stale: bool,
+}
+
+impl Staleness {
+    pub fn observe(&mut self, newest: Option<u64>, limit: Option<Duration>, now: Instant) {
+        self.limit = limit;
+        if self.advanced_at.is_none() || newest != self.newest {
+            self.newest = newest;
+            self.advanced_at = Some(now);
+        }
+    }
+
+    /// The age of the playlist and whether it just turned stale
+    pub fn check(&mut self, now: Instant) -> Option<(Duration, bool)> {
+        let age = now.duration_since(self.advanced_at?);
+        let stale = self.limit.is_some_and(|limit| age > limit);
+        let turned_stale = stale && !self.stale;
+        self.stale = stale;
+        Some((age, turned_stale))
+    }
+}
+
+pub fn resolve_uri(base: &str, uri: &str) -> String {
+    match Url::parse(base).and_then(|base| base.join(uri)) {
+        Ok(url) => url.to_string(),
+        Err(_) => match base.rfind('/') {
+            Some(idx) => format!("{}/{}", &base[..idx], uri),
+            None => uri.to_string(),
+        },
+    }
+}
+
+/// One stream per variant of the master playlist `stream` reads, labelled
+/// with the declared bandwidth and resolution. At most `max_variants`
+/// variants are kept, in playlist order, 0 keeps all.
+fn variant_streams(
+    stream: &StreamConfig,
+    master: &str,
+    variants: &[Variant],
+    max_variants: usize,
+) -> Vec<StreamConfig> {
+    let limit = if max_variants == 0 {
+        variants.len()
+    } else {
+        max_variants
+    };
+    variants
+        .iter()
+        .take(limit)
+        .enumerate()
+        .map(|(i, variant)| {
+            let mut labels = stream.labels.clone();
+            let bandwidth = variant.bandwidth.map(|b| b.to_string());
+            labels.insert("bandwidth".to_string(), bandwidth.unwrap_or_default());
+            let resolution = variant.resolution.clone();
+            labels.insert("resolution".to_string(), resolution.unwrap_or_default());
+            StreamConfig {
+                name: format!("{}-{}", stream.name, i),
+                input: resolve_uri(master, &variant.uri),
+                declared_bandwidth: variant.average_bandwidth.or(variant.bandwidth),
+                labels,
+                ..stream.clone()
+            }
+        })
+        .collect()
+}
+
+/// Replaces every HLS stream whose input is a master playlist by one stream
+/// per variant, so broken ABR rungs are not hidden behind the variant ffprobe
+/// selects. Streams whose playlist cannot be fetched are kept as they are.
+pub fn discover_variants(
+    streams: Vec<StreamConfig>,
+    options: &MonitorOptions,
+) -> Vec<StreamConfig> {
+    let mut discovered = Vec::new();
+    for stream in streams {
+        if !matches!(
+            StreamType::from_input(&stream.input),
+            Ok(StreamType::Hls(_))
+        ) {
+            discovered.push(stream);
+            continue;
+        }
+        let fetched = stream.monitor_options(options).and_then(|options| {
+            let agent = http_agent(&options.http)?;
+            parse_playlist(&fetch_playlist(
+                &agent,
+                &stream.input,
+                &options.http,
+                READ_TIMEOUT,
+            )?)
+        });
+        match fetched {
+            Ok(Playlist::Master(variants)) => {
+                let variants =
+                    variant_streams(&stream, &stream.input, &variants, options.hls_max_variants);
+                info!(
+                    "Monitoring {} variants of HLS stream {}",
+                    variants.len(),
+                    stream.name
+                );
+                discovered.extend(variants);
+            }
+            Ok(Playlist::Media(_)) => discovered.push(stream),
+            Err(e) => {
+                warn!(
+                    "Failed to discover the variants of HLS stream {}: {:#}",
+                    stream.name, e
+                );
+                discovered.push(stream);
+            }
+        }
+    }
+    discovered
+}
+
+/// Periodically fetches the media playlist of an HLS input alongside ffprobe
+/// and exports playlist-level metrics.
+pub struct PlaylistPoller {
+    url: String,
+    http: HttpOptions,
+    stream_type: &'static str,
+    /// Download new segments to measure their size and fetch time
+    fetch_segments: bool,
+    /// Target durations without a new segment before the playlist is stale
+    stale_factor: f64,
+    metrics: StreamMetrics,
+    running: Arc<AtomicBool>,
+}
+
+impl PlaylistPoller {
+    pub fn new(
+        url: String,
+        options: &MonitorOptions,
+        stream_type: &'static str,
+        metrics: StreamMetrics,
+        running: Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            url,
+            http: options.http.clone(),
+            stream_type,
+            fetch_segments: options.hls_segment_fetch,
+            stale_factor: options.hls_stale_factor,
+            metrics,
+            running,
+        }
+    }
+
+    pub fn spawn(self) -> thread::JoinHandle<()> {
+        thread::spawn(move || self.run())
+    }
+
+    fn run(&self) {
+        info!("Starting HLS playlist poller for {}", self.url);
+        let agent = match http_agent(&self.http) {
+            Ok(agent) => agent,
+            Err(e) => {
+                error!("Failed to set up HLS playlist requests: {:#}", e);
+                return;
+            }
+        };
+        let mut media_url = self.url.clone();
+        let mut advance = SegmentAdvance::default();
+        let mut parts = PartAdvance::default();
+        let mut target_duration = None;
+        let staleness = Arc::new(Mutex::new(Staleness::default()));
+        let reporter = self.spawn_age_reporter(staleness.clone());
+
+        while self.running.load(Ordering::SeqCst) {
+            let polled = self.poll_once(
+                &agent,
+                &mut media_url,
+                &mut advance,
+                &mut parts,
+                &staleness,
+                &mut target_duration,
+            );
+            let interval = match polled {
+                Ok(interval) => interval,
+                Err(e) => {
+                    warn!("HLS playlist poll failed: {:#}", e);
+                    DEFAULT_POLL_INTERVAL
+                }
+            };
+
+            let steps = (interval.as_millis() / 100).max(1);
+            for _ in 0..steps {
+                if !self.running.load(Ordering::SeqCst) {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+        }
+        let _ = reporter.join();
+        debug!("HLS playlist poller for {} stopped", self.url);
+    }
+
+    /// Reports the playlist age from a timer, so it keeps growing while a
+    /// request hangs
+    fn spawn_age_reporter(&self, staleness: Arc<Mutex<Staleness>>) -> thread::JoinHandle<()> {
+        let url = self.url.clone();
+        let stream_type = self.stream_type;
+        let metrics = self.metrics.clone();
+        let running = self.running.clone();
+        thread::spawn(move || {
+            let steps = AGE_INTERVAL.as_millis() / 100;
+            while running.load(Ordering::SeqCst) {
+                report_staleness(&url, stream_type, &metrics, &mut staleness.lock().unwrap());
+                for _ in 0..steps {
+                    if !running.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    thread::sleep(Duration::from_millis(100));
+                }
+            }
+        })
+    }
+
+    /// How long a playlist request may take: as long as the playlist may go
+    /// without a new segment, and for LL-HLS blocking reloads the up to three
+    /// target durations a server holds the request on top
+    fn request_timeout(&self, target_duration: Option<f64>, blocking: bool) -> Duration {
+        let target = target_duration.unwrap_or(DEFAULT_POLL_INTERVAL.as_secs_f64());
+        let factor = if blocking {
+            self.stale_factor + 3.0
+        } else {
+            self.stale_factor
+        };
+        Duration::try_from_secs_f64(target * factor)
+            .unwrap_or(READ_TIMEOUT)
+            .max(MIN_REQUEST_TIMEOUT)
+    }
+
+    fn poll_once(
+        &self,
+        agent: &ureq::Agent,
+        media_url: &mut String,
+        advance: &mut SegmentAdvance,
+        parts: &mut PartAdvance,
+        staleness: &Mutex<Staleness>,
+        target_duration: &mut Option<f64>,
+    ) -> Result<Duration> {
+        let blocking = parts.take_blocking_reload();
+        let location = match blocking {
+            Some(directive) => blocking_reload_url(media_url, directive),
+            None => media_url.clone(),
+        };
+        let timeout = self.request_timeout(*target_duration, blocking.is_some());
+        let mut playlist = parse_playlist(&fetch_playlist(agent, &location, &self.http, timeout)?)?;
+
+        if let Playlist::Master(variants) = &playlist {
+            // Follow the first variant, which is what ffprobe selects by default
+            let variant = variants
+                .first()
+                .context("Master playlist has no variants")?;
+            *media_url = resolve_uri(media_url, &variant.uri);
+            debug!("Following HLS variant {}", media_url);
+            let timeout = self.request_timeout(*target_duration, false);
+            playlist = parse_playlist(&fetch_playlist(agent, media_url, &self.http, timeout)?)?;
+        }
+
+        let Playlist::Media(playlist) = playlist else {
+            anyhow::bail!("Nested master playlist at {}", media_url);
+        };
+
+        if let Some(pdt) = playlist
+            .newest_segment()
+            .and_then(|segment| segment.program_date_time)
+        {
+            let offset = Utc::now().signed_duration_since(pdt).num_milliseconds() as f64 / 1000.0;
+            self.metrics
+                .hls_pdt_offset
+                .with_label_values(&[self.stream_type])
+                .set(offset);
+        }
+        if let Some(edge) = playlist.live_edge() {
+            let latency = Utc::now().signed_duration_since(edge).num_milliseconds() as f64 / 1000.0;
+            self.metrics
+                .hls_live_latency
+                .with_label_values(&[self.stream_type])
+                .set(latency);
+        }
+
+        let stale_limit = playlist
+            .target_duration
+            .filter(|_| !playlist.ended)
+            .and_then(|target| Duration::try_from_secs_f64(target * self.stale_factor).ok());
+        let newest = playlist.newest_segment().map(|segment| segment.sequence);
+        staleness
+            .lock()
+            .unwrap()
+            .observe(newest, stale_limit, Instant::now());
+        *target_duration = playlist.target_duration;
+
+        for segment in advance.new_segments(&playlist) {
+            self.observe_segment(agent, media_url, segment, playlist.target_duration);
+        }
+        for part in parts.new_parts(&playlist) {
+            self.observe_part(part, playlist.part_target);
+        }
+
+        if parts.blocking_reload.is_some() {
+            // The server holds the next request until a new part exists
+            return Ok(Duration::ZERO);
+        }
+        if let Some(part_target) = playlist
+            .part_target
+            .and_then(|target| Duration::try_from_secs_f64(target).ok())
+        {
+            return Ok(part_target.max(MIN_PART_POLL_INTERVAL));
+        }
+        let interval = playlist
+            .target_duration
+            .and_then(|target| Duration::try_from_secs_f64(target).ok())
+            .unwrap_or(DEFAULT_POLL_INTERVAL);
+        Ok(interval.max(MIN_POLL_INTERVAL))
+    }
+
+    fn observe_part(&self, part: &Part, part_target: Option<f64>) {
+        let labels = [self.stream_type];
+        self.metrics.hls_parts.with_label_values(&labels).inc();
+        self.metrics
+            .hls_part_duration
+            .with_label_values(&labels)
+            .observe(part.duration);
+        if let Some(target) = part_target {
+            self.metrics
+                .hls_part_target_deviation
+                .with_label_values(&labels)
+                .set(part.duration - target);
+        }
+    }
+
+    fn observe_segment(
+        &self,
+        agent: &ureq::Agent,
+        media_url: &str,
+        segment: &Segment,
+        target_duration: Option<f64>,
+    ) {
+        let labels = [self.stream_type];
+        self.metrics.hls_segments.with_label_values(&labels).inc();
+        self.metrics
+            .hls_segment_duration
+            .with_label_values(&labels)
+            .observe(segment.duration);
+        if let Some(target) = target_duration {
+            self.metrics
+                .hls_segment_target_deviation
+                .with_label_values(&labels)
+                .set(segment.duration - target);
+        }
+
+        if let Some(length) = segment.byte_range {
+            // Sub-range segments are not downloaded; their size is declared
+            self.metrics
+                .hls_segment_size
+                .with_label_values(&labels)
+                .observe(length as f64);
+        } else if self.fetch_segments {
+            let started = Instant::now();
+            match fetch_segment(agent, &resolve_uri(media_url, &segment.uri), &self.http) {
+                Ok(size) => {
+                    self.metrics
+                        .hls_segment_size
+                        .with_label_values(&labels)
+                        .observe(size as f64);
+                    self.metrics
+                        .hls_segment_fetch_duration
+                        .with_label_values(&labels)
+                        .observe(started.elapsed().as_secs_f64());
+                }
+                Err(e) => {
+                    warn!("HLS segment fetch failed: {:#}", e);
+                    self.metrics
+                        .hls_segment_fetch_errors
+                        .with_label_values(&labels)
+                        .inc();
+                }
+            }
+        }
+    }
+}
+
+fn report_staleness(
+    url: &str,
+    stream_type: &str,
+    metrics: &StreamMetrics,
+    staleness: &mut Staleness,
+) {
+    let Some((age, turned_stale)) = staleness.check(Instant::now()) else {
+        return;
+    };
+    let labels = [stream_type];
+    metrics
+        .hls_playlist_age
+        .with_label_values(&labels)
+        .set(age.as_secs_f64());
+    if turned_stale {
+        warn!(
+            "HLS playlist {} has not advanced for {:.1}s",
+            url,
+            age.as_secs_f64()
+        );
+        metrics.hls_playlist_stale.with_label_values(&labels).inc();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Args;
+    use clap::Parser;
+
+    const MEDIA_PLAYLIST: &str = "#EXTM3U
+#EXT-X-VERSION:3
+#EXT-X-TARGETDURATION:6
+#EXT-X-MEDIA-SEQUENCE:100
+#EXT-X-PROGRAM-DATE-TIME:2024-05-01T12:00:00.000Z
+#EXTINF:6.000,
+seg100.ts
+#EXTINF:6.000,
+seg101.ts
+#EXTINF:4.500,
+seg102.ts
+";
+
+    #[test]
+    fn test_parse_media_playlist_extrapolates_pdt() {
+        let Playlist::Media(playlist) = parse_playlist(MEDIA_PLAYLIST).unwrap() else {
+            panic!("expected media playlist");
+        };
+        assert_eq!(playlist.target_duration, Some(6.0));
+        assert_eq!(playlist.segments.len(), 3);
+
+        let newest = playlist.newest_segment().unwrap();
+        assert_eq!(
+            newest.program_date_time.unwrap().to_rfc3339(),
+            "2024-05-01T12:00:12+00:00"
+        );
+        assert_eq!(newest.uri, "seg102.ts");
+        assert_eq!(newest.sequence, 102);
+        assert_eq!(newest.duration, 4.5);
+        assert_eq!(
+            playlist.live_edge().unwrap().to_rfc3339(),
+            "2024-05-01T12:00:16.500+00:00"
+        );
+    }
+
+    #[test]
+    fn test_parse_hostile_playlist() {
+        let text = "#EXTM3U
+#EXT-X-TARGETDURATION:-1
+#EXT-X-PROGRAM-DATE-TIME:2024-05-01T12:00:00.000Z
+#EXTINF:1e300,
+seg0.ts
+#EXTINF:NaN,
+seg1.ts
+#EXTINF:inf,
+seg2.ts
+";
+        let Playlist::Media(playlist) = parse_playlist(text).unwrap() else {
+            panic!("expected media playlist");
+        };
+        assert_eq!(playlist.target_duration, None);
+        assert_eq!(playlist.segments[0].duration, 1e300);
+        // Past the end of time, there is nothing to extrapolate
+        assert!(playlist.segments[1].program_date_time.is_none());
+        assert_eq!(playlist.segments[1].duration, 0.0);
+        assert_eq!(playlist.segments[2].duration, 0.0);
+        assert!(parse_playlist("#EXTM3U\n#EXT-X-TARGETDURATION:inf\n").is_ok());
+
+        let text = "#EXTM3U
+#EXT-X-PART-INF:PART-TARGET=-1
+#EXT-X-PART:DURATION=NaN,URI=\"part0.mp4\"
+";
+        let Playlist::Media(playlist) = parse_playlist(text).unwrap() else {
+            panic!("expected media playlist");
+        };
+        assert_eq!(playlist.part_target, None);
+        assert_eq!(playlist.parts[0].duration, 0.0);
+
+        // The newest segment ends past the range of timestamps
+        let text = "#EXTM3U
+#EXT-X-TARGETDURATION:6
+#EXT-X-PROGRAM-DATE-TIME:2024-05-01T12:00:00.000Z
+#EXTINF:1e300,
+seg0.ts
+";
+        let Playlist::Media(playlist) = parse_playlist(text).unwrap() else {
+            panic!("expected media playlist");
+        };
+        assert_eq!(playlist.live_edge(), None);
+
+        // None of it stops the poller
+        let dir = std::env::temp_dir().join(format!("hls-hostile-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("index.m3u8");
+        std::fs::write(&path, text.replace(":6", ":-1")).unwrap();
+        let url = path.display().to_string();
+        let options = MonitorOptions::from(&Args::parse_from(["ffmpeg_exporter", "--input", &url]));
+        let metrics =
+            StreamMetrics::new(&prometheus::Registry::new(), &Default::default()).unwrap();
+        let poller = PlaylistPoller::new(
+            url.clone(),
+            &options,
+            "hls",
+            metrics,
+            Arc::new(AtomicBool::new(true)),
+        );
+        let agent = http_agent(&HttpOptions::default()).unwrap();
+        let interval = poller.poll_once(
+            &agent,
+            &mut url.clone(),
+            &mut SegmentAdvance::default(),
+            &mut PartAdvance::default(),
+            &Mutex::new(Staleness::default()),
+            &mut None,
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(interval.unwrap(), DEFAULT_POLL_INTERVAL);
+    }
+
+    #[test]
+    fn test_segment_advance() {
+        let parse = |text: &str| match parse_playlist(text).unwrap() {
+            Playlist::Media(playlist) => playlist,
+            Playlist::Master(_) => panic!("expected media playlist"),
+        };
+        let mut advance = SegmentAdvance::default();
+        assert!(advance.new_segments(&parse(MEDIA_PLAYLIST)).is_empty());
+
+        let next = MEDIA_PLAYLIST
+            .replace("SEQUENCE:100", "SEQUENCE:101")
+            .replace("#EXTINF:6.000,\nseg100.ts\n", "")
+            + "#EXT-X-BYTERANGE:75232@0\n#EXTINF:7.200,\nseg103.ts\n";
+        let next = parse(&next);
+        let new = advance.new_segments(&next);
+        assert_eq!(new.len(), 1);
+        assert_eq!(new[0].sequence, 103);
+        assert_eq!(new[0].byte_range, Some(75232));
+        assert!(advance.new_segments(&next).is_empty());
+
+        // A restarted packager starts over
+        let restarted = MEDIA_PLAYLIST.replace("SEQUENCE:100", "SEQUENCE:0");
+        assert!(advance.new_segments(&parse(&restarted)).is_empty());
+    }
+
+    const LL_PLAYLIST: &str = "#EXTM3U
+#EXT-X-TARGETDURATION:4
+#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK=1.0
+#EXT-X-PART-INF:PART-TARGET=0.33334
+#EXT-X-MEDIA-SEQUENCE:266
+#EXT-X-PART:DURATION=0.33334,URI=\"seg266.0.mp4\",INDEPENDENT=YES
+#EXT-X-PART:DURATION=0.33334,URI=\"seg266.1.mp4\"
+#EXTINF:4.00008,
+seg266.mp4
+#EXT-X-PART:DURATION=0.33334,URI=\"seg267.0.mp4\",INDEPENDENT=YES
+#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"seg267.1.mp4\"
+";
+
+    #[test]
+    fn test_parse_low_latency_playlist() {
+        let parse = |text: &str| match parse_playlist(text).unwrap() {
+            Playlist::Media(playlist) => playlist,
+            Playlist::Master(_) => panic!("expected media playlist"),
+        };
+        let playlist = parse(LL_PLAYLIST);
+        assert!(playlist.can_block_reload);
+        assert_eq!(playlist.part_target, Some(0.33334));
+        assert_eq!(playlist.segments.len(), 1);
+        // The preload hint announces a part that does not exist yet
+        assert_eq!(playlist.parts.len(), 3);
+        let newest = playlist.parts.last().unwrap();
+        assert_eq!((newest.sequence, newest.index), (267, 0));
+        assert_eq!(playlist.next_part(), Some((267, 1)));
+        assert_eq!(
+            blocking_reload_url("https://ll.example.com/live/index.m3u8", (267, 1)),
+            "https://ll.example.com/live/index.m3u8?_HLS_msn=267&_HLS_part=1"
+        );
+
+        let mut parts = PartAdvance::default();
+        assert!(parts.new_parts(&playlist).is_empty());
+        assert_eq!(parts.take_blocking_reload(), Some((267, 1)));
+        assert_eq!(parts.take_blocking_reload(), None);
+
+        let next = parse(&LL_PLAYLIST.replace(
+            "#EXT-X-PRELOAD-HINT",
+            "#EXT-X-PART:DURATION=0.3,URI=\"seg267.1.mp4\"\n#EXT-X-PRELOAD-HINT",
+        ));
+        let new = parts.new_parts(&next);
+        assert_eq!(new.len(), 1);
+        assert_eq!(new[0].duration, 0.3);
+
+        // Parts of a completed segment continue with the next segment
+        let completed = parse(&format!("{}#EXTINF:4.0,\nseg267.mp4\n", LL_PLAYLIST));
+        assert_eq!(completed.next_part(), Some((268, 0)));
+        assert!(parse(MEDIA_PLAYLIST).next_part().is_none());
+
+        // The live edge includes the parts of the segment being written
+        let dated = parse(&LL_PLAYLIST.replace(
+            "#EXTINF:4.00008",
+            "#EXT-X-PROGRAM-DATE-TIME:2024-05-01T12:00:00.000Z\n#EXTINF:4.0",
+        ));
+        assert_eq!(
+            dated.live_edge().unwrap().to_rfc3339(),
+            "2024-05-01T12:00:04.333+00:00"
+        );
+        assert!(playlist.live_edge().is_none());
+    }
+
+    #[test]
+    fn test_playlist_staleness() {
+        let start = Instant::now();
+        let limit = Some(Duration::from_secs(18));
+        let mut staleness = Staleness::default();
+        assert_eq!(staleness.check(start), None);
+
+        staleness.observe(Some(102), limit, start);
+        let at = |secs| start + Duration::from_secs(secs);
+        assert_eq!(
+            staleness.check(at(6)),
+            Some((Duration::from_secs(6), false))
+        );
+        // The same newest segment does not reset the age
+        staleness.observe(Some(102), limit, at(12));
+        assert_eq!(
+            staleness.check(at(19)),
+            Some((Duration::from_secs(19), true))
+        );
+        assert_eq!(
+            staleness.check(at(25)),
+            Some((Duration::from_secs(25), false))
+        );
+
+        staleness.observe(Some(103), limit, at(30));
+        assert_eq!(
+            staleness.check(at(31)),
+            Some((Duration::from_secs(1), false))
+        );
+        // Ended playlists never turn stale
+        staleness.observe(Some(103), None, at(31));
+        assert_eq!(
+            staleness.check(at(90)),
+            Some((Duration::from_secs(60), false))
+        );
+    }
+
+    #[test]
+    fn test_request_timeout() {
+        let options = MonitorOptions::from(&Args::parse_from([
+            "ffmpeg_exporter",
+            "--input",
+            "https://origin/index.m3u8",
+        ]));
+        let poller = PlaylistPoller::new(
+            "https://origin/index.m3u8".to_string(),
+            &options,
+            "hls",
+            StreamMetrics::new(
+                &prometheus::Registry::new(),
+                &std::collections::HashMap::new(),
+            )
+            .unwrap(),
+            Arc::new(AtomicBool::new(true)),
+        );
+        // A request may hang as long as the playlist may go without a segment
+        assert_eq!(
+            poller.request_timeout(Some(6.0), false),
+            Duration::from_secs(18)
+        );
+        assert_eq!(
+            poller.request_timeout(Some(4.0), true),
+            Duration::from_secs(24)
+        );
+        assert_eq!(poller.request_timeout(None, false), Duration::from_secs(18));
+        assert_eq!(
+            poller.request_timeout(Some(0.0), false),
+            MIN_REQUEST_TIMEOUT
+        );
+    }
+
+    #[test]
+    fn test_fetch_playlist_times_out() {
+        // An origin that accepts the connection but never answers
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/index.m3u8", listener.local_addr().unwrap());
+        let agent = http_agent(&HttpOptions::default()).unwrap();
+        let started = Instant::now();
+        let fetched = fetch_playlist(
+            &agent,
+            &url,
+            &HttpOptions::default(),
+            Duration::from_millis(200),
+        );
+        assert!(fetched.is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+        drop(listener);
+    }
+
+    #[test]
+    fn test_poll_segment_metrics() {
+        let dir = std::env::temp_dir().join(format!("hls-poll-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let playlist = dir.join("index.m3u8");
+        std::fs::write(&playlist, MEDIA_PLAYLIST).unwrap();
+        std::fs::write(dir.join("seg103.ts"), [0u8; 1880]).unwrap();
+
+        let registry = prometheus::Registry::new();
+        let metrics = StreamMetrics::new(&registry, &std::collections::HashMap::new()).unwrap();
+        let url = playlist.display().to_string();
+        let options = MonitorOptions::from(&Args::parse_from([
+            "ffmpeg_exporter",
+            "--input",
+            &url,
+            "--hls-segment-fetch",
+        ]));
+        let poller = PlaylistPoller::new(
+            url.clone(),
+            &options,
+            "hls",
+            metrics.clone(),
+            Arc::new(AtomicBool::new(true)),
+        );
+        let agent = http_agent(&HttpOptions::default()).unwrap();
+        let mut advance = SegmentAdvance::default();
+        let mut parts = PartAdvance::default();
+        let staleness = Mutex::new(Staleness::default());
+        let mut target_duration = None;
+        let mut media_url = url.clone();
+        let mut poll = || {
+            poller
+                .poll_once(
+                    &agent,
+                    &mut media_url,
+                    &mut advance,
+                    &mut parts,
+                    &staleness,
+                    &mut target_duration,
+                )
+                .unwrap()
+        };
+        poll();
+
+        std::fs::write(
+            &playlist,
+            format!(
+                "{}#EXTINF:7.200,\nseg103.ts\n#EXTINF:6.000,\nmissing.ts\n",
+                MEDIA_PLAYLIST
+            ),
+        )
+        .unwrap();
+        poll();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let labels = ["hls"];
+        assert_eq!(metrics.hls_segments.with_label_values(&labels).get(), 2.0);
+        // The live edge is the end of the newest segment, 6s after its start
+        let offset = metrics.hls_pdt_offset.with_label_values(&labels).get();
+        let latency = metrics.hls_live_latency.with_label_values(&labels).get();
+        assert!((offset - latency - 6.0).abs() < 0.01);
+        let durations = metrics.hls_segment_duration.with_label_values(&labels);
+        assert_eq!(durations.get_sample_sum(), 13.2);
+        assert_eq!(
+            metrics
+                .hls_segment_target_deviation
+                .with_label_values(&labels)
+                .get(),
+            0.0
+        );
+        let sizes = metrics.hls_segment_size.with_label_values(&labels);
+        assert_eq!(sizes.get_sample_count(), 1);
+        assert_eq!(sizes.get_sample_sum(), 1880.0);
+        assert_eq!(
+            metrics
+                .hls_segment_fetch_errors
+                .with_label_values(&labels)
+                .get(),
+            1.0
+        );
+    }
+
+    #[test]
+    fn test_parse_master_playlist() {
+        let text = "#EXTM3U
+#EXT-X-STREAM-INF:BANDWIDTH=1280000,CODECS=\"avc1.4d401f,mp4a.40.2\",RESOLUTION=1280x720
+720p/index.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=640000,RESOLUTION=640x360
+360p/index.m3u8
+";
+        let Playlist::Master(variants) = parse_playlist(text).unwrap() else {
+            panic!("expected master playlist");
+        };
+        assert_eq!(variants.len(), 2);
+        assert_eq!(variants[0].uri, "720p/index.m3u8");
+        assert_eq!(variants[0].bandwidth, Some(1280000));
+        assert_eq!(variants[0].resolution.as_deref(), Some("1280x720"));
+        assert_eq!(
+            resolve_uri("https://cdn.example.com/live/master.m3u8", &variants[1].uri),
+            "https://cdn.example.com/live/360p/index.m3u8"
+        );
+    }
+
+    #[test]
+    fn test_variant_streams() {
+        let text = "#EXTM3U
+#EXT-X-STREAM-INF:BANDWIDTH=1280000,CODECS=\"avc1.4d401f,mp4a.40.2\",RESOLUTION=1280x720
+720p/index.m3u8
+#EXT-X-STREAM-INF:CODECS=\"mp4a.40.2\",BANDWIDTH=96000,AVERAGE-BANDWIDTH=80000
+audio/index.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=640000,RESOLUTION=640x360
+360p/index.m3u8
+";
+        let Playlist::Master(variants) = parse_playlist(text).unwrap() else {
+            panic!("expected master playlist");
+        };
+        let stream = StreamConfig {
+            name: "news".to_string(),
+            input: "https://cdn.example.com/news/master.m3u8".to_string(),
+            labels: [("channel".to_string(), "news".to_string())].into(),
+            ..Default::default()
+        };
+        let streams = variant_streams(&stream, &stream.input, &variants, 2);
+        assert_eq!(streams.len(), 2);
+        assert_eq!(streams[0].name, "news-0");
+        assert_eq!(
+            streams[0].input,
+            "https://cdn.example.com/news/720p/index.m3u8"
+        );
+        let label = |i: usize, name| streams[i].labels.get(name).map(String::as_str);
+        assert_eq!(label(0, "resolution"), Some("1280x720"));
+        assert_eq!(label(0, "channel"), Some("news"));
+        // Audio-only variants have no resolution
+        assert_eq!(label(1, "bandwidth"), Some("96000"));
+        assert_eq!(label(1, "resolution"), Some(""));
+        // The average is preferred over the peak bandwidth
+        assert_eq!(streams[0].declared_bandwidth, Some(1280000));
+        assert_eq!(streams[1].declared_bandwidth, Some(80000));
+        assert_eq!(
+            variant_streams(&stream, &stream.input, &variants, 0).len(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_http_agent_tls_options() {
+        assert!(http_agent(&HttpOptions::default()).is_ok());
+
+        let insecure = HttpOptions {
+            tls_insecure: Some(true),
+            ..Default::default()
+        };
+        assert!(http_agent(&insecure).is_ok());
+
+        let missing_ca = HttpOptions {
+            tls_ca_file: Some("/nonexistent/ca.pem".into()),
+            ..Default::default()
+        };
+        assert!(http_agent(&missing_ca).is_err());
+    }
+}
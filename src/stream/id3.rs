@@ -0,0 +1,111 @@
+// stream/id3.rs
+
+use crate::metrics::StreamMetrics;
+use crate::stream::records::PacketRecord;
+use std::collections::HashMap;
+
+/// ffprobe's codec name of timed ID3 metadata streams
+pub const ID3_CODEC: &str = "timed_id3";
+
+/// Larger jumps between packets are timestamp discontinuities
+const MAX_JUMP: f64 = 10.0;
+
+/// Counts the timed ID3 packets of the metadata streams found by the stream
+/// probe, used for ad markers and program boundaries in HLS and MPEG-TS. The
+/// time since the last packet is measured in stream time against the packets
+/// of all streams, so it keeps growing while only audio and video arrive.
+#[derive(Default)]
+pub struct Id3Tracker {
+    /// Stream time of the last ID3 packet per metadata stream, or of the
+    /// first packet while there was none yet
+    streams: HashMap<String, Option<f64>>,
+    last_dts: Option<f64>,
+}
+
+impl Id3Tracker {
+    pub fn insert(&mut self, stream_id: &str) {
+        self.streams.insert(stream_id.to_string(), None);
+    }
+
+    pub fn observe(&mut self, packet: &PacketRecord, metrics: &StreamMetrics) {
+        if self.streams.is_empty() {
+            return;
+        }
+        if self.streams.contains_key(packet.stream_id) {
+            metrics
+                .id3_packets
+                .with_label_values(&[packet.stream_id])
+                .inc();
+        }
+        let Some(dts) = packet.dts_time else {
+            return;
+        };
+
+        // Timestamp discontinuities move the references along with the stream
+        let jump = dts - self.last_dts.replace(dts).unwrap_or(dts);
+        let discontinuity = jump.abs() >= MAX_JUMP;
+        for (stream_id, since) in &mut self.streams {
+            let since = since.get_or_insert(dts);
+            if discontinuity {
+                *since += jump;
+            }
+            if stream_id == packet.stream_id {
+                *since = dts;
+            }
+            metrics
+                .id3_gap
+                .with_label_values(&[stream_id])
+                .set((dts - *since).max(0.0));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::Registry;
+
+    fn packet(stream_id: &'static str, dts: f64) -> PacketRecord<'static> {
+        PacketRecord {
+            media_type: if stream_id == "2" { "data" } else { "video" },
+            stream_id,
+            size: Some(100.0),
+            dts_time: Some(dts),
+            flags: "__",
+        }
+    }
+
+    #[test]
+    fn test_id3_packets_and_gap() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let mut tracker = Id3Tracker::default();
+        tracker.insert("2");
+        let packets = || metrics.id3_packets.with_label_values(&["2"]).get();
+        let gap = || metrics.id3_gap.with_label_values(&["2"]).get();
+
+        tracker.observe(&packet("0", 10.0), &metrics);
+        tracker.observe(&packet("0", 13.0), &metrics);
+        assert_eq!(gap(), 3.0);
+
+        tracker.observe(&packet("2", 13.5), &metrics);
+        tracker.observe(&packet("0", 14.0), &metrics);
+        assert_eq!(packets(), 1.0);
+        assert_eq!(gap(), 0.5);
+
+        // A timestamp reset keeps the gap growing steadily
+        tracker.observe(&packet("0", 0.0), &metrics);
+        tracker.observe(&packet("0", 1.0), &metrics);
+        assert_eq!(gap(), 1.5);
+        assert_eq!(packets(), 1.0);
+    }
+
+    #[test]
+    fn test_no_id3_streams() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let mut tracker = Id3Tracker::default();
+        tracker.observe(&packet("2", 1.0), &metrics);
+        assert_eq!(metrics.id3_packets.with_label_values(&["2"]).get(), 0.0);
+    }
+}
@@ -0,0 +1,107 @@
+// stream/latency.rs
+
+use crate::metrics::StreamMetrics;
+use crate::stream::records::FrameRecord;
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// Timestamp jumps larger than this restart the measurement
+const MAX_JUMP: f64 = 10.0;
+
+struct LagState {
+    /// Stream time minus wall clock time of the previous frame
+    last_offset: f64,
+    /// Largest stream time minus wall clock time seen since the last restart
+    best_offset: f64,
+}
+
+/// Estimates how far a live input has fallen behind realtime from the drift
+/// between wall clock time and the progression of the presentation
+/// timestamps. Reading faster than realtime (initial buffer bursts) raises the
+/// baseline, so the lag is measured against the best point seen.
+pub struct LiveLag {
+    start: Instant,
+    streams: HashMap<String, LagState>,
+}
+
+impl Default for LiveLag {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            streams: HashMap::new(),
+        }
+    }
+}
+
+impl LiveLag {
+    pub fn observe(&mut self, frame: &FrameRecord, metrics: &StreamMetrics) {
+        let wall = self.start.elapsed().as_secs_f64();
+        self.observe_at(wall, frame, metrics);
+    }
+
+    fn observe_at(&mut self, wall: f64, frame: &FrameRecord, metrics: &StreamMetrics) {
+        let Some(pts) = frame.pts_time else {
+            return;
+        };
+        let offset = pts - wall;
+
+        let state = match self.streams.get_mut(frame.stream_id) {
+            // Discontinuities shift the offset, start measuring over
+            Some(state) if (offset - state.last_offset).abs() <= MAX_JUMP => state,
+            _ => {
+                self.streams.insert(
+                    frame.stream_id.to_string(),
+                    LagState {
+                        last_offset: offset,
+                        best_offset: offset,
+                    },
+                );
+                return;
+            }
+        };
+        state.last_offset = offset;
+        state.best_offset = state.best_offset.max(offset);
+        metrics
+            .live_lag
+            .with_label_values(&[frame.stream_id])
+            .set(state.best_offset - offset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::Registry;
+
+    fn frame(pts: f64) -> FrameRecord<'static> {
+        FrameRecord {
+            media_type: "video",
+            stream_id: "0",
+            pts_time: Some(pts),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_live_lag() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let mut lag = LiveLag::default();
+        let current = || metrics.live_lag.with_label_values(&["0"]).get();
+
+        // Two buffered seconds arrive at once, then realtime
+        lag.observe_at(0.0, &frame(100.0), &metrics);
+        lag.observe_at(0.1, &frame(102.0), &metrics);
+        lag.observe_at(1.1, &frame(103.0), &metrics);
+        assert!(current().abs() < 1e-9);
+
+        // The source slows down to half speed for four seconds
+        lag.observe_at(5.1, &frame(105.0), &metrics);
+        assert!((current() - 2.0).abs() < 1e-9);
+
+        // A timestamp reset is not lag
+        lag.observe_at(6.1, &frame(0.0), &metrics);
+        lag.observe_at(7.1, &frame(1.0), &metrics);
+        assert!(current().abs() < 1e-9);
+    }
+}
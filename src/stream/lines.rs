@@ -0,0 +1,225 @@
+// stream/lines.rs
+
+use anyhow::{Context, Result};
+use std::borrow::Cow;
+use std::io::{self, BufRead};
+use std::time::{Duration, Instant};
+
+/// Upper bound of CSV fields kept per line; ffprobe frame records with side
+/// data, and analysis frames with astats tags of up to 32 channels, stay
+/// below this.
+pub const MAX_FIELDS: usize = 128;
+
+/// Longer lines are truncated, the remainder is read and discarded.
+pub const MAX_LINE_BYTES: usize = 64 * 1024;
+
+/// Like `read_until(b'\n')`, but never buffers more than `max` bytes of a
+/// line. Returns the number of bytes consumed from the reader.
+fn read_line_bounded<R: BufRead>(
+    reader: &mut R,
+    buf: &mut Vec<u8>,
+    max: usize,
+) -> io::Result<usize> {
+    let mut consumed = 0;
+    loop {
+        let available = match reader.fill_buf() {
+            Ok(available) => available,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if available.is_empty() {
+            return Ok(consumed);
+        }
+
+        let (used, done) = match available.iter().position(|&b| b == b'\n') {
+            Some(idx) => (idx + 1, true),
+            None => (available.len(), false),
+        };
+        let room = max.saturating_sub(buf.len());
+        buf.extend_from_slice(&available[..used.min(room)]);
+        reader.consume(used);
+        consumed += used;
+
+        if done {
+            return Ok(consumed);
+        }
+    }
+}
+
+/// Calls `f` for every line of `reader`, reusing a single byte buffer instead
+/// of allocating a `String` per line.
+pub fn for_each_line<R, F>(mut reader: R, mut f: F) -> Result<()>
+where
+    R: BufRead,
+    F: FnMut(&str) -> Result<()>,
+{
+    let mut buf = Vec::with_capacity(1024);
+    loop {
+        buf.clear();
+        let read = read_line_bounded(&mut reader, &mut buf, MAX_LINE_BYTES)
+            .context("Failed to read line")?;
+        if read == 0 {
+            return Ok(());
+        }
+
+        let mut end = buf.len();
+        while end > 0 && matches!(buf[end - 1], b'\n' | b'\r') {
+            end -= 1;
+        }
+
+        // ffprobe output is UTF-8 except for the odd mangled metadata tag
+        let line = match std::str::from_utf8(&buf[..end]) {
+            Ok(line) => Cow::Borrowed(line),
+            Err(_) => String::from_utf8_lossy(&buf[..end]),
+        };
+        f(&line)?;
+    }
+}
+
+/// Caps the number of lines and bytes admitted per second; `0` disables a
+/// limit.
+pub struct LineBudget {
+    max_lines: u64,
+    max_bytes: u64,
+    window_start: Instant,
+    lines: u64,
+    bytes: u64,
+}
+
+impl LineBudget {
+    pub fn new(max_lines: u64, max_bytes: u64) -> Self {
+        Self {
+            max_lines,
+            max_bytes,
+            window_start: Instant::now(),
+            lines: 0,
+            bytes: 0,
+        }
+    }
+
+    pub fn admit(&mut self, len: usize) -> bool {
+        self.admit_at(Instant::now(), len)
+    }
+
+    fn admit_at(&mut self, now: Instant, len: usize) -> bool {
+        if now.duration_since(self.window_start) >= Duration::from_secs(1) {
+            self.window_start = now;
+            self.lines = 0;
+            self.bytes = 0;
+        }
+
+        let len = len as u64;
+        if (self.max_lines > 0 && self.lines >= self.max_lines)
+            || (self.max_bytes > 0 && self.bytes + len > self.max_bytes)
+        {
+            return false;
+        }
+        self.lines += 1;
+        self.bytes += len;
+        true
+    }
+}
+
+/// The comma separated fields of a CSV line, borrowed from the line itself.
+pub struct Fields<'a> {
+    parts: [&'a str; MAX_FIELDS],
+    len: usize,
+}
+
+impl<'a> Fields<'a> {
+    pub fn split(line: &'a str) -> Self {
+        let mut parts = [""; MAX_FIELDS];
+        let mut len = 0;
+        for part in line.split(',').take(MAX_FIELDS) {
+            parts[len] = part;
+            len += 1;
+        }
+        Self { parts, len }
+    }
+
+    pub fn as_slice(&self) -> &[&'a str] {
+        &self.parts[..self.len]
+    }
+
+    /// Looks up the value of a `key=value` field
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.as_slice()
+            .iter()
+            .find_map(|part| part.strip_prefix(key)?.strip_prefix('='))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_each_line_strips_line_endings() {
+        let input = "packet,video,0\r\nframe,audio,1\nlast";
+        let mut lines = Vec::new();
+        for_each_line(input.as_bytes(), |line| {
+            lines.push(line.to_string());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(lines, vec!["packet,video,0", "frame,audio,1", "last"]);
+    }
+
+    #[test]
+    fn test_for_each_line_replaces_invalid_utf8() {
+        let input: &[u8] = b"frame,\xff,1\n";
+        let mut lines = Vec::new();
+        for_each_line(input, |line| {
+            lines.push(line.to_string());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(lines, vec!["frame,\u{fffd},1"]);
+    }
+
+    #[test]
+    fn test_for_each_line_truncates_long_lines() {
+        let mut input = vec![b'x'; MAX_LINE_BYTES * 2];
+        input.extend_from_slice(b"\nnext\n");
+        let mut lens = Vec::new();
+        for_each_line(input.as_slice(), |line| {
+            lens.push(line.len());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(lens, vec![MAX_LINE_BYTES, 4]);
+    }
+
+    #[test]
+    fn test_line_budget() {
+        let mut budget = LineBudget::new(2, 100);
+        let start = budget.window_start;
+        assert!(budget.admit_at(start, 10));
+        assert!(budget.admit_at(start, 10));
+        // Line limit reached
+        assert!(!budget.admit_at(start, 10));
+
+        // A new window starts after a second
+        let later = start + Duration::from_secs(1);
+        assert!(budget.admit_at(later, 90));
+        // Byte limit reached
+        assert!(!budget.admit_at(later, 20));
+
+        let mut unlimited = LineBudget::new(0, 0);
+        assert!((0..1000).all(|_| unlimited.admit_at(start, 1000)));
+    }
+
+    #[test]
+    fn test_fields_split() {
+        let fields = Fields::split("packet,video,0,,12");
+        assert_eq!(fields.as_slice(), &["packet", "video", "0", "", "12"]);
+    }
+
+    #[test]
+    fn test_fields_get() {
+        let fields = Fields::split("frame,media_type=video,pts=1,pts_time=0.04");
+        assert_eq!(fields.get("pts"), Some("1"));
+        assert_eq!(fields.get("pts_time"), Some("0.04"));
+        assert_eq!(fields.get("width"), None);
+    }
+}
@@ -0,0 +1,401 @@
+// stream/manager.rs
+
+use crate::config::{MonitorOptions, ResourceAttributes, StreamConfig, StreamType, redact};
+use crate::events::EventLog;
+use crate::metrics::StreamMetrics;
+use crate::stream::monitor::{DeepProbe, FFprobeMonitor};
+use crate::stream::snapshot::SnapshotStore;
+use crate::stream::status::{StreamReport, StreamStatus};
+use anyhow::{Context, Result};
+use prometheus::Registry;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tokio::task::{self, JoinHandle};
+use tracing::{error, info, warn};
+
+/// Error of `StreamManager::add` for a stream that is monitored already
+#[derive(Debug)]
+pub struct AlreadyMonitored(pub String);
+
+impl fmt::Display for AlreadyMonitored {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Stream {} is already monitored", self.0)
+    }
+}
+
+impl std::error::Error for AlreadyMonitored {}
+
+struct MonitorHandle {
+    running: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+    status: StreamStatus,
+    snapshots: SnapshotStore,
+    deep_probe: DeepProbe,
+    metrics: StreamMetrics,
+    input: String,
+    stream_type: &'static str,
+    task: JoinHandle<Result<()>>,
+}
+
+impl MonitorHandle {
+    fn report(&self, name: &str, resource: &ResourceAttributes) -> StreamReport {
+        let running = self.running.load(Ordering::SeqCst) && !self.task.is_finished();
+        StreamReport::new(
+            name,
+            self.input.clone(),
+            self.stream_type,
+            &self.status,
+            &self.metrics,
+            running,
+            resource,
+        )
+    }
+}
+
+/// Runs one `FFprobeMonitor` per stream, all feeding the shared registry.
+/// Streams can be added and removed while the exporter is running.
+#[derive(Clone)]
+pub struct StreamManager {
+    registry: Registry,
+    options: MonitorOptions,
+    label_names: BTreeSet<String>,
+    events: EventLog,
+    /// Probe labels of the status reports
+    resource: ResourceAttributes,
+    streams: Arc<Mutex<HashMap<String, MonitorHandle>>>,
+    shutdown: Arc<Notify>,
+}
+
+impl StreamManager {
+    /// `label_names` are the custom labels every stream exports, streams added
+    /// later may only use these.
+    pub fn new(
+        registry: Registry,
+        options: MonitorOptions,
+        label_names: BTreeSet<String>,
+        events: EventLog,
+        resource: ResourceAttributes,
+    ) -> Self {
+        Self {
+            registry,
+            options,
+            label_names,
+            events,
+            resource,
+            streams: Arc::new(Mutex::new(HashMap::new())),
+            shutdown: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Starts monitoring a stream, failing with `AlreadyMonitored` if a
+    /// stream of its name is monitored already
+    pub fn add(&self, stream: StreamConfig) -> Result<()> {
+        // Checked and inserted under one lock, so concurrent adds of a name
+        // cannot both start a monitor
+        let mut streams = self.streams.lock().unwrap();
+        if streams.contains_key(&stream.name) {
+            return Err(AlreadyMonitored(stream.name).into());
+        }
+
+        let stream_type = StreamType::from_input(&stream.input)
+            .with_context(|| format!("Failed to determine stream type of {}", stream.name))?;
+        stream_type.check_components(&self.options.ffprobe_components)?;
+        stream.validate()?;
+        let const_labels = stream.const_labels(&self.label_names)?;
+        let metrics = StreamMetrics::with_buckets(
+            &self.registry,
+            &const_labels,
+            &self.options.histogram_buckets,
+        )
+        .with_context(|| format!("Failed to register metrics of {}", stream.name))?
+        .with_label_limits(&self.options.label_limits);
+
+        let name = stream.name.clone();
+        let input = redact(&stream.input);
+        let type_str = stream_type.get_type_str();
+        let options = stream.monitor_options(&self.options)?;
+        let events = self.events.recorder(&name);
+        let monitor = FFprobeMonitor::new(stream, stream_type, metrics.clone(), options, events);
+        let running = monitor.get_running_handle();
+        let paused = monitor.get_paused_handle();
+        let status = monitor.get_status_handle();
+        let snapshots = monitor.get_snapshot_handle();
+        let deep_probe = monitor.get_deep_probe();
+        let registry = self.registry.clone();
+        let manager = self.clone();
+        let task_metrics = metrics.clone();
+        let task_status = status.clone();
+
+        // Monitors block on the ffprobe pipes, so each gets its own blocking thread
+        let task = task::spawn_blocking(move || {
+            let result = monitor.run();
+            if let Err(e) = task_metrics.remove(&registry) {
+                warn!("Failed to remove stream metrics: {:#}", e);
+            }
+            // A monitor only fails when it gave up on its stream, which shuts
+            // down the exporter with an error
+            if let Err(e) = &result {
+                task_status.failed(&format!("{:#}", e));
+                manager.stop_all();
+            }
+            result
+        });
+
+        info!("Started monitoring stream {}", name);
+        streams.insert(
+            name,
+            MonitorHandle {
+                running,
+                paused,
+                status,
+                snapshots,
+                deep_probe,
+                metrics,
+                input,
+                stream_type: type_str,
+                task,
+            },
+        );
+        Ok(())
+    }
+
+    /// Stops the monitor of a stream and waits until its metrics are
+    /// unregistered. Returns `false` if the stream is not monitored.
+    pub async fn remove(&self, name: &str) -> Result<bool> {
+        let Some(handle) = self.streams.lock().unwrap().remove(name) else {
+            return Ok(false);
+        };
+        handle.running.store(false, Ordering::SeqCst);
+
+        match handle.task.await {
+            Ok(Ok(())) => info!("Stopped monitoring stream {}", name),
+            Ok(Err(e)) => warn!("Monitor of removed stream {} failed: {:#}", name, e),
+            Err(e) => anyhow::bail!("Monitor task of stream {} panicked: {}", name, e),
+        }
+        Ok(true)
+    }
+
+    /// Number of monitors whose ffprobe currently delivers data
+    pub fn healthy(&self) -> usize {
+        self.streams
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|handle| handle.status.healthy() && !handle.task.is_finished())
+            .count()
+    }
+
+    /// Status of all monitored streams, sorted by name
+    pub fn reports(&self) -> Vec<StreamReport> {
+        let streams = self.streams.lock().unwrap();
+        let mut reports: Vec<_> = streams
+            .iter()
+            .map(|(name, handle)| handle.report(name, &self.resource))
+            .collect();
+        reports.sort_by(|a, b| a.name.cmp(&b.name));
+        reports
+    }
+
+    /// Status of one stream, `None` if it is not monitored
+    pub fn report(&self, name: &str) -> Option<StreamReport> {
+        let streams = self.streams.lock().unwrap();
+        streams
+            .get(name)
+            .map(|handle| handle.report(name, &self.resource))
+    }
+
+    /// Pauses or resumes the monitor of a stream. Returns `false` if the
+    /// stream is not monitored.
+    pub fn set_paused(&self, name: &str, paused: bool) -> bool {
+        let streams = self.streams.lock().unwrap();
+        let Some(handle) = streams.get(name) else {
+            return false;
+        };
+        if handle.paused.swap(paused, Ordering::SeqCst) != paused {
+            let action = if paused { "Paused" } else { "Resumed" };
+            info!("{} monitoring stream {}", action, name);
+        }
+        true
+    }
+
+    /// Snapshots of one stream, `None` if it is not monitored
+    pub fn snapshots(&self, name: &str) -> Option<SnapshotStore> {
+        let streams = self.streams.lock().unwrap();
+        streams.get(name).map(|handle| handle.snapshots.clone())
+    }
+
+    /// The deep probe of one stream, `None` if it is not monitored
+    pub fn deep_probe(&self, name: &str) -> Option<DeepProbe> {
+        let streams = self.streams.lock().unwrap();
+        streams.get(name).map(|handle| handle.deep_probe.clone())
+    }
+
+    pub fn stop_all(&self) {
+        for handle in self.streams.lock().unwrap().values() {
+            handle.running.store(false, Ordering::SeqCst);
+        }
+        self.shutdown.notify_one();
+    }
+
+    /// Resolves once `stop_all` has been called.
+    pub async fn wait_for_shutdown(&self) {
+        self.shutdown.notified().await;
+    }
+
+    /// Waits for all monitors to finish, returning the first failure.
+    pub async fn join_all(&self) -> Result<()> {
+        let handles: Vec<_> = self.streams.lock().unwrap().drain().collect();
+
+        let mut result = Ok(());
+        for (name, handle) in handles {
+            match handle.task.await {
+                Ok(Ok(())) => {
+                    info!("Monitor of stream {} shut down gracefully", name);
+                }
+                Ok(Err(e)) => {
+                    error!("Monitor of stream {} failed: {:#}", name, e);
+                    if result.is_ok() {
+                        result = Err(e.context(format!("Monitor of stream {} failed", name)));
+                    }
+                }
+                Err(e) => {
+                    error!("Monitor task of stream {} panicked: {}", name, e);
+                    if result.is_ok() {
+                        result = Err(anyhow::anyhow!(
+                            "Monitor task of stream {} panicked: {}",
+                            name,
+                            e
+                        ));
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Args;
+    use crate::stream::status::StreamState;
+    use clap::Parser;
+    use std::time::Duration;
+
+    fn manager(registry: &Registry) -> StreamManager {
+        manager_with(registry, "/nonexistent/ffprobe")
+    }
+
+    fn manager_with(registry: &Registry, ffprobe_path: &str) -> StreamManager {
+        let args = Args::parse_from([
+            "ffmpeg_exporter",
+            "--input",
+            "unused",
+            "--ffprobe-path",
+            ffprobe_path,
+        ]);
+        StreamManager::new(
+            registry.clone(),
+            MonitorOptions::from(&args),
+            BTreeSet::new(),
+            EventLog::new(0),
+            ResourceAttributes::default(),
+        )
+    }
+
+    fn stream(name: &str) -> StreamConfig {
+        StreamConfig {
+            name: name.to_string(),
+            input: "srt://localhost:9000".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_and_remove_stream() {
+        let registry = Registry::new();
+        let manager = manager(&registry);
+
+        manager.add(stream("sport")).unwrap();
+        assert!(manager.report("sport").is_some());
+        let error = manager.add(stream("sport")).unwrap_err();
+        assert!(error.is::<AlreadyMonitored>());
+        // ffprobe cannot be spawned, so the stream never gets healthy
+        assert_eq!(manager.healthy(), 0);
+        let report = manager.report("sport").unwrap();
+        assert_eq!(report.input, "srt://localhost:9000");
+        assert_eq!(report.stream_type, "srt");
+        assert_ne!(report.state, StreamState::Connected);
+        assert_eq!(manager.reports(), [report]);
+        assert!(manager.report("news").is_none());
+
+        assert!(manager.remove("sport").await.unwrap());
+        assert!(manager.report("sport").is_none());
+        assert!(!manager.remove("sport").await.unwrap());
+
+        // The metrics were unregistered, so the name can be reused
+        manager.add(stream("sport")).unwrap();
+        manager.stop_all();
+        manager.join_all().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_give_up_shuts_down() {
+        let registry = Registry::new();
+        let mut manager = manager(&registry);
+        manager.options.retry_initial = 0;
+        manager.options.max_retries = 2;
+
+        // ffprobe cannot be spawned, so no session ever delivers data
+        manager.add(stream("sport")).unwrap();
+        tokio::time::timeout(Duration::from_secs(10), manager.wait_for_shutdown())
+            .await
+            .unwrap();
+        let error = manager.join_all().await.unwrap_err();
+        assert!(format!("{:#}", error).contains("Giving up after 2 retries"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_pause_during_retry_wait() {
+        // The first session fails, the later ones deliver data
+        let script = format!(
+            "[ -e \"$0.failed\" ] || {{ touch \"$0.failed\"; exit 1; }}\n{}",
+            crate::stream::process::DELIVERING_FFPROBE
+        );
+        let registry = Registry::new();
+        let mut manager = manager_with(&registry, &crate::stream::process::fake_ffprobe(&script));
+        manager.options.retry_initial = 60;
+        manager.options.retry_max = 60;
+        assert!(!manager.set_paused("sport", true));
+        manager.add(stream("sport")).unwrap();
+
+        let report = || manager.report("sport").unwrap();
+        let wait_for = async |state: StreamState| {
+            let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+            while report().state != state {
+                assert!(tokio::time::Instant::now() < deadline, "never {:?}", state);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        };
+
+        wait_for(StreamState::Disconnected).await;
+        assert_eq!(report().restarts, 1);
+        assert!(manager.set_paused("sport", true));
+        wait_for(StreamState::Paused).await;
+        assert_eq!(manager.healthy(), 1);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(report().restarts, 1);
+
+        // Resuming starts a session instead of finishing the retry wait
+        assert!(manager.set_paused("sport", false));
+        wait_for(StreamState::Connected).await;
+        assert_eq!(report().restarts, 1);
+
+        manager.stop_all();
+        manager.join_all().await.unwrap();
+    }
+}
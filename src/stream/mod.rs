@@ -1,4 +1,50 @@
+mod analysis;
+mod backoff;
+mod bitrate;
+mod cadence;
+mod captions;
+mod dash;
+mod downtime;
+mod format;
+mod fps;
+mod gop;
+mod hls;
+mod id3;
+mod latency;
+mod lines;
+mod manager;
 mod monitor;
 mod patterns;
+mod pcr;
+mod probe;
+mod process;
+mod programs;
+mod psi;
+mod qp;
+mod records;
+mod rtp;
+mod severity;
+mod snapshot;
+mod srt_stats;
+mod status;
+mod subtitles;
+mod sync;
+mod test_pattern;
+mod timecode;
+mod timestamps;
+mod tr101290;
+mod tracks;
+mod transport;
+mod version;
+mod watchdog;
 
+pub use hls::{discover_variants, http_agent};
+pub use manager::{AlreadyMonitored, StreamManager};
 pub use monitor::FFprobeMonitor;
+pub use probe::probe;
+pub use process::kill_orphans;
+#[cfg(all(test, unix))]
+pub use process::{DELIVERING_FFPROBE, fake_ffprobe};
+pub use severity::is_error_category;
+pub use status::StreamReport;
+pub use version::{FFprobeBuild, FFprobeComponents, FFprobeVersion};
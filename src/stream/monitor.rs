@@ -1,49 +1,147 @@
-use crate::config::StreamType;
-use crate::metrics::StreamMetrics;
-use crate::stream::patterns::StreamPatterns;
+use crate::config::{MonitorOptions, StreamConfig, StreamType, redact, redact_args};
+use crate::events::EventRecorder;
+use crate::logging::LineSampler;
+use crate::metrics::{OTHER, StreamMetrics};
+use crate::stream::analysis::{AnalysisSession, AnalysisTracker, analysis_args, analysis_graph};
+use crate::stream::backoff::Backoff;
+use crate::stream::bitrate::{BitrateTracker, declared_deviation};
+use crate::stream::cadence::{CadenceTracker, parse_frame_rate};
+use crate::stream::captions::CaptionTracker;
+use crate::stream::dash::ManifestPoller;
+use crate::stream::downtime::Downtime;
+use crate::stream::format::FormatTracker;
+use crate::stream::fps::FpsTracker;
+use crate::stream::gop::GopTracker;
+use crate::stream::hls::PlaylistPoller;
+use crate::stream::id3::{ID3_CODEC, Id3Tracker};
+use crate::stream::latency::LiveLag;
+use crate::stream::lines::{Fields, LineBudget, for_each_line};
+use crate::stream::patterns::{StreamPatterns, codec_error_type};
+use crate::stream::process::{child_command, spawn, terminate};
+use crate::stream::programs::ProgramNames;
+use crate::stream::qp::QpTracker;
+use crate::stream::records::{FormatRecord, FrameRecord, PacketRecord, StreamRecord};
+use crate::stream::rtp::RtpJitter;
+use crate::stream::severity::Severities;
+use crate::stream::snapshot::{SnapshotSession, SnapshotStore};
+use crate::stream::srt_stats::SrtStatsSession;
+use crate::stream::status::StreamStatus;
+use crate::stream::subtitles::SubtitleTracks;
+use crate::stream::sync::AvSync;
+use crate::stream::timecode::TimecodeTracker;
+use crate::stream::timestamps::TimestampUnwrapper;
+use crate::stream::tracks::AudioTracks;
+use crate::stream::transport::TransportSession;
+use crate::stream::watchdog::{Activity, StallWatchdog};
 use anyhow::{Context, Result};
-use std::collections::HashMap;
-use std::io::{BufRead, BufReader};
-use std::process::{Command, Stdio};
+use std::io::{BufRead, BufReader, Read};
+use std::net::UdpSocket;
+use std::process::{Command, ExitStatus, Stdio};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
 use std::time::{Duration, Instant};
 use tracing::{debug, error, info, instrument, warn};
 
-#[cfg(windows)]
-use std::os::windows::process::CommandExt;
+/// Grace period of the stream info probe on top of the analyze duration
+const STREAM_INFO_TIMEOUT: Duration = Duration::from_secs(10);
 
-pub struct FFprobeMonitor {
+/// What the stream probe learned about the input, handed to the ffprobe
+/// session that follows it
+#[derive(Default)]
+struct ProbedStreams {
+    tracks: AudioTracks,
+    cadence: CadenceTracker,
+    id3: Id3Tracker,
+    subtitles: SubtitleTracks,
+}
+
+/// Why an ffprobe session ended, the `reason` label of
+/// `ffmpeg_stream_restarts_total`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RestartReason {
+    /// ffprobe exited on its own, e.g. at the end of a file
+    Exit,
+    /// ffprobe failed or its output could not be processed
+    Error,
+    /// Restarted by `--stall-restart`
+    Stall,
+    /// Restarted by `--freeze-restart`
+    Freeze,
+    /// Restarted for printing nothing within `--probe-timeout`
+    Timeout,
+}
+
+impl RestartReason {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RestartReason::Exit => "exit",
+            RestartReason::Error => "error",
+            RestartReason::Stall => "stall",
+            RestartReason::Freeze => "freeze",
+            RestartReason::Timeout => "timeout",
+        }
+    }
+}
+
+/// The one-shot ffprobe of `/api/v1/streams/{name}/probe`, describing the
+/// format, streams and programs of an input
+#[derive(Clone)]
+pub struct DeepProbe {
     ffprobe_path: String,
+    args: Vec<String>,
+    logged: Vec<String>,
+    timeout: Duration,
+    grace: Duration,
+}
+
+impl DeepProbe {
+    /// Runs ffprobe to its end and returns its JSON output, which describes
+    /// the error if the exit status is not successful
+    pub fn run(&self) -> Result<(ExitStatus, String)> {
+        let cmd = child_command(&self.ffprobe_path, self.args.clone(), self.logged.clone());
+        run_to_end(cmd, self.timeout, self.grace, || false)
+    }
+}
+
+pub struct FFprobeMonitor {
+    name: String,
     input: String,
     stream_type: StreamType,
     metrics: StreamMetrics,
-    probe_size: u32,
-    analyze_duration: u32,
-    report: bool,
+    options: MonitorOptions,
+    events: EventRecorder,
     running: Arc<AtomicBool>,
+    /// Set while monitoring is paused through the admin API
+    paused: Arc<AtomicBool>,
+    /// State for `/readyz` and the status API
+    status: StreamStatus,
+    /// Latest picture for `/snapshot/{stream}`
+    snapshots: SnapshotStore,
+    /// Service names for the transport stream analysis
+    programs: ProgramNames,
 }
 
 impl FFprobeMonitor {
     pub fn new(
-        ffprobe_path: String,
-        input: String,
+        stream: StreamConfig,
         stream_type: StreamType,
         metrics: StreamMetrics,
-        probe_size: u32,
-        analyze_duration: u32,
-        report: bool,
+        options: MonitorOptions,
+        events: EventRecorder,
     ) -> Self {
         Self {
-            ffprobe_path,
-            input,
+            name: stream.name,
+            input: stream.input,
             stream_type,
             metrics,
-            probe_size,
-            analyze_duration,
-            report,
+            options,
+            events,
             running: Arc::new(AtomicBool::new(true)),
+            paused: Arc::new(AtomicBool::new(false)),
+            status: StreamStatus::default(),
+            snapshots: SnapshotStore::default(),
+            programs: ProgramNames::default(),
         }
     }
 
@@ -51,96 +149,403 @@ impl FFprobeMonitor {
         self.running.clone()
     }
 
-    fn build_ffprobe_command(&self) -> Command {
-        let mut cmd = Command::new(&self.ffprobe_path);
+    pub fn get_paused_handle(&self) -> Arc<AtomicBool> {
+        self.paused.clone()
+    }
+
+    pub fn get_status_handle(&self) -> StreamStatus {
+        self.status.clone()
+    }
+
+    pub fn get_snapshot_handle(&self) -> SnapshotStore {
+        self.snapshots.clone()
+    }
 
-        #[cfg(windows)]
-        {
-            cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
+    pub fn get_deep_probe(&self) -> DeepProbe {
+        let args = self.stream_type.get_deep_probe_args(&self.options);
+        DeepProbe {
+            ffprobe_path: self.options.ffprobe_path.clone(),
+            logged: redact_args(&args),
+            args,
+            timeout: self.info_probe_timeout(),
+            grace: self.kill_grace_period(),
         }
+    }
 
-        // Use the stream-specific arguments from StreamType
-        let args =
-            self.stream_type
-                .get_ffprobe_args(self.probe_size, self.analyze_duration, self.report);
-        cmd.args(args).stdout(Stdio::piped()).stderr(Stdio::piped());
+    fn build_ffprobe_command(&self, args: Vec<String>) -> Command {
+        let logged = redact_args(&args);
+        self.ffprobe_command(args, logged)
+    }
 
-        debug!("FFprobe command: {:?}", cmd);
-        cmd
+    /// `logged` are the arguments shown in the debug log, with secrets masked
+    fn ffprobe_command(&self, args: Vec<String>, logged: Vec<String>) -> Command {
+        child_command(&self.options.ffprobe_path, args, logged)
     }
 
-    #[instrument(skip(self))]
+    #[instrument(skip(self), fields(stream = %self.name))]
     pub fn run(&self) -> Result<()> {
-        info!("Starting FFprobe monitoring for {}", self.input);
-        const RETRY_DELAY: Duration = Duration::from_secs(10);
+        info!(
+            "Starting FFprobe monitoring for {} with ffprobe {}",
+            redact(&self.input),
+            self.options.ffprobe_version
+        );
+
+        // HLS and DASH inputs get a playlist or manifest poller next to ffprobe
+        // for playlist-level metrics
+        let poller = match &self.stream_type {
+            StreamType::Hls(url) => Some(
+                PlaylistPoller::new(
+                    url.clone(),
+                    &self.options,
+                    self.stream_type.get_type_str(),
+                    self.metrics.clone(),
+                    self.running.clone(),
+                )
+                .spawn(),
+            ),
+            StreamType::Dash(url) => Some(
+                ManifestPoller::new(
+                    url.clone(),
+                    &self.options,
+                    self.stream_type.get_type_str(),
+                    self.metrics.clone(),
+                    self.running.clone(),
+                )
+                .spawn(),
+            ),
+            _ => None,
+        };
+
+        let result = self.run_loop();
+
+        // Make sure no helper task outlives the monitor
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(poller) = poller {
+            let _ = poller.join();
+        }
+        result
+    }
+
+    fn run_loop(&self) -> Result<()> {
+        let mut backoff = Backoff::new(
+            Duration::from_secs(self.options.retry_initial),
+            Duration::from_secs(self.options.retry_max),
+            self.options.retry_jitter,
+        );
+        let mut watchdog = StallWatchdog::new(Duration::from_secs(self.options.stall_timeout));
+        let mut downtime = Downtime::new(
+            self.options.stale_gauges,
+            Duration::from_secs(self.options.stale_gauge_ttl),
+        );
 
         while self.running.load(Ordering::SeqCst) {
+            if self.paused.load(Ordering::SeqCst) {
+                self.wait_while_paused(&mut watchdog, &mut backoff, &mut downtime);
+                continue;
+            }
             info!("Initiating new FFprobe process");
-            let _start_time = Instant::now();
+            self.status.connecting();
             self.metrics
                 .connection_state
                 .with_label_values(&[self.stream_type.get_type_str()])
                 .set(1.0);
 
-            match self.run_single_monitor() {
-                Ok(()) => {
+            let result = self.run_single_monitor(&mut watchdog, &mut backoff, &mut downtime);
+            self.metrics
+                .connection_state
+                .with_label_values(&[self.stream_type.get_type_str()])
+                .set(0.0);
+            self.status.disconnected();
+            if !self.running.load(Ordering::SeqCst) {
+                break;
+            }
+            // Pausing is no outage and no restart
+            if self.paused.load(Ordering::SeqCst) {
+                continue;
+            }
+            downtime.down();
+            downtime.update(&self.metrics, self.stream_type.get_type_str());
+            let reason = match result {
+                Ok(RestartReason::Exit) => {
                     // Process exited normally, continue monitoring
                     info!("FFprobe process completed normally, restarting");
-                    self.metrics
-                        .connection_state
-                        .with_label_values(&[self.stream_type.get_type_str()])
-                        .set(0.0);
-                    self.metrics
-                        .connection_reset
-                        .with_label_values(&[self.stream_type.get_type_str()])
-                        .inc();
-
-                    // Wait before restarting
-                    warn!(
-                        "Waiting before restarting FFprobe process for {}",
-                        RETRY_DELAY.as_secs()
+                    self.events
+                        .record("reconnect", None, "FFprobe process completed");
+                    RestartReason::Exit
+                }
+                Ok(reason) => {
+                    info!("Restarting FFprobe process, reason: {}", reason.as_str());
+                    self.events.record(
+                        "reconnect",
+                        Some(reason.as_str()),
+                        "FFprobe process restarted",
                     );
-                    for _ in 0..100 {
-                        if !self.running.load(Ordering::SeqCst) {
-                            info!("Shutdown requested during restart wait");
-                            return Ok(());
-                        }
-                        thread::sleep(RETRY_DELAY / 100);
-                    }
+                    reason
                 }
                 Err(e) => {
                     error!(?e, "FFprobe process failed");
-                    self.metrics
-                        .connection_state
-                        .with_label_values(&[self.stream_type.get_type_str()])
-                        .set(0.0);
-                    self.metrics
-                        .connection_reset
-                        .with_label_values(&[self.stream_type.get_type_str()])
-                        .inc();
-
-                    warn!(
-                        "Waiting before retrying FFprobe process for {}",
-                        RETRY_DELAY.as_secs()
-                    );
-                    for _ in 0..100 {
-                        if !self.running.load(Ordering::SeqCst) {
-                            info!("Shutdown requested during retry wait");
-                            return Ok(());
-                        }
-                        thread::sleep(RETRY_DELAY / 100);
-                    }
+                    let severity = Severities::new(&self.options.severities).of("ffprobe_failed");
+                    self.metrics.record_error("ffprobe_failed", severity);
+                    self.events
+                        .record("reconnect", Some("ffprobe_failed"), &format!("{:#}", e));
+                    self.status.failed(&format!("{:#}", e));
+                    RestartReason::Error
                 }
+            };
+            self.metrics
+                .connection_reset
+                .with_label_values(&[self.stream_type.get_type_str()])
+                .inc();
+            self.metrics
+                .restarts
+                .with_label_values(&[self.stream_type.get_type_str(), reason.as_str()])
+                .inc();
+
+            self.check_retry_budget(&mut backoff)?;
+            let delay = backoff.next_delay();
+            self.metrics.retry_backoff.set(delay.as_secs_f64());
+            warn!(
+                "Waiting {:.1}s before restarting FFprobe process",
+                delay.as_secs_f64()
+            );
+            for _ in 0..100 {
+                if !self.running.load(Ordering::SeqCst) {
+                    info!("Shutdown requested during retry wait");
+                    return Ok(());
+                }
+                if self.paused.load(Ordering::SeqCst) {
+                    break;
+                }
+                thread::sleep(delay / 100);
+                downtime.update(&self.metrics, self.stream_type.get_type_str());
             }
         }
 
         Ok(())
     }
 
-    #[instrument(skip(self))]
-    fn run_single_monitor(&self) -> Result<()> {
-        let mut cmd = self.build_ffprobe_command();
-        let mut child = cmd.spawn().context("Failed to spawn ffprobe process")?;
+    /// Blocks until monitoring is resumed or stopped. An ongoing outage or
+    /// stall ends, and the retry delay starts over.
+    fn wait_while_paused(
+        &self,
+        watchdog: &mut StallWatchdog,
+        backoff: &mut Backoff,
+        downtime: &mut Downtime,
+    ) {
+        let stream_type = self.stream_type.get_type_str();
+        info!("Monitoring paused");
+        self.events
+            .record("pause", None, "Monitoring paused through the admin API");
+        self.status.paused();
+        self.metrics
+            .paused
+            .with_label_values(&[stream_type])
+            .set(1.0);
+        downtime.up(&self.metrics, stream_type);
+        watchdog.reset(&self.metrics, stream_type);
+        // Nothing measures a paused stream
+        self.metrics.clear_session_gauges(self.options.stale_gauges);
+        backoff.reset();
+        self.metrics.retry_backoff.set(0.0);
+
+        while self.running.load(Ordering::SeqCst) && self.paused.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        self.metrics
+            .paused
+            .with_label_values(&[stream_type])
+            .set(0.0);
+        if self.running.load(Ordering::SeqCst) {
+            info!("Monitoring resumed");
+            self.events
+                .record("pause", Some("resumed"), "Monitoring resumed");
+        }
+    }
+
+    /// Fails once the stream went without data for more restarts or longer
+    /// than allowed, handing the restart policy to whatever runs the exporter
+    fn check_retry_budget(&self, backoff: &mut Backoff) -> Result<()> {
+        let max_retries = self.options.max_retries;
+        if max_retries > 0 && backoff.retries() >= max_retries {
+            anyhow::bail!("Giving up after {} retries without data", max_retries);
+        }
+        let max_downtime = self.options.max_downtime;
+        let downtime = backoff.downtime();
+        if max_downtime > 0 && downtime >= Duration::from_secs(max_downtime) {
+            anyhow::bail!("Giving up after {}s without data", downtime.as_secs());
+        }
+        Ok(())
+    }
+
+    /// Runs a short `-show_streams` probe and replaces the stream info series
+    /// with the streams found in the input
+    fn update_stream_info(&self) -> Result<ProbedStreams> {
+        let args = self.stream_type.get_stream_info_args(&self.options);
+        let output = run_info_probe(
+            self.build_ffprobe_command(args),
+            self.info_probe_timeout(),
+            self.kill_grace_period(),
+            || self.stopped(),
+        )?;
+
+        self.programs.update(&output);
+        self.metrics.stream_info.reset();
+        self.metrics.audio_sample_rate.reset();
+        self.metrics.audio_channels.reset();
+        self.metrics.audio_channel_layout.reset();
+        self.metrics.audio_track_bitrate.reset();
+        self.metrics.audio_track_present.reset();
+        self.metrics.subtitle_track_present.reset();
+        self.metrics.rtmp_metadata_info.reset();
+        self.metrics.rtmp_metadata_datarate.reset();
+        let rtmp = matches!(self.stream_type, StreamType::Rtmp(_));
+        Ok(process_stream_info(&output, &self.metrics, rtmp))
+    }
+
+    /// Whether monitoring stopped or is paused, ending the running session
+    fn stopped(&self) -> bool {
+        !self.running.load(Ordering::SeqCst) || self.paused.load(Ordering::SeqCst)
+    }
+
+    fn info_probe_timeout(&self) -> Duration {
+        Duration::from_micros(self.options.analyze_duration.into()) + STREAM_INFO_TIMEOUT
+    }
+
+    /// Time child processes get to exit before they are killed
+    fn kill_grace_period(&self) -> Duration {
+        Duration::from_secs(self.options.kill_grace_period)
+    }
+
+    /// Probes the service names again on a thread of its own, so the session
+    /// keeps being watched meanwhile
+    fn refresh_program_names(&self) {
+        let args = self.stream_type.get_program_info_args(&self.options);
+        let cmd = self.build_ffprobe_command(args);
+        let timeout = self.info_probe_timeout();
+        let grace = self.kill_grace_period();
+        let running = self.running.clone();
+        let paused = self.paused.clone();
+        let programs = self.programs.clone();
+        let stopped = move || !running.load(Ordering::SeqCst) || paused.load(Ordering::SeqCst);
+        thread::spawn(move || match run_info_probe(cmd, timeout, grace, stopped) {
+            Ok(output) => programs.update(&output),
+            Err(e) => warn!(?e, "Failed to refresh program names"),
+        });
+    }
+
+    /// Starts the content analysis ffprobe next to the monitoring session,
+    /// if any analysis is enabled
+    fn start_analysis(&self, probed: &ProbedStreams) -> Option<AnalysisSession> {
+        let input = self.stream_type.input_url(&self.options);
+        let format = self.stream_type.input_format();
+        let tracks = probed.tracks.ordered_labels();
+        let reference = self.options.reference.as_deref();
+        let graph = analysis_graph(&input, format, reference, &self.options, &tracks)?;
+        let logged = analysis_graph(
+            &redact(&input),
+            format,
+            reference.map(redact).as_deref(),
+            &self.options,
+            &tracks,
+        )?;
+        let cmd = self.ffprobe_command(analysis_args(&graph), analysis_args(&logged));
+        let tracker = AnalysisTracker::new(&self.options, &tracks);
+        AnalysisSession::start(cmd, self.metrics.clone(), tracker, self.kill_grace_period())
+            .inspect_err(|e| warn!(?e, "Failed to start content analysis"))
+            .ok()
+    }
+
+    /// Starts the ffmpeg passing the raw transport stream through for the
+    /// transport stream analysis, if enabled and the input carries an MPEG-TS
+    fn start_ts_analysis(&self) -> Option<TransportSession> {
+        if !self.options.ts_analysis {
+            return None;
+        }
+        let args = self.stream_type.get_ts_analysis_args(&self.options)?;
+        let logged = redact_args(&args);
+        let cmd = child_command(&self.options.ffmpeg_path, args, logged);
+        TransportSession::start(
+            cmd,
+            self.metrics.clone(),
+            self.stream_type.is_live(),
+            self.programs.clone(),
+            self.kill_grace_period(),
+        )
+        .inspect_err(|e| warn!(?e, "Failed to start transport stream analysis"))
+        .ok()
+    }
+
+    /// Starts the ffmpeg taking snapshots of the video, if enabled
+    fn start_snapshots(&self) -> Option<SnapshotSession> {
+        if self.options.snapshot_interval == 0 {
+            return None;
+        }
+        let args = self.stream_type.get_snapshot_args(&self.options);
+        let logged = redact_args(&args);
+        let cmd = child_command(&self.options.ffmpeg_path, args, logged);
+        SnapshotSession::start(cmd, self.snapshots.clone(), self.kill_grace_period())
+            .inspect_err(|e| warn!(?e, "Failed to start snapshots"))
+            .ok()
+    }
+
+    /// Starts the srt-live-transmit receiver reporting the socket statistics
+    /// of SRT inputs, if enabled
+    fn start_srt_stats(&self) -> Option<SrtStatsSession> {
+        if !self.options.srt_stats {
+            return None;
+        }
+        let sink = UdpSocket::bind("127.0.0.1:0")
+            .and_then(|sink| sink.local_addr().map(|addr| (sink, addr)));
+        let (sink, addr) = match sink {
+            Ok(sink) => sink,
+            Err(e) => {
+                warn!(?e, "Failed to bind the SRT statistics sink");
+                return None;
+            }
+        };
+        let output = format!("udp://{}", addr);
+        let args = self
+            .stream_type
+            .get_srt_stats_args(&self.options, &output)?;
+        let logged = redact_args(&args);
+        let cmd = child_command(&self.options.srt_live_transmit_path, args, logged);
+        SrtStatsSession::start(cmd, sink, self.metrics.clone(), self.kill_grace_period())
+            .inspect_err(|e| warn!(?e, "Failed to start SRT statistics"))
+            .ok()
+    }
+
+    #[instrument(skip(self, watchdog, backoff, downtime), fields(stream = %self.name))]
+    /// `backoff` starts over and an outage in `downtime` ends once the
+    /// session delivers data. Returns why the session ended unless ffprobe
+    /// failed.
+    fn run_single_monitor(
+        &self,
+        watchdog: &mut StallWatchdog,
+        backoff: &mut Backoff,
+        downtime: &mut Downtime,
+    ) -> Result<RestartReason> {
+        // Codecs and resolution may change between sessions, e.g. after an
+        // encoder restart, so the stream info is refreshed on every reconnect
+        let mut probed = self.update_stream_info().unwrap_or_else(|e| {
+            warn!(?e, "Failed to probe stream info");
+            ProbedStreams::default()
+        });
+        if self.stopped() {
+            return Ok(RestartReason::Exit);
+        }
+
+        let args = self.stream_type.get_ffprobe_args(&self.options);
+        let mut cmd = self.build_ffprobe_command(args);
+        let mut child = spawn(&mut cmd).context("Failed to spawn ffprobe process")?;
+        let activity = Activity::new();
+
+        // Totals keep counting across restarts, the session gauge starts over
+        self.metrics.session_frames.reset();
+        let analysis = self.start_analysis(&probed);
+        let _transport = self.start_ts_analysis();
+        let _srt_stats = self.start_srt_stats();
+        let _snapshots = self.start_snapshots();
 
         let stdout = child.stdout.take().context("Failed to capture stdout")?;
         let stderr = child.stderr.take().context("Failed to capture stderr")?;
@@ -148,21 +553,32 @@ impl FFprobeMonitor {
         let stdout_reader = BufReader::new(stdout);
         let stderr_reader = BufReader::new(stderr);
 
-        let patterns = StreamPatterns::new()?;
+        let patterns =
+            StreamPatterns::new()?.with_severities(Severities::new(&self.options.severities));
         let (error_tx, error_rx) = std::sync::mpsc::channel();
 
         // Spawn stderr processing thread
         let stream_type = self.stream_type.clone();
         let metrics = self.metrics.clone();
         let patterns_clone = patterns.clone();
+        let events = self.events.clone();
         let error_tx_clone = error_tx.clone();
         let running = self.running.clone();
+        let mut sampler =
+            LineSampler::new(self.options.log_raw_lines, self.options.log_sample_rate);
+        let mut budget = LineBudget::new(
+            self.options.stderr_max_lines_per_sec,
+            self.options.stderr_max_bytes_per_sec,
+        );
         thread::spawn(move || {
             if let Err(e) = process_stderr(
                 stderr_reader,
                 &patterns_clone,
                 &metrics,
+                &events,
                 stream_type.get_type_str(),
+                &mut sampler,
+                &mut budget,
             ) {
                 error!(?e, "Error processing stderr");
                 let _ = error_tx_clone.send(e);
@@ -175,19 +591,36 @@ impl FFprobeMonitor {
         let stream_type = self.stream_type.clone();
         let error_tx_clone = error_tx.clone();
         let running_clone = self.running.clone();
+        let stdout_events = self.events.clone();
+        let options = self.options.clone();
+        let stdout_activity = activity.clone();
         thread::spawn(move || {
-            if let Err(e) = process_stdout(stdout_reader, &metrics, &stream_type) {
+            if let Err(e) = process_stdout(
+                stdout_reader,
+                &metrics,
+                &stream_type,
+                &stdout_events,
+                &options,
+                &mut probed,
+                &stdout_activity,
+            ) {
                 error!(?e, "Error processing stdout");
                 let _ = error_tx_clone.send(e);
                 running_clone.store(false, Ordering::SeqCst);
             }
         });
 
+        // Connecting to RTMP servers takes a handshake and a play request
+        // before the first packet
+        let mut connect_pending = matches!(self.stream_type, StreamType::Rtmp(_));
+        let mut data_pending = true;
+        let probe_timeout = Duration::from_secs(self.options.probe_timeout);
+
         // Monitor the process and error channels
         loop {
             match error_rx.try_recv() {
                 Ok(error) => {
-                    let _ = child.kill();
+                    terminate(&mut child, self.kill_grace_period());
                     return Err(error);
                 }
                 Err(std::sync::mpsc::TryRecvError::Empty) => {}
@@ -206,6 +639,59 @@ impl FFprobeMonitor {
                     break;
                 }
                 Ok(None) => {
+                    if connect_pending && let Some(connect) = activity.first_record() {
+                        self.metrics.rtmp_connect.set(connect.as_secs_f64());
+                        connect_pending = false;
+                    }
+                    if data_pending {
+                        if let Some(connect) = activity.first_record() {
+                            self.metrics
+                                .connect_duration
+                                .with_label_values(&[self.stream_type.get_type_str()])
+                                .observe(connect.as_secs_f64());
+                            backoff.reset();
+                            self.metrics.retry_backoff.set(0.0);
+                            downtime.up(&self.metrics, self.stream_type.get_type_str());
+                            data_pending = false;
+                        } else {
+                            downtime.update(&self.metrics, self.stream_type.get_type_str());
+                        }
+                    }
+                    if self.programs.take_stale() {
+                        self.refresh_program_names();
+                    }
+                    if activity.startup_timed_out(probe_timeout) {
+                        warn!(
+                            "No output from ffprobe within {} seconds, restarting",
+                            probe_timeout.as_secs()
+                        );
+                        self.metrics
+                            .probe_timeouts
+                            .with_label_values(&[self.stream_type.get_type_str()])
+                            .inc();
+                        terminate(&mut child, self.kill_grace_period());
+                        return Ok(RestartReason::Timeout);
+                    }
+                    let stalled = watchdog.check(
+                        &activity,
+                        &self.metrics,
+                        &self.events,
+                        self.stream_type.get_type_str(),
+                    );
+                    if !data_pending {
+                        self.status.delivering(stalled);
+                    }
+                    if stalled && self.options.stall_restart {
+                        terminate(&mut child, self.kill_grace_period());
+                        return Ok(RestartReason::Stall);
+                    }
+                    if self.options.freeze_restart
+                        && analysis.as_ref().is_some_and(AnalysisSession::frozen)
+                    {
+                        warn!("Video frozen, restarting ffprobe");
+                        terminate(&mut child, self.kill_grace_period());
+                        return Ok(RestartReason::Freeze);
+                    }
                     thread::sleep(Duration::from_millis(100));
                 }
                 Err(e) => {
@@ -213,172 +699,842 @@ impl FFprobeMonitor {
                 }
             }
 
-            if !self.running.load(Ordering::SeqCst) {
-                let _ = child.kill();
+            if self.stopped() {
+                terminate(&mut child, self.kill_grace_period());
                 break;
             }
         }
 
-        Ok(())
+        Ok(RestartReason::Exit)
     }
 }
 
-fn process_stderr(
-    reader: impl BufRead,
-    patterns: &StreamPatterns,
-    metrics: &StreamMetrics,
-    stream_type: &str,
-) -> Result<()> {
-    for line in reader.lines() {
-        let line = line.context("Failed to read stderr line")?;
-        debug!("FFprobe stderr: {}", line);
+/// Runs a short probe to its end and returns its output, terminating it
+/// after `timeout` or once `stopped`, with `grace` to exit on its own
+fn run_info_probe(
+    cmd: Command,
+    timeout: Duration,
+    grace: Duration,
+    stopped: impl Fn() -> bool,
+) -> Result<String> {
+    let (status, output) = run_to_end(cmd, timeout, grace, stopped)?;
+    if !status.success() {
+        anyhow::bail!(
+            "Stream info probe failed with exit code: {}",
+            status.code().unwrap_or(-1)
+        );
+    }
+    Ok(output)
+}
 
-        // Check for SRT dropped packets
-        if let Some(caps) = patterns.srt_dropped.captures(&line) {
-            if let Some(count) = caps.get(1).and_then(|m| m.as_str().parse::<f64>().ok()) {
-                metrics
-                    .dropped_packets
-                    .with_label_values(&[stream_type])
-                    .inc_by(count);
-            }
+/// Runs a probe to its end and returns its exit status and output
+fn run_to_end(
+    mut cmd: Command,
+    timeout: Duration,
+    grace: Duration,
+    stopped: impl Fn() -> bool,
+) -> Result<(ExitStatus, String)> {
+    cmd.stderr(Stdio::null());
+    let mut child = spawn(&mut cmd).context("Failed to spawn ffprobe process")?;
+
+    // Drain stdout on its own thread so a chatty probe cannot block on a full pipe
+    let mut stdout = child.stdout.take().context("Failed to capture stdout")?;
+    let reader = thread::spawn(move || {
+        let mut output = String::new();
+        stdout.read_to_string(&mut output).map(|_| output)
+    });
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if stopped() || started.elapsed() > timeout {
+            terminate(&mut child, grace);
+            anyhow::bail!("Stream info probe did not finish");
         }
+        thread::sleep(Duration::from_millis(100));
+    };
+    let output = reader
+        .join()
+        .map_err(|_| anyhow::anyhow!("Stream info reader panicked"))?
+        .context("Failed to read stream info")?;
+    Ok((status, output))
+}
 
-        // Check for corrupt packets
-        if let Some(caps) = patterns.packet_corrupt.captures(&line) {
-            if let Some(stream_id) = caps.get(1) {
-                let stream_id = stream_id.as_str();
+/// `rtmp` inputs report their `onMetaData` in the format record and the
+/// stream bitrates
+fn process_stream_info(output: &str, metrics: &StreamMetrics, rtmp: bool) -> ProbedStreams {
+    let mut probed = ProbedStreams::default();
+    for line in output.lines() {
+        let fields = Fields::split(line);
+        match fields.as_slice().first() {
+            Some(&"stream") => {}
+            Some(&"format") if rtmp => {
+                process_rtmp_metadata(&FormatRecord::parse(&fields), metrics);
+                continue;
+            }
+            _ => continue,
+        }
+        if let Some(mut stream) = StreamRecord::parse(&fields) {
+            // Streams beyond the label limits are not described
+            stream.stream_id = metrics.stream_ids.admit(stream.stream_id);
+            if stream.stream_id == OTHER {
+                continue;
+            }
+            // The FLV demuxer multiplies the announced kbit/s by 1024
+            if rtmp && let Some(bit_rate) = stream.bit_rate {
                 metrics
-                    .packet_corrupt
-                    .with_label_values(&[stream_id, "unknown"])
-                    .inc();
+                    .rtmp_metadata_datarate
+                    .with_label_values(&[stream.media_type])
+                    .set(bit_rate / 1024.0);
+            }
+            metrics
+                .stream_info
+                .with_label_values(&[
+                    stream.stream_id,
+                    stream.media_type,
+                    stream.codec,
+                    stream.profile,
+                    stream.width,
+                    stream.height,
+                    stream.pix_fmt,
+                ])
+                .set(1.0);
+            if stream.media_type == "audio" {
+                process_audio_info(&stream, metrics);
+                probed.tracks.insert(&stream);
+            }
+            if stream.media_type == "video"
+                && let Some(rate) = parse_frame_rate(stream.frame_rate)
+            {
+                probed.cadence.set_frame_rate(stream.stream_id, rate);
+            }
+            if stream.media_type == "subtitle" {
+                probed.subtitles.insert(&stream, metrics);
+            }
+            if stream.codec == ID3_CODEC {
+                probed.id3.insert(stream.stream_id);
             }
         }
+    }
+    probed
+}
 
-        // Check for codec-specific errors
-        if let Some(caps) = patterns.codec_error.captures(&line) {
-            let error_type = match caps.get(2).map(|m| m.as_str()) {
-                Some(msg) if msg.contains("SEI") => "sei_error",
-                Some(msg) if msg.contains("PPS") => "pps_error",
-                Some(msg) if msg.contains("decode_slice_header") => "slice_header_error",
-                Some(msg) if msg.contains("no frame") => "missing_frame",
-                _ => "other",
-            };
+fn process_rtmp_metadata(format: &FormatRecord, metrics: &StreamMetrics) {
+    metrics
+        .rtmp_metadata_duration
+        .set(format.duration.unwrap_or_default());
+    if !format.encoder.is_empty() {
+        metrics
+            .rtmp_metadata_info
+            .with_label_values(&[format.encoder])
+            .set(1.0);
+    }
+}
+
+fn process_audio_info(stream: &StreamRecord, metrics: &StreamMetrics) {
+    if let Ok(sample_rate) = stream.sample_rate.parse::<f64>() {
+        metrics
+            .audio_sample_rate
+            .with_label_values(&[stream.stream_id])
+            .set(sample_rate);
+    }
+    if let Ok(channels) = stream.channels.parse::<f64>() {
+        metrics
+            .audio_channels
+            .with_label_values(&[stream.stream_id])
+            .set(channels);
+    }
+    if !stream.channel_layout.is_empty() {
+        metrics
+            .audio_channel_layout
+            .with_label_values(&[stream.stream_id, stream.channel_layout])
+            .set(1.0);
+    }
+}
+
+fn process_stderr(
+    reader: impl BufRead,
+    patterns: &StreamPatterns,
+    metrics: &StreamMetrics,
+    events: &EventRecorder,
+    stream_type: &str,
+    sampler: &mut LineSampler,
+    budget: &mut LineBudget,
+) -> Result<()> {
+    for_each_line(reader, |line| {
+        // Keep draining the pipe so ffprobe never blocks, but skip parsing the excess
+        if !budget.admit(line.len()) {
             metrics
-                .codec_errors
-                .with_label_values(&[error_type, "0"])
+                .stderr_lines_dropped
+                .with_label_values(&[stream_type])
                 .inc();
+            return Ok(());
+        }
+        if sampler.should_log() {
+            debug!("FFprobe stderr: {}", line);
         }
+        process_stderr_line(line, patterns, metrics, events, stream_type);
+        Ok(())
+    })
+    .context("Failed to read stderr line")
+}
+
+fn process_stderr_line(
+    line: &str,
+    patterns: &StreamPatterns,
+    metrics: &StreamMetrics,
+    events: &EventRecorder,
+    stream_type: &str,
+) {
+    // Check for SRT dropped packets
+    if let Some(caps) = patterns.srt_dropped.captures(line)
+        && let Some(count) = caps.get(1).and_then(|m| m.as_str().parse::<f64>().ok())
+    {
+        metrics
+            .dropped_packets
+            .with_label_values(&[stream_type])
+            .inc_by(count);
+        metrics.record_error("dropped_packets", patterns.severities.of("dropped_packets"));
+        events.record("dropped_packets", None, line);
+    }
+
+    // Check for UDP receive buffer overruns
+    if patterns.udp_overrun.is_match(line) {
+        metrics.udp_overruns.with_label_values(&[stream_type]).inc();
+        metrics.record_error("udp_overrun", patterns.severities.of("udp_overrun"));
+        events.record("udp_overrun", None, line);
+    }
+
+    // The kernel caps the socket receive buffer, which makes overruns likely
+    if let Some(caps) = patterns.udp_buffer_capped.captures(line)
+        && let Ok(granted) = caps[2].parse::<f64>()
+    {
+        metrics.udp_receive_buffer.set(granted);
+        warn!(
+            requested = &caps[1],
+            granted = &caps[2],
+            "UDP receive buffer capped by the kernel, raise net.core.rmem_max"
+        );
+    }
+
+    // Check for MPEG-TS continuity counter errors, only logged at debug level
+    if let Some(caps) = patterns.cc_error.captures(line)
+        && let Some(pid) = caps.get(1)
+    {
+        metrics
+            .ts_cc_errors
+            .with_label_values(&[metrics.pids.admit(pid.as_str())])
+            .inc();
+        metrics.record_error("cc_error", patterns.severities.of("cc_error"));
+        events.record("cc_error", None, line);
+    }
+
+    // Check for gaps in the RTP sequence numbers of RTSP inputs
+    if let Some(caps) = patterns.rtp_missed.captures(line)
+        && let Ok(count) = caps[1].parse::<f64>()
+    {
+        metrics
+            .rtp_packets_lost
+            .with_label_values(&[stream_type])
+            .inc_by(count);
+        metrics.record_error("rtp_packet_loss", patterns.severities.of("rtp_packet_loss"));
+        events.record("rtp_packet_loss", None, line);
+    }
+
+    // Check for corrupt packets
+    if let Some(caps) = patterns.packet_corrupt.captures(line)
+        && let Some(stream_id) = caps.get(1)
+    {
+        let stream_id = metrics.stream_ids.admit(stream_id.as_str());
+        metrics
+            .packet_corrupt
+            .with_label_values(&[stream_id, "unknown"])
+            .inc();
+        metrics.record_error("packet_corrupt", patterns.severities.of("packet_corrupt"));
+        events.record("packet_corrupt", None, line);
+    }
+
+    // Check for codec-specific errors
+    if let Some(caps) = patterns.codec_error.captures(line) {
+        let error_type = caps
+            .get(2)
+            .map_or("other", |m| codec_error_type(m.as_str()));
+        metrics
+            .codec_errors
+            .with_label_values(&[error_type, "0"])
+            .inc();
+        metrics.record_error(error_type, patterns.severities.of(error_type));
+        events.record("codec_error", Some(error_type), line);
     }
-    Ok(())
 }
 
 fn process_stdout(
     reader: impl BufRead,
     metrics: &StreamMetrics,
     stream_type: &StreamType,
+    events: &EventRecorder,
+    options: &MonitorOptions,
+    probed: &mut ProbedStreams,
+    activity: &Activity,
 ) -> Result<()> {
-    let mut frame_times: Vec<(String, f64)> = Vec::new();
-    let mut last_fps_update = Instant::now();
-
-    for line in reader.lines() {
-        let line = line.context("Failed to read stdout line")?;
-        debug!("FFprobe stdout: {:?}", line);
-        let parts: Vec<&str> = line.split(',').collect();
-
-        if parts.len() < 3 {
-            continue;
-        }
-
-        match parts[0] {
-            "packet" => process_packet_line(&parts, metrics)?,
-            "frame" => process_frame_line(
-                &parts,
-                metrics,
-                stream_type,
-                &mut frame_times,
-                &mut last_fps_update,
-            )?,
-            _ => continue,
+    let mut sampler = LineSampler::new(options.log_raw_lines, options.log_sample_rate);
+    let mut fps = FpsTracker::default();
+    let mut decimator = FrameDecimator::new(options.frame_sample_rate);
+    let mut bitrates = BitrateTracker::default();
+    let mut sync = AvSync::default();
+    let mut gops = GopTracker::default();
+    let mut formats = FormatTracker::with_events(events.clone());
+    let mut lag = stream_type.is_live().then(LiveLag::default);
+    let mut jitter = matches!(stream_type, StreamType::Rtsp(_)).then(RtpJitter::default);
+    let mut unwrapper = TimestampUnwrapper::default();
+    let mut qps = QpTracker::default();
+    let mut timecodes = TimecodeTracker::default();
+    let mut captions = CaptionTracker::default();
+
+    for_each_line(reader, |line| {
+        if sampler.should_log() {
+            debug!("FFprobe stdout: {:?}", line);
+        }
+        let fields = Fields::split(line);
+
+        match fields.as_slice()[0] {
+            "packet" | "frame" => activity.touch(),
+            _ => {}
+        }
+        match fields.as_slice()[0] {
+            "packet" => {
+                if let Some(mut packet) = PacketRecord::parse(&fields) {
+                    // Streams beyond the label limits share the packet and
+                    // bitrate metrics of `other`, per stream state would mix
+                    // them up
+                    packet.stream_id = metrics.stream_ids.admit(packet.stream_id);
+                    let suppressed = packet.stream_id == OTHER;
+                    if !suppressed {
+                        packet.dts_time = packet
+                            .dts_time
+                            .map(|time| unwrapper.unwrap(packet.stream_id, time));
+                    }
+                    process_packet(&packet, metrics, &mut bitrates, &probed.tracks);
+                    if let Some(declared) = options.declared_bandwidth
+                        && let Some(deviation) = bitrates
+                            .total_average()
+                            .and_then(|total| declared_deviation(total, declared))
+                    {
+                        metrics
+                            .bitrate_declared_deviation
+                            .with_label_values(&[])
+                            .set(deviation);
+                    }
+                    if suppressed {
+                        return Ok(());
+                    }
+                    if let Some(jitter) = &mut jitter {
+                        jitter.observe(&packet, metrics);
+                    }
+                    gops.observe(&packet, metrics);
+                    probed.id3.observe(&packet, metrics);
+                    probed.subtitles.observe(&packet, metrics);
+                }
+            }
+            "frame" => {
+                let Some(mut frame) = FrameRecord::parse(&fields) else {
+                    return Ok(());
+                };
+                // Frames of streams beyond the label limits are only counted,
+                // each of them, as the decimation is per stream. Their
+                // timestamps come from several streams, so they have no rate
+                frame.stream_id = metrics.stream_ids.admit(frame.stream_id);
+                if frame.stream_id == OTHER {
+                    count_frame(&frame, metrics, 1.0);
+                    return Ok(());
+                }
+                frame.pts_time = frame
+                    .pts_time
+                    .map(|time| unwrapper.unwrap(frame.stream_id, time));
+                // Format changes are rare, so every frame is checked
+                formats.observe(&frame, metrics);
+                probed.tracks.observe_frame(&frame, metrics);
+                probed.cadence.observe(&frame, metrics);
+                qps.observe(&frame, metrics);
+                timecodes.observe(&frame, metrics);
+                captions.observe(&frame, metrics);
+                if let Some(lag) = &mut lag {
+                    lag.observe(&frame, metrics);
+                }
+                if decimator.sample(frame.stream_id) {
+                    sync.observe(&frame, metrics);
+                    process_frame(
+                        &frame,
+                        metrics,
+                        stream_type,
+                        &mut fps,
+                        decimator.every as f64,
+                    );
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    })
+    .context("Failed to read stdout line")
+}
+
+/// Lets only every Nth frame record of each stream through to the per-frame
+/// metrics.
+struct FrameDecimator {
+    every: u64,
+    seen: Vec<u64>,
+}
+
+impl FrameDecimator {
+    fn new(every: u64) -> Self {
+        Self {
+            every: every.max(1),
+            seen: Vec::new(),
         }
     }
 
-    Ok(())
+    fn sample(&mut self, stream_id: &str) -> bool {
+        if self.every == 1 {
+            return true;
+        }
+        let Ok(index) = stream_id.parse::<usize>() else {
+            return true;
+        };
+        if index >= self.seen.len() {
+            self.seen.resize(index + 1, 0);
+        }
+        let sampled = self.seen[index].is_multiple_of(self.every);
+        self.seen[index] += 1;
+        sampled
+    }
 }
 
-fn process_packet_line(parts: &[&str], metrics: &StreamMetrics) -> Result<()> {
-    if parts.len() >= 12 {
-        let media_type = parts[1];
-        let stream_id = parts[2];
+fn process_packet(
+    packet: &PacketRecord,
+    metrics: &StreamMetrics,
+    bitrates: &mut BitrateTracker,
+    tracks: &AudioTracks,
+) {
+    let media_type = packet.media_type;
+    let stream_id = packet.stream_id;
 
-        if let Ok(size) = parts[9].parse::<f64>() {
+    if let Some(size) = packet.size {
+        metrics
+            .packet_size
+            .with_label_values(&[media_type])
+            .observe(size);
+    }
+
+    if let (Some(size), Some(time)) = (packet.size, packet.dts_time) {
+        let (instant, average) = bitrates.push(stream_id, time, size);
+        if let Some(kbits) = instant {
             metrics
                 .bitrate
                 .with_label_values(&[stream_id, media_type])
-                .set(size * 8.0 / 1000.0);
+                .set(kbits);
+            tracks.observe_bitrate(stream_id, kbits, metrics);
         }
-
-        // Check flags for corruption
-        if parts.len() >= 11 && parts[11].contains('C') {
+        if let Some(kbits) = average {
             metrics
-                .packet_corrupt
+                .bitrate_avg
                 .with_label_values(&[stream_id, media_type])
-                .inc();
+                .set(kbits);
         }
     }
-    Ok(())
+
+    // Check flags for corruption
+    if packet.is_corrupt() {
+        metrics
+            .packet_corrupt
+            .with_label_values(&[stream_id, media_type])
+            .inc();
+    }
 }
 
-fn process_frame_line(
-    parts: &[&str],
+/// Counts a frame standing for `scale` frames
+fn count_frame(frame: &FrameRecord, metrics: &StreamMetrics, scale: f64) {
+    metrics
+        .frame_counter
+        .with_label_values(&["processed", frame.stream_id, frame.media_type])
+        .inc_by(scale);
+    metrics
+        .session_frames
+        .with_label_values(&[frame.stream_id, frame.media_type])
+        .add(scale);
+}
+
+fn process_frame(
+    frame: &FrameRecord,
     metrics: &StreamMetrics,
     stream_type: &StreamType,
-    frame_times: &mut Vec<(String, f64)>,
-    last_fps_update: &mut Instant,
-) -> Result<()> {
-    if parts.len() >= 6 {
-        let media_type = parts[1];
-        let stream_id = parts[2];
+    fps: &mut FpsTracker,
+    scale: f64,
+) {
+    // With decimation each sampled frame stands for `scale` frames
+    count_frame(frame, metrics, scale);
 
-        metrics
-            .frame_counter
-            .with_label_values(&["processed", stream_id, media_type])
-            .inc();
+    if let Some(pts_time) = frame.pts_time {
+        fps.observe(frame.stream_id, frame.media_type, pts_time, scale);
+        fps.update(|stream_id, media_type, rate| {
+            metrics
+                .fps
+                .with_label_values(&[stream_type.get_type_str(), stream_id, media_type])
+                .set(rate);
+        });
+    }
+}
 
-        if let Ok(pts_time) = parts[5].parse::<f64>() {
-            frame_times.push((format!("{}_{}", stream_id, media_type), pts_time));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventLog;
+    use std::collections::HashMap;
 
-            // Keep only last 100 frames per stream
-            while frame_times.len() > 100 {
-                frame_times.remove(0);
-            }
+    /// A monitor of an SRT stream run by the fake ffprobe `script`, waiting a
+    /// minute between restarts
+    #[cfg(unix)]
+    fn fake_monitor(
+        script: &str,
+        configure: impl FnOnce(&mut MonitorOptions),
+    ) -> (FFprobeMonitor, StreamMetrics) {
+        use crate::config::Args;
+        use clap::Parser;
 
-            // Update FPS every second
-            if last_fps_update.elapsed().as_secs() >= 1 {
-                // Group frames by stream_id and media_type
-                let mut stream_frames: HashMap<String, Vec<f64>> = HashMap::new();
+        let ffprobe = crate::stream::process::fake_ffprobe(script);
+        let args = Args::parse_from([
+            "ffmpeg_exporter",
+            "--input",
+            "unused",
+            "--ffprobe-path",
+            &ffprobe,
+        ]);
+        let mut options = MonitorOptions::from(&args);
+        options.retry_initial = 60;
+        options.retry_max = 60;
+        configure(&mut options);
+        let stream = StreamConfig {
+            name: "sport".to_string(),
+            input: "srt://localhost:9000".to_string(),
+            ..Default::default()
+        };
+        let stream_type = StreamType::from_input(&stream.input).unwrap();
+        let metrics = StreamMetrics::new(&prometheus::Registry::new(), &HashMap::new()).unwrap();
+        let monitor = FFprobeMonitor::new(
+            stream,
+            stream_type,
+            metrics.clone(),
+            options,
+            EventLog::new(0).recorder("sport"),
+        );
+        (monitor, metrics)
+    }
 
-                for (key, time) in frame_times.iter() {
-                    stream_frames.entry(key.clone()).or_default().push(*time);
-                }
+    #[cfg(unix)]
+    fn wait_for(condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
 
-                // Calculate FPS for each stream
-                for (key, times) in stream_frames {
-                    if times.len() >= 2 {
-                        let time_diff = times.last().unwrap() - times.first().unwrap();
-                        let fps = times.len() as f64 / time_diff;
+    #[cfg(unix)]
+    #[test]
+    fn test_restart_reasons() {
+        use crate::stream::process::DELIVERING_FFPROBE;
 
-                        let (stream_id, media_type) =
-                            key.split_once('_').unwrap_or(("0", "unknown"));
+        const REASONS: [&str; 5] = ["exit", "error", "timeout", "stall", "freeze"];
+        let packet = "echo packet,codec_type=video,stream_index=0,pts_time=0.0,size=1000";
+        // The freeze detection runs in an ffprobe of its own, reading the
+        // lavfi graph
+        let frozen = r#"case " $* " in *" lavfi "*)
+  echo "frame,media_type=video,stream_index=0,pts_time=12.0,tag:lavfi.freezedetect.freeze_start=10,tag:lavfi.freezedetect.freeze_duration=2"
+  exec sleep 60 ;;
+esac"#;
+        type Configure = fn(&mut MonitorOptions);
+        let cases: [(&str, String, Configure); 5] = [
+            ("exit", format!("{}\nexit 0", packet), |_| {}),
+            ("error", "exit 1".to_string(), |_| {}),
+            ("timeout", "exec sleep 60".to_string(), |options| {
+                options.probe_timeout = 1
+            }),
+            ("stall", format!("{}\nexec sleep 60", packet), |options| {
+                options.stall_timeout = 1;
+                options.stall_restart = true;
+            }),
+            (
+                "freeze",
+                format!("{}\n{}", frozen, DELIVERING_FFPROBE),
+                |options| {
+                    options.freezedetect = true;
+                    options.freeze_restart = true;
+                },
+            ),
+        ];
 
-                        metrics
-                            .fps
-                            .with_label_values(&[stream_type.get_type_str(), stream_id, media_type])
-                            .set(fps);
-                    }
-                }
-                *last_fps_update = Instant::now();
+        // The monitors run side by side, each until its first restart
+        let monitors: Vec<_> = cases
+            .into_iter()
+            .map(|(reason, script, configure)| {
+                let (monitor, metrics) = fake_monitor(&script, configure);
+                let running = monitor.get_running_handle();
+                (
+                    reason,
+                    metrics,
+                    running,
+                    thread::spawn(move || monitor.run()),
+                )
+            })
+            .collect();
+        for (reason, metrics, running, task) in monitors {
+            let restarts = |reason| metrics.restarts.with_label_values(&["srt", reason]).get();
+            wait_for(|| restarts(reason) == 1.0);
+            running.store(false, Ordering::SeqCst);
+            task.join().unwrap().unwrap();
+            for other in REASONS.into_iter().filter(|other| *other != reason) {
+                assert_eq!(restarts(other), 0.0, "{} counted as {}", reason, other);
             }
         }
     }
-    Ok(())
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pause_during_retry_wait() {
+        // The first session fails, the later ones deliver data
+        let script = format!(
+            "[ -e \"$0.failed\" ] || {{ touch \"$0.failed\"; exit 1; }}\n{}",
+            crate::stream::process::DELIVERING_FFPROBE
+        );
+        let (monitor, metrics) = fake_monitor(&script, |_| {});
+        let running = monitor.get_running_handle();
+        let paused = monitor.get_paused_handle();
+        let status = monitor.get_status_handle();
+        let task = thread::spawn(move || monitor.run());
+
+        let restarts = || metrics.restarts.with_label_values(&["srt", "error"]).get();
+        let paused_gauge = || metrics.paused.with_label_values(&["srt"]).get();
+
+        // The failed session starts an outage and a retry wait of a minute
+        wait_for(|| restarts() == 1.0);
+        wait_for(|| metrics.retry_backoff.get() > 0.0);
+
+        // Pausing ends the wait and the outage
+        paused.store(true, Ordering::SeqCst);
+        wait_for(|| paused_gauge() == 1.0);
+        // Paused on purpose, so healthy
+        assert!(status.healthy());
+        assert_eq!(metrics.retry_backoff.get(), 0.0);
+        let outages = metrics.outage_duration.with_label_values(&["srt"]);
+        assert_eq!(outages.get_sample_count(), 1);
+        let downtime = metrics.downtime.with_label_values(&["srt"]).get();
+        thread::sleep(Duration::from_millis(300));
+        // Pausing is no restart and no outage
+        assert_eq!(restarts(), 1.0);
+        assert_eq!(metrics.downtime.with_label_values(&["srt"]).get(), downtime);
+        assert_eq!(outages.get_sample_count(), 1);
+
+        // Resuming starts a session right away instead of finishing the wait
+        paused.store(false, Ordering::SeqCst);
+        let connected = || metrics.connection_state.with_label_values(&["srt"]).get() == 1.0;
+        wait_for(|| connected() && status.healthy());
+        assert_eq!(paused_gauge(), 0.0);
+        assert_eq!(restarts(), 1.0);
+        assert_eq!(outages.get_sample_count(), 1);
+
+        running.store(false, Ordering::SeqCst);
+        task.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_frame_decimator_samples_per_stream() {
+        let mut decimator = FrameDecimator::new(3);
+        let video: Vec<bool> = (0..6).map(|_| decimator.sample("0")).collect();
+        assert_eq!(video, vec![true, false, false, true, false, false]);
+        // Other streams are counted independently
+        assert!(decimator.sample("1"));
+        assert!(!decimator.sample("1"));
+    }
+
+    #[test]
+    fn test_stderr_udp_overrun() {
+        let registry = prometheus::Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let patterns = StreamPatterns::new().unwrap();
+        process_stderr_line(
+            "[udp @ 0x55d5c8a0] Circular buffer overrun. Surge in UDP traffic, or fifo_size too small.",
+            &patterns,
+            &metrics,
+            &EventLog::new(0).recorder("test"),
+            "udp",
+        );
+        assert_eq!(metrics.udp_overruns.with_label_values(&["udp"]).get(), 1.0);
+
+        process_stderr_line(
+            "[udp @ 0x55d5c8a0] attempted to set receive buffer to size 8388608 but it only ended up set as 425984",
+            &patterns,
+            &metrics,
+            &EventLog::new(0).recorder("test"),
+            "udp",
+        );
+        assert_eq!(metrics.udp_receive_buffer.get(), 425_984.0);
+        assert_eq!(metrics.udp_overruns.with_label_values(&["udp"]).get(), 1.0);
+    }
+
+    #[test]
+    fn test_stderr_cc_error() {
+        let registry = prometheus::Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let patterns = StreamPatterns::new().unwrap();
+        let events = EventLog::new(0).recorder("test");
+        for line in [
+            "[mpegts @ 0x55d5c8a0] Continuity check failed for pid 256 expected 5 got 7",
+            "[mpegts @ 0x55d5c8a0] Continuity check failed for pid 257 expected 14 got 0",
+            "[mpegts @ 0x55d5c8a0] Continuity check failed for pid 256 expected 8 got 10",
+        ] {
+            process_stderr_line(line, &patterns, &metrics, &events, "srt");
+        }
+        assert_eq!(metrics.ts_cc_errors.with_label_values(&["256"]).get(), 2.0);
+        assert_eq!(metrics.ts_cc_errors.with_label_values(&["257"]).get(), 1.0);
+    }
+
+    #[test]
+    fn test_stderr_rtp_missed() {
+        let registry = prometheus::Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let patterns = StreamPatterns::new().unwrap();
+        let events = EventLog::new(0).recorder("test");
+        for line in [
+            "[rtsp @ 0x5591c2d0] RTP: missed 3 packets",
+            "[rtsp @ 0x5591c2d0] RTP: missed 12 packets",
+            "[rtsp @ 0x5591c2d0] max delay reached. need to consume packet",
+        ] {
+            process_stderr_line(line, &patterns, &metrics, &events, "rtsp");
+        }
+        assert_eq!(
+            metrics.rtp_packets_lost.with_label_values(&["rtsp"]).get(),
+            15.0
+        );
+    }
+
+    #[test]
+    fn test_process_stream_info() {
+        let registry = prometheus::Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let output = "stream,index=0,codec_name=h264,profile=High,codec_type=video,width=1920,height=1080,pix_fmt=yuv420p\n\
+                      stream,index=1,codec_name=aac,profile=LC,codec_type=audio,sample_fmt=fltp,sample_rate=48000,channels=2,channel_layout=stereo\n";
+        process_stream_info(output, &metrics, false);
+        let video = ["0", "video", "h264", "High", "1920", "1080", "yuv420p"];
+        assert_eq!(metrics.stream_info.with_label_values(&video).get(), 1.0);
+        let audio = ["1", "audio", "aac", "LC", "", "", ""];
+        assert_eq!(metrics.stream_info.with_label_values(&audio).get(), 1.0);
+        assert_eq!(
+            metrics.audio_sample_rate.with_label_values(&["1"]).get(),
+            48000.0
+        );
+        assert_eq!(metrics.audio_channels.with_label_values(&["1"]).get(), 2.0);
+        assert_eq!(
+            metrics
+                .audio_channel_layout
+                .with_label_values(&["1", "stereo"])
+                .get(),
+            1.0
+        );
+    }
+
+    #[test]
+    fn test_process_rtmp_metadata() {
+        let metrics = StreamMetrics::new(&prometheus::Registry::new(), &HashMap::new()).unwrap();
+        let output = "stream,index=0,codec_name=h264,codec_type=video,bit_rate=6144000\n\
+                      stream,index=1,codec_name=aac,codec_type=audio,bit_rate=163840\n\
+                      format,filename=rtmp://ingest/live/key,format_name=flv,duration=0.000000,tag:encoder=obs-output module (libobs version 30.1.2)\n";
+        process_stream_info(output, &metrics, true);
+        let datarate = |media_type| {
+            metrics
+                .rtmp_metadata_datarate
+                .with_label_values(&[media_type])
+                .get()
+        };
+        assert_eq!(datarate("video"), 6000.0);
+        assert_eq!(datarate("audio"), 160.0);
+        assert_eq!(metrics.rtmp_metadata_duration.get(), 0.0);
+        let encoder = ["obs-output module (libobs version 30.1.2)"];
+        assert_eq!(
+            metrics.rtmp_metadata_info.with_label_values(&encoder).get(),
+            1.0
+        );
+    }
+
+    #[test]
+    fn test_frame_counts_with_decimation_and_suppressed_streams() {
+        use crate::config::{Args, LabelLimits};
+        use clap::Parser;
+        use prometheus::core::Collector;
+
+        let metrics = StreamMetrics::new(&prometheus::Registry::new(), &HashMap::new())
+            .unwrap()
+            .with_label_limits(&LabelLimits {
+                max_values: 2,
+                ..Default::default()
+            });
+        let options = MonitorOptions::from(&Args::parse_from([
+            "ffmpeg_exporter",
+            "--input",
+            "udp://127.0.0.1:5000",
+            "--frame-sample-rate",
+            "4",
+        ]));
+        // 25 fps video, 50 fps audio and a third stream beyond the label
+        // limits with timestamps of its own
+        let frame = |stream_id: u32, media_type: &str, pts: f64| {
+            format!(
+                "frame,media_type={},stream_index={},pts_time={:.6}\n",
+                media_type, stream_id, pts
+            )
+        };
+        let mut input = String::new();
+        for i in 0..400 {
+            input.push_str(&frame(0, "video", i as f64 * 0.04));
+            input.push_str(&frame(1, "audio", i as f64 * 0.02));
+            input.push_str(&frame(2, "video", 1000.0 + i as f64 * 0.5));
+        }
+        let stream_type = StreamType::Udp("udp://127.0.0.1:5000".to_string());
+        process_stdout(
+            BufReader::new(input.as_bytes()),
+            &metrics,
+            &stream_type,
+            &EventLog::new(0).recorder("test"),
+            &options,
+            &mut ProbedStreams::default(),
+            &Activity::new(),
+        )
+        .unwrap();
+
+        let processed = |stream_id: &str, media_type: &str| {
+            metrics
+                .frame_counter
+                .with_label_values(&["processed", stream_id, media_type])
+                .get()
+        };
+        // Sampled frames are scaled back up, suppressed ones counted each
+        assert_eq!(processed("0", "video"), 400.0);
+        assert_eq!(processed("1", "audio"), 400.0);
+        assert_eq!(processed(OTHER, "video"), 400.0);
+        // The rates themselves are covered by the fps tests, the suppressed
+        // stream never gets one
+        assert!(
+            metrics.fps.collect()[0]
+                .get_metric()
+                .iter()
+                .all(|m| m.get_label().iter().all(|l| l.get_value() != OTHER))
+        );
+    }
+
+    #[test]
+    fn test_frame_decimator_full_fidelity() {
+        let mut decimator = FrameDecimator::new(1);
+        assert!((0..10).all(|_| decimator.sample("0")));
+    }
 }
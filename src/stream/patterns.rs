@@ -1,5 +1,6 @@
 // stream/patterns.rs
 
+use crate::stream::severity::Severities;
 use anyhow::Result;
 use regex::Regex;
 
@@ -8,6 +9,12 @@ pub struct StreamPatterns {
     pub packet_corrupt: Regex,
     pub srt_dropped: Regex,
     pub codec_error: Regex,
+    pub udp_overrun: Regex,
+    pub udp_buffer_capped: Regex,
+    pub cc_error: Regex,
+    pub rtp_missed: Regex,
+    /// Severity of each error category the patterns detect
+    pub severities: Severities,
 }
 
 impl StreamPatterns {
@@ -15,7 +22,126 @@ impl StreamPatterns {
         Ok(Self {
             packet_corrupt: Regex::new(r"Packet corrupt \(stream = (\d+), dts = (\d+)\)")?,
             srt_dropped: Regex::new(r"RCV-DROPPED (\d+) packet")?,
-            codec_error: Regex::new(r"\[(h264|hevc|vp8|vp9|av1).*?\] (.*?)(?:\n|$)")?,
+            codec_error: Regex::new(
+                r"\[(h264|hevc|vp8|vp9|av1|aac|ac3|eac3|opus|mpeg2video|mp2).*?\] (.*?)(?:\n|$)",
+            )?,
+            udp_overrun: Regex::new(r"[Cc]ircular buffer overrun")?,
+            udp_buffer_capped: Regex::new(
+                r"attempted to set receive buffer to size (\d+) but it only ended up set as (\d+)",
+            )?,
+            cc_error: Regex::new(
+                r"Continuity check failed for pid (\d+) expected (\d+) got (\d+)",
+            )?,
+            rtp_missed: Regex::new(r"RTP: missed (\d+) packets")?,
+            severities: Severities::default(),
         })
     }
+
+    pub fn with_severities(self, severities: Severities) -> Self {
+        Self { severities, ..self }
+    }
+}
+
+/// Classifies the message of a decoder error into the `error_type` label of
+/// `ffmpeg_codec_errors_total`:
+///
+/// - `sei_error`, `pps_error`, `slice_header_error`, `missing_frame`: H.26x
+///   parameter set and slice problems
+/// - `concealment`: the decoder concealed damaged macroblocks
+/// - `missing_reference`: a reference picture was lost
+/// - `header_error`: frame or packet headers could not be found or parsed
+/// - `truncated_frame`: the frame ended before the decoder expected it to
+/// - `bitstream_error`: invalid values inside the frame
+/// - `other`: anything else
+pub fn codec_error_type(message: &str) -> &'static str {
+    if message.contains("SEI") {
+        return "sei_error";
+    }
+    if message.contains("PPS") {
+        return "pps_error";
+    }
+    if message.contains("decode_slice_header") {
+        return "slice_header_error";
+    }
+    if message.contains("no frame") {
+        return "missing_frame";
+    }
+
+    let message = message.to_lowercase();
+    let contains_any = |needles: &[&str]| needles.iter().any(|needle| message.contains(needle));
+    if contains_any(&["concealing", "damaged", "mvs not available"]) {
+        "concealment"
+    } else if contains_any(&["reference", "ref with poc"]) {
+        "missing_reference"
+    } else if contains_any(&["header", "frame sync error"]) {
+        "header_error"
+    } else if contains_any(&["incomplete frame", "input buffer exhausted", "overread"]) {
+        "truncated_frame"
+    } else if contains_any(&[
+        "error decoding",
+        "error parsing",
+        "invalid",
+        "out-of-range",
+        "out of range",
+        "not allocated",
+        "not allowed",
+    ]) {
+        "bitstream_error"
+    } else {
+        "other"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codec_error_fixtures() {
+        let patterns = StreamPatterns::new().unwrap();
+        let classified: Vec<_> = include_str!("fixtures/codec_errors.log")
+            .lines()
+            .map(|line| {
+                let caps = patterns.codec_error.captures(line)?;
+                Some((caps[1].to_string(), codec_error_type(&caps[2])))
+            })
+            .collect();
+        let expected = [
+            Some(("h264", "sei_error")),
+            Some(("h264", "pps_error")),
+            Some(("h264", "slice_header_error")),
+            Some(("h264", "missing_frame")),
+            Some(("h264", "concealment")),
+            Some(("h264", "missing_reference")),
+            Some(("hevc", "missing_reference")),
+            Some(("hevc", "bitstream_error")),
+            Some(("aac", "header_error")),
+            Some(("aac", "bitstream_error")),
+            Some(("aac", "truncated_frame")),
+            Some(("aac", "bitstream_error")),
+            Some(("ac3", "header_error")),
+            Some(("ac3", "truncated_frame")),
+            Some(("eac3", "bitstream_error")),
+            Some(("eac3", "bitstream_error")),
+            Some(("opus", "header_error")),
+            Some(("opus", "bitstream_error")),
+            Some(("mpeg2video", "concealment")),
+            Some(("mpeg2video", "concealment")),
+            Some(("mpeg2video", "bitstream_error")),
+            Some(("mp2", "header_error")),
+            Some(("mp2", "bitstream_error")),
+            // Demuxer and protocol messages are not codec errors
+            None,
+            None,
+        ];
+        assert_eq!(classified.len(), expected.len());
+        for (line, (got, want)) in classified.iter().zip(expected).enumerate() {
+            assert_eq!(
+                got.as_ref().map(|(codec, kind)| (codec.as_str(), *kind)),
+                want,
+                "line {}",
+                line + 1
+            );
+        }
+    }
 }
@@ -0,0 +1,251 @@
+// stream/pcr.rs
+
+use crate::metrics::{OTHER, StreamMetrics};
+use crate::stream::transport::{SYNC_BYTE, pid};
+use std::collections::HashMap;
+
+/// PCR clock ticks per second
+pub const PCR_CLOCK: f64 = 27_000_000.0;
+
+/// PCRs wrap after 2^33 base ticks of 300 extension ticks each
+pub const PCR_WRAP: u64 = (1 << 33) * 300;
+
+/// ETSI TR 101 290 PCR repetition limit
+const MAX_PCR_INTERVAL: f64 = 0.04;
+
+/// Intervals beyond this are jumps of the clock rather than late PCRs; the
+/// measurement starts over after them
+const MAX_PCR_JUMP: f64 = 10.0;
+
+/// Seconds of PCR time the gauges summarize
+const WINDOW: f64 = 1.0;
+
+/// A PCR carried in the adaptation field of a transport stream packet
+#[derive(Debug, PartialEq)]
+pub struct PcrSample {
+    pub pid: u16,
+    /// 27 MHz ticks
+    pub pcr: u64,
+    /// The discontinuity indicator announces a new time base
+    pub discontinuity: bool,
+}
+
+/// Ticks from the PCR `previous` to `pcr`, across a wrap of the clock
+pub fn pcr_delta(previous: u64, pcr: u64) -> u64 {
+    (pcr + PCR_WRAP - previous) % PCR_WRAP
+}
+
+pub fn parse_pcr(packet: &[u8]) -> Option<PcrSample> {
+    if packet.len() < 12 || packet[0] != SYNC_BYTE {
+        return None;
+    }
+    let has_adaptation = packet[3] & 0x20 != 0;
+    if !has_adaptation || packet[4] < 7 || packet[5] & 0x10 == 0 {
+        return None;
+    }
+    let base = (u64::from(packet[6]) << 25)
+        | (u64::from(packet[7]) << 17)
+        | (u64::from(packet[8]) << 9)
+        | (u64::from(packet[9]) << 1)
+        | (u64::from(packet[10]) >> 7);
+    let extension = (u64::from(packet[10] & 0x01) << 8) | u64::from(packet[11]);
+    Some(PcrSample {
+        pid: pid(packet),
+        pcr: base * 300 + extension,
+        discontinuity: packet[5] & 0x80 != 0,
+    })
+}
+
+#[derive(Default)]
+struct PidState {
+    /// Previous PCR and the byte position of its packet
+    previous: Option<(u64, u64)>,
+    /// PCR ticks per byte between the previous two PCRs
+    rate: Option<f64>,
+    /// Seconds of PCR time since the measurement started
+    clock: f64,
+    window_start: f64,
+    max_interval: f64,
+    max_inaccuracy: f64,
+    /// Smallest and largest arrival time minus PCR time in the window
+    offsets: Option<(f64, f64)>,
+}
+
+/// Measures the PCRs of every PID of a transport stream
+#[derive(Default)]
+pub struct PcrTracker {
+    pids: HashMap<u16, PidState>,
+}
+
+impl PcrTracker {
+    /// Observes a transport stream packet at byte `position` of the stream,
+    /// `arrival` is its receive time in seconds for inputs received in
+    /// realtime
+    pub fn observe(
+        &mut self,
+        packet: &[u8],
+        position: u64,
+        arrival: Option<f64>,
+        metrics: &StreamMetrics,
+    ) {
+        let Some(sample) = parse_pcr(packet) else {
+            return;
+        };
+        // PIDs beyond the label limits count their violations under
+        // `other`, their gauges are left out
+        let pid = metrics.pids.admit(&sample.pid.to_string()).to_string();
+        let state = self.pids.entry(sample.pid).or_default();
+        if sample.discontinuity {
+            *state = PidState::default();
+        }
+
+        if let Some((previous, previous_position)) = state.previous {
+            let delta = pcr_delta(previous, sample.pcr);
+            let interval = delta as f64 / PCR_CLOCK;
+            let bytes = position.saturating_sub(previous_position);
+            if interval > MAX_PCR_INTERVAL {
+                metrics
+                    .pcr_interval_violations
+                    .with_label_values(&[&pid])
+                    .inc();
+            }
+            if interval > MAX_PCR_JUMP {
+                *state = PidState::default();
+            } else {
+                if let Some(rate) = state.rate {
+                    let inaccuracy = (delta as f64 - rate * bytes as f64).abs() / PCR_CLOCK;
+                    state.max_inaccuracy = state.max_inaccuracy.max(inaccuracy);
+                }
+                state.rate = (bytes > 0).then(|| delta as f64 / bytes as f64);
+                state.clock += interval;
+                state.max_interval = state.max_interval.max(interval);
+            }
+        }
+        state.previous = Some((sample.pcr, position));
+
+        if let Some(arrival) = arrival {
+            let offset = arrival - state.clock;
+            state.offsets = Some(match state.offsets {
+                Some((low, high)) => (low.min(offset), high.max(offset)),
+                None => (offset, offset),
+            });
+        }
+
+        if state.clock - state.window_start >= WINDOW && pid != OTHER {
+            let labels = [pid.as_str()];
+            metrics
+                .pcr_interval
+                .with_label_values(&labels)
+                .set(state.max_interval);
+            metrics
+                .pcr_accuracy
+                .with_label_values(&labels)
+                .set(state.max_inaccuracy);
+            if let Some((low, high)) = state.offsets {
+                metrics
+                    .pcr_jitter
+                    .with_label_values(&labels)
+                    .set(high - low);
+            }
+            state.window_start = state.clock;
+            state.max_interval = 0.0;
+            state.max_inaccuracy = 0.0;
+            state.offsets = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::transport::TS_PACKET_SIZE;
+    use prometheus::Registry;
+
+    /// A packet of `pid` carrying `pcr`, padded with stuffing
+    fn packet(pid: u16, pcr: u64, discontinuity: bool) -> [u8; TS_PACKET_SIZE] {
+        let mut packet = [0xffu8; TS_PACKET_SIZE];
+        let (base, extension) = (pcr / 300, pcr % 300);
+        packet[..12].copy_from_slice(&[
+            SYNC_BYTE,
+            (pid >> 8) as u8,
+            pid as u8,
+            0x20,
+            183,
+            0x10 | if discontinuity { 0x80 } else { 0 },
+            (base >> 25) as u8,
+            (base >> 17) as u8,
+            (base >> 9) as u8,
+            (base >> 1) as u8,
+            ((base & 1) << 7) as u8 | 0x7e | (extension >> 8) as u8,
+            extension as u8,
+        ]);
+        packet
+    }
+
+    #[test]
+    fn test_parse_pcr() {
+        let pcr = PCR_WRAP - 1;
+        assert_eq!(
+            parse_pcr(&packet(0x100, pcr, true)),
+            Some(PcrSample {
+                pid: 0x100,
+                pcr,
+                discontinuity: true
+            })
+        );
+        let mut payload_only = packet(0x100, pcr, false);
+        payload_only[3] = 0x10;
+        assert_eq!(parse_pcr(&payload_only), None);
+    }
+
+    #[test]
+    fn test_pcr_tracker() {
+        let metrics = StreamMetrics::new(&Registry::new(), &HashMap::new()).unwrap();
+        let mut tracker = PcrTracker::default();
+        let ticks = |seconds: f64| (seconds * PCR_CLOCK) as u64;
+        // 30 ms PCR interval, 100 packets apart, the wall clock runs in step
+        // except for the PCR at 600 ms, which arrives 2 ms late
+        let start = PCR_WRAP - ticks(0.3);
+        for i in 0..=40u64 {
+            let at = i as f64 * 0.03;
+            let position = i * 100 * TS_PACKET_SIZE as u64;
+            let late = if i == 20 { 0.002 } else { 0.0 };
+            let pcr = (start + ticks(at)) % PCR_WRAP;
+            tracker.observe(
+                &packet(0x100, pcr, false),
+                position,
+                Some(at + late),
+                &metrics,
+            );
+        }
+        let gauge = |vec: &prometheus::GaugeVec| vec.with_label_values(&["256"]).get();
+        assert!((gauge(&metrics.pcr_interval) - 0.03).abs() < 1e-6);
+        assert!(gauge(&metrics.pcr_accuracy) < 1e-6);
+        assert!((gauge(&metrics.pcr_jitter) - 0.002).abs() < 1e-6);
+        let violations = || {
+            metrics
+                .pcr_interval_violations
+                .with_label_values(&["256"])
+                .get()
+        };
+        assert_eq!(violations(), 0.0);
+
+        // A PCR 50 ms late for its byte position
+        let position = 41 * 100 * TS_PACKET_SIZE as u64;
+        let pcr = (start + ticks(1.28)) % PCR_WRAP;
+        tracker.observe(&packet(0x100, pcr, false), position, None, &metrics);
+        assert_eq!(violations(), 1.0);
+        for i in 42..=80u64 {
+            let pcr = (start + ticks(0.05 + i as f64 * 0.03)) % PCR_WRAP;
+            let position = i * 100 * TS_PACKET_SIZE as u64;
+            tracker.observe(&packet(0x100, pcr, false), position, None, &metrics);
+        }
+        assert!((gauge(&metrics.pcr_interval) - 0.08).abs() < 1e-6);
+        assert!((gauge(&metrics.pcr_accuracy) - 0.05).abs() < 1e-6);
+
+        // A signalled discontinuity is no violation
+        let pcr = ticks(5000.0);
+        tracker.observe(&packet(0x100, pcr, true), 0, None, &metrics);
+        assert_eq!(violations(), 1.0);
+    }
+}
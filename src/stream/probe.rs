@@ -0,0 +1,142 @@
+// stream/probe.rs
+
+use crate::config::{MonitorOptions, ResourceLabels, StreamConfig, StreamType};
+use crate::events::EventLog;
+use crate::metrics::StreamMetrics;
+use crate::stream::FFprobeMonitor;
+use anyhow::{Context, Result};
+use prometheus::core::Collector;
+use prometheus::{Gauge, Registry};
+use std::collections::BTreeSet;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use tokio::task;
+use tracing::info;
+use url::Url;
+
+/// URL schemes a `/probe` target may have. Local files, capture devices and
+/// ffmpeg protocols like `concat:` stay out of reach of scrapers.
+const TARGET_SCHEMES: [&str; 6] = ["srt", "rtmp", "rtsp", "udp", "http", "https"];
+
+/// Watches a single target for `options.probe_duration` seconds and returns a
+/// registry holding only the metrics of that session, in the style of the
+/// blackbox exporter's `/probe` endpoint.
+pub async fn probe(
+    target: &str,
+    resource: &ResourceLabels,
+    options: MonitorOptions,
+) -> Result<Registry> {
+    check_target(target)?;
+    let stream_type = StreamType::from_input(target)
+        .with_context(|| format!("Failed to determine stream type of {}", target))?;
+    stream_type.check_components(&options.ffprobe_components)?;
+    let stream = StreamConfig {
+        name: target.to_string(),
+        input: target.to_string(),
+        ..Default::default()
+    };
+
+    let registry = Registry::new_custom(
+        resource.metric_prefix.clone(),
+        Some(resource.const_labels()),
+    )?;
+    let metrics = StreamMetrics::with_buckets(
+        &registry,
+        &stream.const_labels(&BTreeSet::new())?,
+        &options.histogram_buckets,
+    )?
+    .with_label_limits(&options.label_limits);
+    let probe_success = Gauge::new(
+        "ffmpeg_probe_success",
+        "Whether the probe received any frames from the target",
+    )?;
+    let probe_duration = Gauge::new(
+        "ffmpeg_probe_duration_seconds",
+        "How long the probe took to complete in seconds",
+    )?;
+    registry.register(Box::new(probe_success.clone()))?;
+    registry.register(Box::new(probe_duration.clone()))?;
+
+    let duration = Duration::from_secs(options.probe_duration);
+    info!("Probing {} for {}s", target, duration.as_secs());
+    let start = Instant::now();
+
+    // A probe giving up early would fail the request instead of reporting
+    // `ffmpeg_probe_success` 0
+    let options = MonitorOptions {
+        max_retries: 0,
+        max_downtime: 0,
+        // Nothing serves the snapshots of a probe
+        snapshot_interval: 0,
+        ..options
+    };
+
+    // Probe sessions are short lived and report through their own registry only
+    let events = EventLog::new(0).recorder(target);
+    let monitor = FFprobeMonitor::new(stream, stream_type, metrics.clone(), options, events);
+    let running = monitor.get_running_handle();
+    let session = task::spawn_blocking(move || monitor.run());
+
+    tokio::time::sleep(duration).await;
+    running.store(false, Ordering::SeqCst);
+    session.await.context("Probe task panicked")??;
+
+    probe_duration.set(start.elapsed().as_secs_f64());
+    probe_success.set(if frames_seen(&metrics) { 1.0 } else { 0.0 });
+    Ok(registry)
+}
+
+fn check_target(target: &str) -> Result<()> {
+    let scheme = Url::parse(target)
+        .with_context(|| format!("Probe target {} is no URL", target))?
+        .scheme()
+        .to_string();
+    if !TARGET_SCHEMES.contains(&scheme.as_str()) {
+        anyhow::bail!(
+            "Probe targets must be {} URLs, not {}",
+            TARGET_SCHEMES.join(", "),
+            scheme
+        );
+    }
+    Ok(())
+}
+
+fn frames_seen(metrics: &StreamMetrics) -> bool {
+    metrics
+        .frame_counter
+        .collect()
+        .iter()
+        .flat_map(|family| family.get_metric())
+        .any(|metric| metric.get_counter().get_value() > 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_frames_seen() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        assert!(!frames_seen(&metrics));
+
+        metrics
+            .frame_counter
+            .with_label_values(&["processed", "0", "video"])
+            .inc();
+        assert!(frames_seen(&metrics));
+    }
+
+    #[test]
+    fn test_check_target() {
+        assert!(check_target("srt://encoder1:9000").is_ok());
+        assert!(check_target("https://cdn.example.com/news/index.m3u8").is_ok());
+        assert!(check_target("udp://239.0.0.1:1234").is_ok());
+
+        assert!(check_target("/etc/passwd").is_err());
+        assert!(check_target("file:///etc/passwd").is_err());
+        assert!(check_target("v4l2:/dev/video0").is_err());
+        assert!(check_target("concat:/tmp/a.ts|/tmp/b.ts").is_err());
+    }
+}
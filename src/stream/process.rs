@@ -0,0 +1,361 @@
+// stream/process.rs
+
+use std::io;
+use std::process::{Child, Command, Stdio};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+#[cfg(windows)]
+use std::os::windows::process::CommandExt;
+
+/// How often a terminating child is checked for having exited
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Environment variable tagging our child processes with the pid and start
+/// time of the exporter that started them, to find them again should it die
+pub const MARKER_ENV: &str = "FFMPEG_EXPORTER_PARENT_PID";
+
+/// Command of an ffprobe or ffmpeg child process with piped output, `logged`
+/// are the arguments shown in the debug log, with secrets masked
+pub fn child_command(program: &str, args: Vec<String>, logged: Vec<String>) -> Command {
+    let mut cmd = Command::new(program);
+
+    #[cfg(windows)]
+    {
+        // CTRL_BREAK only reaches children sharing our console, in a process
+        // group of their own. Without a console they would open a window.
+        if has_console() {
+            cmd.creation_flags(0x00000200); // CREATE_NEW_PROCESS_GROUP
+        } else {
+            cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
+        }
+    }
+
+    // Signals for the terminal, like Ctrl+C, go to the exporter only, which
+    // stops its children itself
+    #[cfg(unix)]
+    cmd.process_group(0);
+
+    debug!("Child process command: {} {}", program, logged.join(" "));
+    cmd.args(args)
+        .env(MARKER_ENV, marker())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    cmd
+}
+
+/// Spawns a child process built by `child_command`. On Windows it joins a
+/// job object that kills it when the exporter exits, however it exits.
+pub fn spawn(cmd: &mut Command) -> io::Result<Child> {
+    let child = cmd.spawn()?;
+    #[cfg(windows)]
+    assign_to_job(&child);
+    Ok(child)
+}
+
+#[cfg(windows)]
+fn assign_to_job(child: &Child) {
+    use std::os::windows::io::AsRawHandle;
+    use std::sync::OnceLock;
+    use winapi::um::jobapi2::{
+        AssignProcessToJobObject, CreateJobObjectW, SetInformationJobObject,
+    };
+    use winapi::um::winnt::{
+        HANDLE, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+        JobObjectExtendedLimitInformation,
+    };
+
+    // The handle is never closed, the system closes it when we exit
+    static JOB: OnceLock<usize> = OnceLock::new();
+    let job = *JOB.get_or_init(|| {
+        // SAFETY: plain FFI calls on a job object we own, with a zeroed
+        // limit structure of the size passed
+        unsafe {
+            let job = CreateJobObjectW(std::ptr::null_mut(), std::ptr::null());
+            if job.is_null() {
+                return 0;
+            }
+            let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = std::mem::zeroed();
+            info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+            SetInformationJobObject(
+                job,
+                JobObjectExtendedLimitInformation,
+                &mut info as *mut _ as *mut _,
+                std::mem::size_of_val(&info) as u32,
+            );
+            job as usize
+        }
+    });
+    // SAFETY: both handles stay valid for the duration of the call
+    if job == 0
+        || unsafe { AssignProcessToJobObject(job as HANDLE, child.as_raw_handle() as HANDLE) } == 0
+    {
+        warn!(
+            "Failed to add child process {} to the job object",
+            child.id()
+        );
+    }
+}
+
+/// Kills the child processes left behind by exporters that died without
+/// stopping them, e.g. after a panic or SIGKILL, as they keep holding their
+/// SRT and RTSP sessions. They carry our marker, but the exporter named in it
+/// is gone: no process has its pid and start time any more. Processes in
+/// other pid namespaces, like containers, are left alone, as the pids of
+/// their markers mean other processes here. Returns the number of processes
+/// killed.
+#[cfg(target_os = "linux")]
+pub fn kill_orphans() -> usize {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return 0;
+    };
+    let namespace = std::fs::read_link("/proc/self/ns/pid").ok();
+    let mut killed = 0;
+    for entry in entries.flatten() {
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse::<libc::pid_t>().ok())
+        else {
+            continue;
+        };
+        // Processes of other users cannot be read, nor killed
+        let Ok(environ) = std::fs::read(entry.path().join("environ")) else {
+            continue;
+        };
+        let Some((exporter, started)) = parse_marker(&environ) else {
+            continue;
+        };
+        if namespace.is_none() || std::fs::read_link(entry.path().join("ns/pid")).ok() != namespace
+        {
+            continue;
+        }
+        let exporter_stat = std::fs::read_to_string(format!("/proc/{}/stat", exporter)).ok();
+        if exporter_alive(exporter_stat.as_deref(), started) {
+            continue;
+        }
+        warn!(
+            "Killing orphaned child process {} of exporter {}",
+            pid, exporter
+        );
+        // SAFETY: plain signal to a process we just read, at worst it
+        // exited in between and the call fails
+        if unsafe { libc::kill(pid, libc::SIGKILL) } == 0 {
+            killed += 1;
+        }
+    }
+    killed
+}
+
+/// Orphans are only found through `/proc`; on Windows the job object
+/// already took care of them
+#[cfg(not(target_os = "linux"))]
+pub fn kill_orphans() -> usize {
+    0
+}
+
+/// Our marker, `<pid>:<start time>`. The start time, in clock ticks since
+/// boot, tells this exporter apart from a later process reusing its pid.
+fn marker() -> &'static str {
+    static MARKER: OnceLock<String> = OnceLock::new();
+    MARKER.get_or_init(|| {
+        let started = std::fs::read_to_string("/proc/self/stat")
+            .ok()
+            .and_then(|stat| process_state(&stat).map(|(_, started)| started))
+            .unwrap_or(0);
+        format!("{}:{}", std::process::id(), started)
+    })
+}
+
+/// The exporter pid and start time of our marker in a NUL separated
+/// environment
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_marker(environ: &[u8]) -> Option<(i32, u64)> {
+    let prefix = format!("{}=", MARKER_ENV);
+    let value = environ
+        .split(|byte| *byte == 0)
+        .find_map(|var| var.strip_prefix(prefix.as_bytes()))?;
+    let (pid, started) = std::str::from_utf8(value).ok()?.split_once(':')?;
+    Some((pid.parse().ok()?, started.parse().ok()?))
+}
+
+/// State and start time of a `/proc/<pid>/stat` line. The command name before
+/// them is in parentheses and may contain spaces and parentheses itself.
+fn process_state(stat: &str) -> Option<(&str, u64)> {
+    let (_, fields) = stat.rsplit_once(')')?;
+    let mut fields = fields.split_whitespace();
+    let state = fields.next()?;
+    // The start time is field 22, the state field 3
+    let started = fields.nth(18)?.parse().ok()?;
+    Some((state, started))
+}
+
+/// Whether the process of the stat line `stat`, `None` if there is no such
+/// process, is running and was started at `started`
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn exporter_alive(stat: Option<&str>, started: u64) -> bool {
+    // Dead but not yet reaped exporters are zombies
+    stat.and_then(process_state)
+        .is_some_and(|(state, start)| start == started && !matches!(state, "Z" | "X"))
+}
+
+/// Asks a child process to exit (SIGTERM, CTRL_BREAK on Windows), so ffmpeg
+/// can finish its `-report` log and release its sockets, and kills it if it
+/// is still running after `grace`. Reaps the child either way.
+pub fn terminate(child: &mut Child, grace: Duration) {
+    if matches!(child.try_wait(), Ok(Some(_))) {
+        return;
+    }
+    if !grace.is_zero() && request_exit(child) {
+        let started = Instant::now();
+        while started.elapsed() < grace {
+            match child.try_wait() {
+                Ok(Some(_)) => return,
+                Ok(None) => thread::sleep(EXIT_POLL_INTERVAL),
+                Err(_) => break,
+            }
+        }
+        warn!(
+            "Child process {} did not exit within {:?}, killing it",
+            child.id(),
+            grace
+        );
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[cfg(unix)]
+fn request_exit(child: &Child) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(child.id()) else {
+        return false;
+    };
+    // SAFETY: signals the child we spawned and have not reaped yet, so the
+    // pid cannot have been reused
+    unsafe { libc::kill(pid, libc::SIGTERM) == 0 }
+}
+
+#[cfg(windows)]
+fn request_exit(child: &Child) -> bool {
+    use winapi::um::wincon::{CTRL_BREAK_EVENT, GenerateConsoleCtrlEvent};
+    // SAFETY: plain FFI call; the group id is the pid of the child that
+    // leads its own process group
+    has_console() && unsafe { GenerateConsoleCtrlEvent(CTRL_BREAK_EVENT, child.id()) != 0 }
+}
+
+#[cfg(windows)]
+fn has_console() -> bool {
+    // SAFETY: plain FFI call without arguments
+    unsafe { !winapi::um::wincon::GetConsoleWindow().is_null() }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn request_exit(_child: &Child) -> bool {
+    false
+}
+
+/// Writes a shell script standing in for ffprobe. `script` runs the
+/// monitoring sessions, the stream info probes find no streams.
+#[cfg(all(test, unix))]
+pub fn fake_ffprobe(script: &str) -> String {
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "fake-ffprobe-{}-{}",
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::SeqCst)
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("ffprobe");
+    let info = r#"case " $* " in *" -show_streams "*) exit 0 ;; esac"#;
+    std::fs::write(&path, format!("#!/bin/sh\n{}\n{}\n", info, script)).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+/// A fake ffprobe delivering a video packet every 100 ms
+#[cfg(all(test, unix))]
+pub const DELIVERING_FFPROBE: &str = r#"while :; do
+  echo "packet,codec_type=video,stream_index=0,pts_time=0.0,dts_time=0.0,size=1000,flags=K__"
+  sleep 0.1
+done"#;
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_marker_and_exporter() {
+        let environ = b"PATH=/usr/bin\0FFMPEG_EXPORTER_PARENT_PID=4242:98765\0HOME=/root\0";
+        assert_eq!(parse_marker(environ), Some((4242, 98765)));
+        assert_eq!(parse_marker(b"PATH=/usr/bin\0"), None);
+        // Markers without a start time cannot be checked
+        assert_eq!(parse_marker(b"FFMPEG_EXPORTER_PARENT_PID=4242\0"), None);
+
+        let stat = |name: &str, state: &str, started: u64| {
+            format!(
+                "4242 ({}) {} 1 4242 4242 0 -1 4194560 100 0 0 0 10 5 0 0 20 0 1 0 {} 1000 200",
+                name, state, started
+            )
+        };
+        assert_eq!(
+            process_state(&stat("ffprobe (x) 2", "S", 98765)),
+            Some(("S", 98765))
+        );
+
+        let running = stat("ffmpeg_exporter", "S", 98765);
+        assert!(exporter_alive(Some(&running), 98765));
+        // Renamed binaries are still the same exporter
+        assert!(exporter_alive(
+            Some(&stat("exporter-v2", "S", 98765)),
+            98765
+        ));
+        // The exporter is gone, or died and was not reaped yet
+        assert!(!exporter_alive(None, 98765));
+        assert!(!exporter_alive(
+            Some(&stat("ffmpeg_exporter", "Z", 98765)),
+            98765
+        ));
+        // Its pid was reused by a later process
+        assert!(!exporter_alive(
+            Some(&stat("ffmpeg_exporter", "S", 99000)),
+            98765
+        ));
+
+        // Our own marker names a running exporter
+        let (pid, started) = marker().split_once(':').unwrap();
+        assert_eq!(pid, std::process::id().to_string());
+        let own = std::fs::read_to_string("/proc/self/stat").ok();
+        if let Some(own) = own {
+            assert!(exporter_alive(Some(&own), started.parse().unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_terminate() {
+        // Exits on SIGTERM
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        let started = Instant::now();
+        terminate(&mut child, Duration::from_secs(10));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(child.try_wait().unwrap().is_some());
+
+        // Ignores SIGTERM and gets killed after the grace period
+        let mut child = Command::new("sh")
+            .args(["-c", "trap '' TERM; exec sleep 30"])
+            .spawn()
+            .unwrap();
+        thread::sleep(Duration::from_millis(200));
+        let started = Instant::now();
+        terminate(&mut child, Duration::from_millis(300));
+        assert!(started.elapsed() >= Duration::from_millis(300));
+        assert!(child.try_wait().unwrap().is_some());
+    }
+}
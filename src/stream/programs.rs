@@ -0,0 +1,99 @@
+// stream/programs.rs
+
+use crate::stream::lines::Fields;
+use crate::stream::records::ProgramRecord;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Service name and provider of a program
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Service {
+    pub name: String,
+    pub provider: String,
+}
+
+#[derive(Default)]
+struct Shared {
+    services: Mutex<HashMap<u16, Service>>,
+    /// Bumped whenever the services are replaced
+    generation: AtomicU64,
+    /// Set when the program composition changed and the names should be
+    /// probed again
+    stale: AtomicBool,
+}
+
+/// The service names the stream info probe found, shared with the transport
+/// stream analysis labelling its per program metrics
+#[derive(Clone, Default)]
+pub struct ProgramNames {
+    shared: Arc<Shared>,
+}
+
+impl ProgramNames {
+    /// Replaces the services with the program records of a probe output
+    pub fn update(&self, output: &str) {
+        let services = output
+            .lines()
+            .map(Fields::split)
+            .filter(|fields| fields.as_slice().first() == Some(&"program"))
+            .filter_map(|fields| {
+                let program = ProgramRecord::parse(&fields)?;
+                let service = Service {
+                    name: program.service_name.to_string(),
+                    provider: program.service_provider.to_string(),
+                };
+                Some((program.program_num, service))
+            })
+            .collect();
+        *self.shared.services.lock().unwrap() = services;
+        self.shared.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// The service of `program`, empty if the probe did not report one
+    pub fn get(&self, program: u16) -> Service {
+        let services = self.shared.services.lock().unwrap();
+        services.get(&program).cloned().unwrap_or_default()
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.shared.generation.load(Ordering::SeqCst)
+    }
+
+    pub fn mark_stale(&self) {
+        self.shared.stale.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the names should be probed again, clearing the request
+    pub fn take_stale(&self) -> bool {
+        self.shared.stale.swap(false, Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_program_names() {
+        let names = ProgramNames::default();
+        let output = "program,program_num=4164,tag:service_name=BBC One HD,tag:service_provider=BSkyB\n\
+                      program,program_num=4165\n\
+                      stream,index=0,codec_type=video\n";
+        names.update(output);
+        assert_eq!(names.generation(), 1);
+        assert_eq!(
+            names.get(4164),
+            Service {
+                name: "BBC One HD".to_string(),
+                provider: "BSkyB".to_string()
+            }
+        );
+        assert_eq!(names.get(4165), Service::default());
+
+        assert!(!names.take_stale());
+        names.clone().mark_stale();
+        assert!(names.take_stale());
+        assert!(!names.take_stale());
+    }
+}
@@ -0,0 +1,488 @@
+// stream/psi.rs
+
+use crate::metrics::StreamMetrics;
+use crate::stream::programs::ProgramNames;
+use crate::stream::transport::{payload, payload_unit_start, pid};
+use std::collections::{BTreeMap, HashMap};
+use tracing::warn;
+
+pub const PAT_PID: u16 = 0x0000;
+pub const CAT_PID: u16 = 0x0001;
+pub const PAT_TABLE_ID: u8 = 0x00;
+pub const CAT_TABLE_ID: u8 = 0x01;
+pub const PMT_TABLE_ID: u8 = 0x02;
+
+/// CRC-32/MPEG-2 of PSI sections. Run over a section including its CRC it
+/// yields 0 for an intact section.
+pub fn crc32_mpeg2(data: &[u8]) -> u32 {
+    data.iter().fold(0xffff_ffff, |crc, byte| {
+        (0..8).fold(crc ^ (u32::from(*byte) << 24), |crc, _| {
+            if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// A PSI section with the long header all tables used here have
+#[derive(Debug)]
+pub struct Section<'a> {
+    pub table_id: u8,
+    /// Transport stream id of the PAT, program number of a PMT
+    pub extension: u16,
+    pub version: u8,
+    /// The current_next_indicator: the table applies now, not later
+    pub current: bool,
+    pub section_number: u8,
+    /// The table data between header and CRC
+    pub body: &'a [u8],
+    pub crc: u32,
+}
+
+/// Parses a complete section, `None` if it is damaged
+pub fn parse_section(data: &[u8]) -> Option<Section<'_>> {
+    if data.len() < 12 || data[1] & 0x80 == 0 || crc32_mpeg2(data) != 0 {
+        return None;
+    }
+    let crc_start = data.len() - 4;
+    Some(Section {
+        table_id: data[0],
+        extension: u16::from_be_bytes([data[3], data[4]]),
+        version: (data[5] >> 1) & 0x1f,
+        current: data[5] & 0x01 != 0,
+        section_number: data[6],
+        body: &data[8..crc_start],
+        crc: u32::from_be_bytes(data[crc_start..].try_into().ok()?),
+    })
+}
+
+/// A complete section as it arrived, before it was checked
+#[derive(Debug, PartialEq)]
+pub struct SectionSeen {
+    pub pid: u16,
+    pub table_id: u8,
+    pub crc_ok: bool,
+}
+
+/// Reassembles the PSI sections of one PID from its packets
+#[derive(Default)]
+struct SectionBuffer {
+    data: Vec<u8>,
+    /// Whether the start of the buffered section was seen
+    active: bool,
+}
+
+impl SectionBuffer {
+    fn push(&mut self, packet: &[u8]) -> Vec<Vec<u8>> {
+        let mut sections = Vec::new();
+        let Some(payload) = payload(packet) else {
+            return sections;
+        };
+        let rest = if payload_unit_start(packet) {
+            let start = (1 + usize::from(payload[0])).min(payload.len());
+            // The pointer field skips the end of the previous section
+            if self.active {
+                self.data.extend_from_slice(&payload[1..start]);
+                self.take_sections(&mut sections);
+            }
+            self.data.clear();
+            self.active = true;
+            &payload[start..]
+        } else if self.active {
+            payload
+        } else {
+            return sections;
+        };
+        self.data.extend_from_slice(rest);
+        self.take_sections(&mut sections);
+        sections
+    }
+
+    fn take_sections(&mut self, sections: &mut Vec<Vec<u8>>) {
+        while self.data.len() >= 3 {
+            // Stuffing fills the rest of the packet
+            if self.data[0] == 0xff {
+                self.data.clear();
+                self.active = false;
+                return;
+            }
+            let length = 3 + ((usize::from(self.data[1] & 0x0f) << 8) | usize::from(self.data[2]));
+            if self.data.len() < length {
+                return;
+            }
+            sections.push(self.data.drain(..length).collect());
+        }
+    }
+}
+
+/// Follows the PAT and the PMTs it announces, counting table changes and
+/// reporting which programs are present
+#[derive(Default)]
+pub struct PsiTracker {
+    names: ProgramNames,
+    /// Generation of the names the presence gauges are labelled with
+    generation: u64,
+    buffers: HashMap<u16, SectionBuffer>,
+    /// Programs of each PAT section as program number and PMT PID
+    pat_sections: BTreeMap<u8, Vec<(u16, u16)>>,
+    /// PMT PID of every program of the PAT
+    programs: BTreeMap<u16, u16>,
+    /// Elementary stream PIDs of the PMT of every program
+    streams: BTreeMap<u16, Vec<u16>>,
+    /// CRC of the last section of each table by PID, table id, extension and
+    /// section number
+    crcs: HashMap<(u16, u8, u16, u8), u32>,
+}
+
+impl PsiTracker {
+    pub fn new(names: ProgramNames) -> Self {
+        Self {
+            generation: names.generation(),
+            names,
+            ..Default::default()
+        }
+    }
+
+    /// PIDs carrying the PMTs of the programs of the PAT
+    pub fn pmt_pids(&self) -> impl Iterator<Item = u16> + '_ {
+        self.programs.values().copied()
+    }
+
+    /// PIDs of the elementary streams the PMTs announce
+    pub fn elementary_pids(&self) -> impl Iterator<Item = u16> + '_ {
+        self.streams.values().flatten().copied()
+    }
+
+    /// Observes a packet, returning the sections of the PAT, CAT and PMT
+    /// PIDs it completed
+    pub fn observe(&mut self, packet: &[u8], metrics: &StreamMetrics) -> Vec<SectionSeen> {
+        // Probed names replace the labels of the programs present
+        let generation = self.names.generation();
+        if generation != self.generation {
+            self.generation = generation;
+            metrics.ts_program_present.reset();
+            for number in self.programs.keys() {
+                self.set_present(*number, true, metrics);
+            }
+        }
+
+        let pid = pid(packet);
+        if pid != PAT_PID && pid != CAT_PID && !self.programs.values().any(|pmt| *pmt == pid) {
+            return Vec::new();
+        }
+        let sections = self.buffers.entry(pid).or_default().push(packet);
+        let mut seen = Vec::with_capacity(sections.len());
+        for data in sections {
+            seen.push(SectionSeen {
+                pid,
+                table_id: data[0],
+                crc_ok: crc32_mpeg2(&data) == 0,
+            });
+            let Some(section) = parse_section(&data) else {
+                continue;
+            };
+            if !section.current {
+                continue;
+            }
+            let table = match (pid, section.table_id) {
+                (PAT_PID, PAT_TABLE_ID) => "pat",
+                (PAT_PID | CAT_PID, _) => continue,
+                (_, PMT_TABLE_ID) => "pmt",
+                _ => continue,
+            };
+
+            // A table changes with its version, but an unannounced change of
+            // the content breaks receivers just the same
+            let key = (
+                pid,
+                section.table_id,
+                section.extension,
+                section.section_number,
+            );
+            let previous = self.crcs.insert(key, section.crc);
+            if previous.is_some_and(|crc| crc != section.crc) {
+                warn!(
+                    "{} of {} changed to version {}",
+                    table.to_uppercase(),
+                    section.extension,
+                    section.version
+                );
+                metrics.ts_table_changes.with_label_values(&[table]).inc();
+                if table == "pmt" {
+                    self.names.mark_stale();
+                }
+            }
+
+            if table == "pat" {
+                let programs = section
+                    .body
+                    .chunks_exact(4)
+                    .map(|entry| {
+                        let number = u16::from_be_bytes([entry[0], entry[1]]);
+                        let pmt = u16::from_be_bytes([entry[2] & 0x1f, entry[3]]);
+                        (number, pmt)
+                    })
+                    // Program 0 points at the network information table
+                    .filter(|(number, _)| *number != 0)
+                    .collect();
+                self.pat_sections.insert(section.section_number, programs);
+                self.update_programs(metrics);
+            } else if let Some(streams) = parse_pmt_streams(section.body) {
+                self.streams.insert(section.extension, streams);
+            }
+        }
+        seen
+    }
+
+    fn update_programs(&mut self, metrics: &StreamMetrics) {
+        let programs: BTreeMap<u16, u16> = self.pat_sections.values().flatten().copied().collect();
+        for number in self.programs.keys() {
+            if !programs.contains_key(number) {
+                warn!("Program {} left the PAT", number);
+                self.set_present(*number, false, metrics);
+            }
+        }
+        for number in programs.keys() {
+            self.set_present(*number, true, metrics);
+        }
+        // New programs have no names yet
+        if programs
+            .keys()
+            .any(|number| !self.programs.contains_key(number))
+            && !self.programs.is_empty()
+        {
+            self.names.mark_stale();
+        }
+        let removed: Vec<u16> = self
+            .programs
+            .iter()
+            .filter(|(number, pmt)| {
+                !programs.contains_key(number) && !programs.values().any(|p| p == *pmt)
+            })
+            .map(|(_, pmt)| *pmt)
+            .collect();
+        self.crcs.retain(|(pid, ..), _| !removed.contains(pid));
+        self.streams
+            .retain(|number, _| programs.contains_key(number));
+        self.buffers
+            .retain(|pid, _| *pid == PAT_PID || !removed.contains(pid));
+        self.programs = programs;
+    }
+
+    fn set_present(&self, number: u16, present: bool, metrics: &StreamMetrics) {
+        let service = self.names.get(number);
+        metrics
+            .ts_program_present
+            .with_label_values(&[&number.to_string(), &service.name, &service.provider])
+            .set(if present { 1.0 } else { 0.0 });
+    }
+}
+
+/// The elementary stream PIDs of a PMT body
+fn parse_pmt_streams(body: &[u8]) -> Option<Vec<u16>> {
+    let info_length = usize::from(u16::from_be_bytes([*body.get(2)?, *body.get(3)?]) & 0x0fff);
+    let mut rest = body.get(4 + info_length..)?;
+    let mut pids = Vec::new();
+    while rest.len() >= 5 {
+        pids.push(u16::from_be_bytes([rest[1] & 0x1f, rest[2]]));
+        let es_info_length = usize::from(u16::from_be_bytes([rest[3], rest[4]]) & 0x0fff);
+        rest = rest.get(5 + es_info_length..)?;
+    }
+    Some(pids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::transport::{SYNC_BYTE, TS_PACKET_SIZE};
+    use prometheus::Registry;
+
+    /// A section with the long header, its length and CRC filled in
+    fn section(table_id: u8, extension: u16, version: u8, body: &[u8]) -> Vec<u8> {
+        let length = 5 + body.len() + 4;
+        let mut data = vec![
+            table_id,
+            0xb0 | (length >> 8) as u8,
+            length as u8,
+            (extension >> 8) as u8,
+            extension as u8,
+            0xc1 | (version << 1),
+            0,
+            0,
+        ];
+        data.extend_from_slice(body);
+        data.extend_from_slice(&crc32_mpeg2(&data).to_be_bytes());
+        data
+    }
+
+    fn pat(version: u8, programs: &[(u16, u16)]) -> Vec<u8> {
+        let body: Vec<u8> = programs
+            .iter()
+            .flat_map(|(number, pmt)| {
+                let [high, low] = number.to_be_bytes();
+                [high, low, 0xe0 | (pmt >> 8) as u8, *pmt as u8]
+            })
+            .collect();
+        section(PAT_TABLE_ID, 1, version, &body)
+    }
+
+    /// Packets of `pid` carrying `data` as one section
+    fn packets(pid: u16, data: &[u8]) -> Vec<[u8; TS_PACKET_SIZE]> {
+        let mut payload = vec![0u8];
+        payload.extend_from_slice(data);
+        payload
+            .chunks(TS_PACKET_SIZE - 4)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let mut packet = [0xffu8; TS_PACKET_SIZE];
+                let start = if i == 0 { 0x40 } else { 0 };
+                packet[..4].copy_from_slice(&[
+                    SYNC_BYTE,
+                    start | (pid >> 8) as u8,
+                    pid as u8,
+                    0x10,
+                ]);
+                packet[4..4 + chunk.len()].copy_from_slice(chunk);
+                packet
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_section() {
+        let data = pat(3, &[(1, 0x100)]);
+        let section = parse_section(&data).unwrap();
+        assert_eq!(section.version, 3);
+        assert!(section.current);
+        assert_eq!(section.body, [0x00, 0x01, 0xe1, 0x00]);
+
+        let mut damaged = data.clone();
+        damaged[9] ^= 0x01;
+        assert!(parse_section(&damaged).is_none());
+    }
+
+    #[test]
+    fn test_sections_across_packets() {
+        // Enough programs to need two packets
+        let programs: Vec<(u16, u16)> = (1..=60).map(|n| (n, 0x100 + n)).collect();
+        let data = pat(0, &programs);
+        let mut buffer = SectionBuffer::default();
+        let packets = packets(PAT_PID, &data);
+        assert_eq!(packets.len(), 2);
+        assert!(buffer.push(&packets[0]).is_empty());
+        assert_eq!(buffer.push(&packets[1]), vec![data]);
+    }
+
+    #[test]
+    fn test_table_changes_and_programs() {
+        let metrics = StreamMetrics::new(&Registry::new(), &HashMap::new()).unwrap();
+        let mut tracker = PsiTracker::default();
+        let mut feed = |pid, data: &[u8]| {
+            for packet in packets(pid, data) {
+                tracker.observe(&packet, &metrics);
+            }
+        };
+        let changes = |table| metrics.ts_table_changes.with_label_values(&[table]).get();
+        let present = |program| {
+            metrics
+                .ts_program_present
+                .with_label_values(&[program, "", ""])
+                .get()
+        };
+
+        feed(PAT_PID, &pat(0, &[(0, 0x10), (101, 0x100), (102, 0x200)]));
+        feed(PAT_PID, &pat(0, &[(0, 0x10), (101, 0x100), (102, 0x200)]));
+        assert_eq!(changes("pat"), 0.0);
+        assert_eq!(present("101"), 1.0);
+        assert_eq!(present("102"), 1.0);
+
+        let pmt = |version, pid: u8| section(PMT_TABLE_ID, 101, version, &[0xe1, pid, 0xf0, 0x00]);
+        feed(0x100, &pmt(4, 0x01));
+        feed(0x100, &pmt(4, 0x01));
+        feed(0x100, &pmt(5, 0x01));
+        // The content changed without a new version
+        feed(0x100, &pmt(5, 0x02));
+        assert_eq!(changes("pmt"), 2.0);
+
+        feed(PAT_PID, &pat(1, &[(0, 0x10), (101, 0x100)]));
+        assert_eq!(changes("pat"), 1.0);
+        assert_eq!(present("102"), 0.0);
+        // PMTs of programs that left the PAT are not followed anymore
+        feed(
+            0x200,
+            &section(PMT_TABLE_ID, 102, 0, &[0xe1, 0x01, 0xf0, 0x00]),
+        );
+        feed(
+            0x200,
+            &section(PMT_TABLE_ID, 102, 1, &[0xe1, 0x01, 0xf0, 0x00]),
+        );
+        assert_eq!(changes("pmt"), 2.0);
+    }
+
+    #[test]
+    fn test_pmt_streams() {
+        let metrics = StreamMetrics::new(&Registry::new(), &HashMap::new()).unwrap();
+        let mut tracker = PsiTracker::default();
+        // PCR PID, no program info, then an H.264 stream on PID 0x101 with
+        // a descriptor and an AAC stream on 0x102
+        let streams = [
+            0xe1, 0x01, 0xf0, 0x00, 0x1b, 0xe1, 0x01, 0xf0, 0x02, 0x52, 0x00, 0x0f, 0xe1, 0x02,
+            0xf0, 0x00,
+        ];
+        let mut seen = Vec::new();
+        for (pid, data) in [
+            (PAT_PID, pat(0, &[(101, 0x100)])),
+            (0x100, section(PMT_TABLE_ID, 101, 0, &streams)),
+        ] {
+            for packet in packets(pid, &data) {
+                seen.extend(tracker.observe(&packet, &metrics));
+            }
+        }
+        assert_eq!(tracker.pmt_pids().collect::<Vec<_>>(), [0x100]);
+        assert_eq!(
+            tracker.elementary_pids().collect::<Vec<_>>(),
+            [0x101, 0x102]
+        );
+        assert_eq!(
+            seen[1],
+            SectionSeen {
+                pid: 0x100,
+                table_id: PMT_TABLE_ID,
+                crc_ok: true
+            }
+        );
+    }
+
+    #[test]
+    fn test_program_names() {
+        let metrics = StreamMetrics::new(&Registry::new(), &HashMap::new()).unwrap();
+        let names = ProgramNames::default();
+        let mut tracker = PsiTracker::new(names.clone());
+        let mut feed = |pid, data: &[u8]| {
+            for packet in packets(pid, data) {
+                tracker.observe(&packet, &metrics);
+            }
+        };
+        let present = |labels: &[&str]| metrics.ts_program_present.with_label_values(labels).get();
+
+        feed(PAT_PID, &pat(0, &[(4164, 0x100)]));
+        assert_eq!(present(&["4164", "", ""]), 1.0);
+        names.update(
+            "program,program_num=4164,tag:service_name=BBC One HD,tag:service_provider=BSkyB",
+        );
+        feed(PAT_PID, &pat(0, &[(4164, 0x100)]));
+        assert_eq!(present(&["4164", "BBC One HD", "BSkyB"]), 1.0);
+
+        // A changed PMT and a new program ask for the names again
+        let pmt = |version| section(PMT_TABLE_ID, 4164, version, &[0xe1, 0x01, 0xf0, 0x00]);
+        feed(0x100, &pmt(0));
+        assert!(!names.take_stale());
+        feed(0x100, &pmt(1));
+        assert!(names.take_stale());
+        feed(PAT_PID, &pat(1, &[(4164, 0x100), (4165, 0x200)]));
+        assert!(names.take_stale());
+        assert_eq!(present(&["4165", "", ""]), 1.0);
+    }
+}
@@ -0,0 +1,70 @@
+// stream/qp.rs
+
+use crate::metrics::StreamMetrics;
+use crate::stream::records::FrameRecord;
+use std::collections::{HashMap, VecDeque};
+
+/// Number of recent frames the average quantizer is taken over
+const QP_HISTORY: usize = 32;
+
+/// Averages the frame quantizer of each video stream, the cheapest proxy for
+/// encode quality degradation under constant bitrate. Only fed when ffprobe
+/// exports the video encoding parameters side data.
+#[derive(Default)]
+pub struct QpTracker {
+    streams: HashMap<String, VecDeque<f64>>,
+}
+
+impl QpTracker {
+    pub fn observe(&mut self, frame: &FrameRecord, metrics: &StreamMetrics) {
+        let Some(qp) = frame.qp else {
+            return;
+        };
+        if !self.streams.contains_key(frame.stream_id) {
+            self.streams
+                .insert(frame.stream_id.to_string(), VecDeque::new());
+        }
+        let history = self.streams.get_mut(frame.stream_id).unwrap();
+        history.push_back(qp);
+        if history.len() > QP_HISTORY {
+            history.pop_front();
+        }
+
+        let average = history.iter().sum::<f64>() / history.len() as f64;
+        metrics
+            .video_qp
+            .with_label_values(&[frame.stream_id])
+            .set(average);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::lines::Fields;
+    use prometheus::Registry;
+
+    #[test]
+    fn test_average_qp() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let mut tracker = QpTracker::default();
+
+        for qp in [20, 30] {
+            let line = format!(
+                "frame,media_type=video,stream_index=0,key_frame=0,\
+                 side_data_type=Video encoding parameters,type=0,qp={qp},nb_blocks=8160"
+            );
+            tracker.observe(
+                &FrameRecord::parse(&Fields::split(&line)).unwrap(),
+                &metrics,
+            );
+        }
+        assert_eq!(metrics.video_qp.with_label_values(&["0"]).get(), 25.0);
+
+        // Frames without side data leave the gauge alone
+        let line = "frame,media_type=video,stream_index=0,key_frame=0";
+        tracker.observe(&FrameRecord::parse(&Fields::split(line)).unwrap(), &metrics);
+        assert_eq!(metrics.video_qp.with_label_values(&["0"]).get(), 25.0);
+    }
+}
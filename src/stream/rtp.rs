@@ -0,0 +1,108 @@
+// stream/rtp.rs
+
+use crate::metrics::StreamMetrics;
+use crate::stream::records::PacketRecord;
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// Timestamp jumps larger than this restart the estimate
+const MAX_JUMP: f64 = 10.0;
+
+struct JitterState {
+    /// Arrival time minus timestamp of the previous packet
+    last_transit: f64,
+    jitter: f64,
+}
+
+/// Estimates the interarrival jitter of RTSP inputs the way RTCP receiver
+/// reports do (RFC 3550, section 6.4.1): the variation of the transit time,
+/// the wall clock arrival of a packet minus its timestamp, smoothed with a
+/// gain of 1/16. ffprobe hands out demuxed frames rather than RTP packets
+/// and only after its reorder queue, so the estimate includes the jitter the
+/// host adds.
+pub struct RtpJitter {
+    start: Instant,
+    streams: HashMap<String, JitterState>,
+}
+
+impl Default for RtpJitter {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            streams: HashMap::new(),
+        }
+    }
+}
+
+impl RtpJitter {
+    pub fn observe(&mut self, packet: &PacketRecord, metrics: &StreamMetrics) {
+        let wall = self.start.elapsed().as_secs_f64();
+        self.observe_at(wall, packet, metrics);
+    }
+
+    fn observe_at(&mut self, wall: f64, packet: &PacketRecord, metrics: &StreamMetrics) {
+        let Some(time) = packet.dts_time else {
+            return;
+        };
+        let transit = wall - time;
+
+        let state = match self.streams.get_mut(packet.stream_id) {
+            Some(state) if (transit - state.last_transit).abs() <= MAX_JUMP => state,
+            _ => {
+                self.streams.insert(
+                    packet.stream_id.to_string(),
+                    JitterState {
+                        last_transit: transit,
+                        jitter: 0.0,
+                    },
+                );
+                return;
+            }
+        };
+        let difference = (transit - state.last_transit).abs();
+        state.last_transit = transit;
+        state.jitter += (difference - state.jitter) / 16.0;
+        metrics
+            .rtp_jitter
+            .with_label_values(&[packet.stream_id])
+            .set(state.jitter);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::Registry;
+
+    fn packet(time: f64) -> PacketRecord<'static> {
+        PacketRecord {
+            media_type: "video",
+            stream_id: "0",
+            size: None,
+            dts_time: Some(time),
+            flags: "",
+        }
+    }
+
+    #[test]
+    fn test_rtp_jitter() {
+        let metrics = StreamMetrics::new(&Registry::new(), &HashMap::new()).unwrap();
+        let mut jitter = RtpJitter::default();
+        let current = || metrics.rtp_jitter.with_label_values(&["0"]).get();
+
+        // Packets arriving exactly in time have no jitter
+        for i in 0..10 {
+            let time = f64::from(i) * 0.04;
+            jitter.observe_at(time + 5.0, &packet(time), &metrics);
+        }
+        assert_eq!(current(), 0.0);
+
+        // A packet 16 ms late moves the estimate by a sixteenth
+        jitter.observe_at(5.416, &packet(0.4), &metrics);
+        assert!((current() - 0.001).abs() < 1e-9);
+
+        // A timestamp reset restarts the estimate without a jump
+        jitter.observe_at(5.44, &packet(1000.0), &metrics);
+        assert!((current() - 0.001).abs() < 1e-9);
+    }
+}
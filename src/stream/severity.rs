@@ -0,0 +1,76 @@
+// stream/severity.rs
+
+use crate::config::Severity;
+use std::collections::BTreeMap;
+
+/// Every error category with its default severity. The codec categories are
+/// the `error_type` values of `ffmpeg_codec_errors_total`.
+pub const DEFAULT_SEVERITIES: [(&str, Severity); 16] = [
+    ("sei_error", Severity::Info),
+    ("pps_error", Severity::Error),
+    ("slice_header_error", Severity::Error),
+    ("missing_frame", Severity::Warning),
+    ("concealment", Severity::Warning),
+    ("missing_reference", Severity::Warning),
+    ("header_error", Severity::Error),
+    ("truncated_frame", Severity::Warning),
+    ("bitstream_error", Severity::Error),
+    ("other", Severity::Warning),
+    ("packet_corrupt", Severity::Error),
+    ("dropped_packets", Severity::Error),
+    ("udp_overrun", Severity::Error),
+    ("cc_error", Severity::Error),
+    ("rtp_packet_loss", Severity::Error),
+    ("ffprobe_failed", Severity::Fatal),
+];
+
+pub fn is_error_category(name: &str) -> bool {
+    DEFAULT_SEVERITIES
+        .iter()
+        .any(|(category, _)| *category == name)
+}
+
+/// Maps error categories to severities, so a benign SEI warning and a failing
+/// decoder can be told apart. Overrides come from the `--severity` flags.
+#[derive(Debug, Clone, Default)]
+pub struct Severities {
+    overrides: BTreeMap<String, Severity>,
+}
+
+impl Severities {
+    pub fn new(overrides: &BTreeMap<String, Severity>) -> Self {
+        Self {
+            overrides: overrides.clone(),
+        }
+    }
+
+    pub fn of(&self, category: &str) -> Severity {
+        if let Some(severity) = self.overrides.get(category) {
+            return *severity;
+        }
+        DEFAULT_SEVERITIES
+            .iter()
+            .find(|(name, _)| *name == category)
+            .map_or(Severity::Warning, |(_, severity)| *severity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_severity_overrides() {
+        let defaults = Severities::default();
+        assert_eq!(defaults.of("sei_error"), Severity::Info);
+        assert_eq!(defaults.of("ffprobe_failed"), Severity::Fatal);
+
+        let overrides = BTreeMap::from([("sei_error".to_string(), Severity::Error)]);
+        let severities = Severities::new(&overrides);
+        assert_eq!(severities.of("sei_error"), Severity::Error);
+        assert_eq!(severities.of("concealment"), Severity::Warning);
+
+        assert!(is_error_category("udp_overrun"));
+        assert!(!is_error_category("sei"));
+    }
+}
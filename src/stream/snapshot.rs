@@ -0,0 +1,153 @@
+// stream/snapshot.rs
+
+use crate::stream::lines::for_each_line;
+use crate::stream::process::{spawn, terminate};
+use anyhow::{Context, Result};
+use std::io::{self, BufReader, Read};
+use std::process::{Child, Command};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use tracing::warn;
+
+/// JPEG end of image marker. The entropy coded data stuffs every 0xFF with a
+/// zero byte, so the marker only shows up at the end of an image.
+const END_OF_IMAGE: [u8; 2] = [0xFF, 0xD9];
+
+/// Bound of a single image, in case ffmpeg writes something else
+const MAX_JPEG_SIZE: usize = 16 * 1024 * 1024;
+
+/// A JPEG of the video and when it arrived
+#[derive(Clone)]
+pub struct Snapshot {
+    pub jpeg: Arc<[u8]>,
+    pub captured: SystemTime,
+}
+
+/// The latest snapshot of a stream, written by its snapshot session and read
+/// by `/snapshot/{stream}`
+#[derive(Clone, Default)]
+pub struct SnapshotStore {
+    latest: Arc<Mutex<Option<Snapshot>>>,
+}
+
+impl SnapshotStore {
+    pub fn latest(&self) -> Option<Snapshot> {
+        self.latest.lock().unwrap().clone()
+    }
+
+    fn store(&self, jpeg: Vec<u8>) {
+        *self.latest.lock().unwrap() = Some(Snapshot {
+            jpeg: jpeg.into(),
+            captured: SystemTime::now(),
+        });
+    }
+
+    fn clear(&self) {
+        *self.latest.lock().unwrap() = None;
+    }
+}
+
+/// The ffmpeg process writing snapshots of a stream, terminated on drop.
+/// The snapshot of a session ends with it, so a disconnected stream shows no
+/// stale picture.
+pub struct SnapshotSession {
+    child: Child,
+    store: SnapshotStore,
+    grace: Duration,
+}
+
+impl SnapshotSession {
+    /// `grace` is the time ffmpeg gets to exit before it is killed
+    pub fn start(mut cmd: Command, store: SnapshotStore, grace: Duration) -> Result<Self> {
+        let mut child = spawn(&mut cmd).context("Failed to spawn snapshot ffmpeg process")?;
+        let stdout = child.stdout.take().context("Failed to capture stdout")?;
+        let stderr = child.stderr.take().context("Failed to capture stderr")?;
+
+        let images = store.clone();
+        thread::spawn(move || {
+            if let Err(e) = split_jpegs(stdout, |jpeg| images.store(jpeg)) {
+                warn!(?e, "Error reading snapshots");
+            }
+        });
+        thread::spawn(move || {
+            let _ = for_each_line(BufReader::new(stderr), |line| {
+                warn!("Snapshot ffmpeg: {}", line);
+                Ok(())
+            });
+        });
+        Ok(Self {
+            child,
+            store,
+            grace,
+        })
+    }
+}
+
+impl Drop for SnapshotSession {
+    fn drop(&mut self) {
+        terminate(&mut self.child, self.grace);
+        self.store.clear();
+    }
+}
+
+/// Hands the concatenated JPEGs of the image2pipe muxer to `on_jpeg` one by
+/// one, until `reader` ends
+fn split_jpegs(mut reader: impl Read, mut on_jpeg: impl FnMut(Vec<u8>)) -> io::Result<()> {
+    let mut pending = Vec::new();
+    let mut chunk = vec![0u8; 64 * 1024];
+    // Where to look for the next marker, which may span two reads
+    let mut scanned = 0;
+    loop {
+        let read = reader.read(&mut chunk)?;
+        if read == 0 {
+            return Ok(());
+        }
+        pending.extend_from_slice(&chunk[..read]);
+        while let Some(end) = pending[scanned..]
+            .windows(END_OF_IMAGE.len())
+            .position(|window| window == END_OF_IMAGE)
+        {
+            let len = scanned + end + END_OF_IMAGE.len();
+            on_jpeg(pending.drain(..len).collect());
+            scanned = 0;
+        }
+        scanned = pending.len().saturating_sub(END_OF_IMAGE.len() - 1);
+        if pending.len() > MAX_JPEG_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "No end of image within 16 MiB",
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hands out `data` a few bytes at a time
+    struct Trickle<'a> {
+        data: &'a [u8],
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = self.data.len().min(buf.len()).min(3);
+            buf[..len].copy_from_slice(&self.data[..len]);
+            self.data = &self.data[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn test_split_jpegs() {
+        let first = [0xFF, 0xD8, 0x01, 0xFF, 0x00, 0x02, 0xFF, 0xD9];
+        let second = [0xFF, 0xD8, 0x03, 0xFF, 0xD9];
+        let data = [&first[..], &second[..], &[0xFF, 0xD8, 0x04]].concat();
+        let mut jpegs = Vec::new();
+        split_jpegs(Trickle { data: &data }, |jpeg| jpegs.push(jpeg)).unwrap();
+        // The truncated image is dropped
+        assert_eq!(jpegs, vec![first.to_vec(), second.to_vec()]);
+    }
+}
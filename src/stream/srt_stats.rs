@@ -0,0 +1,234 @@
+// stream/srt_stats.rs
+//
+// ffprobe's libsrt input only logs dropped packets. The socket statistics
+// are read from srt-live-transmit, libsrt's own receiver, which reports them
+// as CSV with a header line naming the columns.
+
+use crate::metrics::StreamMetrics;
+use crate::stream::lines::for_each_line;
+use crate::stream::process::{spawn, terminate};
+use anyhow::{Context, Result};
+use std::io::BufReader;
+use std::net::UdpSocket;
+use std::process::{Child, Command};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+
+/// How often the sink draining the received stream looks for the stop flag
+const SINK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// One statistics report. Packet counts are those since the previous report.
+#[derive(Debug, Default, PartialEq)]
+pub struct SrtReport {
+    pub rtt_ms: f64,
+    /// Estimated link bandwidth
+    pub bandwidth_mbps: f64,
+    pub receive_rate_mbps: f64,
+    pub flight_size: f64,
+    /// Milliseconds of stream held in the receive buffer
+    pub receive_buffer_ms: f64,
+    pub receive_buffer_available_bytes: f64,
+    pub received: f64,
+    pub lost: f64,
+    pub dropped: f64,
+    pub retransmitted: f64,
+    pub belated: f64,
+}
+
+/// Reads the reports from the CSV lines of srt-live-transmit
+#[derive(Default)]
+pub struct SrtStatsParser {
+    columns: Vec<String>,
+}
+
+impl SrtStatsParser {
+    /// The report of a data line, `None` for the header and other lines
+    pub fn parse(&mut self, line: &str) -> Option<SrtReport> {
+        let values: Vec<&str> = line.split(',').map(str::trim).collect();
+        if values.contains(&"msRTT") {
+            self.columns = values.into_iter().map(str::to_string).collect();
+            return None;
+        }
+        if self.columns.len() != values.len() {
+            return None;
+        }
+        let value = |column: &str| -> Option<f64> {
+            let index = self.columns.iter().position(|name| name == column)?;
+            values[index].parse().ok()
+        };
+        Some(SrtReport {
+            rtt_ms: value("msRTT")?,
+            bandwidth_mbps: value("mbpsBandwidth").unwrap_or_default(),
+            receive_rate_mbps: value("mbpsRecvRate").unwrap_or_default(),
+            flight_size: value("pktFlightSize").unwrap_or_default(),
+            receive_buffer_ms: value("msRcvBuf").unwrap_or_default(),
+            receive_buffer_available_bytes: value("byteAvailRcvBuf").unwrap_or_default(),
+            received: value("pktRecv").unwrap_or_default(),
+            lost: value("pktRcvLoss").unwrap_or_default(),
+            dropped: value("pktRcvDrop").unwrap_or_default(),
+            retransmitted: value("pktRcvRetrans").unwrap_or_default(),
+            belated: value("pktRcvBelated").unwrap_or_default(),
+        })
+    }
+}
+
+pub fn process_report(report: &SrtReport, metrics: &StreamMetrics) {
+    metrics
+        .srt_rtt
+        .with_label_values(&[])
+        .set(report.rtt_ms / 1000.0);
+    metrics
+        .srt_bandwidth
+        .with_label_values(&[])
+        .set(report.bandwidth_mbps * 1000.0);
+    metrics
+        .srt_receive_rate
+        .with_label_values(&[])
+        .set(report.receive_rate_mbps * 1000.0);
+    metrics
+        .srt_flight_size
+        .with_label_values(&[])
+        .set(report.flight_size);
+    metrics
+        .srt_receive_buffer
+        .with_label_values(&[])
+        .set(report.receive_buffer_ms / 1000.0);
+    metrics
+        .srt_receive_buffer_available
+        .with_label_values(&[])
+        .set(report.receive_buffer_available_bytes);
+    for (counter, count) in [
+        (&metrics.srt_packets_received, report.received),
+        (&metrics.srt_packets_lost, report.lost),
+        (&metrics.srt_packets_dropped, report.dropped),
+        (&metrics.srt_packets_retransmitted, report.retransmitted),
+        (&metrics.srt_packets_belated, report.belated),
+    ] {
+        if count > 0.0 {
+            counter.inc_by(count);
+        }
+    }
+}
+
+/// A running srt-live-transmit receiving the SRT input next to ffprobe. The
+/// stream it receives goes to a local UDP sink that discards it. Like the
+/// other helper sessions it is terminated when dropped.
+pub struct SrtStatsSession {
+    child: Child,
+    stop: Arc<AtomicBool>,
+    grace: Duration,
+}
+
+impl SrtStatsSession {
+    /// `sink` is the socket the command sends the received stream to
+    pub fn start(
+        mut cmd: Command,
+        sink: UdpSocket,
+        metrics: StreamMetrics,
+        grace: Duration,
+    ) -> Result<Self> {
+        sink.set_read_timeout(Some(SINK_TIMEOUT))
+            .context("Failed to set the sink timeout")?;
+        let mut child = spawn(&mut cmd).context("Failed to spawn srt-live-transmit process")?;
+        let stdout = child.stdout.take().context("Failed to capture stdout")?;
+        let stderr = child.stderr.take().context("Failed to capture stderr")?;
+
+        thread::spawn(move || {
+            let mut parser = SrtStatsParser::default();
+            let result = for_each_line(BufReader::new(stdout), |line| {
+                if let Some(report) = parser.parse(line) {
+                    process_report(&report, &metrics);
+                }
+                Ok(())
+            });
+            if let Err(e) = result {
+                warn!(?e, "Error processing SRT statistics");
+            }
+        });
+        thread::spawn(move || {
+            let _ = for_each_line(BufReader::new(stderr), |line| {
+                warn!("srt-live-transmit: {}", line);
+                Ok(())
+            });
+        });
+        let stop = Arc::new(AtomicBool::new(false));
+        let sink_stop = stop.clone();
+        thread::spawn(move || {
+            let mut buffer = [0u8; 65536];
+            while !sink_stop.load(Ordering::SeqCst) {
+                match sink.recv(&mut buffer) {
+                    Ok(_) => {}
+                    Err(e)
+                        if matches!(
+                            e.kind(),
+                            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                        ) => {}
+                    Err(_) => break,
+                }
+            }
+        });
+        Ok(Self { child, stop, grace })
+    }
+}
+
+impl Drop for SrtStatsSession {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        terminate(&mut self.child, self.grace);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::Registry;
+    use std::collections::HashMap;
+
+    const HEADER: &str = "Timepoint,Time,SocketID,pktFlowWindow,pktCongestionWindow,pktFlightSize,msRTT,mbpsBandwidth,mbpsMaxBW,pktSent,pktSndLoss,pktSndDrop,pktRetrans,byteSent,byteAvailSndBuf,byteSndDrop,mbpsSendRate,usPktSndPeriod,msSndBuf,pktRecv,pktRcvLoss,pktRcvDrop,pktRcvRetrans,pktRcvBelated,byteRecv,byteAvailRcvBuf,byteRcvLoss,byteRcvDrop,mbpsRecvRate,msRcvBuf,msRcvTsbPdDelay";
+
+    const REPORT: &str = "2025-03-01T10:00:01.250+0000,1250,521378412,25600,8192,12,23.4,912.5,1000,0,0,0,0,0,12288000,0,0,10,0,1000,3,1,4,2,1316000,11890000,3948,1316,10.53,118,120";
+
+    #[test]
+    fn test_parse_srt_stats() {
+        let mut parser = SrtStatsParser::default();
+        // Data before the header cannot be told apart
+        assert_eq!(parser.parse(REPORT), None);
+        assert_eq!(parser.parse(HEADER), None);
+        let report = parser.parse(REPORT).unwrap();
+        assert_eq!(
+            report,
+            SrtReport {
+                rtt_ms: 23.4,
+                bandwidth_mbps: 912.5,
+                receive_rate_mbps: 10.53,
+                flight_size: 12.0,
+                receive_buffer_ms: 118.0,
+                receive_buffer_available_bytes: 11_890_000.0,
+                received: 1000.0,
+                lost: 3.0,
+                dropped: 1.0,
+                retransmitted: 4.0,
+                belated: 2.0,
+            }
+        );
+        assert_eq!(parser.parse("Connected"), None);
+
+        let metrics = StreamMetrics::new(&Registry::new(), &HashMap::new()).unwrap();
+        process_report(&report, &metrics);
+        process_report(&report, &metrics);
+        assert!((metrics.srt_rtt.with_label_values(&[]).get() - 0.0234).abs() < 1e-9);
+        assert_eq!(
+            metrics.srt_bandwidth.with_label_values(&[]).get(),
+            912_500.0
+        );
+        assert_eq!(
+            metrics.srt_receive_buffer.with_label_values(&[]).get(),
+            0.118
+        );
+        assert_eq!(metrics.srt_packets_received.get(), 2000.0);
+        assert_eq!(metrics.srt_packets_retransmitted.get(), 8.0);
+    }
+}
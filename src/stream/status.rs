@@ -0,0 +1,267 @@
+// stream/status.rs
+
+use crate::config::ResourceAttributes;
+use crate::metrics::StreamMetrics;
+use chrono::{SecondsFormat, Utc};
+use prometheus::core::Collector;
+use prometheus::proto::{Metric, MetricFamily};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamState {
+    /// ffprobe runs but delivered nothing yet
+    #[default]
+    Connecting,
+    /// ffprobe delivers packets or frames
+    Connected,
+    /// ffprobe delivered data but went quiet for `--stall-timeout` seconds
+    Stalled,
+    /// Waiting to restart ffprobe
+    Disconnected,
+    /// Paused through the admin API, ffprobe does not run
+    Paused,
+    /// The monitor ended, after it was stopped or gave up
+    Stopped,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LastError {
+    /// RFC 3339 time of the failure
+    pub timestamp: String,
+    pub message: String,
+}
+
+#[derive(Default)]
+struct Inner {
+    state: StreamState,
+    /// When the current session delivered its first data
+    up_since: Option<Instant>,
+    last_error: Option<LastError>,
+}
+
+/// State of a monitor, updated by its thread and read by the HTTP API
+#[derive(Clone, Default)]
+pub struct StreamStatus {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl StreamStatus {
+    pub fn connecting(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.state = StreamState::Connecting;
+        inner.up_since = None;
+    }
+
+    /// The session delivered data, `stalled` if not for a while
+    pub fn delivering(&self, stalled: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.state = if stalled {
+            StreamState::Stalled
+        } else {
+            StreamState::Connected
+        };
+        inner.up_since.get_or_insert_with(Instant::now);
+    }
+
+    pub fn disconnected(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.state = StreamState::Disconnected;
+        inner.up_since = None;
+    }
+
+    pub fn paused(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.state = StreamState::Paused;
+        inner.up_since = None;
+    }
+
+    pub fn failed(&self, message: &str) {
+        self.inner.lock().unwrap().last_error = Some(LastError {
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            message: message.to_string(),
+        });
+    }
+
+    /// Whether the current session delivers data, or the stream is paused
+    /// on purpose
+    pub fn healthy(&self) -> bool {
+        matches!(
+            self.inner.lock().unwrap().state,
+            StreamState::Connected | StreamState::Paused
+        )
+    }
+}
+
+/// JSON view of a monitored stream for `/api/v1/streams`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StreamReport {
+    pub name: String,
+    /// The input URL with secrets redacted
+    pub input: String,
+    pub stream_type: &'static str,
+    pub state: StreamState,
+    /// Seconds since the current session delivered its first data
+    pub uptime_seconds: Option<f64>,
+    /// ffprobe restarts of any reason
+    pub restarts: u64,
+    /// Frame rate of the video stream with the highest one
+    pub fps: Option<f64>,
+    /// Bitrate of all elementary streams over the last second
+    pub bitrate_kbits: Option<f64>,
+    /// The latest failure of ffprobe
+    pub last_error: Option<LastError>,
+    #[serde(flatten)]
+    pub resource: ResourceAttributes,
+}
+
+impl StreamReport {
+    /// `running` is false once the monitor ended
+    pub fn new(
+        name: &str,
+        input: String,
+        stream_type: &'static str,
+        status: &StreamStatus,
+        metrics: &StreamMetrics,
+        running: bool,
+        resource: &ResourceAttributes,
+    ) -> Self {
+        let inner = status.inner.lock().unwrap();
+        let state = if running {
+            inner.state
+        } else {
+            StreamState::Stopped
+        };
+        let video = |metric: &Metric| {
+            metric
+                .get_label()
+                .iter()
+                .any(|l| l.get_name() == "media_type" && l.get_value() == "video")
+        };
+        Self {
+            name: name.to_string(),
+            input,
+            stream_type,
+            state,
+            uptime_seconds: inner
+                .up_since
+                .filter(|_| running)
+                .map(|since| since.elapsed().as_secs_f64()),
+            restarts: values(&metrics.restarts.collect(), |_| true)
+                .iter()
+                .sum::<f64>() as u64,
+            fps: values(&metrics.fps.collect(), video)
+                .into_iter()
+                .reduce(f64::max),
+            bitrate_kbits: values(&metrics.bitrate.collect(), |_| true)
+                .into_iter()
+                .reduce(|a, b| a + b),
+            last_error: inner.last_error.clone(),
+            resource: resource.clone(),
+        }
+    }
+}
+
+/// Values of the counter and gauge series of `families` passing `filter`
+fn values(families: &[MetricFamily], filter: impl Fn(&Metric) -> bool) -> Vec<f64> {
+    let mut values = Vec::new();
+    for family in families {
+        for metric in family.get_metric() {
+            if !filter(metric) {
+                continue;
+            }
+            values.push(if metric.has_counter() {
+                metric.get_counter().get_value()
+            } else {
+                metric.get_gauge().get_value()
+            });
+        }
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::Registry;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_stream_report() {
+        let metrics = StreamMetrics::new(&Registry::new(), &HashMap::new()).unwrap();
+        let status = StreamStatus::default();
+        let resource = ResourceAttributes {
+            region: None,
+            site: Some("studio-a".to_string()),
+            probe_id: "probe-1".to_string(),
+        };
+        let report = |running| {
+            StreamReport::new(
+                "news",
+                "udp://x".into(),
+                "udp",
+                &status,
+                &metrics,
+                running,
+                &resource,
+            )
+        };
+
+        status.connecting();
+        assert_eq!(report(true).state, StreamState::Connecting);
+        assert_eq!(report(true).uptime_seconds, None);
+        assert_eq!(report(true).fps, None);
+
+        metrics
+            .fps
+            .with_label_values(&["udp", "0", "video"])
+            .set(25.0);
+        metrics
+            .fps
+            .with_label_values(&["udp", "1", "audio"])
+            .set(46.875);
+        metrics
+            .bitrate
+            .with_label_values(&["0", "video"])
+            .set(4000.0);
+        metrics
+            .bitrate
+            .with_label_values(&["1", "audio"])
+            .set(128.0);
+        metrics.restarts.with_label_values(&["udp", "stall"]).inc();
+        metrics.restarts.with_label_values(&["udp", "error"]).inc();
+        status.delivering(false);
+        assert!(status.healthy());
+        let current = report(true);
+        assert_eq!(current.state, StreamState::Connected);
+        assert!(current.uptime_seconds.is_some());
+        assert_eq!(current.fps, Some(25.0));
+        assert_eq!(current.bitrate_kbits, Some(4128.0));
+        assert_eq!(current.restarts, 2);
+
+        status.delivering(true);
+        assert!(!status.healthy());
+        assert_eq!(report(true).state, StreamState::Stalled);
+
+        status.failed("FFprobe process failed with exit code: 1");
+        status.disconnected();
+        let current = report(true);
+        assert_eq!(current.state, StreamState::Disconnected);
+        assert_eq!(current.uptime_seconds, None);
+        assert_eq!(
+            current.last_error.unwrap().message,
+            "FFprobe process failed with exit code: 1"
+        );
+        status.paused();
+        assert!(status.healthy());
+        assert_eq!(report(true).state, StreamState::Paused);
+        assert_eq!(report(false).state, StreamState::Stopped);
+
+        let json = serde_json::to_value(report(true)).unwrap();
+        assert_eq!(json["site"], "studio-a");
+        assert_eq!(json["probe_id"], "probe-1");
+        assert!(json.get("region").is_none());
+    }
+}
@@ -0,0 +1,100 @@
+// stream/subtitles.rs
+
+use crate::metrics::StreamMetrics;
+use crate::stream::records::{PacketRecord, StreamRecord};
+use crate::stream::tracks::UNDETERMINED;
+use std::collections::HashMap;
+
+/// The subtitle tracks found by the stream probe (DVB subtitles, teletext,
+/// WebVTT and the like), numbered in stream order like ffmpeg's `s:N` stream
+/// specifiers. Subtitle packets only arrive while there is something to show,
+/// so presence follows the probe and activity is left to the packet counter.
+#[derive(Default)]
+pub struct SubtitleTracks {
+    /// Label values per stream id: track, codec and language
+    tracks: HashMap<String, [String; 3]>,
+}
+
+impl SubtitleTracks {
+    pub fn insert(&mut self, stream: &StreamRecord, metrics: &StreamMetrics) {
+        let language = if stream.language.is_empty() {
+            UNDETERMINED
+        } else {
+            stream.language
+        };
+        let labels = [
+            self.tracks.len().to_string(),
+            stream.codec.to_string(),
+            language.to_string(),
+        ];
+        metrics
+            .subtitle_track_present
+            .with_label_values(&[stream.stream_id, &labels[0], &labels[1], &labels[2]])
+            .set(1.0);
+        self.tracks.insert(stream.stream_id.to_string(), labels);
+    }
+
+    pub fn observe(&self, packet: &PacketRecord, metrics: &StreamMetrics) {
+        if let Some([track, codec, language]) = self.tracks.get(packet.stream_id) {
+            metrics
+                .subtitle_track_packets
+                .with_label_values(&[packet.stream_id, track, codec, language])
+                .inc();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::lines::Fields;
+    use prometheus::Registry;
+
+    fn packet(stream_id: &'static str) -> PacketRecord<'static> {
+        PacketRecord {
+            media_type: "subtitle",
+            stream_id,
+            size: Some(200.0),
+            dts_time: Some(1.0),
+            flags: "K_",
+        }
+    }
+
+    #[test]
+    fn test_subtitle_tracks() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let mut tracks = SubtitleTracks::default();
+        for line in [
+            "stream,index=3,codec_name=dvb_subtitle,codec_type=subtitle,tag:language=fra",
+            "stream,index=4,codec_name=dvb_teletext,codec_type=subtitle",
+        ] {
+            tracks.insert(
+                &StreamRecord::parse(&Fields::split(line)).unwrap(),
+                &metrics,
+            );
+        }
+
+        tracks.observe(&packet("3"), &metrics);
+        tracks.observe(&packet("3"), &metrics);
+        // Packets of other streams are ignored
+        tracks.observe(&packet("0"), &metrics);
+
+        let present = |labels: [&str; 4]| {
+            metrics
+                .subtitle_track_present
+                .with_label_values(&labels)
+                .get()
+        };
+        assert_eq!(present(["3", "0", "dvb_subtitle", "fra"]), 1.0);
+        assert_eq!(present(["4", "1", "dvb_teletext", "und"]), 1.0);
+        let packets = |labels: [&str; 4]| {
+            metrics
+                .subtitle_track_packets
+                .with_label_values(&labels)
+                .get()
+        };
+        assert_eq!(packets(["3", "0", "dvb_subtitle", "fra"]), 2.0);
+        assert_eq!(packets(["4", "1", "dvb_teletext", "und"]), 0.0);
+    }
+}
@@ -0,0 +1,86 @@
+// stream/sync.rs
+
+use crate::metrics::StreamMetrics;
+use crate::stream::records::FrameRecord;
+use std::collections::HashMap;
+
+/// Compares the presentation timestamps of the latest video and audio frames
+/// to spot lip-sync drift.
+#[derive(Default)]
+pub struct AvSync {
+    video: HashMap<String, f64>,
+    audio: HashMap<String, f64>,
+}
+
+impl AvSync {
+    /// Records the frame and, for video frames, updates the skew against every
+    /// audio track. Positive values mean video is ahead of audio.
+    pub fn observe(&mut self, frame: &FrameRecord, metrics: &StreamMetrics) {
+        let Some(pts) = frame.pts_time else {
+            return;
+        };
+        let latest = match frame.media_type {
+            "video" => &mut self.video,
+            "audio" => &mut self.audio,
+            _ => return,
+        };
+        match latest.get_mut(frame.stream_id) {
+            Some(time) => *time = pts,
+            None => {
+                latest.insert(frame.stream_id.to_string(), pts);
+            }
+        }
+
+        if frame.media_type == "video" {
+            for (audio_id, audio_pts) in &self.audio {
+                metrics
+                    .av_sync_skew
+                    .with_label_values(&[frame.stream_id, audio_id])
+                    .set((pts - audio_pts) * 1000.0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::Registry;
+
+    fn frame(media_type: &'static str, stream_id: &'static str, pts: f64) -> FrameRecord<'static> {
+        FrameRecord {
+            media_type,
+            stream_id,
+            pts_time: Some(pts),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_av_sync_skew() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let mut sync = AvSync::default();
+
+        // No audio yet, nothing to compare against
+        sync.observe(&frame("video", "0", 10.0), &metrics);
+        assert!(
+            registry
+                .gather()
+                .iter()
+                .all(|family| family.get_name() != "ffmpeg_av_sync_skew_ms")
+        );
+
+        sync.observe(&frame("audio", "1", 10.0), &metrics);
+        sync.observe(&frame("audio", "2", 10.12), &metrics);
+        sync.observe(&frame("video", "0", 10.08), &metrics);
+        let skew = |audio_id| {
+            metrics
+                .av_sync_skew
+                .with_label_values(&["0", audio_id])
+                .get()
+        };
+        assert!((skew("1") - 80.0).abs() < 1e-6);
+        assert!((skew("2") + 40.0).abs() < 1e-6);
+    }
+}
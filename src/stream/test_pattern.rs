@@ -0,0 +1,140 @@
+// stream/test_pattern.rs
+
+use crate::stream::lines::Fields;
+
+/// Sample rate the audio is resampled to before `astats`, so zero crossing
+/// rates translate to frequencies
+pub const TONE_SAMPLE_RATE: u32 = 48000;
+
+/// Line-up tone frequency in Hz and the deviation still accepted. A frame of
+/// about 20 ms resolves the frequency to a few ten Hz.
+const TONE_FREQUENCY: f64 = 1000.0;
+const TONE_TOLERANCE: f64 = 100.0;
+
+/// Crest factor (peak to RMS ratio) of a sine is the square root of two;
+/// speech and music stay well above it
+const TONE_CREST_FACTOR: std::ops::RangeInclusive<f64> = 1.3..=1.55;
+
+/// Quieter sines are not line-up tone
+const TONE_MIN_RMS_LEVEL: f64 = -40.0;
+
+/// Colour bars are a still picture of saturated colours spanning most of the
+/// luma range. Thresholds are in 8 bit levels and scaled to the bit depth.
+const BARS_MAX_DIFFERENCE: f64 = 1.0;
+const BARS_MIN_SATURATION: f64 = 40.0;
+const BARS_MIN_LUMA_SPREAD: f64 = 100.0;
+
+/// `astats` measurements the tone heuristic needs
+pub const TONE_MEASURES: [&str; 3] = ["RMS_level", "Crest_factor", "Zero_crossings_rate"];
+
+fn tag(fields: &Fields, key: &str) -> Option<f64> {
+    fields.get(key).and_then(|v| v.parse().ok())
+}
+
+/// Bit depth of a pixel format like `yuv420p10le`, 8 if it names none
+pub fn bit_depth(pix_fmt: &str) -> u32 {
+    let name = pix_fmt.trim_end_matches("le").trim_end_matches("be");
+    let digits = name.len() - name.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    match name[name.len() - digits..].parse() {
+        Ok(depth) if name[..name.len() - digits].ends_with('p') && depth > 8 => depth,
+        _ => 8,
+    }
+}
+
+/// Whether a video frame with `signalstats` tags looks like colour bars
+pub fn is_bars(fields: &Fields) -> bool {
+    let stat = |name| tag(fields, &format!("tag:lavfi.signalstats.{}", name));
+    let (Some(difference), Some(saturation), Some(low), Some(high)) =
+        (stat("YDIF"), stat("SATAVG"), stat("YLOW"), stat("YHIGH"))
+    else {
+        return false;
+    };
+    let scale = f64::from(1u32 << (bit_depth(fields.get("pix_fmt").unwrap_or("")) - 8));
+    difference <= BARS_MAX_DIFFERENCE * scale
+        && saturation >= BARS_MIN_SATURATION * scale
+        && high - low >= BARS_MIN_LUMA_SPREAD * scale
+}
+
+/// Whether any channel of an audio frame with `astats` tags carries a 1 kHz
+/// sine, the audio is expected at `TONE_SAMPLE_RATE`
+pub fn is_tone(fields: &Fields) -> bool {
+    fields.as_slice().iter().any(|part| {
+        let Some((channel, rate)) = part
+            .strip_prefix("tag:lavfi.astats.")
+            .and_then(|tag| tag.split_once(".Zero_crossings_rate="))
+        else {
+            return false;
+        };
+        let stat = |name| tag(fields, &format!("tag:lavfi.astats.{}.{}", channel, name));
+        let (Ok(rate), Some(crest), Some(rms)) =
+            (rate.parse::<f64>(), stat("Crest_factor"), stat("RMS_level"))
+        else {
+            return false;
+        };
+        // A sine crosses zero twice per period
+        let frequency = rate * f64::from(TONE_SAMPLE_RATE) / 2.0;
+        (frequency - TONE_FREQUENCY).abs() <= TONE_TOLERANCE
+            && TONE_CREST_FACTOR.contains(&crest)
+            && rms >= TONE_MIN_RMS_LEVEL
+    })
+}
+
+/// Reports a condition once it held for `min_duration` seconds of stream time
+pub struct Sustained {
+    min_duration: f64,
+    since: Option<f64>,
+}
+
+impl Sustained {
+    pub fn new(min_duration: f64) -> Self {
+        Self {
+            min_duration,
+            since: None,
+        }
+    }
+
+    pub fn observe(&mut self, pts: f64, condition: bool) -> bool {
+        if !condition {
+            self.since = None;
+            return false;
+        }
+        let since = *self.since.get_or_insert(pts);
+        pts - since >= self.min_duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bars_and_tone_heuristics() {
+        let bars = "frame,media_type=video,pix_fmt=yuv420p,tag:lavfi.signalstats.YDIF=0.0,\
+                    tag:lavfi.signalstats.SATAVG=62.3,tag:lavfi.signalstats.YLOW=35,\
+                    tag:lavfi.signalstats.YHIGH=180";
+        assert!(is_bars(&Fields::split(bars)));
+        // The same levels of a 10 bit picture are far too flat
+        assert!(!is_bars(&Fields::split(
+            &bars.replace("yuv420p", "yuv420p10le")
+        )));
+        let moving = bars.replace("YDIF=0.0", "YDIF=6.5");
+        assert!(!is_bars(&Fields::split(&moving)));
+        assert_eq!(bit_depth("yuv422p10be"), 10);
+        assert_eq!(bit_depth("nv12"), 8);
+
+        let tone = "frame,media_type=audio,tag:lavfi.astats.1.RMS_level=-18.0,\
+                    tag:lavfi.astats.1.Crest_factor=1.414,\
+                    tag:lavfi.astats.1.Zero_crossings_rate=0.0417";
+        assert!(is_tone(&Fields::split(tone)));
+        let speech = tone.replace("Crest_factor=1.414", "Crest_factor=4.2");
+        assert!(!is_tone(&Fields::split(&speech)));
+        let high = tone.replace("rate=0.0417", "rate=0.0833");
+        assert!(!is_tone(&Fields::split(&high)));
+
+        let mut sustained = Sustained::new(2.0);
+        assert!(!sustained.observe(10.0, true));
+        assert!(sustained.observe(12.0, true));
+        assert!(!sustained.observe(12.5, false));
+        assert!(!sustained.observe(13.0, true));
+    }
+}
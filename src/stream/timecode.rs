@@ -0,0 +1,189 @@
+// stream/timecode.rs
+
+use crate::metrics::StreamMetrics;
+use crate::stream::records::FrameRecord;
+use std::collections::HashMap;
+
+const SECONDS_PER_DAY: u64 = 24 * 3600;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Timecode {
+    hours: u64,
+    minutes: u64,
+    seconds: u64,
+    frames: u64,
+    /// Drop frame timecodes use `;` or `.` before the frame count
+    drop_frame: bool,
+}
+
+impl Timecode {
+    /// Parses `HH:MM:SS:FF`, or `HH:MM:SS;FF` for drop frame timecodes
+    fn parse(value: &str) -> Option<Self> {
+        let (time, frames) = value.rsplit_once([':', ';', '.'])?;
+        let drop_frame = value.as_bytes()[time.len()] != b':';
+        let mut parts = time.split(':').map(|part| part.parse::<u64>().ok());
+        let timecode = Self {
+            hours: parts.next()??,
+            minutes: parts.next()??,
+            seconds: parts.next()??,
+            frames: frames.parse().ok()?,
+            drop_frame,
+        };
+        (parts.next().is_none() && timecode.minutes < 60 && timecode.seconds < 60)
+            .then_some(timecode)
+    }
+
+    fn whole_seconds(&self) -> u64 {
+        (self.hours * 60 + self.minutes) * 60 + self.seconds
+    }
+
+    /// Whether `self` is the frame right after `previous` at `rate` frames
+    /// per second, including the midnight rollover and the frame numbers
+    /// skipped by drop frame timecodes at the start of most minutes
+    fn follows(&self, previous: &Timecode, rate: u64) -> bool {
+        let position = |tc: &Timecode| tc.whole_seconds() * rate + tc.frames;
+        let day = SECONDS_PER_DAY * rate;
+        let step = (position(self) + day - position(previous)) % day;
+        let skipped = if self.drop_frame && self.seconds == 0 && !self.minutes.is_multiple_of(10) {
+            rate / 15
+        } else {
+            0
+        };
+        step == 1 || (skipped > 0 && self.frames == skipped && step == skipped + 1)
+    }
+}
+
+#[derive(Default)]
+struct TimecodeState {
+    last: Option<Timecode>,
+    /// Learned from the highest frame number once the seconds roll over
+    rate: Option<u64>,
+    max_frames: u64,
+}
+
+/// Follows the embedded SMPTE timecode of each video stream, so master control
+/// can verify house timecode survives the contribution chain.
+#[derive(Default)]
+pub struct TimecodeTracker {
+    streams: HashMap<String, TimecodeState>,
+}
+
+impl TimecodeTracker {
+    pub fn observe(&mut self, frame: &FrameRecord, metrics: &StreamMetrics) {
+        let Some(timecode) = frame.timecode.and_then(Timecode::parse) else {
+            return;
+        };
+        metrics
+            .timecode_seconds
+            .with_label_values(&[frame.stream_id])
+            .set(timecode.whole_seconds() as f64);
+        metrics
+            .timecode_frames
+            .with_label_values(&[frame.stream_id])
+            .set(timecode.frames as f64);
+
+        if !self.streams.contains_key(frame.stream_id) {
+            self.streams
+                .insert(frame.stream_id.to_string(), TimecodeState::default());
+        }
+        let state = self.streams.get_mut(frame.stream_id).unwrap();
+        let Some(last) = state.last.replace(timecode) else {
+            state.max_frames = timecode.frames;
+            return;
+        };
+        // Field based streams repeat the timecode for both fields
+        if last == timecode {
+            return;
+        }
+
+        if state.rate.is_none()
+            && timecode.frames == 0
+            && timecode.whole_seconds() == last.whole_seconds() + 1
+        {
+            state.rate = Some(state.max_frames.max(last.frames) + 1);
+        }
+        state.max_frames = state.max_frames.max(timecode.frames);
+
+        if let Some(rate) = state.rate
+            && !timecode.follows(&last, rate)
+        {
+            metrics
+                .timecode_discontinuities
+                .with_label_values(&[frame.stream_id])
+                .inc();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::Registry;
+
+    fn frame(timecode: &'static str) -> FrameRecord<'static> {
+        FrameRecord {
+            media_type: "video",
+            stream_id: "0",
+            timecode: Some(timecode),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_timecode() {
+        let tc = Timecode::parse("10:00:01:24").unwrap();
+        assert_eq!(tc.whole_seconds(), 36001);
+        assert_eq!(tc.frames, 24);
+        assert!(!tc.drop_frame);
+        assert!(Timecode::parse("01:02:03;04").unwrap().drop_frame);
+        assert_eq!(Timecode::parse("garbage"), None);
+        assert_eq!(Timecode::parse("00:61:00:00"), None);
+    }
+
+    #[test]
+    fn test_drop_frame_follows() {
+        let before = Timecode::parse("00:00:59;29").unwrap();
+        // Frames 0 and 1 are skipped at the start of minute 1, but not minute 10
+        assert!(Timecode::parse("00:01:00;02").unwrap().follows(&before, 30));
+        let before = Timecode::parse("00:09:59;29").unwrap();
+        assert!(Timecode::parse("00:10:00;00").unwrap().follows(&before, 30));
+        // Midnight rollover
+        let before = Timecode::parse("23:59:59:24").unwrap();
+        assert!(Timecode::parse("00:00:00:00").unwrap().follows(&before, 25));
+    }
+
+    #[test]
+    fn test_timecode_discontinuities() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let mut tracker = TimecodeTracker::default();
+        let discontinuities = || {
+            metrics
+                .timecode_discontinuities
+                .with_label_values(&["0"])
+                .get()
+        };
+
+        for timecode in [
+            "10:00:00:22",
+            "10:00:00:23",
+            "10:00:00:24",
+            "10:00:01:00",
+            "10:00:01:01",
+        ] {
+            tracker.observe(&frame(timecode), &metrics);
+        }
+        assert_eq!(discontinuities(), 0.0);
+        assert_eq!(
+            metrics.timecode_seconds.with_label_values(&["0"]).get(),
+            36001.0
+        );
+        assert_eq!(metrics.timecode_frames.with_label_values(&["0"]).get(), 1.0);
+
+        // A splice jumps the timecode
+        tracker.observe(&frame("11:30:00:00"), &metrics);
+        assert_eq!(discontinuities(), 1.0);
+        tracker.observe(&frame("11:30:00:01"), &metrics);
+        assert_eq!(discontinuities(), 1.0);
+    }
+}
@@ -0,0 +1,70 @@
+// stream/timestamps.rs
+
+use std::collections::HashMap;
+
+/// Period of the 33 bit MPEG-TS timestamps at 90 kHz, about 26.5 hours
+pub const PTS_WRAP: f64 = (1u64 << 33) as f64 / 90_000.0;
+
+/// Undoes the rollover of 33 bit MPEG-TS timestamps, so long running monitors
+/// see steadily increasing times instead of a jump back to zero once a day.
+/// Every timestamp is placed in the wrap period closest to the previous one of
+/// its stream, which also keeps reordered timestamps around the rollover in
+/// order.
+#[derive(Default)]
+pub struct TimestampUnwrapper {
+    /// Last unwrapped time and the offset added to get it, per stream
+    streams: HashMap<String, (f64, f64)>,
+}
+
+impl TimestampUnwrapper {
+    pub fn unwrap(&mut self, stream_id: &str, time: f64) -> f64 {
+        let Some((last, offset)) = self.streams.get_mut(stream_id) else {
+            self.streams.insert(stream_id.to_string(), (time, 0.0));
+            return time;
+        };
+
+        let mut unwrapped = time + *offset;
+        while unwrapped - *last < -PTS_WRAP / 2.0 {
+            unwrapped += PTS_WRAP;
+        }
+        while unwrapped - *last > PTS_WRAP / 2.0 {
+            unwrapped -= PTS_WRAP;
+        }
+        *offset = unwrapped - time;
+        *last = unwrapped;
+        unwrapped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unwrap_rollover() {
+        let mut unwrapper = TimestampUnwrapper::default();
+        let before = PTS_WRAP - 0.08;
+        assert_eq!(unwrapper.unwrap("0", before), before);
+
+        // The counter rolls over to zero
+        let after = unwrapper.unwrap("0", 0.04);
+        assert!((after - (PTS_WRAP + 0.04)).abs() < 1e-6);
+
+        // A reordered timestamp from before the rollover stays before it
+        let reordered = unwrapper.unwrap("0", PTS_WRAP - 0.04);
+        assert!((reordered - (PTS_WRAP - 0.04)).abs() < 1e-6);
+        let next = unwrapper.unwrap("0", 0.08);
+        assert!((next - (PTS_WRAP + 0.08)).abs() < 1e-6);
+
+        // Streams are unwrapped independently
+        assert_eq!(unwrapper.unwrap("1", 0.5), 0.5);
+    }
+
+    #[test]
+    fn test_small_jumps_are_kept() {
+        let mut unwrapper = TimestampUnwrapper::default();
+        unwrapper.unwrap("0", 3600.0);
+        // A discontinuity well below half the wrap period is left alone
+        assert_eq!(unwrapper.unwrap("0", 10.0), 10.0);
+    }
+}
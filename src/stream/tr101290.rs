@@ -0,0 +1,474 @@
+// stream/tr101290.rs
+//
+// Checks of the transport stream after the first and second priority of ETSI
+// TR 101 290. Time is taken from the PCRs of the first PID carrying them, so
+// the repetition checks hold for files read faster than realtime too.
+
+use crate::metrics::StreamMetrics;
+use crate::stream::pcr::{PCR_CLOCK, parse_pcr, pcr_delta};
+use crate::stream::psi::{CAT_PID, CAT_TABLE_ID, PAT_PID, PAT_TABLE_ID, PsiTracker, SectionSeen};
+use crate::stream::transport::{NULL_PID, payload, payload_unit_start, pid};
+use std::collections::HashMap;
+
+/// Longest PAT and PMT repetition period
+const MAX_PSI_INTERVAL: f64 = 0.5;
+/// Longest silence of an elementary stream the PMT announces
+const MAX_PID_INTERVAL: f64 = 5.0;
+const MAX_PCR_INTERVAL: f64 = 0.04;
+/// Longer PCR gaps need the discontinuity indicator
+const MAX_PCR_GAP: f64 = 0.1;
+const MAX_PTS_INTERVAL: f64 = 0.7;
+/// Larger PCR steps of the reference PID do not advance the clock
+const MAX_CLOCK_STEP: f64 = 10.0;
+
+/// The checks and their priority
+pub const CHECKS: [(&str, &str); 11] = [
+    ("ts_sync_loss", "1"),
+    ("pat_error", "1"),
+    ("continuity_count_error", "1"),
+    ("pmt_error", "1"),
+    ("pid_error", "1"),
+    ("transport_error", "2"),
+    ("crc_error", "2"),
+    ("pcr_repetition_error", "2"),
+    ("pcr_discontinuity_indicator_error", "2"),
+    ("pts_error", "2"),
+    ("cat_error", "2"),
+];
+
+/// Creates the series of every check, so they read 0 before the first error
+pub fn register_checks(metrics: &StreamMetrics) {
+    for (check, priority) in CHECKS {
+        metrics
+            .tr101290_errors
+            .with_label_values(&[priority, check]);
+    }
+}
+
+fn count(metrics: &StreamMetrics, check: &str) {
+    if let Some((check, priority)) = CHECKS.iter().find(|(name, _)| *name == check) {
+        metrics
+            .tr101290_errors
+            .with_label_values(&[priority, check])
+            .inc();
+    }
+}
+
+fn scrambled(packet: &[u8]) -> bool {
+    packet[3] & 0xc0 != 0
+}
+
+fn discontinuity(packet: &[u8]) -> bool {
+    packet[3] & 0x20 != 0 && packet[4] > 0 && packet[5] & 0x80 != 0
+}
+
+/// Whether the packet starts a PES packet with a PTS
+fn starts_pes_with_pts(packet: &[u8]) -> bool {
+    if !payload_unit_start(packet) {
+        return false;
+    }
+    matches!(
+        payload(packet),
+        Some([0x00, 0x00, 0x01, stream_id, _, _, _, flags, ..])
+            // Streams without the optional PES header, e.g. padding
+            if !matches!(stream_id, 0xbc | 0xbe | 0xbf | 0xf0 | 0xf1 | 0xf2 | 0xf8 | 0xff)
+                && flags & 0x80 != 0
+    )
+}
+
+struct Continuity {
+    counter: u8,
+    /// Whether the last packet repeated its predecessor
+    repeated: bool,
+}
+
+/// Runs the checks on the packets of one transport stream
+#[derive(Default)]
+pub struct Tr101290Checker {
+    /// PID and last PCR of the PID the clock follows
+    reference: Option<(u16, u64)>,
+    /// Seconds since the first reference PCR
+    now: f64,
+    continuity: HashMap<u16, Continuity>,
+    pcrs: HashMap<u16, u64>,
+    last_pat: f64,
+    last_pmt: HashMap<u16, f64>,
+    last_packet: HashMap<u16, f64>,
+    last_pts: HashMap<u16, f64>,
+    cat_present: bool,
+    /// Scrambled packets without a CAT are reported once until one arrives
+    cat_missing_reported: bool,
+}
+
+impl Tr101290Checker {
+    /// Packets arriving out of alignment lost the sync
+    pub fn sync_lost(&mut self, metrics: &StreamMetrics) {
+        count(metrics, "ts_sync_loss");
+        // Counters around the gap cannot be compared
+        self.continuity.clear();
+    }
+
+    /// Observes a packet along with the PSI sections it completed
+    pub fn observe(
+        &mut self,
+        packet: &[u8],
+        sections: &[SectionSeen],
+        psi: &PsiTracker,
+        metrics: &StreamMetrics,
+    ) {
+        let pid = pid(packet);
+        if packet[1] & 0x80 != 0 {
+            count(metrics, "transport_error");
+        }
+        if pid == NULL_PID {
+            return;
+        }
+        self.last_packet.insert(pid, self.now);
+        self.check_continuity(pid, packet, metrics);
+
+        if scrambled(packet) {
+            if pid == PAT_PID {
+                count(metrics, "pat_error");
+            } else if psi.pmt_pids().any(|pmt| pmt == pid) {
+                count(metrics, "pmt_error");
+            } else if !self.cat_present && !self.cat_missing_reported {
+                count(metrics, "cat_error");
+                self.cat_missing_reported = true;
+            }
+        }
+
+        for section in sections {
+            if !section.crc_ok {
+                count(metrics, "crc_error");
+            }
+            match (section.pid, section.table_id) {
+                (PAT_PID, PAT_TABLE_ID) => self.last_pat = self.now,
+                (PAT_PID, _) => count(metrics, "pat_error"),
+                (CAT_PID, CAT_TABLE_ID) => {
+                    self.cat_present = true;
+                    self.cat_missing_reported = false;
+                }
+                (CAT_PID, _) => count(metrics, "cat_error"),
+                (pid, _) => {
+                    self.last_pmt.insert(pid, self.now);
+                }
+            }
+        }
+
+        if starts_pes_with_pts(packet) {
+            self.last_pts.insert(pid, self.now);
+        }
+        if let Some(sample) = parse_pcr(packet) {
+            self.check_pcr(sample.pid, sample.pcr, sample.discontinuity, metrics);
+            if self.advance_clock(sample.pid, sample.pcr, sample.discontinuity) {
+                self.check_intervals(psi, metrics);
+            }
+        }
+    }
+
+    fn check_continuity(&mut self, pid: u16, packet: &[u8], metrics: &StreamMetrics) {
+        let counter = packet[3] & 0x0f;
+        let has_payload = packet[3] & 0x10 != 0;
+        let Some(state) = self.continuity.get_mut(&pid) else {
+            self.continuity.insert(
+                pid,
+                Continuity {
+                    counter,
+                    repeated: false,
+                },
+            );
+            return;
+        };
+        let ok = if discontinuity(packet) {
+            true
+        } else if !has_payload {
+            // The counter only advances with a payload
+            counter == state.counter
+        } else if counter == state.counter {
+            // A packet may be sent twice, but not three times
+            !state.repeated
+        } else {
+            counter == (state.counter + 1) & 0x0f
+        };
+        if !ok {
+            count(metrics, "continuity_count_error");
+        }
+        state.repeated = has_payload && counter == state.counter && ok;
+        state.counter = counter;
+    }
+
+    fn check_pcr(&mut self, pid: u16, pcr: u64, discontinuity: bool, metrics: &StreamMetrics) {
+        let previous = self.pcrs.insert(pid, pcr);
+        if discontinuity {
+            return;
+        }
+        if let Some(previous) = previous {
+            let interval = pcr_delta(previous, pcr) as f64 / PCR_CLOCK;
+            if interval > MAX_PCR_GAP {
+                count(metrics, "pcr_discontinuity_indicator_error");
+            } else if interval > MAX_PCR_INTERVAL {
+                count(metrics, "pcr_repetition_error");
+            }
+        }
+    }
+
+    /// Advances the clock on the PCRs of the reference PID, returning whether
+    /// it moved
+    fn advance_clock(&mut self, pid: u16, pcr: u64, discontinuity: bool) -> bool {
+        match self.reference {
+            None => {
+                self.reference = Some((pid, pcr));
+                false
+            }
+            Some((reference, previous)) if reference == pid => {
+                self.reference = Some((pid, pcr));
+                let step = pcr_delta(previous, pcr) as f64 / PCR_CLOCK;
+                if discontinuity || step > MAX_CLOCK_STEP {
+                    return false;
+                }
+                self.now += step;
+                true
+            }
+            Some(_) => false,
+        }
+    }
+
+    fn check_intervals(&mut self, psi: &PsiTracker, metrics: &StreamMetrics) {
+        let now = self.now;
+        if now - self.last_pat > MAX_PSI_INTERVAL {
+            count(metrics, "pat_error");
+            self.last_pat = now;
+        }
+        for pmt in psi.pmt_pids() {
+            let last = self.last_pmt.entry(pmt).or_insert(now);
+            if now - *last > MAX_PSI_INTERVAL {
+                count(metrics, "pmt_error");
+                *last = now;
+            }
+        }
+        for stream in psi.elementary_pids() {
+            let last = self.last_packet.entry(stream).or_insert(now);
+            if now - *last > MAX_PID_INTERVAL {
+                count(metrics, "pid_error");
+                *last = now;
+            }
+        }
+        // Streams dropped from the PMT no longer need their PTS
+        for stream in psi.elementary_pids() {
+            let Some(last) = self.last_pts.get_mut(&stream) else {
+                continue;
+            };
+            if now - *last > MAX_PTS_INTERVAL {
+                count(metrics, "pts_error");
+                *last = now;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::psi::{PMT_TABLE_ID, crc32_mpeg2};
+    use crate::stream::transport::{SYNC_BYTE, TS_PACKET_SIZE};
+    use prometheus::Registry;
+
+    fn errors(metrics: &StreamMetrics, check: &str) -> f64 {
+        let (_, priority) = CHECKS.iter().find(|(name, _)| *name == check).unwrap();
+        metrics
+            .tr101290_errors
+            .with_label_values(&[priority, check])
+            .get()
+    }
+
+    fn packet(pid: u16, counter: u8) -> [u8; TS_PACKET_SIZE] {
+        let mut packet = [0xffu8; TS_PACKET_SIZE];
+        packet[..4].copy_from_slice(&[SYNC_BYTE, (pid >> 8) as u8, pid as u8, 0x10 | counter]);
+        packet
+    }
+
+    fn pcr_packet(pid: u16, counter: u8, pcr: u64) -> [u8; TS_PACKET_SIZE] {
+        let mut packet = packet(pid, counter);
+        let base = pcr / 300;
+        packet[3] = 0x20 | counter;
+        packet[4..12].copy_from_slice(&[
+            183,
+            0x10,
+            (base >> 25) as u8,
+            (base >> 17) as u8,
+            (base >> 9) as u8,
+            (base >> 1) as u8,
+            ((base & 1) << 7) as u8 | 0x7e,
+            0,
+        ]);
+        packet
+    }
+
+    /// A packet starting a PES with a PTS
+    fn pes_packet(pid: u16, counter: u8) -> [u8; TS_PACKET_SIZE] {
+        let mut packet = packet(pid, counter);
+        packet[1] |= 0x40;
+        packet[4..12].copy_from_slice(&[0x00, 0x00, 0x01, 0xe0, 0x00, 0x00, 0x80, 0x80]);
+        packet
+    }
+
+    /// A packet of `pid` carrying `section`, its CRC appended
+    fn section_packet(pid: u16, mut section: Vec<u8>) -> [u8; TS_PACKET_SIZE] {
+        section.extend_from_slice(&crc32_mpeg2(&section).to_be_bytes());
+        let mut packet = packet(pid, 0);
+        packet[1] |= 0x40;
+        packet[4] = 0;
+        packet[5..5 + section.len()].copy_from_slice(&section);
+        packet
+    }
+
+    /// Sends the PAT of one program with its PMT on 0x100, announcing the
+    /// video `streams`
+    fn announce(
+        streams: &[u16],
+        version: u8,
+        psi: &mut PsiTracker,
+        checker: &mut Tr101290Checker,
+        metrics: &StreamMetrics,
+    ) {
+        let pat = vec![
+            0x00, 0xb0, 13, 0x00, 0x01, 0xc1, 0, 0, 0x00, 0x01, 0xe1, 0x00,
+        ];
+        let length = 13 + 5 * streams.len() as u8;
+        let mut pmt = vec![
+            PMT_TABLE_ID,
+            0xb0,
+            length,
+            0x00,
+            0x01,
+            0xc1 | (version << 1),
+            0,
+            0,
+            0xe1,
+            0x01,
+            0xf0,
+            0x00,
+        ];
+        for stream in streams {
+            pmt.extend_from_slice(&[0x1b, 0xe0 | (stream >> 8) as u8, *stream as u8, 0xf0, 0]);
+        }
+        for packet in [section_packet(PAT_PID, pat), section_packet(0x100, pmt)] {
+            let seen = psi.observe(&packet, metrics);
+            checker.observe(&packet, &seen, psi, metrics);
+        }
+    }
+
+    #[test]
+    fn test_continuity_count() {
+        let metrics = StreamMetrics::new(&Registry::new(), &HashMap::new()).unwrap();
+        let psi = PsiTracker::default();
+        let mut checker = Tr101290Checker::default();
+        // 14, 15, 0, a repetition, 1, then 3 skips a packet and 3 is sent
+        // three times
+        for counter in [14, 15, 0, 0, 1, 3, 3, 3] {
+            checker.observe(&packet(0x100, counter), &[], &psi, &metrics);
+        }
+        assert_eq!(errors(&metrics, "continuity_count_error"), 2.0);
+
+        // The null PID carries no counter, the indicator restarts it
+        checker.observe(&packet(NULL_PID, 9), &[], &psi, &metrics);
+        let mut restart = pcr_packet(0x100, 9, 0);
+        restart[5] |= 0x80;
+        checker.observe(&restart, &[], &psi, &metrics);
+        assert_eq!(errors(&metrics, "continuity_count_error"), 2.0);
+
+        checker.sync_lost(&metrics);
+        checker.observe(&packet(0x100, 5), &[], &psi, &metrics);
+        assert_eq!(errors(&metrics, "continuity_count_error"), 2.0);
+        assert_eq!(errors(&metrics, "ts_sync_loss"), 1.0);
+    }
+
+    #[test]
+    fn test_repetition_checks() {
+        let metrics = StreamMetrics::new(&Registry::new(), &HashMap::new()).unwrap();
+        let mut psi = PsiTracker::default();
+        let mut checker = Tr101290Checker::default();
+        register_checks(&metrics);
+        assert_eq!(errors(&metrics, "pts_error"), 0.0);
+
+        announce(&[0x101], 0, &mut psi, &mut checker, &metrics);
+        checker.observe(&pes_packet(0x101, 0), &[], &psi, &metrics);
+
+        // PCRs every 30 ms for a second, then one 60 ms and one 200 ms apart
+        let mut pcr = 0;
+        for i in 0..34u8 {
+            checker.observe(&pcr_packet(0x101, (i + 1) & 0x0f, pcr), &[], &psi, &metrics);
+            pcr += 810_000;
+        }
+        assert_eq!(errors(&metrics, "pcr_repetition_error"), 0.0);
+        pcr += 810_000;
+        checker.observe(&pcr_packet(0x101, 3, pcr), &[], &psi, &metrics);
+        pcr += 5_400_000;
+        checker.observe(&pcr_packet(0x101, 4, pcr), &[], &psi, &metrics);
+        assert_eq!(errors(&metrics, "pcr_repetition_error"), 1.0);
+        assert_eq!(errors(&metrics, "pcr_discontinuity_indicator_error"), 1.0);
+
+        // The PAT, the PMT and the PTS are all overdue by now
+        assert!(errors(&metrics, "pat_error") >= 1.0);
+        assert!(errors(&metrics, "pmt_error") >= 1.0);
+        assert!(errors(&metrics, "pts_error") >= 1.0);
+        assert_eq!(errors(&metrics, "pid_error"), 0.0);
+        assert_eq!(errors(&metrics, "crc_error"), 0.0);
+    }
+
+    #[test]
+    fn test_pts_of_dropped_streams() {
+        let metrics = StreamMetrics::new(&Registry::new(), &HashMap::new()).unwrap();
+        let mut psi = PsiTracker::default();
+        let mut checker = Tr101290Checker::default();
+        register_checks(&metrics);
+
+        // 0x102 is dropped from the PMT after its first PES
+        announce(&[0x101, 0x102], 0, &mut psi, &mut checker, &metrics);
+        checker.observe(&pes_packet(0x101, 0), &[], &psi, &metrics);
+        checker.observe(&pes_packet(0x102, 0), &[], &psi, &metrics);
+        announce(&[0x101], 1, &mut psi, &mut checker, &metrics);
+
+        // Two seconds of PCRs on 0x1ff, with a PES of 0x101 every 300 ms
+        let mut pcr = 0;
+        for i in 1..=67u8 {
+            if i % 10 == 0 {
+                checker.observe(&pes_packet(0x101, i & 0x0f), &[], &psi, &metrics);
+            }
+            checker.observe(&pcr_packet(0x1ff, i & 0x0f, pcr), &[], &psi, &metrics);
+            pcr += 810_000;
+        }
+        assert_eq!(errors(&metrics, "pts_error"), 0.0);
+    }
+
+    #[test]
+    fn test_section_checks() {
+        let metrics = StreamMetrics::new(&Registry::new(), &HashMap::new()).unwrap();
+        let psi = PsiTracker::default();
+        let mut checker = Tr101290Checker::default();
+        let sections = [
+            SectionSeen {
+                pid: PAT_PID,
+                table_id: PMT_TABLE_ID,
+                crc_ok: true,
+            },
+            SectionSeen {
+                pid: CAT_PID,
+                table_id: CAT_TABLE_ID,
+                crc_ok: false,
+            },
+        ];
+        let mut errored = packet(CAT_PID, 0);
+        errored[1] |= 0x80;
+        checker.observe(&errored, &sections, &psi, &metrics);
+        assert_eq!(errors(&metrics, "pat_error"), 1.0);
+        assert_eq!(errors(&metrics, "crc_error"), 1.0);
+        assert_eq!(errors(&metrics, "transport_error"), 1.0);
+
+        // Scrambled packets are fine once there is a CAT
+        let mut scrambled = packet(0x101, 0);
+        scrambled[3] |= 0x80;
+        checker.observe(&scrambled, &[], &psi, &metrics);
+        assert_eq!(errors(&metrics, "cat_error"), 0.0);
+    }
+}
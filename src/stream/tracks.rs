@@ -0,0 +1,153 @@
+// stream/tracks.rs
+
+use crate::metrics::StreamMetrics;
+use crate::stream::records::{FrameRecord, StreamRecord};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// A track counts as present while it delivered a frame within this time
+const PRESENCE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// ISO 639-2 code for tracks without a language tag
+pub(crate) const UNDETERMINED: &str = "und";
+
+struct AudioTrack {
+    track: String,
+    language: String,
+    last_seen: Option<Instant>,
+}
+
+/// The audio tracks found by the stream probe, numbered in stream order like
+/// ffmpeg's `a:N` stream specifiers, so multi-language feeds can be told apart.
+#[derive(Default)]
+pub struct AudioTracks {
+    tracks: HashMap<String, AudioTrack>,
+    last_check: Option<Instant>,
+}
+
+impl AudioTracks {
+    pub fn insert(&mut self, stream: &StreamRecord) {
+        let language = if stream.language.is_empty() {
+            UNDETERMINED
+        } else {
+            stream.language
+        };
+        let track = AudioTrack {
+            track: self.tracks.len().to_string(),
+            language: language.to_string(),
+            last_seen: None,
+        };
+        self.tracks.insert(stream.stream_id.to_string(), track);
+    }
+
+    fn labels<'a>(&'a self, stream_id: &'a str) -> Option<[&'a str; 3]> {
+        let track = self.tracks.get(stream_id)?;
+        Some([stream_id, &track.track, &track.language])
+    }
+
+    /// Stream id, track number and language of every track, in track order
+    pub fn ordered_labels(&self) -> Vec<[String; 3]> {
+        let mut labels: Vec<_> = self
+            .tracks
+            .iter()
+            .map(|(stream_id, track)| {
+                [
+                    stream_id.clone(),
+                    track.track.clone(),
+                    track.language.clone(),
+                ]
+            })
+            .collect();
+        labels.sort_by_key(|[_, track, _]| track.parse::<usize>().unwrap_or_default());
+        labels
+    }
+
+    pub fn observe_bitrate(&self, stream_id: &str, kbits: f64, metrics: &StreamMetrics) {
+        if let Some(labels) = self.labels(stream_id) {
+            metrics
+                .audio_track_bitrate
+                .with_label_values(&labels)
+                .set(kbits);
+        }
+    }
+
+    /// Counts the frame against its track and refreshes the presence of all
+    /// tracks at most once per second.
+    pub fn observe_frame(&mut self, frame: &FrameRecord, metrics: &StreamMetrics) {
+        self.observe_frame_at(Instant::now(), frame, metrics);
+    }
+
+    fn observe_frame_at(&mut self, now: Instant, frame: &FrameRecord, metrics: &StreamMetrics) {
+        if let Some(labels) = self.labels(frame.stream_id) {
+            metrics.audio_track_frames.with_label_values(&labels).inc();
+        }
+        if let Some(track) = self.tracks.get_mut(frame.stream_id) {
+            track.last_seen = Some(now);
+        }
+
+        if self
+            .last_check
+            .is_some_and(|last| now.duration_since(last) < Duration::from_secs(1))
+        {
+            return;
+        }
+        self.last_check = Some(now);
+        for (stream_id, track) in &self.tracks {
+            let present = track
+                .last_seen
+                .is_some_and(|seen| now.duration_since(seen) < PRESENCE_TIMEOUT);
+            metrics
+                .audio_track_present
+                .with_label_values(&[stream_id, &track.track, &track.language])
+                .set(if present { 1.0 } else { 0.0 });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::lines::Fields;
+    use prometheus::Registry;
+
+    fn audio_frame(stream_id: &'static str) -> FrameRecord<'static> {
+        FrameRecord {
+            media_type: "audio",
+            stream_id,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_audio_track_presence() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let mut tracks = AudioTracks::default();
+        for line in [
+            "stream,index=1,codec_name=aac,codec_type=audio,tag:language=eng",
+            "stream,index=2,codec_name=aac,codec_type=audio,tag:language=deu",
+            "stream,index=3,codec_name=ac3,codec_type=audio",
+        ] {
+            tracks.insert(&StreamRecord::parse(&Fields::split(line)).unwrap());
+        }
+
+        let start = Instant::now();
+        tracks.observe_frame_at(start, &audio_frame("1"), &metrics);
+        tracks.observe_frame_at(start, &audio_frame("2"), &metrics);
+        tracks.observe_frame_at(start, &audio_frame("2"), &metrics);
+        let present =
+            |labels: [&str; 3]| metrics.audio_track_present.with_label_values(&labels).get();
+        assert_eq!(present(["1", "0", "eng"]), 1.0);
+        assert_eq!(present(["3", "2", "und"]), 0.0);
+        let frames = metrics
+            .audio_track_frames
+            .with_label_values(&["2", "1", "deu"]);
+        assert_eq!(frames.get(), 2.0);
+
+        // The German track goes silent
+        let later = start + PRESENCE_TIMEOUT;
+        tracks.observe_frame_at(later, &audio_frame("1"), &metrics);
+        assert_eq!(present(["1", "0", "eng"]), 1.0);
+        assert_eq!(present(["2", "1", "deu"]), 0.0);
+    }
+}
@@ -0,0 +1,252 @@
+// stream/transport.rs
+
+use crate::metrics::StreamMetrics;
+use crate::stream::lines::for_each_line;
+use crate::stream::pcr::PcrTracker;
+use crate::stream::process::{spawn, terminate};
+use crate::stream::programs::ProgramNames;
+use crate::stream::psi::PsiTracker;
+use crate::stream::tr101290::{self, Tr101290Checker};
+use anyhow::{Context, Result};
+use std::io::{BufReader, Read};
+use std::process::{Child, Command};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+pub const TS_PACKET_SIZE: usize = 188;
+pub const SYNC_BYTE: u8 = 0x47;
+/// PID of the null packets stuffing a constant bitrate multiplex
+pub const NULL_PID: u16 = 0x1fff;
+
+/// Packets the null packet ratio is taken over
+const NULL_RATIO_WINDOW: u32 = 5000;
+
+pub fn pid(packet: &[u8]) -> u16 {
+    (u16::from(packet[1] & 0x1f) << 8) | u16::from(packet[2])
+}
+
+/// Whether a PES packet or PSI section starts in the packet
+pub fn payload_unit_start(packet: &[u8]) -> bool {
+    packet[1] & 0x40 != 0
+}
+
+/// The payload after the adaptation field, if the packet carries one
+pub fn payload(packet: &[u8]) -> Option<&[u8]> {
+    let control = (packet[3] >> 4) & 0x03;
+    if control & 0x01 == 0 {
+        return None;
+    }
+    let start = if control & 0x02 != 0 {
+        5 + usize::from(packet[4])
+    } else {
+        4
+    };
+    packet.get(start..).filter(|payload| !payload.is_empty())
+}
+
+/// The analyses run on every packet of the transport stream
+pub struct TransportTracker {
+    pcr: PcrTracker,
+    psi: PsiTracker,
+    tr101290: Tr101290Checker,
+    /// Packets and null packets of the current null ratio window
+    packets: u32,
+    null_packets: u32,
+}
+
+impl TransportTracker {
+    pub fn new(names: ProgramNames) -> Self {
+        Self {
+            pcr: PcrTracker::default(),
+            psi: PsiTracker::new(names),
+            tr101290: Tr101290Checker::default(),
+            packets: 0,
+            null_packets: 0,
+        }
+    }
+
+    pub fn observe(
+        &mut self,
+        packet: &[u8],
+        position: u64,
+        arrival: Option<f64>,
+        metrics: &StreamMetrics,
+    ) {
+        self.pcr.observe(packet, position, arrival, metrics);
+        let sections = self.psi.observe(packet, metrics);
+        self.tr101290.observe(packet, &sections, &self.psi, metrics);
+
+        self.packets += 1;
+        if pid(packet) == NULL_PID {
+            self.null_packets += 1;
+        }
+        if self.packets == NULL_RATIO_WINDOW {
+            metrics
+                .ts_null_packet_ratio
+                .with_label_values(&[])
+                .set(f64::from(self.null_packets) / f64::from(self.packets));
+            self.packets = 0;
+            self.null_packets = 0;
+        }
+    }
+
+    pub fn sync_lost(&mut self, metrics: &StreamMetrics) {
+        self.tr101290.sync_lost(metrics);
+    }
+}
+
+/// Reads the next transport stream packet, skipping bytes until the sync
+/// byte if the stream lost alignment. Returns the number of bytes skipped,
+/// `None` at the end of the stream.
+fn read_packet(reader: &mut impl Read, packet: &mut [u8; TS_PACKET_SIZE]) -> Result<Option<u64>> {
+    let mut skipped = 0;
+    loop {
+        match reader.read_exact(&mut packet[..1]) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        if packet[0] == SYNC_BYTE {
+            break;
+        }
+        skipped += 1;
+    }
+    match reader.read_exact(&mut packet[1..]) {
+        Ok(()) => Ok(Some(skipped)),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Feeds the packets of a raw transport stream to the tracker until it ends
+fn process_transport_stream(
+    mut reader: impl Read,
+    live: bool,
+    metrics: &StreamMetrics,
+    tracker: &mut TransportTracker,
+) -> Result<()> {
+    let started = Instant::now();
+    let mut packet = [0u8; TS_PACKET_SIZE];
+    let mut position = 0;
+    while let Some(skipped) = read_packet(&mut reader, &mut packet)? {
+        if skipped > 0 {
+            tracker.sync_lost(metrics);
+            position += skipped;
+        }
+        let arrival = live.then(|| started.elapsed().as_secs_f64());
+        tracker.observe(&packet, position, arrival, metrics);
+        position += TS_PACKET_SIZE as u64;
+    }
+    Ok(())
+}
+
+/// A running ffmpeg passing the raw transport stream through for the
+/// transport stream analysis. It is terminated when dropped, like the
+/// content analysis session.
+pub struct TransportSession {
+    child: Child,
+    grace: Duration,
+}
+
+impl TransportSession {
+    /// `live` inputs arrive in realtime, so their arrival times measure
+    /// jitter. `names` label the per program metrics. `grace` is the time
+    /// ffmpeg gets to exit before it is killed.
+    pub fn start(
+        mut cmd: Command,
+        metrics: StreamMetrics,
+        live: bool,
+        names: ProgramNames,
+        grace: Duration,
+    ) -> Result<Self> {
+        let mut child =
+            spawn(&mut cmd).context("Failed to spawn transport stream analysis ffmpeg process")?;
+        let stdout = child.stdout.take().context("Failed to capture stdout")?;
+        let stderr = child.stderr.take().context("Failed to capture stderr")?;
+
+        metrics.pcr_interval.reset();
+        metrics.pcr_accuracy.reset();
+        metrics.pcr_jitter.reset();
+        metrics.ts_program_present.reset();
+        metrics.ts_null_packet_ratio.with_label_values(&[]).set(0.0);
+        tr101290::register_checks(&metrics);
+        thread::spawn(move || {
+            let mut tracker = TransportTracker::new(names);
+            let reader = BufReader::with_capacity(TS_PACKET_SIZE * 7, stdout);
+            if let Err(e) = process_transport_stream(reader, live, &metrics, &mut tracker) {
+                warn!(?e, "Error processing transport stream analysis output");
+            }
+        });
+        thread::spawn(move || {
+            let _ = for_each_line(BufReader::new(stderr), |line| {
+                warn!("Transport stream analysis ffmpeg: {}", line);
+                Ok(())
+            });
+        });
+        Ok(Self { child, grace })
+    }
+}
+
+impl Drop for TransportSession {
+    fn drop(&mut self) {
+        terminate(&mut self.child, self.grace);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::Registry;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_packet_fields() {
+        let mut packet = [0xffu8; TS_PACKET_SIZE];
+        packet[..6].copy_from_slice(&[SYNC_BYTE, 0x41, 0x00, 0x30, 1, 0x00]);
+        assert_eq!(pid(&packet), 0x100);
+        assert!(payload_unit_start(&packet));
+        // One byte of adaptation field after its length
+        assert_eq!(payload(&packet).unwrap().len(), TS_PACKET_SIZE - 6);
+        packet[3] = 0x20;
+        assert_eq!(payload(&packet), None);
+    }
+
+    #[test]
+    fn test_null_packet_ratio() {
+        let metrics = StreamMetrics::new(&Registry::new(), &HashMap::new()).unwrap();
+        let mut tracker = TransportTracker::new(ProgramNames::default());
+        let mut packet = [0xffu8; TS_PACKET_SIZE];
+        for i in 0..NULL_RATIO_WINDOW {
+            // Every fourth packet is stuffing
+            let pid = if i % 4 == 0 { NULL_PID } else { 0x100 };
+            packet[..4].copy_from_slice(&[SYNC_BYTE, (pid >> 8) as u8, pid as u8, 0x10]);
+            tracker.observe(&packet, 0, None, &metrics);
+            if i == 0 {
+                assert_eq!(
+                    metrics.ts_null_packet_ratio.with_label_values(&[]).get(),
+                    0.0
+                );
+            }
+        }
+        assert_eq!(
+            metrics.ts_null_packet_ratio.with_label_values(&[]).get(),
+            0.25
+        );
+    }
+
+    #[test]
+    fn test_read_packet_resyncs() {
+        let mut packet = [0u8; TS_PACKET_SIZE];
+        packet[..3].copy_from_slice(&[SYNC_BYTE, 0x01, 0x00]);
+        let mut stream = vec![0x00, 0x12];
+        stream.extend_from_slice(&packet);
+        stream.extend_from_slice(&[SYNC_BYTE, 0x01]);
+        let mut reader = stream.as_slice();
+        let mut buffer = [0u8; TS_PACKET_SIZE];
+        assert_eq!(read_packet(&mut reader, &mut buffer).unwrap(), Some(2));
+        assert_eq!(pid(&buffer), 0x100);
+        // A truncated packet ends the stream
+        assert_eq!(read_packet(&mut reader, &mut buffer).unwrap(), None);
+    }
+}
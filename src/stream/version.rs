@@ -0,0 +1,292 @@
+// stream/version.rs
+
+use anyhow::{Context, Result};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::process::Command;
+use tracing::warn;
+
+#[cfg(windows)]
+use std::os::windows::process::CommandExt;
+
+/// The oldest ffprobe release whose output we parse
+pub const MIN_SUPPORTED: FFprobeVersion = FFprobeVersion::Release { major: 4, minor: 0 };
+
+/// What `ffprobe -version`, `-protocols` and `-demuxers` report about the
+/// binary
+#[derive(Debug, Clone, Default)]
+pub struct FFprobeBuild {
+    pub version: FFprobeVersion,
+    /// The version as printed, e.g. `6.1.1-3ubuntu5`
+    pub release: String,
+    /// The `./configure` arguments of the build
+    pub configuration: String,
+    pub components: FFprobeComponents,
+}
+
+impl FFprobeBuild {
+    pub fn detect(ffprobe_path: &str) -> Result<Self> {
+        let output = run(ffprobe_path, "-version")?;
+        let release = output
+            .lines()
+            .next()
+            .and_then(|line| line.split_whitespace().nth(2))
+            .unwrap_or("unknown")
+            .to_string();
+        let configuration = output
+            .lines()
+            .find_map(|line| line.strip_prefix("configuration:"))
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        // Without a list nothing is refused, ffprobe still reports what it lacks
+        let components = FFprobeComponents {
+            protocols: run(ffprobe_path, "-protocols")
+                .map(|output| parse_protocols(&output))
+                .inspect_err(|e| warn!("Failed to list the ffprobe protocols: {:#}", e))
+                .ok(),
+            demuxers: run(ffprobe_path, "-demuxers")
+                .map(|output| parse_demuxers(&output))
+                .inspect_err(|e| warn!("Failed to list the ffprobe demuxers: {:#}", e))
+                .ok(),
+        };
+        Ok(Self {
+            version: FFprobeVersion::parse(&output),
+            release,
+            configuration,
+            components,
+        })
+    }
+}
+
+/// Protocols and demuxers compiled into ffprobe. A list that could not be
+/// read refuses nothing.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FFprobeComponents {
+    pub protocols: Option<BTreeSet<String>>,
+    pub demuxers: Option<BTreeSet<String>>,
+}
+
+impl FFprobeComponents {
+    /// Fails for an input whose protocol or demuxer is missing, which ffprobe
+    /// would only report on every reconnect
+    pub fn check(&self, input: &str, protocol: Option<&str>, demuxer: Option<&str>) -> Result<()> {
+        let missing = |list: &Option<BTreeSet<String>>, name: Option<&str>| matches!((list, name), (Some(list), Some(name)) if !list.contains(name));
+        if missing(&self.protocols, protocol) {
+            anyhow::bail!(
+                "ffprobe was built without the {} protocol needed by {}, see `ffprobe -protocols`",
+                protocol.unwrap_or_default(),
+                input
+            );
+        }
+        if missing(&self.demuxers, demuxer) {
+            anyhow::bail!(
+                "ffprobe was built without the {} demuxer needed by {}, see `ffprobe -demuxers`",
+                demuxer.unwrap_or_default(),
+                input
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Runs ffprobe with a single informational option and returns its stdout
+fn run(ffprobe_path: &str, option: &str) -> Result<String> {
+    let mut cmd = Command::new(ffprobe_path);
+
+    #[cfg(windows)]
+    {
+        cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
+    }
+
+    let output = cmd
+        .args(["-hide_banner", option])
+        .output()
+        .with_context(|| format!("Failed to run {} {}", ffprobe_path, option))?;
+    if !output.status.success() {
+        anyhow::bail!("{} {} failed with {}", ffprobe_path, option, output.status);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The input protocols of `ffprobe -protocols`, listed after `Input:`
+fn parse_protocols(output: &str) -> BTreeSet<String> {
+    output
+        .lines()
+        .skip_while(|line| line.trim() != "Input:")
+        .skip(1)
+        .take_while(|line| line.trim() != "Output:")
+        .map(|line| line.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// The names of `ffprobe -demuxers`, after the `--` separating the legend.
+/// A line is ` D  mpegts  MPEG-TS (MPEG-2 Transport Stream)`, newer releases
+/// add a `d` flag for devices and several names share a line, e.g.
+/// `video4linux2,v4l2`.
+fn parse_demuxers(output: &str) -> BTreeSet<String> {
+    output
+        .lines()
+        .skip_while(|line| !line.trim().starts_with("--"))
+        .skip(1)
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let flags = fields.next()?;
+            if !flags.contains('D') {
+                return None;
+            }
+            fields.find(|field| *field != "d")
+        })
+        .flat_map(|names| names.split(','))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Version of the ffprobe binary, as reported by `ffprobe -version`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FFprobeVersion {
+    Release {
+        major: u32,
+        minor: u32,
+    },
+    /// Git snapshots and vendor builds without a release number; treated as
+    /// the newest release
+    #[default]
+    Unknown,
+}
+
+impl FFprobeVersion {
+    /// Parses the banner line, e.g. `ffprobe version 4.2.7-0ubuntu0.1 Copyright ...`,
+    /// `ffprobe version n6.1.1` or `ffprobe version N-113012-g4d6f3c1`.
+    pub fn parse(output: &str) -> Self {
+        let Some(version) = output
+            .lines()
+            .next()
+            .and_then(|line| line.split_whitespace().nth(2))
+        else {
+            return Self::Unknown;
+        };
+
+        let version = version.strip_prefix('n').unwrap_or(version);
+        let mut numbers = version
+            .split(|c: char| !c.is_ascii_digit())
+            .map(|part| part.parse::<u32>());
+        match (numbers.next(), numbers.next()) {
+            (Some(Ok(major)), Some(Ok(minor)))
+                if version.starts_with(|c: char| c.is_ascii_digit()) =>
+            {
+                Self::Release { major, minor }
+            }
+            _ => Self::Unknown,
+        }
+    }
+
+    pub fn is_supported(&self) -> bool {
+        match MIN_SUPPORTED {
+            Self::Release { major, minor } => self.at_least(major, minor),
+            Self::Unknown => true,
+        }
+    }
+
+    /// Whether this is the given release or a newer one
+    pub fn at_least(&self, min_major: u32, min_minor: u32) -> bool {
+        match self {
+            Self::Release { major, minor } => (*major, *minor) >= (min_major, min_minor),
+            Self::Unknown => true,
+        }
+    }
+}
+
+impl fmt::Display for FFprobeVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Release { major, minor } => write!(f, "{}.{}", major, minor),
+            Self::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_versions() {
+        let cases = [
+            (
+                "ffprobe version 4.2.7-0ubuntu0.1 Copyright (c) 2007-2022 the FFmpeg developers",
+                FFprobeVersion::Release { major: 4, minor: 2 },
+            ),
+            (
+                "ffprobe version 5.1.4-0+deb12u1 Copyright (c) 2007-2023 the FFmpeg developers",
+                FFprobeVersion::Release { major: 5, minor: 1 },
+            ),
+            (
+                "ffprobe version n6.1.1 Copyright (c) 2007-2023 the FFmpeg developers",
+                FFprobeVersion::Release { major: 6, minor: 1 },
+            ),
+            (
+                "ffprobe version 7.0.1-static https://johnvansickle.com/ffmpeg/",
+                FFprobeVersion::Release { major: 7, minor: 0 },
+            ),
+            (
+                "ffprobe version N-113012-g4d6f3c1 Copyright (c) 2007-2024 the FFmpeg developers",
+                FFprobeVersion::Unknown,
+            ),
+            ("", FFprobeVersion::Unknown),
+        ];
+        for (banner, expected) in cases {
+            assert_eq!(FFprobeVersion::parse(banner), expected, "{}", banner);
+        }
+    }
+
+    #[test]
+    fn test_parse_components() {
+        let protocols =
+            "Supported file protocols:\nInput:\n  file\n  http\n  udp\nOutput:\n  file\n  rtmp\n";
+        assert_eq!(
+            parse_protocols(protocols),
+            BTreeSet::from(["file".to_string(), "http".to_string(), "udp".to_string()])
+        );
+
+        let demuxers = "Demuxers:\n D. = Demuxing supported\n .E = Muxing supported\n ..d = Is a device\n ---\n D d alsa            ALSA audio input\n D   hls             Apple HTTP Live Streaming\n D d video4linux2,v4l2 Video4Linux2 device grab\n";
+        let demuxers = parse_demuxers(demuxers);
+        assert_eq!(
+            demuxers,
+            ["alsa", "hls", "v4l2", "video4linux2"]
+                .into_iter()
+                .map(str::to_string)
+                .collect()
+        );
+
+        let components = FFprobeComponents {
+            protocols: Some(parse_protocols(protocols)),
+            demuxers: Some(demuxers),
+        };
+        assert!(components.check("udp://:1234", Some("udp"), None).is_ok());
+        let error = components
+            .check("srt://:9000", Some("srt"), None)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("without the srt protocol"), "{}", error);
+        assert!(
+            components
+                .check("http://a/b.mpd", Some("http"), Some("dash"))
+                .is_err()
+        );
+        // Unknown lists refuse nothing
+        assert!(
+            FFprobeComponents::default()
+                .check("srt://:9000", Some("srt"), Some("dash"))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_is_supported() {
+        assert!(FFprobeVersion::Release { major: 4, minor: 2 }.is_supported());
+        assert!(FFprobeVersion::Unknown.is_supported());
+        assert!(!FFprobeVersion::Release { major: 3, minor: 4 }.is_supported());
+    }
+}
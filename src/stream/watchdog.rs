@@ -0,0 +1,242 @@
+// stream/watchdog.rs
+
+use crate::events::EventRecorder;
+use crate::metrics::StreamMetrics;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Marks an `Activity` without any record yet
+const NEVER: u64 = u64::MAX;
+
+/// Time of the last ffprobe output record, shared between the stdout reader
+/// and the thread supervising the process.
+#[derive(Clone)]
+pub struct Activity {
+    start: Instant,
+    /// Milliseconds since `start`
+    last: Arc<AtomicU64>,
+    first: Arc<AtomicU64>,
+}
+
+impl Activity {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            last: Arc::new(AtomicU64::new(NEVER)),
+            first: Arc::new(AtomicU64::new(NEVER)),
+        }
+    }
+
+    /// Whether any record arrived since the session started
+    pub fn received(&self) -> bool {
+        self.last.load(Ordering::Relaxed) != NEVER
+    }
+
+    pub fn touch(&self) {
+        let elapsed = self.start.elapsed().as_millis() as u64;
+        self.last.store(elapsed, Ordering::Relaxed);
+        let _ = self
+            .first
+            .compare_exchange(NEVER, elapsed, Ordering::Relaxed, Ordering::Relaxed);
+    }
+
+    /// Time from the start of the session to its first record
+    pub fn first_record(&self) -> Option<Duration> {
+        match self.first.load(Ordering::Relaxed) {
+            NEVER => None,
+            millis => Some(Duration::from_millis(millis)),
+        }
+    }
+
+    pub fn idle(&self) -> Duration {
+        self.idle_at(Instant::now())
+    }
+
+    /// Whether ffprobe ran for `timeout` without a single record, e.g. hung
+    /// analysing an input that never delivers. A timeout of zero disables it.
+    pub fn startup_timed_out(&self, timeout: Duration) -> bool {
+        self.startup_timed_out_at(timeout, Instant::now())
+    }
+
+    fn startup_timed_out_at(&self, timeout: Duration, now: Instant) -> bool {
+        !timeout.is_zero() && !self.received() && self.idle_at(now) >= timeout
+    }
+
+    fn idle_at(&self, now: Instant) -> Duration {
+        let last = match self.last.load(Ordering::Relaxed) {
+            NEVER => self.start,
+            millis => self.start + Duration::from_millis(millis),
+        };
+        now.saturating_duration_since(last)
+    }
+}
+
+/// Flags streams whose ffprobe stays connected without delivering data, e.g.
+/// a frozen origin or a dead multicast group. Lives across ffprobe sessions
+/// so a stall spanning a restart is only counted once.
+pub struct StallWatchdog {
+    timeout: Duration,
+    stalled: bool,
+}
+
+impl StallWatchdog {
+    /// A timeout of zero disables the watchdog
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            stalled: false,
+        }
+    }
+
+    /// Updates the stall metrics, records the start and end of stalls, and
+    /// returns whether the stream is stalled
+    pub fn check(
+        &mut self,
+        activity: &Activity,
+        metrics: &StreamMetrics,
+        events: &EventRecorder,
+        stream_type: &str,
+    ) -> bool {
+        let (idle, received) = (activity.idle(), activity.received());
+        self.check_idle(idle, received, metrics, events, stream_type)
+    }
+
+    /// Forgets a stall without recording its end, e.g. when monitoring is
+    /// paused
+    pub fn reset(&mut self, metrics: &StreamMetrics, stream_type: &str) {
+        if self.stalled {
+            metrics.stalled.with_label_values(&[stream_type]).set(0.0);
+            self.stalled = false;
+        }
+    }
+
+    fn check_idle(
+        &mut self,
+        idle: Duration,
+        received: bool,
+        metrics: &StreamMetrics,
+        events: &EventRecorder,
+        stream_type: &str,
+    ) -> bool {
+        if self.timeout.is_zero() {
+            return false;
+        }
+
+        // After a restart the stall only ends once the new session gets data
+        let stalled = idle >= self.timeout || (self.stalled && !received);
+        if stalled && !self.stalled {
+            warn!(
+                "No data received for {} seconds, stream stalled",
+                idle.as_secs()
+            );
+            metrics.stalls.with_label_values(&[stream_type]).inc();
+            events.record(
+                "stall",
+                None,
+                &format!("No data received for {} seconds", idle.as_secs()),
+            );
+        } else if !stalled && self.stalled {
+            info!("Data flowing again, stream recovered from stall");
+            events.record("stall", Some("recovered"), "Data flowing again");
+        }
+        if stalled != self.stalled {
+            metrics
+                .stalled
+                .with_label_values(&[stream_type])
+                .set(if stalled { 1.0 } else { 0.0 });
+        }
+        self.stalled = stalled;
+        stalled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventLog;
+    use prometheus::Registry;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_activity_idle() {
+        let activity = Activity::new();
+        let later = activity.start + Duration::from_secs(5);
+        assert_eq!(activity.idle_at(later), Duration::from_secs(5));
+        assert_eq!(activity.first_record(), None);
+        activity.touch();
+        assert!(activity.idle_at(later) <= Duration::from_secs(5));
+        assert!(activity.received());
+        let first = activity.first_record().unwrap();
+        activity.touch();
+        assert_eq!(activity.first_record(), Some(first));
+    }
+
+    #[test]
+    fn test_startup_timeout() {
+        let activity = Activity::new();
+        let timeout = Duration::from_secs(60);
+        let later = |secs| activity.start + Duration::from_secs(secs);
+        assert!(!activity.startup_timed_out_at(timeout, later(59)));
+        assert!(activity.startup_timed_out_at(timeout, later(60)));
+        assert!(!activity.startup_timed_out_at(Duration::ZERO, later(3600)));
+
+        // Established sessions are left to the stall watchdog
+        activity.touch();
+        assert!(!activity.startup_timed_out_at(timeout, later(3600)));
+    }
+
+    #[test]
+    fn test_stall_watchdog() {
+        let registry = Registry::new();
+        let metrics = StreamMetrics::new(&registry, &HashMap::new()).unwrap();
+        let mut watchdog = StallWatchdog::new(Duration::from_secs(10));
+        let log = EventLog::new(10);
+        let events = log.recorder("test");
+        let stalled = || metrics.stalled.with_label_values(&["udp"]).get();
+        let stalls = || metrics.stalls.with_label_values(&["udp"]).get();
+
+        let mut check = |idle, received| {
+            watchdog.check_idle(
+                Duration::from_secs(idle),
+                received,
+                &metrics,
+                &events,
+                "udp",
+            )
+        };
+
+        assert!(!check(9, true));
+        assert!(check(10, true));
+        assert!(check(20, true));
+        // A restarted session without data yet is still stalled
+        assert!(check(0, false));
+        assert_eq!(stalled(), 1.0);
+        assert_eq!(stalls(), 1.0);
+
+        assert!(!check(0, true));
+        assert_eq!(stalled(), 0.0);
+        assert_eq!(stalls(), 1.0);
+
+        // Pausing ends a stall silently, a session without data starts fresh
+        assert!(check(10, true));
+        watchdog.reset(&metrics, "udp");
+        assert_eq!(stalled(), 0.0);
+        assert!(!watchdog.check_idle(Duration::ZERO, false, &metrics, &events, "udp"));
+
+        let mut disabled = StallWatchdog::new(Duration::ZERO);
+        let idle = Duration::from_secs(3600);
+        assert!(!disabled.check_idle(idle, false, &metrics, &events, "udp"));
+
+        let kinds: Vec<_> = log.list(None).iter().map(|e| (e.kind, e.detail)).collect();
+        assert_eq!(
+            kinds,
+            [
+                ("stall", None),
+                ("stall", Some("recovered")),
+                ("stall", None)
+            ]
+        );
+    }
+}